@@ -0,0 +1,17 @@
+// 把initramfs打包进内核镜像
+// XXOS_INITRAMFS指定cpio(newc)归档的路径，没有指定时打包一个空文件，启动时不使用initramfs
+use std::{env, fs, path::PathBuf};
+
+fn main() {
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap()).join("initramfs.cpio");
+    println!("cargo:rerun-if-env-changed=XXOS_INITRAMFS");
+    match env::var("XXOS_INITRAMFS") {
+        Ok(path) if !path.is_empty() => {
+            println!("cargo:rerun-if-changed={}", path);
+            if let Err(err) = fs::copy(&path, &out) {
+                panic!("failed to copy initramfs {}: {}", path, err);
+            }
+        }
+        _ => fs::write(&out, []).unwrap(),
+    }
+}
@@ -0,0 +1,284 @@
+// 在宿主机上制作xxfs磁盘镜像
+// usage: mkfs [-b blocks] [-i inodes] fs.img [file|dir ...]
+// 普通文件复制到根目录下，目录连同其中的内容一起复制
+#[allow(dead_code)]
+#[path = "../../src/fs/xxfs/layout.rs"]
+mod layout;
+
+use layout::{
+    read_struct, write_struct, DiskDirent, DiskInode, DiskSuperBlock, BPB, BSIZE, DIRENT_SIZE,
+    MAXFILE, NDIRECT, NINDIRECT, ROOTINO, SUPERBLOCK_NO,
+};
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    os::unix::fs::PermissionsExt,
+    path::Path,
+    process,
+};
+
+const FSSIZE: u32 = 32 * 1024;
+const NINODES: u32 = 1024;
+
+const S_IFREG: u16 = 0o100000;
+const S_IFDIR: u16 = 0o040000;
+
+struct Image {
+    file: File,
+    sb: DiskSuperBlock,
+    // 下一个空闲的数据块与inode，镜像只追加，不会释放
+    freeblock: u32,
+    freeinode: u32,
+}
+
+impl Image {
+    fn create(path: &str, size: u32, ninodes: u32) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len(size as u64 * BSIZE as u64)?;
+        let sb = DiskSuperBlock::new(size, ninodes);
+        if sb.nblocks == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "image too small"));
+        }
+        let mut image = Self {
+            file,
+            freeblock: sb.data_start(),
+            sb,
+            freeinode: ROOTINO,
+        };
+        let mut buf = [0; BSIZE];
+        write_struct(&mut buf, &image.sb);
+        image.wsect(SUPERBLOCK_NO, &buf)?;
+        Ok(image)
+    }
+
+    fn wsect(&mut self, blockno: u32, buf: &[u8; BSIZE]) -> io::Result<()> {
+        self.file
+            .seek(SeekFrom::Start(blockno as u64 * BSIZE as u64))?;
+        self.file.write_all(buf)
+    }
+
+    fn rsect(&mut self, blockno: u32) -> io::Result<[u8; BSIZE]> {
+        let mut buf = [0; BSIZE];
+        self.file
+            .seek(SeekFrom::Start(blockno as u64 * BSIZE as u64))?;
+        self.file.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn winode(&mut self, inum: u32, dinode: &DiskInode) -> io::Result<()> {
+        let blockno = self.sb.iblock(inum);
+        let mut buf = self.rsect(blockno)?;
+        write_struct(&mut buf[DiskInode::offset(inum)..], dinode);
+        self.wsect(blockno, &buf)
+    }
+
+    fn rinode(&mut self, inum: u32) -> io::Result<DiskInode> {
+        let buf = self.rsect(self.sb.iblock(inum))?;
+        Ok(read_struct(&buf[DiskInode::offset(inum)..]))
+    }
+
+    fn ialloc(&mut self, mode: u16) -> io::Result<u32> {
+        if self.freeinode >= self.sb.ninodes {
+            return Err(io::Error::other("out of inodes"));
+        }
+        let inum = self.freeinode;
+        self.freeinode += 1;
+        let dinode = DiskInode {
+            mode,
+            nlink: 1,
+            ..Default::default()
+        };
+        self.winode(inum, &dinode)?;
+        Ok(inum)
+    }
+
+    // 数据块按顺序分配，位图在最后统一写入
+    fn balloc(&mut self) -> io::Result<u32> {
+        if self.freeblock >= self.sb.size {
+            return Err(io::Error::other("out of blocks"));
+        }
+        let blockno = self.freeblock;
+        self.freeblock += 1;
+        Ok(blockno)
+    }
+
+    // 读出或者分配间接块block中的第index项
+    fn indirect(&mut self, block: u32, index: usize) -> io::Result<u32> {
+        let mut buf = self.rsect(block)?;
+        let mut addr: u32 = read_struct(&buf[index * 4..]);
+        if addr == 0 {
+            addr = self.balloc()?;
+            write_struct(&mut buf[index * 4..], &addr);
+            self.wsect(block, &buf)?;
+        }
+        Ok(addr)
+    }
+
+    fn bmap(&mut self, dinode: &mut DiskInode, bn: usize) -> io::Result<u32> {
+        let slot = |image: &mut Self, addr: &mut u32| -> io::Result<u32> {
+            if *addr == 0 {
+                *addr = image.balloc()?;
+            }
+            Ok(*addr)
+        };
+        if bn < NDIRECT {
+            return slot(self, &mut dinode.addrs[bn]);
+        }
+        let bn = bn - NDIRECT;
+        if bn < NINDIRECT {
+            let indirect = slot(self, &mut dinode.addrs[NDIRECT])?;
+            return self.indirect(indirect, bn);
+        }
+        let bn = bn - NINDIRECT;
+        if bn >= NINDIRECT * NINDIRECT {
+            return Err(io::Error::other("file too large"));
+        }
+        let double = slot(self, &mut dinode.addrs[NDIRECT + 1])?;
+        let indirect = self.indirect(double, bn / NINDIRECT)?;
+        self.indirect(indirect, bn % NINDIRECT)
+    }
+
+    fn iappend(&mut self, inum: u32, mut data: &[u8]) -> io::Result<()> {
+        let mut dinode = self.rinode(inum)?;
+        let mut offset = dinode.size as usize;
+        if offset + data.len() > MAXFILE * BSIZE {
+            return Err(io::Error::other("file too large"));
+        }
+        while !data.is_empty() {
+            let blockno = self.bmap(&mut dinode, offset / BSIZE)?;
+            let boff = offset % BSIZE;
+            let len = (BSIZE - boff).min(data.len());
+            let mut buf = self.rsect(blockno)?;
+            buf[boff..boff + len].copy_from_slice(&data[..len]);
+            self.wsect(blockno, &buf)?;
+            offset += len;
+            data = &data[len..];
+        }
+        dinode.size = offset as u32;
+        self.winode(inum, &dinode)
+    }
+
+    fn dirlink(&mut self, dir: u32, name: &str, inum: u32) -> io::Result<()> {
+        let dirent = DiskDirent::new(inum, name).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("bad name {:?}", name))
+        })?;
+        let mut bytes = [0; DIRENT_SIZE];
+        write_struct(&mut bytes, &dirent);
+        self.iappend(dir, &bytes)
+    }
+
+    fn mkdir(&mut self, parent: u32, name: &str, perm: u16) -> io::Result<u32> {
+        let inum = self.ialloc(S_IFDIR | perm)?;
+        let mut dinode = self.rinode(inum)?;
+        dinode.nlink = 2;
+        self.winode(inum, &dinode)?;
+        self.dirlink(inum, ".", inum)?;
+        self.dirlink(inum, "..", parent)?;
+        if parent != inum {
+            self.dirlink(parent, name, inum)?;
+            let mut pinode = self.rinode(parent)?;
+            pinode.nlink += 1;
+            self.winode(parent, &pinode)?;
+        }
+        Ok(inum)
+    }
+
+    // 复制宿主机上的文件或目录到dir中
+    fn add(&mut self, dir: u32, path: &Path) -> io::Result<()> {
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "bad file name"))?;
+        let meta = fs::metadata(path)?;
+        let perm = (meta.permissions().mode() & 0o7777) as u16;
+        if meta.is_dir() {
+            let inum = self.mkdir(dir, name, perm)?;
+            let mut entries = fs::read_dir(path)?
+                .map(|entry| entry.map(|e| e.path()))
+                .collect::<io::Result<Vec<_>>>()?;
+            entries.sort();
+            for entry in entries {
+                self.add(inum, &entry)?;
+            }
+        } else {
+            let inum = self.ialloc(S_IFREG | perm)?;
+            self.dirlink(dir, name, inum)?;
+            self.iappend(inum, &fs::read(path)?)?;
+        }
+        Ok(())
+    }
+
+    // 标记[0, freeblock)为已用
+    fn write_bitmap(&mut self) -> io::Result<()> {
+        let used = self.freeblock as usize;
+        for (i, base) in (0..self.sb.size as usize).step_by(BPB).enumerate() {
+            let mut buf = [0; BSIZE];
+            for bit in 0..BPB.min(used.saturating_sub(base)) {
+                buf[bit / 8] |= 1 << (bit % 8);
+            }
+            self.wsect(self.sb.bmapstart + i as u32, &buf)?;
+        }
+        Ok(())
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("usage: mkfs [-b blocks] [-i inodes] fs.img [file|dir ...]");
+    process::exit(1);
+}
+
+fn run() -> io::Result<()> {
+    let mut args = env::args().skip(1);
+    let mut size = FSSIZE;
+    let mut ninodes = NINODES;
+    let mut image_path = None;
+    let mut files = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-b" => size = args.next().and_then(|v| v.parse().ok()).unwrap_or_else(|| usage()),
+            "-i" => ninodes = args.next().and_then(|v| v.parse().ok()).unwrap_or_else(|| usage()),
+            _ if image_path.is_none() => image_path = Some(arg),
+            _ => files.push(arg),
+        }
+    }
+    let image_path = image_path.unwrap_or_else(|| usage());
+
+    let mut image = Image::create(&image_path, size, ninodes)?;
+    let sb = image.sb;
+    println!(
+        "mkfs: {} blocks: {} log, {} inode, {} bitmap, {} data; {} inodes",
+        sb.size,
+        sb.nlog,
+        sb.bmapstart - sb.inodestart,
+        sb.data_start() - sb.bmapstart,
+        sb.nblocks,
+        sb.ninodes
+    );
+
+    let root = image.mkdir(ROOTINO, "/", 0o755)?;
+    assert_eq!(root, ROOTINO);
+    for file in files {
+        image.add(root, Path::new(&file))?;
+    }
+    image.write_bitmap()?;
+    image.file.sync_all()?;
+    println!(
+        "mkfs: {} inodes and {} data blocks used",
+        image.freeinode - ROOTINO,
+        image.freeblock - sb.data_start()
+    );
+    Ok(())
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("mkfs: {}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,446 @@
+use super::{
+    block_device, queue::RequestQueue, ramdisk::RamDisk, register_block_device, BlockDevice, DevId,
+};
+use crate::{
+    error::{Errno, ErrorTrace, Result},
+    mm::kmem_cache::{KBox, KmemCache},
+    sched::sleeplock::SleepLock,
+};
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+use xx_mutex_lock::Mutex;
+use xxos_log::{error, info, warn};
+
+// 文件系统块的大小，与设备的块大小无关
+pub const BSIZE: usize = 1024;
+// 缓存的块数
+pub const NBUF: usize = 128;
+
+pub struct BufData {
+    pub data: [u8; BSIZE],
+    valid: bool,
+    dirty: bool,
+}
+
+impl BufData {
+    const fn new() -> Self {
+        Self {
+            data: [0; BSIZE],
+            valid: false,
+            dirty: false,
+        }
+    }
+}
+
+static BUF_CACHE: KmemCache<BufData> = KmemCache::new("buffer", Some(BufData::new));
+
+// 一个缓存块，回收后会换成另一个(dev, blockno)继续使用
+// refcnt只在持有BCACHE的锁时修改，数据由睡眠锁保护
+// dev与blockno还需要同时持有睡眠锁才能修改，持有睡眠锁时它们不会改变
+// BCACHE的锁是自旋锁，持有它时不能等待睡眠锁或者访问设备
+pub struct Buf {
+    dev: AtomicUsize,
+    blockno: AtomicU64,
+    refcnt: AtomicUsize,
+    inner: SleepLock<KBox<BufData>>,
+}
+
+impl Buf {
+    fn new(dev: DevId, blockno: u64) -> Result<Self> {
+        Ok(Self {
+            dev: AtomicUsize::new(dev),
+            blockno: AtomicU64::new(blockno),
+            refcnt: AtomicUsize::new(0),
+            inner: SleepLock::new(BUF_CACHE.construct()?),
+        })
+    }
+
+    fn key(&self) -> (DevId, u64) {
+        (
+            self.dev.load(Ordering::Relaxed),
+            self.blockno.load(Ordering::Relaxed),
+        )
+    }
+}
+
+// Buffer cache
+// 按(dev, blockno)索引，lru队首为最近使用的块，从队尾回收引用计数为0的块
+struct BCache {
+    map: BTreeMap<(DevId, u64), Arc<Buf>>,
+    lru: VecDeque<Arc<Buf>>,
+    hits: usize,
+    misses: usize,
+}
+
+static BCACHE: Mutex<BCache> = Mutex::new(BCache {
+    map: BTreeMap::new(),
+    lru: VecDeque::new(),
+    hits: 0,
+    misses: 0,
+});
+
+// 每个文件系统块包含的设备块数，设备的块大小必须能整除BSIZE
+fn blocks_per_buf(device: &dyn BlockDevice) -> Result<u64> {
+    match device.block_size() {
+        size if size <= BSIZE && BSIZE % size == 0 => Ok((BSIZE / size) as u64),
+        _ => Err(Errno::EINVAL.into()),
+    }
+}
+
+fn device_io(dev: DevId, blockno: u64, data: &mut [u8; BSIZE], write: bool) -> Result<()> {
+    let device = block_device(dev).ok_or_else(|| ErrorTrace::from_errno(Errno::ENXIO))?;
+    let per_block = blocks_per_buf(device.as_ref())?;
+    if write {
+        device.write_blocks(blockno * per_block, data)
+    } else {
+        device.read_blocks(blockno * per_block, data)
+    }
+}
+
+// 持有睡眠锁时调用，把脏数据写回缓存块当前所属的块
+fn writeback(buf: &Buf) -> Result<()> {
+    let (dev, blockno) = buf.key();
+    let inner = unsafe { buf.inner.get_mut() };
+    if inner.dirty {
+        device_io(dev, blockno, &mut inner.data, true)?;
+        inner.dirty = false;
+    }
+    Ok(())
+}
+
+// 找到或者分配(dev, blockno)对应的缓存块，返回时引用计数已经加一，但还没有加锁
+// 回收的块先在BCACHE的锁之外写回脏数据，之后才换成新的key
+fn bget(dev: DevId, blockno: u64) -> Result<Arc<Buf>> {
+    let mut new_buf = None;
+    loop {
+        let mut cache = BCACHE.lock();
+        if let Some(buf) = cache.map.get(&(dev, blockno)).cloned() {
+            buf.refcnt.fetch_add(1, Ordering::SeqCst);
+            cache.hits += 1;
+            return Ok(buf);
+        }
+
+        if cache.lru.len() < NBUF {
+            // 分配新的缓存块时不持有BCACHE的锁，回来之后重新检查
+            let Some(buf) = new_buf.take() else {
+                drop(cache);
+                new_buf = Some(Arc::new(Buf::new(dev, blockno)?));
+                continue;
+            };
+            cache.misses += 1;
+            buf.refcnt.store(1, Ordering::SeqCst);
+            cache.map.insert((dev, blockno), buf.clone());
+            cache.lru.push_front(buf.clone());
+            return Ok(buf);
+        }
+
+        // 回收最久没有使用的空闲块
+        let victim = cache
+            .lru
+            .iter()
+            .rev()
+            .find(|buf| buf.refcnt.load(Ordering::SeqCst) == 0)
+            .cloned()
+            .ok_or_else(|| {
+                warn!("bcache: no free buffers");
+                ErrorTrace::from_errno(Errno::ENOMEM)
+            })?;
+        // 写回期间旧的key仍然在map中，读旧块的人会等待写回完成
+        victim.refcnt.store(1, Ordering::SeqCst);
+        drop(cache);
+
+        victim.inner.acquire();
+        let result = writeback(&victim);
+        let mut cache = BCACHE.lock();
+        // 写回失败时脏数据留在原来的块中；写回期间有人使用了旧块，
+        // 或者别人已经读入了新块时放弃这次回收，重新查找
+        let reclaimed = result.is_ok()
+            && victim.refcnt.load(Ordering::SeqCst) == 1
+            && !cache.map.contains_key(&(dev, blockno));
+        if reclaimed {
+            cache.misses += 1;
+            cache.map.remove(&victim.key());
+            victim.dev.store(dev, Ordering::Relaxed);
+            victim.blockno.store(blockno, Ordering::Relaxed);
+            cache.map.insert((dev, blockno), victim.clone());
+            unsafe { victim.inner.get_mut() }.valid = false;
+        } else {
+            victim.refcnt.fetch_sub(1, Ordering::SeqCst);
+        }
+        drop(cache);
+        victim.inner.release();
+        result?;
+        if reclaimed {
+            return Ok(victim);
+        }
+    }
+}
+
+// 已加锁的缓存块，drop时自动brelse
+pub struct BufRef {
+    buf: Arc<Buf>,
+}
+
+impl BufRef {
+    pub fn dev(&self) -> DevId {
+        self.buf.key().0
+    }
+
+    pub fn blockno(&self) -> u64 {
+        self.buf.key().1
+    }
+
+    // 持有BufRef即持有缓存块的睡眠锁
+    fn inner(&mut self) -> &mut BufData {
+        unsafe { self.buf.inner.get_mut() }
+    }
+
+    fn inner_ref(&self) -> &BufData {
+        unsafe { self.buf.inner.get() }
+    }
+
+    pub fn data(&self) -> &[u8; BSIZE] {
+        &self.inner_ref().data
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8; BSIZE] {
+        &mut self.inner().data
+    }
+
+    // 延迟写：标记为脏块，在回收或者bflush时写回
+    pub fn mark_dirty(&mut self) {
+        self.inner().dirty = true;
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.inner_ref().dirty
+    }
+}
+
+impl Deref for BufRef {
+    type Target = [u8; BSIZE];
+    fn deref(&self) -> &Self::Target {
+        self.data()
+    }
+}
+
+impl DerefMut for BufRef {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.data_mut()
+    }
+}
+
+impl Drop for BufRef {
+    fn drop(&mut self) {
+        self.buf.inner.release();
+        let mut cache = BCACHE.lock();
+        if self.buf.refcnt.fetch_sub(1, Ordering::SeqCst) == 1 {
+            // 没有人使用了，移到lru队首
+            if let Some(index) = cache.lru.iter().position(|buf| Arc::ptr_eq(buf, &self.buf)) {
+                let buf = cache.lru.remove(index).unwrap();
+                cache.lru.push_front(buf);
+            }
+        }
+    }
+}
+
+// return a locked buffer with the contents of the indicated block
+pub fn bread(dev: DevId, blockno: u64) -> Result<BufRef> {
+    let buf = bget(dev, blockno)?;
+    buf.inner.acquire();
+    let mut buf = BufRef { buf };
+    let inner = buf.inner();
+    if !inner.valid {
+        device_io(dev, blockno, &mut inner.data, false)?;
+        inner.valid = true;
+    }
+    Ok(buf)
+}
+
+// 从dev的字节偏移offset处读出buf.len()个字节，可以跨越多个缓存块
+pub fn bread_bytes(dev: DevId, mut offset: u64, buf: &mut [u8]) -> Result<()> {
+    let mut done = 0;
+    while done < buf.len() {
+        let block = bread(dev, offset / BSIZE as u64)?;
+        let boff = offset as usize % BSIZE;
+        let len = (BSIZE - boff).min(buf.len() - done);
+        buf[done..done + len].copy_from_slice(&block[boff..boff + len]);
+        done += len;
+        offset += len as u64;
+    }
+    Ok(())
+}
+
+// 把data写到dev的字节偏移offset处，每个缓存块修改后立即写回
+pub fn bwrite_bytes(dev: DevId, mut offset: u64, data: &[u8]) -> Result<()> {
+    let mut done = 0;
+    while done < data.len() {
+        let mut block = bread(dev, offset / BSIZE as u64)?;
+        let boff = offset as usize % BSIZE;
+        let len = (BSIZE - boff).min(data.len() - done);
+        block[boff..boff + len].copy_from_slice(&data[done..done + len]);
+        bwrite(&mut block)?;
+        done += len;
+        offset += len as u64;
+    }
+    Ok(())
+}
+
+// write the buffer's contents to disk immediately
+pub fn bwrite(buf: &mut BufRef) -> Result<()> {
+    let (dev, blockno) = buf.buf.key();
+    let inner = buf.inner();
+    device_io(dev, blockno, &mut inner.data, true)?;
+    inner.dirty = false;
+    Ok(())
+}
+
+// 丢弃缓存块中还没有写到磁盘的修改，下次bread时重新读入，日志放弃事务时使用
+pub fn bdiscard(buf: &mut BufRef) {
+    let inner = buf.inner();
+    inner.valid = false;
+    inner.dirty = false;
+}
+
+// release a locked buffer
+pub fn brelse(buf: BufRef) {
+    drop(buf);
+}
+
+// 增加引用计数，使缓存块不会被回收，日志提交之前使用
+pub fn bpin(buf: &BufRef) {
+    let _cache = BCACHE.lock();
+    buf.buf.refcnt.fetch_add(1, Ordering::SeqCst);
+}
+
+pub fn bunpin(buf: &BufRef) {
+    let _cache = BCACHE.lock();
+    buf.buf.refcnt.fetch_sub(1, Ordering::SeqCst);
+}
+
+// 写回dev上的所有脏块，相邻的块合并为一次请求
+// 调用者不能持有dev上的缓存块，否则会一直等待它的锁
+pub fn bflush(dev: DevId) -> Result<()> {
+    let device = block_device(dev).ok_or_else(|| ErrorTrace::from_errno(Errno::ENXIO))?;
+    let per_block = blocks_per_buf(device.as_ref())?;
+    let bufs: Vec<Arc<Buf>> = BCACHE
+        .lock()
+        .map
+        .range((dev, 0)..=(dev, u64::MAX))
+        .map(|(_, buf)| buf.clone())
+        .collect();
+
+    let mut dirty: Vec<(u64, &Arc<Buf>)> = Vec::new();
+    for buf in bufs.iter() {
+        buf.inner.acquire();
+        // 加锁之前缓存块可能已经被回收给了别的块
+        let (d, blockno) = buf.key();
+        if d == dev && unsafe { buf.inner.get() }.dirty {
+            dirty.push((blockno, buf));
+        } else {
+            buf.inner.release();
+        }
+    }
+    // 按块号排序，相邻的块才能合并
+    dirty.sort_unstable_by_key(|(blockno, _)| *blockno);
+
+    let mut queue = RequestQueue::new(device.clone());
+    let mut result = Ok(());
+    for (blockno, buf) in dirty.iter() {
+        let inner = unsafe { buf.inner.get() };
+        if let Err(err) = queue.write(blockno * per_block, &inner.data) {
+            result = Err(err);
+            break;
+        }
+    }
+    if result.is_ok() {
+        result = queue.dispatch().and_then(|_| device.flush());
+    }
+    for (_, buf) in dirty {
+        if result.is_ok() {
+            unsafe { buf.inner.get_mut() }.dirty = false;
+        }
+        buf.inner.release();
+    }
+    result
+}
+
+// 丢弃dev的所有缓存块，卸载文件系统时使用，调用前应当先bflush
+pub fn binvalidate(dev: DevId) {
+    let mut cache = BCACHE.lock();
+    let keys: Vec<_> = cache
+        .map
+        .range((dev, 0)..=(dev, u64::MAX))
+        .filter(|(_, buf)| buf.refcnt.load(Ordering::SeqCst) == 0)
+        .map(|(key, _)| *key)
+        .collect();
+    for key in keys {
+        if let Some(buf) = cache.map.remove(&key) {
+            cache.lru.retain(|b| !Arc::ptr_eq(b, &buf));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BCacheStats {
+    pub buffers: usize,
+    pub hits: usize,
+    pub misses: usize,
+}
+
+pub fn bcache_stats() -> BCacheStats {
+    let cache = BCACHE.lock();
+    BCacheStats {
+        buffers: cache.lru.len(),
+        hits: cache.hits,
+        misses: cache.misses,
+    }
+}
+
+// 在ramdisk上检查延迟写、回收时写回与bflush
+// 写入的块比缓存块多，前面的脏块一定经过了回收时的写回
+pub fn bcache_test() {
+    info!("============ bcache test start ============");
+    const BLOCKS: u64 = NBUF as u64 + 16;
+    let disk = match RamDisk::new("ram0", BLOCKS * 2, BSIZE / 2) {
+        Ok(disk) => Arc::new(disk),
+        Err(err) => {
+            error!("bcache test: {}", err);
+            panic!();
+        }
+    };
+    let dev = register_block_device(disk.clone());
+    let pattern = |blockno: u64| (blockno as u8).wrapping_mul(7).wrapping_add(1);
+
+    let result = (|| -> Result<()> {
+        for blockno in 0..BLOCKS {
+            let mut buf = bread(dev, blockno)?;
+            buf.fill(pattern(blockno));
+            buf.mark_dirty();
+        }
+        bflush(dev)?;
+        binvalidate(dev);
+        let mut raw = [0; BSIZE];
+        for blockno in 0..BLOCKS {
+            disk.read_blocks(blockno * 2, &mut raw)?;
+            let buf = bread(dev, blockno)?;
+            if raw.iter().chain(buf.iter()).any(|&b| b != pattern(blockno)) {
+                error!("bcache test: block {} wrong", blockno);
+                panic!();
+            }
+        }
+        Ok(())
+    })();
+    if let Err(err) = result {
+        error!("bcache test: {}", err);
+        panic!();
+    }
+    info!("============ bcache test end ============");
+}
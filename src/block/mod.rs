@@ -0,0 +1,90 @@
+pub mod bcache;
+pub mod partition;
+pub mod queue;
+pub mod ramdisk;
+
+use crate::{
+    error::{Errno, Result},
+    riscv::registers::sstatus::IntrGuard,
+};
+use alloc::{sync::Arc, vec::Vec};
+use queue::{BlockOp, MergedRequest};
+use xx_mutex_lock::Mutex;
+use xxos_log::info;
+
+// 块设备编号，即在BLOCK_DEVICES中的下标
+pub type DevId = usize;
+
+// Generic block device
+// 文件系统只通过这个接口访问存储设备，块号以设备自身的块大小为单位
+pub trait BlockDevice: Send + Sync {
+    fn name(&self) -> &str;
+    fn block_size(&self) -> usize;
+    // in blocks
+    fn capacity(&self) -> u64;
+    fn read_blocks(&self, block: u64, buf: &mut [u8]) -> Result<()>;
+    fn write_blocks(&self, block: u64, buf: &[u8]) -> Result<()>;
+    fn flush(&self) -> Result<()>;
+
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    // 执行请求队列合并后的请求，支持并发的设备可以一次提交全部请求
+    fn submit(&self, requests: &mut [MergedRequest]) -> Result<()> {
+        for request in requests.iter_mut() {
+            match request.op {
+                BlockOp::Read => self.read_blocks(request.block, &mut request.data)?,
+                BlockOp::Write => self.write_blocks(request.block, &request.data)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// 检查请求是否在设备范围之内，并且长度是块大小的整数倍
+pub fn check_range(dev: &dyn BlockDevice, block: u64, len: usize) -> Result<()> {
+    let block_size = dev.block_size();
+    if len == 0 || len % block_size != 0 {
+        return Err(Errno::EINVAL.into());
+    }
+    match block.checked_add((len / block_size) as u64) {
+        Some(end) if end <= dev.capacity() => Ok(()),
+        _ => Err(Errno::EINVAL.into()),
+    }
+}
+
+static BLOCK_DEVICES: Mutex<Vec<Arc<dyn BlockDevice>>> = Mutex::new(Vec::new());
+
+pub fn register_block_device(dev: Arc<dyn BlockDevice>) -> DevId {
+    info!(
+        "block: {} registered, {} blocks of {} bytes",
+        dev.name(),
+        dev.capacity(),
+        dev.block_size()
+    );
+    let _guard = IntrGuard::new();
+    let mut devices = BLOCK_DEVICES.lock();
+    devices.push(dev);
+    devices.len() - 1
+}
+
+pub fn block_device(id: DevId) -> Option<Arc<dyn BlockDevice>> {
+    let _guard = IntrGuard::new();
+    BLOCK_DEVICES.lock().get(id).cloned()
+}
+
+pub fn find_block_device(name: &str) -> Option<(DevId, Arc<dyn BlockDevice>)> {
+    let _guard = IntrGuard::new();
+    BLOCK_DEVICES
+        .lock()
+        .iter()
+        .enumerate()
+        .find(|(_, dev)| dev.name() == name)
+        .map(|(id, dev)| (id, dev.clone()))
+}
+
+pub fn block_devices() -> Vec<(DevId, Arc<dyn BlockDevice>)> {
+    let _guard = IntrGuard::new();
+    BLOCK_DEVICES.lock().iter().cloned().enumerate().collect()
+}
@@ -0,0 +1,116 @@
+use super::{block_device, check_range, register_block_device, BlockDevice, DevId};
+use crate::error::{Errno, Result};
+use alloc::{format, string::String, sync::Arc, vec};
+use xxos_log::{info, warn};
+
+// MBR partition table
+// 第一个扇区的446字节处有四个16字节的分区项，扇区以0x55 0xAA结尾
+const MBR_SECTOR_SIZE: usize = 512;
+const MBR_TABLE_OFFSET: usize = 446;
+const MBR_ENTRY_SIZE: usize = 16;
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+// 扩展分区与GPT保护分区不作为普通分区处理
+const PART_TYPE_EXTENDED: [u8; 3] = [0x05, 0x0f, 0x85];
+const PART_TYPE_GPT: u8 = 0xee;
+
+// 磁盘上的一段连续的块，作为一个独立的块设备
+pub struct Partition {
+    name: String,
+    parent: Arc<dyn BlockDevice>,
+    // in blocks of the parent device
+    start: u64,
+    blocks: u64,
+    part_type: u8,
+}
+
+impl Partition {
+    pub fn part_type(&self) -> u8 {
+        self.part_type
+    }
+}
+
+impl BlockDevice for Partition {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_size(&self) -> usize {
+        self.parent.block_size()
+    }
+
+    fn capacity(&self) -> u64 {
+        self.blocks
+    }
+
+    fn read_blocks(&self, block: u64, buf: &mut [u8]) -> Result<()> {
+        check_range(self, block, buf.len())?;
+        self.parent.read_blocks(self.start + block, buf)
+    }
+
+    fn write_blocks(&self, block: u64, buf: &[u8]) -> Result<()> {
+        check_range(self, block, buf.len())?;
+        self.parent.write_blocks(self.start + block, buf)
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.parent.flush()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.parent.is_read_only()
+    }
+}
+
+// 读取dev的MBR，把其中的主分区注册为dev1到dev4，返回注册的分区数
+pub fn scan_partitions(dev: DevId) -> Result<usize> {
+    let parent = block_device(dev).ok_or(Errno::ENXIO)?;
+    let block_size = parent.block_size();
+    if block_size < MBR_SECTOR_SIZE || block_size % MBR_SECTOR_SIZE != 0 {
+        return Err(Errno::EINVAL.into());
+    }
+    let mut sector = vec![0; block_size];
+    parent.read_blocks(0, &mut sector)?;
+    if sector[510..512] != MBR_SIGNATURE {
+        return Ok(0);
+    }
+
+    // 分区表中以512字节的扇区为单位
+    let per_block = (block_size / MBR_SECTOR_SIZE) as u64;
+    let mut count = 0;
+    for index in 0..4 {
+        let entry = &sector[MBR_TABLE_OFFSET + index * MBR_ENTRY_SIZE..][..MBR_ENTRY_SIZE];
+        let part_type = entry[4];
+        let lba = u32::from_le_bytes(entry[8..12].try_into().unwrap()) as u64;
+        let sectors = u32::from_le_bytes(entry[12..16].try_into().unwrap()) as u64;
+        if part_type == 0 || sectors == 0 || PART_TYPE_EXTENDED.contains(&part_type) {
+            continue;
+        }
+        if part_type == PART_TYPE_GPT {
+            warn!(
+                "block: {} has a GPT partition table, not supported",
+                parent.name()
+            );
+            return Ok(0);
+        }
+        // VBR之类不是分区表的扇区也可能以0x55 0xAA结尾，范围不对时放弃
+        if lba % per_block != 0 || (lba + sectors) / per_block > parent.capacity() {
+            info!("block: {} has no valid partition table", parent.name());
+            return Ok(count);
+        }
+
+        let partition = Partition {
+            name: format!("{}{}", parent.name(), index + 1),
+            parent: parent.clone(),
+            start: lba / per_block,
+            blocks: sectors / per_block,
+            part_type,
+        };
+        info!(
+            "block: partition {} type {:#x} at block {}",
+            partition.name, part_type, partition.start
+        );
+        register_block_device(Arc::new(partition));
+        count += 1;
+    }
+    Ok(count)
+}
@@ -0,0 +1,194 @@
+use super::{check_range, ramdisk::RamDisk, BlockDevice};
+use crate::error::Result;
+use alloc::{collections::BTreeMap, sync::Arc, vec, vec::Vec};
+use xxos_log::{error, info};
+
+// 合并之后单个请求的最大长度
+const MAX_MERGE_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockOp {
+    Read,
+    Write,
+}
+
+pub type RequestId = usize;
+
+// 由若干个块号相邻的同类请求合并而成
+pub struct MergedRequest {
+    pub op: BlockOp,
+    pub block: u64,
+    pub data: Vec<u8>,
+    // (id, offset in data, len)
+    parts: Vec<(RequestId, usize, usize)>,
+}
+
+impl MergedRequest {
+    fn end(&self, block_size: usize) -> u64 {
+        self.block + (self.data.len() / block_size) as u64
+    }
+
+    fn overlaps(&self, block: u64, end: u64, block_size: usize) -> bool {
+        self.block < end && block < self.end(block_size)
+    }
+}
+
+// Block request queue
+// 请求先在队列中积累，相邻的同类请求合并为一次设备访问，dispatch时统一提交
+// 只有在不越过与之重叠的请求时才会合并，保证读写的先后顺序不变
+pub struct RequestQueue {
+    dev: Arc<dyn BlockDevice>,
+    next_id: RequestId,
+    pending: Vec<MergedRequest>,
+}
+
+impl RequestQueue {
+    pub fn new(dev: Arc<dyn BlockDevice>) -> Self {
+        Self {
+            dev,
+            next_id: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn device(&self) -> &Arc<dyn BlockDevice> {
+        &self.dev
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    // read `count` blocks starting at `block`
+    pub fn read(&mut self, block: u64, count: usize) -> Result<RequestId> {
+        let data = vec![0; count * self.dev.block_size()];
+        self.queue(BlockOp::Read, block, data)
+    }
+
+    pub fn write(&mut self, block: u64, data: &[u8]) -> Result<RequestId> {
+        self.queue(BlockOp::Write, block, data.into())
+    }
+
+    fn queue(&mut self, op: BlockOp, block: u64, data: Vec<u8>) -> Result<RequestId> {
+        check_range(self.dev.as_ref(), block, data.len())?;
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let block_size = self.dev.block_size();
+        let end = block + (data.len() / block_size) as u64;
+        // 从后往前找可以合并的请求，遇到重叠的请求就停止
+        for index in (0..self.pending.len()).rev() {
+            let request = &mut self.pending[index];
+            if request.op == op && request.data.len() + data.len() <= MAX_MERGE_BYTES {
+                // back merge
+                if request.end(block_size) == block {
+                    request.parts.push((id, request.data.len(), data.len()));
+                    request.data.extend_from_slice(&data);
+                    return Ok(id);
+                }
+                // front merge
+                if end == request.block {
+                    request
+                        .parts
+                        .iter_mut()
+                        .for_each(|part| part.1 += data.len());
+                    request.parts.push((id, 0, data.len()));
+                    let mut merged = data;
+                    merged.extend_from_slice(&request.data);
+                    request.data = merged;
+                    request.block = block;
+                    return Ok(id);
+                }
+            }
+            if request.overlaps(block, end, block_size) {
+                break;
+            }
+        }
+
+        self.pending.push(MergedRequest {
+            op,
+            block,
+            parts: vec![(id, 0, data.len())],
+            data,
+        });
+        Ok(id)
+    }
+
+    // 提交所有请求，返回每个读请求读到的数据
+    pub fn dispatch(&mut self) -> Result<BTreeMap<RequestId, Vec<u8>>> {
+        let mut requests = core::mem::take(&mut self.pending);
+        self.dev.submit(&mut requests)?;
+
+        let mut results = BTreeMap::new();
+        for request in requests.iter().filter(|r| r.op == BlockOp::Read) {
+            for &(id, offset, len) in request.parts.iter() {
+                results.insert(id, request.data[offset..offset + len].to_vec());
+            }
+        }
+        Ok(results)
+    }
+}
+
+// 在ramdisk上检查请求的前后合并，以及读写不会越过与之重叠的请求
+pub fn queue_test() {
+    info!("============ request queue test start ============");
+    const BLOCK_SIZE: usize = 512;
+    let block = |byte: u8| [byte; BLOCK_SIZE];
+
+    let result = (|| -> Result<()> {
+        let disk = Arc::new(RamDisk::from_image(
+            "ram1",
+            &[0xff; BLOCK_SIZE * 16],
+            BLOCK_SIZE,
+        )?);
+        let mut queue = RequestQueue::new(disk.clone());
+        queue.write(4, &block(4))?;
+        // back merge
+        queue.write(5, &block(5))?;
+        // front merge
+        queue.write(3, &block(3))?;
+        check(queue.len() == 1, "adjacent writes are not merged")?;
+        queue.write(10, &block(10))?;
+        check(queue.len() == 2, "distant writes are merged")?;
+        let read = queue.read(3, 3)?;
+        // 与读请求不重叠，可以越过它合并到更早的写请求中
+        queue.write(6, &block(6))?;
+        check(queue.len() == 3, "write not merged across a disjoint read")?;
+        // 与读请求重叠，不能合并
+        queue.write(4, &block(0x44))?;
+        check(queue.len() == 4, "write merged across an overlapping read")?;
+
+        let results = queue.dispatch()?;
+        check(queue.is_empty(), "requests left after dispatch")?;
+        let data = results.get(&read).map(Vec::as_slice).unwrap_or_default();
+        let expected: Vec<u8> = [3, 4, 5].iter().flat_map(|&b| block(b)).collect();
+        check(data == expected, "read does not see the earlier writes")?;
+
+        let mut raw = [0; BLOCK_SIZE];
+        for (blockno, byte) in [(3, 3), (4, 0x44), (5, 5), (6, 6), (7, 0xff), (10, 10)] {
+            disk.read_blocks(blockno, &mut raw)?;
+            check(raw == block(byte), "wrong data on the ramdisk")?;
+        }
+        check(
+            disk.read_blocks(16, &mut raw).is_err(),
+            "ramdisk accepts a block past its end",
+        )
+    })();
+    if let Err(err) = result {
+        error!("request queue test: {}", err);
+        panic!();
+    }
+    info!("============ request queue test end ============");
+}
+
+fn check(ok: bool, message: &str) -> Result<()> {
+    if ok {
+        Ok(())
+    } else {
+        Err(crate::error::ErrorTrace::new(message))
+    }
+}
@@ -0,0 +1,77 @@
+use super::{check_range, BlockDevice};
+use crate::error::{Errno, Result};
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use xx_mutex_lock::Mutex;
+
+// RAM-backed block device
+// 内容保存在内核堆中，主要用于测试文件系统
+pub struct RamDisk {
+    name: String,
+    block_size: usize,
+    blocks: u64,
+    data: Mutex<Vec<u8>>,
+}
+
+impl RamDisk {
+    pub fn new(name: &str, blocks: u64, block_size: usize) -> Result<Self> {
+        let size = blocks as usize * block_size;
+        let mut data = Vec::new();
+        data.try_reserve_exact(size).map_err(|_| Errno::ENOMEM)?;
+        data.resize(size, 0);
+        Ok(Self {
+            name: name.to_string(),
+            block_size,
+            blocks,
+            data: Mutex::new(data),
+        })
+    }
+
+    // 用已有的镜像创建，镜像长度必须是块大小的整数倍
+    pub fn from_image(name: &str, image: &[u8], block_size: usize) -> Result<Self> {
+        if image.len() % block_size != 0 {
+            return Err(Errno::EINVAL.into());
+        }
+        let disk = Self::new(name, (image.len() / block_size) as u64, block_size)?;
+        disk.data.lock().copy_from_slice(image);
+        Ok(disk)
+    }
+
+    fn offset(&self, block: u64) -> usize {
+        block as usize * self.block_size
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn capacity(&self) -> u64 {
+        self.blocks
+    }
+
+    fn read_blocks(&self, block: u64, buf: &mut [u8]) -> Result<()> {
+        check_range(self, block, buf.len())?;
+        let offset = self.offset(block);
+        buf.copy_from_slice(&self.data.lock()[offset..offset + buf.len()]);
+        Ok(())
+    }
+
+    fn write_blocks(&self, block: u64, buf: &[u8]) -> Result<()> {
+        check_range(self, block, buf.len())?;
+        let offset = self.offset(block);
+        self.data.lock()[offset..offset + buf.len()].copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,66 @@
+use super::tty::{tty_get_lflag, tty_read, tty_set_lflag, tty_write};
+use crate::{
+    cpu::current_task,
+    error::{Errno, ErrorTrace, Result},
+    fs::{
+        def::{Stat, S_IFCHR},
+        devfs::Device,
+        vfs::{new_file, File, FileRef},
+    },
+    mm::{
+        address::VirtualMemoryAddress,
+        vm::fault::{copy_from_user, copy_to_user},
+    },
+    syscall::def::{TCGETS, TCSETS},
+};
+
+// 控制台作为一个打开的文件，init的标准输入输出都指向它
+pub struct ConsoleFile;
+
+// /dev/console
+impl Device for ConsoleFile {
+    fn open(&self, _flags: u32) -> Result<FileRef> {
+        new_file(ConsoleFile)
+    }
+}
+
+impl File for ConsoleFile {
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        let task = current_task().ok_or_else(|| ErrorTrace::from_errno(Errno::EINVAL))?;
+        let data = tty_read(&task, buf.len())?;
+        buf[..data.len()].copy_from_slice(&data);
+        Ok(data.len())
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        Ok(tty_write(buf))
+    }
+
+    fn stat(&self) -> Result<Stat> {
+        Ok(Stat {
+            mode: S_IFCHR | 0o620,
+            nlink: 1,
+            blksize: 1024,
+            ..Default::default()
+        })
+    }
+
+    // 只支持读取与设置终端的lflag，arg指向一个u32
+    fn ioctl(&self, request: usize, arg: usize) -> Result<usize> {
+        let task = current_task().ok_or_else(|| ErrorTrace::from_errno(Errno::EINVAL))?;
+        let va = VirtualMemoryAddress::new(arg);
+        match request {
+            TCGETS => {
+                let lflag = tty_get_lflag();
+                copy_to_user(&task, va, &lflag.to_ne_bytes())?;
+            }
+            TCSETS => {
+                let mut lflag = [0; 4];
+                copy_from_user(&task, &mut lflag, va)?;
+                tty_set_lflag(u32::from_ne_bytes(lflag));
+            }
+            _ => return Err(Errno::ENOTTY.into()),
+        }
+        Ok(0)
+    }
+}
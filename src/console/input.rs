@@ -1 +1,21 @@
+use super::tty::tty_intr;
+use crate::{
+    driver::uart::{uart_getc, uart_ready},
+    opensbi::Opensbi,
+};
 
+// 读取一个输入字符，没有输入时返回None
+pub fn getchar() -> Option<u8> {
+    if uart_ready() {
+        uart_getc()
+    } else {
+        Opensbi::console_getchar()
+    }
+}
+
+// 控制台的接收中断，把收到的字符交给TTY
+pub fn console_intr() {
+    while let Some(c) = getchar() {
+        tty_intr(c);
+    }
+}
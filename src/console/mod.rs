@@ -1,5 +1,7 @@
+pub mod file;
 pub mod input;
 pub mod output;
+pub mod tty;
 
 // the macro about print
 #[macro_export]
@@ -1,6 +1,10 @@
 #![allow(unused)]
 
-use crate::opensbi::Opensbi;
+use crate::{
+    driver::uart::{uart_putc_sync, uart_ready},
+    opensbi::Opensbi,
+    riscv::registers::sstatus::IntrGuard,
+};
 use core::fmt::{self, Write};
 use xx_mutex_lock::Mutex;
 
@@ -9,14 +13,21 @@ pub static PT: Mutex<Writer> = Mutex::new(Writer);
 pub struct Writer;
 
 impl Write for Writer {
+    // UART初始化之前通过SBI输出
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        for c in s.chars() {
-            Opensbi::console_putchar(c as usize);
+        if uart_ready() {
+            s.bytes().for_each(uart_putc_sync);
+        } else {
+            for c in s.chars() {
+                Opensbi::console_putchar(c as usize);
+            }
         }
         Ok(())
     }
 }
 
 pub fn print(args: fmt::Arguments) {
+    // 中断处理函数中也会打印
+    let _guard = IntrGuard::new();
     PT.lock().write_fmt(args).unwrap();
 }
@@ -0,0 +1,233 @@
+use crate::{
+    driver::uart::{uart_ready, uart_write},
+    error::Result,
+    opensbi::Opensbi,
+    proc::{process::Tcb, TASKMANAGER},
+    riscv::registers::sstatus::IntrGuard,
+    sched::{chan_of, sleep, wakeup},
+    utils::RingBuffer,
+};
+use alloc::vec::Vec;
+use xx_mutex_lock::Mutex;
+use xxos_log::warn;
+
+// TTY line discipline
+// canonical模式下按行编辑输入，读者只能读到完整的一行；raw模式下输入直接交给读者
+const TTY_BUF_SIZE: usize = 128;
+
+const CTRL_C: u8 = 0x03;
+const CTRL_D: u8 = 0x04;
+const BACKSPACE: u8 = 0x08;
+const CTRL_U: u8 = 0x15;
+const DELETE: u8 = 0x7f;
+
+// termios中用到的lflag位，与Linux相同
+pub const ICANON: u32 = 0o0000002;
+pub const ECHO: u32 = 0o0000010;
+
+pub static TTY: Mutex<Tty> = Mutex::new(Tty::new());
+
+pub struct Tty {
+    lflag: u32,
+    // 正在编辑的一行
+    line: RingBuffer<u8, TTY_BUF_SIZE>,
+    // 可以被读取的输入
+    ready: RingBuffer<u8, { TTY_BUF_SIZE * 2 }>,
+    // 在空行上输入^D的次数，每次让read返回0
+    eof: usize,
+    // 最近一次读终端的进程，^C发送给它
+    foreground: Option<usize>,
+}
+
+// 处理输入字符后需要在释放锁之后完成的动作
+#[derive(Default)]
+struct TtyAction {
+    echo: Vec<u8>,
+    wakeup: bool,
+    interrupt: Option<usize>,
+}
+
+impl Tty {
+    pub const fn new() -> Self {
+        Self {
+            lflag: ICANON | ECHO,
+            line: RingBuffer::new(),
+            ready: RingBuffer::new(),
+            eof: 0,
+            foreground: None,
+        }
+    }
+
+    fn is_canonical(&self) -> bool {
+        self.lflag & ICANON != 0
+    }
+
+    fn is_echo(&self) -> bool {
+        self.lflag & ECHO != 0
+    }
+
+    pub fn lflag(&self) -> u32 {
+        self.lflag
+    }
+
+    pub fn set_lflag(&mut self, lflag: u32) {
+        self.lflag = lflag;
+        // 切换到raw模式时，已经编辑的内容立即可读
+        if !self.is_canonical() {
+            self.commit_line();
+        }
+    }
+
+    fn commit_line(&mut self) {
+        while let Some(c) = self.line.pop() {
+            if self.ready.push(c).is_err() {
+                warn!("tty: input overflow");
+                self.line.clear();
+                break;
+            }
+        }
+    }
+
+    fn erase(&mut self, action: &mut TtyAction) -> bool {
+        if self.line.pop_back().is_none() {
+            return false;
+        }
+        if self.is_echo() {
+            action.echo.extend_from_slice(&[BACKSPACE, b' ', BACKSPACE]);
+        }
+        true
+    }
+
+    fn input(&mut self, c: u8, action: &mut TtyAction) {
+        if !self.is_canonical() {
+            if self.ready.push(c).is_ok() {
+                if self.is_echo() {
+                    action.echo.push(c);
+                }
+                action.wakeup = true;
+            }
+            return;
+        }
+
+        match c {
+            CTRL_C => {
+                self.line.clear();
+                if self.is_echo() {
+                    action.echo.extend_from_slice(b"^C\n");
+                }
+                action.interrupt = self.foreground;
+            }
+            CTRL_D => {
+                if self.line.is_empty() {
+                    self.eof += 1;
+                }
+                self.commit_line();
+                action.wakeup = true;
+            }
+            CTRL_U => while self.erase(action) {},
+            BACKSPACE | DELETE => {
+                self.erase(action);
+            }
+            _ => {
+                // 串口终端的回车是'\r'
+                let c = if c == b'\r' { b'\n' } else { c };
+                // 行满时只接受换行
+                if c != b'\n' && self.line.len() >= TTY_BUF_SIZE - 1 {
+                    return;
+                }
+                let _ = self.line.push(c);
+                if self.is_echo() {
+                    action.echo.push(c);
+                }
+                if c == b'\n' {
+                    self.commit_line();
+                    action.wakeup = true;
+                }
+            }
+        }
+    }
+
+    // canonical模式下最多读一行
+    fn read(&mut self, len: usize) -> Vec<u8> {
+        let mut buf = Vec::new();
+        while buf.len() < len {
+            let Some(c) = self.ready.pop() else {
+                break;
+            };
+            buf.push(c);
+            if self.is_canonical() && c == b'\n' {
+                break;
+            }
+        }
+        buf
+    }
+}
+
+impl Default for Tty {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn tty_output(bytes: &[u8]) {
+    if uart_ready() {
+        uart_write(bytes);
+    } else {
+        bytes
+            .iter()
+            .for_each(|&c| Opensbi::console_putchar(c as usize));
+    }
+}
+
+// 由控制台的接收中断调用
+pub fn tty_intr(c: u8) {
+    let mut action = TtyAction::default();
+    TTY.lock().input(c, &mut action);
+
+    tty_output(&action.echo);
+    if action.wakeup {
+        wakeup(chan_of(&TTY));
+    }
+    // ^C终止前台进程，init不会被终止
+    if let Some(pid) = action.interrupt.filter(|&pid| pid != 0) {
+        if let Some(task) = TASKMANAGER.lock().find(pid) {
+            task.kill();
+            task.wakeup(0);
+        }
+    }
+}
+
+// 没有输入时进程进入睡眠，返回空表示EOF
+pub fn tty_read(task: &Tcb, len: usize) -> Result<Vec<u8>> {
+    if len == 0 {
+        return Ok(Vec::new());
+    }
+    let _guard = IntrGuard::new();
+    let mut tty = TTY.lock();
+    tty.foreground = Some(*task.pid());
+    if tty.ready.is_empty() {
+        if tty.eof > 0 {
+            tty.eof -= 1;
+            return Ok(Vec::new());
+        }
+        return Err(sleep(task, chan_of(&TTY)));
+    }
+    Ok(tty.read(len))
+}
+
+pub fn tty_write(bytes: &[u8]) -> usize {
+    tty_output(bytes);
+    bytes.len()
+}
+
+pub fn tty_get_lflag() -> u32 {
+    let _guard = IntrGuard::new();
+    TTY.lock().lflag()
+}
+
+// 只支持ICANON与ECHO，其余位被忽略
+pub fn tty_set_lflag(lflag: u32) {
+    let _guard = IntrGuard::new();
+    TTY.lock().set_lflag(lflag & (ICANON | ECHO));
+    wakeup(chan_of(&TTY));
+}
@@ -1,6 +1,8 @@
-use alloc::sync::Arc;
+use xx_mutex_lock::Mutex;
 
-use crate::proc::process::Tcb;
+use crate::{proc::process::TaskRef, riscv::registers::r_tp};
+
+pub const NCPU: usize = 8;
 
 #[derive(Clone, Copy, Debug, Default)]
 #[repr(C)]
@@ -24,6 +26,25 @@ pub struct Context {
 }
 
 impl Context {
+    pub const fn new() -> Self {
+        Self {
+            ra: 0,
+            sp: 0,
+            s0: 0,
+            s1: 0,
+            s2: 0,
+            s3: 0,
+            s4: 0,
+            s5: 0,
+            s6: 0,
+            s7: 0,
+            s8: 0,
+            s9: 0,
+            s10: 0,
+            s11: 0,
+        }
+    }
+
     pub fn write_zero(&mut self) {
         self.ra = 0;
         self.sp = 0;
@@ -44,5 +65,38 @@ impl Context {
 
 pub struct Cpu {
     pub context: Context,
-    pub tcb: Arc<Tcb>,
+    pub tcb: Option<TaskRef>,
+}
+
+impl Cpu {
+    pub const fn new() -> Self {
+        Self {
+            context: Context::new(),
+            tcb: None,
+        }
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const CPU_INIT: Mutex<Cpu> = Mutex::new(Cpu::new());
+// 每个hart一个，以hartid(tp)为下标
+pub static CPUS: [Mutex<Cpu>; NCPU] = [CPU_INIT; NCPU];
+
+// 当前hart上正在运行的进程
+pub fn current_task() -> Option<TaskRef> {
+    CPUS[r_tp()].lock().tcb.clone()
+}
+
+// 进程是否正在某个hart上运行
+pub fn is_running(pid: usize) -> bool {
+    CPUS.iter().any(|cpu| {
+        cpu.lock()
+            .tcb
+            .as_ref()
+            .is_some_and(|task| *task.pid() == pid)
+    })
+}
+
+pub fn set_current_task(task: Option<TaskRef>) {
+    CPUS[r_tp()].lock().tcb = task;
 }
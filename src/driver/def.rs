@@ -0,0 +1,14 @@
+// qemu virt machine MMIO layout
+pub const PLIC_BASE: usize = 0x0c00_0000;
+pub const PLIC_SIZE: usize = 0x40_0000;
+
+// 设备树中找不到设备时使用的默认值
+pub const UART0_BASE: usize = 0x1000_0000;
+pub const UART0_SIZE: usize = 0x100;
+pub const UART0_IRQ: usize = 10;
+
+// qemu virt最多有8个virtio-mmio设备，每个占0x1000，中断号从1开始
+pub const VIRTIO0_BASE: usize = 0x1000_1000;
+pub const VIRTIO_MMIO_SIZE: usize = 0x1000;
+pub const VIRTIO_MMIO_SLOTS: usize = 8;
+pub const VIRTIO0_IRQ: usize = 1;
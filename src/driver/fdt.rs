@@ -0,0 +1,271 @@
+use core::str;
+use xx_mutex_lock::Mutex;
+use xxos_log::{info, warn};
+
+// Flattened Device Tree
+// OpenSBI启动内核时a1中保存dtb的物理地址，所有数据都是大端序
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+const FDT_MAX_SIZE: usize = 0x10000;
+const FDT_MAX_DEPTH: usize = 16;
+
+// qemu把dtb放在内存的末尾，与内核堆重叠，必须在堆初始化之前拷贝出来
+static mut FDT_COPY: [u8; FDT_MAX_SIZE] = [0; FDT_MAX_SIZE];
+static FDT: Mutex<Option<Fdt>> = Mutex::new(None);
+
+#[derive(Clone, Copy)]
+pub struct Fdt {
+    structs: &'static [u8],
+    strings: &'static [u8],
+}
+
+#[inline]
+fn be32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn cstr(bytes: &'static [u8]) -> &'static str {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    str::from_utf8(&bytes[..len]).unwrap_or("")
+}
+
+// 按cells个32位大端数读出一个数
+fn read_cells(bytes: &[u8], cells: usize) -> usize {
+    (0..cells).fold(0, |acc, i| (acc << 32) | be32(bytes, i * 4) as usize)
+}
+
+impl Fdt {
+    /// # Safety
+    /// `blob` must point to a valid device tree blob.
+    unsafe fn from_raw(blob: usize) -> Option<Self> {
+        let header = core::slice::from_raw_parts(blob as *const u8, 40);
+        if be32(header, 0) != FDT_MAGIC {
+            return None;
+        }
+        let total = be32(header, 4) as usize;
+        if total > FDT_MAX_SIZE {
+            warn!("fdt: blob is too large ({:#x} bytes)", total);
+            return None;
+        }
+
+        let copy = &mut *core::ptr::addr_of_mut!(FDT_COPY);
+        copy[..total].copy_from_slice(core::slice::from_raw_parts(blob as *const u8, total));
+        let copy: &'static [u8] = &copy[..total];
+
+        let off_struct = be32(copy, 8) as usize;
+        let off_strings = be32(copy, 12) as usize;
+        let size_strings = be32(copy, 32) as usize;
+        let size_struct = be32(copy, 36) as usize;
+        Some(Self {
+            structs: &copy[off_struct..off_struct + size_struct],
+            strings: &copy[off_strings..off_strings + size_strings],
+        })
+    }
+
+    pub fn nodes(&self) -> FdtNodes {
+        FdtNodes {
+            fdt: *self,
+            offset: 0,
+            depth: 0,
+            cells: [(2, 1); FDT_MAX_DEPTH],
+        }
+    }
+
+    pub fn find_compatible(&self, compatible: &str) -> Option<FdtNode> {
+        self.nodes().find(|node| node.is_compatible(compatible))
+    }
+
+    // /chosen节点的bootargs，即qemu -append传入的内核命令行
+    pub fn bootargs(&self) -> &'static str {
+        self.nodes()
+            .find(|node| node.name() == "chosen")
+            .and_then(|node| node.property("bootargs"))
+            .map_or("", cstr)
+    }
+
+    pub fn find_all_compatible<'a>(
+        &self,
+        compatible: &'a str,
+    ) -> impl Iterator<Item = FdtNode> + 'a {
+        self.nodes()
+            .filter(move |node| node.is_compatible(compatible))
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct FdtNode {
+    fdt: Fdt,
+    name: &'static str,
+    // 第一个属性的偏移
+    props: usize,
+    // #address-cells and #size-cells of the parent
+    address_cells: usize,
+    size_cells: usize,
+}
+
+impl FdtNode {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn properties(&self) -> FdtProperties {
+        FdtProperties {
+            fdt: self.fdt,
+            offset: self.props,
+        }
+    }
+
+    pub fn property(&self, name: &str) -> Option<&'static [u8]> {
+        self.properties()
+            .find(|(prop, _)| *prop == name)
+            .map(|(_, value)| value)
+    }
+
+    pub fn compatible(&self) -> impl Iterator<Item = &'static str> {
+        self.property("compatible")
+            .unwrap_or(&[])
+            .split(|&b| b == 0)
+            .filter(|s| !s.is_empty())
+            .map(|s| str::from_utf8(s).unwrap_or(""))
+    }
+
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.compatible().any(|c| c == compatible)
+    }
+
+    // the first (base, size) pair of `reg`
+    pub fn reg(&self) -> Option<(usize, usize)> {
+        let reg = self.property("reg")?;
+        let len = (self.address_cells + self.size_cells) * 4;
+        if reg.len() < len {
+            return None;
+        }
+        let base = read_cells(reg, self.address_cells);
+        let size = read_cells(&reg[self.address_cells * 4..], self.size_cells);
+        Some((base, size))
+    }
+
+    // the first interrupt number
+    pub fn irq(&self) -> Option<usize> {
+        let interrupts = self.property("interrupts")?;
+        (interrupts.len() >= 4).then(|| be32(interrupts, 0) as usize)
+    }
+
+    fn cells(&self, name: &str, default: usize) -> usize {
+        self.property(name)
+            .filter(|value| value.len() >= 4)
+            .map_or(default, |value| be32(value, 0) as usize)
+    }
+}
+
+pub struct FdtProperties {
+    fdt: Fdt,
+    offset: usize,
+}
+
+impl Iterator for FdtProperties {
+    type Item = (&'static str, &'static [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let structs = self.fdt.structs;
+        loop {
+            match be32(structs, self.offset) {
+                FDT_NOP => self.offset += 4,
+                FDT_PROP => {
+                    let len = be32(structs, self.offset + 4) as usize;
+                    let name = be32(structs, self.offset + 8) as usize;
+                    let value = &structs[self.offset + 12..self.offset + 12 + len];
+                    self.offset = align4(self.offset + 12 + len);
+                    return Some((cstr(&self.fdt.strings[name..]), value));
+                }
+                // 属性总是在子节点之前
+                _ => return None,
+            }
+        }
+    }
+}
+
+pub struct FdtNodes {
+    fdt: Fdt,
+    offset: usize,
+    depth: usize,
+    // 每一层节点为子节点规定的(#address-cells, #size-cells)
+    cells: [(usize, usize); FDT_MAX_DEPTH],
+}
+
+#[inline]
+fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+impl Iterator for FdtNodes {
+    type Item = FdtNode;
+
+    fn next(&mut self) -> Option<FdtNode> {
+        let structs = self.fdt.structs;
+        while self.offset + 4 <= structs.len() {
+            let token = be32(structs, self.offset);
+            self.offset += 4;
+            match token {
+                FDT_BEGIN_NODE => {
+                    let name = cstr(&structs[self.offset..]);
+                    self.offset = align4(self.offset + name.len() + 1);
+                    let (address_cells, size_cells) =
+                        self.cells[self.depth.saturating_sub(1).min(FDT_MAX_DEPTH - 1)];
+                    let node = FdtNode {
+                        fdt: self.fdt,
+                        name,
+                        props: self.offset,
+                        address_cells,
+                        size_cells,
+                    };
+                    if self.depth < FDT_MAX_DEPTH {
+                        self.cells[self.depth] = (
+                            node.cells("#address-cells", 2),
+                            node.cells("#size-cells", 1),
+                        );
+                    }
+                    self.depth += 1;
+                    return Some(node);
+                }
+                FDT_END_NODE => self.depth = self.depth.saturating_sub(1),
+                FDT_PROP => {
+                    let len = be32(structs, self.offset) as usize;
+                    self.offset = align4(self.offset + 8 + len);
+                }
+                FDT_NOP => {}
+                FDT_END => break,
+                _ => {
+                    warn!("fdt: bad token {:#x}", token);
+                    break;
+                }
+            }
+        }
+        None
+    }
+}
+
+// 解析OpenSBI传入的设备树，必须在堆初始化之前调用
+pub fn fdt_init(dtb: usize) {
+    match unsafe { Fdt::from_raw(dtb) } {
+        Some(fdt) => {
+            *FDT.lock() = Some(fdt);
+            info!("fdt: device tree at {:#x}", dtb);
+        }
+        None => warn!("fdt: no valid device tree at {:#x}", dtb),
+    }
+}
+
+pub fn fdt() -> Option<Fdt> {
+    *FDT.lock()
+}
+
+// 内核命令行中是否有arg这一项
+pub fn has_bootarg(arg: &str) -> bool {
+    fdt().is_some_and(|fdt| fdt.bootargs().split_ascii_whitespace().any(|a| a == arg))
+}
@@ -0,0 +1,20 @@
+pub mod def;
+pub mod fdt;
+pub mod plic;
+pub mod uart;
+pub mod virtio;
+
+use alloc::{vec, vec::Vec};
+use def::{PLIC_BASE, PLIC_SIZE};
+
+// 所有设备的MMIO区域(base, size)，由内核页表直接映射
+pub fn mmio_regions() -> Vec<(usize, usize)> {
+    let uart = uart::uart_probe();
+    let mut regions = vec![(PLIC_BASE, PLIC_SIZE), (uart.base, uart.size)];
+    regions.extend(
+        virtio::virtio_slots()
+            .iter()
+            .map(|slot| (slot.base, slot.size)),
+    );
+    regions
+}
@@ -0,0 +1,96 @@
+use super::def::PLIC_BASE;
+use core::ptr::{read_volatile, write_volatile};
+use xx_mutex_lock::OnceLock;
+
+// Platform-Level Interrupt Controller
+// 每个hart有M态与S态两个context，qemu virt上hart n的S态context为2n+1
+const PLIC_PRIORITY: usize = 0x0;
+const PLIC_PENDING: usize = 0x1000;
+const PLIC_ENABLE: usize = 0x2000;
+const PLIC_ENABLE_STRIDE: usize = 0x80;
+const PLIC_CONTEXT: usize = 0x20_0000;
+const PLIC_CONTEXT_STRIDE: usize = 0x1000;
+const PLIC_THRESHOLD: usize = 0x0;
+const PLIC_CLAIM: usize = 0x4;
+
+pub const PLIC_MAX_IRQ: usize = 1024;
+pub const PLIC_MAX_PRIORITY: u32 = 7;
+
+pub static PLIC: OnceLock<Plic> = OnceLock::new();
+
+pub struct Plic {
+    base: usize,
+}
+
+impl Plic {
+    pub const fn new(base: usize) -> Self {
+        Self { base }
+    }
+
+    #[inline]
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.base + offset) as *const u32) }
+    }
+
+    #[inline]
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.base + offset) as *mut u32, value) }
+    }
+
+    // S-mode context of a hart
+    #[inline]
+    pub fn supervisor_context(hart: usize) -> usize {
+        hart * 2 + 1
+    }
+
+    // 优先级为0的中断源永远不会被送达
+    pub fn set_priority(&self, irq: usize, priority: u32) {
+        self.write(PLIC_PRIORITY + irq * 4, priority.min(PLIC_MAX_PRIORITY));
+    }
+
+    pub fn priority(&self, irq: usize) -> u32 {
+        self.read(PLIC_PRIORITY + irq * 4)
+    }
+
+    pub fn is_pending(&self, irq: usize) -> bool {
+        self.read(PLIC_PENDING + irq / 32 * 4) & (1 << (irq % 32)) != 0
+    }
+
+    pub fn enable(&self, context: usize, irq: usize) {
+        let offset = PLIC_ENABLE + context * PLIC_ENABLE_STRIDE + irq / 32 * 4;
+        self.write(offset, self.read(offset) | (1 << (irq % 32)));
+    }
+
+    pub fn disable(&self, context: usize, irq: usize) {
+        let offset = PLIC_ENABLE + context * PLIC_ENABLE_STRIDE + irq / 32 * 4;
+        self.write(offset, self.read(offset) & !(1 << (irq % 32)));
+    }
+
+    // 只有优先级高于阈值的中断才会送达该context
+    pub fn set_threshold(&self, context: usize, threshold: u32) {
+        self.write(
+            PLIC_CONTEXT + context * PLIC_CONTEXT_STRIDE + PLIC_THRESHOLD,
+            threshold,
+        );
+    }
+
+    // ask the PLIC which interrupt we should serve
+    pub fn claim(&self, context: usize) -> Option<usize> {
+        match self.read(PLIC_CONTEXT + context * PLIC_CONTEXT_STRIDE + PLIC_CLAIM) {
+            0 => None,
+            irq => Some(irq as usize),
+        }
+    }
+
+    // tell the PLIC we've served this IRQ
+    pub fn complete(&self, context: usize, irq: usize) {
+        self.write(
+            PLIC_CONTEXT + context * PLIC_CONTEXT_STRIDE + PLIC_CLAIM,
+            irq as u32,
+        );
+    }
+}
+
+pub fn plic() -> &'static Plic {
+    PLIC.get_or_init(|| Plic::new(PLIC_BASE))
+}
@@ -0,0 +1,167 @@
+use super::{
+    def::{UART0_BASE, UART0_IRQ, UART0_SIZE},
+    fdt::fdt,
+};
+use crate::{
+    console::input::console_intr, error::Result, riscv::registers::sstatus::IntrGuard,
+    trap::irq::register_irq, utils::RingBuffer,
+};
+use core::{
+    ptr::{read_volatile, write_volatile},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use xx_mutex_lock::Mutex;
+use xxos_log::info;
+
+// NS16550a UART
+// 寄存器均为8位，偏移见下
+const RHR: usize = 0; // receive holding register (for input bytes)
+const THR: usize = 0; // transmit holding register (for output bytes)
+const IER: usize = 1; // interrupt enable register
+const FCR: usize = 2; // FIFO control register
+const ISR: usize = 2; // interrupt status register
+const LCR: usize = 3; // line control register
+const LSR: usize = 5; // line status register
+
+const IER_RX_ENABLE: u8 = 1 << 0;
+const IER_TX_ENABLE: u8 = 1 << 1;
+const FCR_FIFO_ENABLE: u8 = 1 << 0;
+const FCR_FIFO_CLEAR: u8 = 3 << 1; // clear the content of the two FIFOs
+const LCR_EIGHT_BITS: u8 = 3 << 0;
+const LCR_BAUD_LATCH: u8 = 1 << 7; // special mode to set baud rate
+const LSR_RX_READY: u8 = 1 << 0; // input is waiting to be read from RHR
+const LSR_TX_IDLE: u8 = 1 << 5; // THR can accept another character to send
+
+const UART_BUF_SIZE: usize = 256;
+
+// 0表示UART还未初始化，此时控制台输出走SBI
+static UART_BASE: AtomicUsize = AtomicUsize::new(0);
+static UART: Mutex<UartBuffer> = Mutex::new(UartBuffer::new());
+
+struct UartBuffer {
+    tx: RingBuffer<u8, UART_BUF_SIZE>,
+    rx: RingBuffer<u8, UART_BUF_SIZE>,
+}
+
+impl UartBuffer {
+    const fn new() -> Self {
+        Self {
+            tx: RingBuffer::new(),
+            rx: RingBuffer::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct UartInfo {
+    pub base: usize,
+    pub size: usize,
+    pub irq: usize,
+}
+
+// 从设备树中查找UART，找不到时使用qemu virt的默认值
+pub fn uart_probe() -> UartInfo {
+    let node = fdt().and_then(|fdt| fdt.find_compatible("ns16550a"));
+    match node.and_then(|node| Some((node.reg()?, node.irq()?))) {
+        Some(((base, size), irq)) => UartInfo { base, size, irq },
+        None => UartInfo {
+            base: UART0_BASE,
+            size: UART0_SIZE,
+            irq: UART0_IRQ,
+        },
+    }
+}
+
+#[inline]
+fn read_reg(base: usize, reg: usize) -> u8 {
+    unsafe { read_volatile((base + reg) as *const u8) }
+}
+
+#[inline]
+fn write_reg(base: usize, reg: usize, value: u8) {
+    unsafe { write_volatile((base + reg) as *mut u8, value) }
+}
+
+// 需要在UART的MMIO区域映射之后调用
+pub fn uart_init() -> Result<()> {
+    let info = uart_probe();
+    let base = info.base;
+
+    // disable interrupts
+    write_reg(base, IER, 0x00);
+    // set baud rate to 38.4K
+    write_reg(base, LCR, LCR_BAUD_LATCH);
+    write_reg(base, 0, 0x03);
+    write_reg(base, 1, 0x00);
+    // leave set-baud mode, and set word length to 8 bits, no parity
+    write_reg(base, LCR, LCR_EIGHT_BITS);
+    // reset and enable FIFOs
+    write_reg(base, FCR, FCR_FIFO_ENABLE | FCR_FIFO_CLEAR);
+
+    register_irq(info.irq, "uart", uart_intr)?;
+    write_reg(base, IER, IER_TX_ENABLE | IER_RX_ENABLE);
+    UART_BASE.store(base, Ordering::SeqCst);
+    info!("uart: ns16550a at {:#x}, irq {}", base, info.irq);
+    Ok(())
+}
+
+pub fn uart_ready() -> bool {
+    UART_BASE.load(Ordering::Relaxed) != 0
+}
+
+// 轮询方式输出一个字符，供内核打印使用，不依赖中断
+pub fn uart_putc_sync(c: u8) {
+    let base = UART_BASE.load(Ordering::Relaxed);
+    while read_reg(base, LSR) & LSR_TX_IDLE == 0 {}
+    write_reg(base, THR, c);
+}
+
+// 写入发送缓冲区，由发送中断取出
+// 缓冲区满时先轮询把数据送出去
+pub fn uart_write(bytes: &[u8]) -> usize {
+    let base = UART_BASE.load(Ordering::Relaxed);
+    for &c in bytes {
+        let _guard = IntrGuard::new();
+        let mut uart = UART.lock();
+        while uart.tx.push(c).is_err() {
+            while read_reg(base, LSR) & LSR_TX_IDLE == 0 {}
+            uart_start(base, &mut uart);
+        }
+        uart_start(base, &mut uart);
+    }
+    bytes.len()
+}
+
+// 从接收缓冲区取出一个字符
+pub fn uart_getc() -> Option<u8> {
+    let _guard = IntrGuard::new();
+    UART.lock().rx.pop()
+}
+
+// THR空闲时从发送缓冲区取出字符送出
+// 没有数据可发送时读ISR清除THR空闲中断，否则电平中断一直有效
+fn uart_start(base: usize, uart: &mut UartBuffer) {
+    while read_reg(base, LSR) & LSR_TX_IDLE != 0 {
+        match uart.tx.pop() {
+            Some(c) => write_reg(base, THR, c),
+            None => {
+                read_reg(base, ISR);
+                break;
+            }
+        }
+    }
+}
+
+// 接收到字符或者THR空闲时触发
+fn uart_intr(_irq: usize) {
+    let base = UART_BASE.load(Ordering::Relaxed);
+    {
+        let mut uart = UART.lock();
+        while read_reg(base, LSR) & LSR_RX_READY != 0 {
+            // 缓冲区满时丢弃新输入的字符
+            let _ = uart.rx.push(read_reg(base, RHR));
+        }
+        uart_start(base, &mut uart);
+    }
+    console_intr();
+}
@@ -0,0 +1,359 @@
+use super::{
+    mmio::VirtioMmio,
+    queue::{Buffer, VirtQueue, QUEUE_SIZE},
+};
+use crate::{
+    block::{
+        check_range,
+        partition::scan_partitions,
+        queue::{BlockOp, MergedRequest},
+        register_block_device, BlockDevice,
+    },
+    error::{Errno, ErrorTrace, Result},
+    riscv::registers::sstatus::{intr_off, intr_on, IntrGuard, Sstatus},
+    trap::irq::register_irq,
+};
+use alloc::{boxed::Box, format, string::String, sync::Arc, vec, vec::Vec};
+use core::{
+    cell::UnsafeCell,
+    hint::spin_loop,
+    mem::size_of,
+    ptr::read_volatile,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use xx_mutex_lock::Mutex;
+use xxos_log::{info, warn};
+
+pub const SECTOR_SIZE: usize = 512;
+
+// feature bits
+const VIRTIO_BLK_F_RO: u64 = 1 << 5;
+
+// request types
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+
+const VIRTIO_BLK_S_OK: u8 = 0;
+// 设备写回状态之前的值
+const VIRTIO_BLK_S_PENDING: u8 = 0xff;
+
+#[repr(C)]
+struct BlkReqHeader {
+    type_: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+// 一次异步读写请求，设备通过中断通知完成
+// 请求头、数据与状态都放在直接映射的内核堆中，地址可以直接交给设备
+pub struct BlkRequest {
+    header: BlkReqHeader,
+    data: UnsafeCell<Box<[u8]>>,
+    status: UnsafeCell<u8>,
+    done: AtomicBool,
+}
+
+unsafe impl Sync for BlkRequest {}
+unsafe impl Send for BlkRequest {}
+
+impl BlkRequest {
+    fn new(type_: u32, sector: u64, data: Box<[u8]>) -> Arc<Self> {
+        Arc::new(Self {
+            header: BlkReqHeader {
+                type_,
+                reserved: 0,
+                sector,
+            },
+            data: UnsafeCell::new(data),
+            status: UnsafeCell::new(VIRTIO_BLK_S_PENDING),
+            done: AtomicBool::new(false),
+        })
+    }
+
+    pub fn read(sector: u64, count: usize) -> Arc<Self> {
+        Self::new(
+            VIRTIO_BLK_T_IN,
+            sector,
+            vec![0; count * SECTOR_SIZE].into_boxed_slice(),
+        )
+    }
+
+    pub fn write(sector: u64, data: &[u8]) -> Arc<Self> {
+        Self::new(VIRTIO_BLK_T_OUT, sector, data.into())
+    }
+
+    pub fn sector(&self) -> u64 {
+        self.header.sector
+    }
+
+    pub fn is_write(&self) -> bool {
+        self.header.type_ == VIRTIO_BLK_T_OUT
+    }
+
+    pub fn sectors(&self) -> usize {
+        self.len() / SECTOR_SIZE
+    }
+
+    fn len(&self) -> usize {
+        unsafe { (&*self.data.get()).len() }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done.load(Ordering::Acquire)
+    }
+
+    // 请求完成之后才能访问数据
+    pub fn data(&self) -> &[u8] {
+        assert!(self.is_done(), "virtio-blk: request is still in flight");
+        unsafe { &*self.data.get() }
+    }
+
+    pub fn result(&self) -> Result<()> {
+        if !self.is_done() {
+            return Err(Errno::EAGAIN.into());
+        }
+        match unsafe { read_volatile(self.status.get()) } {
+            VIRTIO_BLK_S_OK => Ok(()),
+            _ => Err(Errno::EIO.into()),
+        }
+    }
+
+    // 内核中的等待采用忙等，期间必须打开中断
+    pub fn wait(&self) -> Result<()> {
+        let enabled = Sstatus::read().sie();
+        intr_on();
+        while !self.is_done() {
+            spin_loop();
+        }
+        if !enabled {
+            intr_off();
+        }
+        self.result()
+    }
+
+    fn buffers(&self) -> [Buffer; 3] {
+        [
+            Buffer {
+                addr: &self.header as *const _ as usize,
+                len: size_of::<BlkReqHeader>(),
+                write: false,
+            },
+            Buffer {
+                addr: unsafe { (&*self.data.get()).as_ptr() as usize },
+                len: self.len(),
+                write: !self.is_write(),
+            },
+            Buffer {
+                addr: self.status.get() as usize,
+                len: 1,
+                write: true,
+            },
+        ]
+    }
+
+    fn complete(&self) {
+        self.done.store(true, Ordering::Release);
+    }
+}
+
+struct BlkInner {
+    queue: VirtQueue,
+    // 按描述符链头记录正在处理的请求
+    inflight: [Option<Arc<BlkRequest>>; QUEUE_SIZE],
+}
+
+pub struct VirtioBlk {
+    name: String,
+    transport: VirtioMmio,
+    // in sectors
+    capacity: u64,
+    read_only: bool,
+    inner: Mutex<BlkInner>,
+}
+
+impl VirtioBlk {
+    fn new(name: String, transport: VirtioMmio) -> Result<Self> {
+        let features = transport.begin_init(|_| VIRTIO_BLK_F_RO)?;
+        let queue = VirtQueue::new()?;
+        transport.setup_queue(0, &queue)?;
+        transport.finish_init();
+
+        const NONE: Option<Arc<BlkRequest>> = None;
+        Ok(Self {
+            name,
+            transport,
+            capacity: transport.config_read64(0),
+            read_only: features & VIRTIO_BLK_F_RO != 0,
+            inner: Mutex::new(BlkInner {
+                queue,
+                inflight: [NONE; QUEUE_SIZE],
+            }),
+        })
+    }
+
+    pub fn irq(&self) -> usize {
+        self.transport.irq()
+    }
+
+    // 提交请求后立即返回，队列已满时返回EAGAIN
+    pub fn submit(&self, request: Arc<BlkRequest>) -> Result<()> {
+        if request.len() == 0 || request.len() % SECTOR_SIZE != 0 {
+            return Err(Errno::EINVAL.into());
+        }
+        if request.sector() + request.sectors() as u64 > self.capacity {
+            return Err(Errno::EINVAL.into());
+        }
+        if request.is_write() && self.read_only {
+            return Err(Errno::EROFS.into());
+        }
+
+        let _guard = IntrGuard::new();
+        let mut inner = self.inner.lock();
+        let head = inner
+            .queue
+            .push(&request.buffers())
+            .ok_or_else(|| ErrorTrace::from_errno(Errno::EAGAIN))?;
+        inner.inflight[head as usize] = Some(request);
+        self.transport.notify(0);
+        Ok(())
+    }
+
+    fn submit_wait(&self, request: Arc<BlkRequest>) -> Result<()> {
+        self.submit_wait_slot(request.clone())?;
+        request.wait()
+    }
+
+    // 队列已满时等待中断处理函数回收描述符
+    fn submit_wait_slot(&self, request: Arc<BlkRequest>) -> Result<()> {
+        loop {
+            match self.submit(request.clone()) {
+                Err(err) if err.errno() == Some(Errno::EAGAIN) => {
+                    let enabled = Sstatus::read().sie();
+                    intr_on();
+                    spin_loop();
+                    if !enabled {
+                        intr_off();
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn handle_interrupt(&self) {
+        self.transport.ack_interrupt();
+        let mut inner = self.inner.lock();
+        while let Some((head, _)) = inner.queue.pop_used() {
+            match inner.inflight[head as usize].take() {
+                Some(request) => request.complete(),
+                None => warn!("virtio-blk: completion for unknown descriptor {}", head),
+            }
+        }
+    }
+}
+
+impl BlockDevice for VirtioBlk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn read_blocks(&self, block: u64, buf: &mut [u8]) -> Result<()> {
+        check_range(self, block, buf.len())?;
+        let request = BlkRequest::read(block, buf.len() / SECTOR_SIZE);
+        self.submit_wait(request.clone())?;
+        buf.copy_from_slice(request.data());
+        Ok(())
+    }
+
+    fn write_blocks(&self, block: u64, buf: &[u8]) -> Result<()> {
+        check_range(self, block, buf.len())?;
+        self.submit_wait(BlkRequest::write(block, buf))
+    }
+
+    // 写请求完成时数据已经交给设备，没有额外的写缓存需要刷新
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    // 所有请求同时提交给设备，再依次等待完成
+    fn submit(&self, requests: &mut [MergedRequest]) -> Result<()> {
+        let mut inflight = Vec::with_capacity(requests.len());
+        for request in requests.iter() {
+            let blk = match request.op {
+                BlockOp::Read => BlkRequest::read(request.block, request.data.len() / SECTOR_SIZE),
+                BlockOp::Write => BlkRequest::write(request.block, &request.data),
+            };
+            self.submit_wait_slot(blk.clone())?;
+            inflight.push(blk);
+        }
+        for (request, blk) in requests.iter_mut().zip(inflight) {
+            blk.wait()?;
+            if request.op == BlockOp::Read {
+                request.data.copy_from_slice(blk.data());
+            }
+        }
+        Ok(())
+    }
+}
+
+static VIRTIO_BLKS: Mutex<Vec<Arc<VirtioBlk>>> = Mutex::new(Vec::new());
+
+pub fn virtio_blk(index: usize) -> Option<Arc<VirtioBlk>> {
+    let _guard = IntrGuard::new();
+    VIRTIO_BLKS.lock().get(index).cloned()
+}
+
+pub fn virtio_blk_count() -> usize {
+    let _guard = IntrGuard::new();
+    VIRTIO_BLKS.lock().len()
+}
+
+fn virtio_blk_intr(irq: usize) {
+    let device = VIRTIO_BLKS
+        .lock()
+        .iter()
+        .find(|blk| blk.irq() == irq)
+        .cloned();
+    match device {
+        Some(blk) => blk.handle_interrupt(),
+        None => warn!("virtio-blk: no device for irq {}", irq),
+    }
+}
+
+pub fn virtio_blk_init(transport: VirtioMmio) -> Result<()> {
+    // 按发现的顺序命名为vda、vdb...
+    let name = format!("vd{}", (b'a' + virtio_blk_count() as u8) as char);
+    let blk = Arc::new(VirtioBlk::new(name, transport)?);
+    info!(
+        "virtio-blk: {:#x}, {} sectors{}",
+        transport.base(),
+        blk.capacity(),
+        if blk.is_read_only() {
+            ", read-only"
+        } else {
+            ""
+        }
+    );
+    {
+        let _guard = IntrGuard::new();
+        VIRTIO_BLKS.lock().push(blk.clone());
+    }
+    register_irq(transport.irq(), "virtio-blk", virtio_blk_intr)?;
+    let dev = register_block_device(blk);
+    if let Err(err) = scan_partitions(dev) {
+        warn!("virtio-blk: scan partitions failed: {}", err);
+    }
+    Ok(())
+}
@@ -0,0 +1,203 @@
+use super::queue::VirtQueue;
+use crate::{
+    error::{Errno, Result},
+    mm::def::PGSZ,
+};
+use core::ptr::{read_volatile, write_volatile};
+
+// virtio-mmio register layout, see the virtio spec 4.2.2
+const MAGIC_VALUE: usize = 0x000;
+const VERSION: usize = 0x004;
+const DEVICE_ID: usize = 0x008;
+const VENDOR_ID: usize = 0x00c;
+const DEVICE_FEATURES: usize = 0x010;
+const DEVICE_FEATURES_SEL: usize = 0x014;
+const DRIVER_FEATURES: usize = 0x020;
+const DRIVER_FEATURES_SEL: usize = 0x024;
+const GUEST_PAGE_SIZE: usize = 0x028; // legacy only
+const QUEUE_SEL: usize = 0x030;
+const QUEUE_NUM_MAX: usize = 0x034;
+const QUEUE_NUM: usize = 0x038;
+const QUEUE_ALIGN: usize = 0x03c; // legacy only
+const QUEUE_PFN: usize = 0x040; // legacy only
+const QUEUE_READY: usize = 0x044;
+const QUEUE_NOTIFY: usize = 0x050;
+const INTERRUPT_STATUS: usize = 0x060;
+const INTERRUPT_ACK: usize = 0x064;
+const STATUS: usize = 0x070;
+const QUEUE_DESC_LOW: usize = 0x080;
+const QUEUE_DESC_HIGH: usize = 0x084;
+const QUEUE_DRIVER_LOW: usize = 0x090;
+const QUEUE_DRIVER_HIGH: usize = 0x094;
+const QUEUE_DEVICE_LOW: usize = 0x0a0;
+const QUEUE_DEVICE_HIGH: usize = 0x0a4;
+const CONFIG: usize = 0x100;
+
+const VIRTIO_MAGIC: u32 = 0x7472_6976; // "virt"
+
+// device status bits
+const STATUS_ACKNOWLEDGE: u32 = 1;
+const STATUS_DRIVER: u32 = 2;
+const STATUS_DRIVER_OK: u32 = 4;
+const STATUS_FEATURES_OK: u32 = 8;
+const STATUS_FAILED: u32 = 128;
+
+pub const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+// virtio设备类型
+pub const VIRTIO_ID_BLOCK: u32 = 2;
+
+// 一个virtio-mmio设备，同时支持legacy(version 1)与modern(version 2)接口
+#[derive(Debug, Clone, Copy)]
+pub struct VirtioMmio {
+    base: usize,
+    irq: usize,
+    version: u32,
+}
+
+impl VirtioMmio {
+    // 槽位上没有设备时device id为0
+    pub fn probe(base: usize, irq: usize) -> Option<Self> {
+        let mut transport = Self {
+            base,
+            irq,
+            version: 0,
+        };
+        if transport.read(MAGIC_VALUE) != VIRTIO_MAGIC || transport.device_id() == 0 {
+            return None;
+        }
+        transport.version = transport.read(VERSION);
+        matches!(transport.version, 1 | 2).then_some(transport)
+    }
+
+    #[inline]
+    fn read(&self, reg: usize) -> u32 {
+        unsafe { read_volatile((self.base + reg) as *const u32) }
+    }
+
+    #[inline]
+    fn write(&self, reg: usize, value: u32) {
+        unsafe { write_volatile((self.base + reg) as *mut u32, value) }
+    }
+
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    pub fn irq(&self) -> usize {
+        self.irq
+    }
+
+    pub fn is_legacy(&self) -> bool {
+        self.version == 1
+    }
+
+    pub fn device_id(&self) -> u32 {
+        self.read(DEVICE_ID)
+    }
+
+    pub fn vendor_id(&self) -> u32 {
+        self.read(VENDOR_ID)
+    }
+
+    fn device_features(&self) -> u64 {
+        self.write(DEVICE_FEATURES_SEL, 0);
+        let low = self.read(DEVICE_FEATURES) as u64;
+        self.write(DEVICE_FEATURES_SEL, 1);
+        let high = self.read(DEVICE_FEATURES) as u64;
+        (high << 32) | low
+    }
+
+    fn set_driver_features(&self, features: u64) {
+        self.write(DRIVER_FEATURES_SEL, 0);
+        self.write(DRIVER_FEATURES, features as u32);
+        self.write(DRIVER_FEATURES_SEL, 1);
+        self.write(DRIVER_FEATURES, (features >> 32) as u32);
+    }
+
+    // reset the device and negotiate features
+    // negotiate收到设备支持的特性，返回驱动接受的特性
+    pub fn begin_init(&self, negotiate: impl FnOnce(u64) -> u64) -> Result<u64> {
+        self.write(STATUS, 0);
+        let mut status = STATUS_ACKNOWLEDGE;
+        self.write(STATUS, status);
+        status |= STATUS_DRIVER;
+        self.write(STATUS, status);
+
+        let device = self.device_features();
+        let mut features = negotiate(device) & device;
+        if !self.is_legacy() {
+            features |= device & VIRTIO_F_VERSION_1;
+        }
+        self.set_driver_features(features);
+
+        // legacy设备没有FEATURES_OK
+        if !self.is_legacy() {
+            status |= STATUS_FEATURES_OK;
+            self.write(STATUS, status);
+            if self.read(STATUS) & STATUS_FEATURES_OK == 0 {
+                self.write(STATUS, STATUS_FAILED);
+                return Err(Errno::ENODEV.into());
+            }
+        } else {
+            self.write(GUEST_PAGE_SIZE, PGSZ as u32);
+        }
+        Ok(features)
+    }
+
+    pub fn setup_queue(&self, index: u32, queue: &VirtQueue) -> Result<()> {
+        self.write(QUEUE_SEL, index);
+        let ready = if self.is_legacy() {
+            self.read(QUEUE_PFN)
+        } else {
+            self.read(QUEUE_READY)
+        };
+        if ready != 0 {
+            return Err(Errno::EBUSY.into());
+        }
+        let max = self.read(QUEUE_NUM_MAX) as usize;
+        if max == 0 || max < queue.size() {
+            return Err(Errno::ENODEV.into());
+        }
+        self.write(QUEUE_NUM, queue.size() as u32);
+
+        if self.is_legacy() {
+            self.write(QUEUE_ALIGN, PGSZ as u32);
+            self.write(QUEUE_PFN, (queue.desc_pa() / PGSZ) as u32);
+        } else {
+            self.write(QUEUE_DESC_LOW, queue.desc_pa() as u32);
+            self.write(QUEUE_DESC_HIGH, (queue.desc_pa() >> 32) as u32);
+            self.write(QUEUE_DRIVER_LOW, queue.avail_pa() as u32);
+            self.write(QUEUE_DRIVER_HIGH, (queue.avail_pa() >> 32) as u32);
+            self.write(QUEUE_DEVICE_LOW, queue.used_pa() as u32);
+            self.write(QUEUE_DEVICE_HIGH, (queue.used_pa() >> 32) as u32);
+            self.write(QUEUE_READY, 1);
+        }
+        Ok(())
+    }
+
+    pub fn finish_init(&self) {
+        self.write(STATUS, self.read(STATUS) | STATUS_DRIVER_OK);
+    }
+
+    pub fn notify(&self, queue: u32) {
+        self.write(QUEUE_NOTIFY, queue);
+    }
+
+    // 读取并确认中断状态
+    pub fn ack_interrupt(&self) -> u32 {
+        let status = self.read(INTERRUPT_STATUS);
+        self.write(INTERRUPT_ACK, status & 0x3);
+        status
+    }
+
+    pub fn config_read32(&self, offset: usize) -> u32 {
+        self.read(CONFIG + offset)
+    }
+
+    pub fn config_read64(&self, offset: usize) -> u64 {
+        let low = self.config_read32(offset) as u64;
+        let high = self.config_read32(offset + 4) as u64;
+        (high << 32) | low
+    }
+}
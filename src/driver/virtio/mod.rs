@@ -0,0 +1,64 @@
+pub mod blk;
+pub mod mmio;
+pub mod queue;
+
+use super::{
+    def::{VIRTIO0_BASE, VIRTIO0_IRQ, VIRTIO_MMIO_SIZE, VIRTIO_MMIO_SLOTS},
+    fdt::fdt,
+};
+use alloc::vec::Vec;
+use mmio::{VirtioMmio, VIRTIO_ID_BLOCK};
+use xxos_log::{info, warn};
+
+#[derive(Debug, Clone, Copy)]
+pub struct VirtioSlot {
+    pub base: usize,
+    pub size: usize,
+    pub irq: usize,
+}
+
+// 设备树中的virtio,mmio节点，没有设备树时使用qemu virt的默认槽位
+pub fn virtio_slots() -> Vec<VirtioSlot> {
+    let slots: Vec<VirtioSlot> = fdt()
+        .map(|fdt| {
+            fdt.find_all_compatible("virtio,mmio")
+                .filter_map(|node| {
+                    let (base, size) = node.reg()?;
+                    Some(VirtioSlot {
+                        base,
+                        size,
+                        irq: node.irq()?,
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    if !slots.is_empty() {
+        return slots;
+    }
+
+    (0..VIRTIO_MMIO_SLOTS)
+        .map(|i| VirtioSlot {
+            base: VIRTIO0_BASE + i * VIRTIO_MMIO_SIZE,
+            size: VIRTIO_MMIO_SIZE,
+            irq: VIRTIO0_IRQ + i,
+        })
+        .collect()
+}
+
+// 需要在virtio的MMIO区域映射之后调用
+pub fn virtio_init() {
+    for slot in virtio_slots() {
+        let Some(transport) = VirtioMmio::probe(slot.base, slot.irq) else {
+            continue;
+        };
+        match transport.device_id() {
+            VIRTIO_ID_BLOCK => {
+                if let Err(err) = blk::virtio_blk_init(transport) {
+                    warn!("virtio-blk at {:#x} init failed: {}", slot.base, err);
+                }
+            }
+            id => info!("virtio: unsupported device {} at {:#x}", id, slot.base),
+        }
+    }
+}
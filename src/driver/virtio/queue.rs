@@ -0,0 +1,194 @@
+use crate::{
+    error::{Errno, Result},
+    mm::def::PGSZ,
+};
+use alloc::{
+    alloc::{alloc_zeroed, dealloc, Layout},
+    vec::Vec,
+};
+use core::{
+    mem::size_of,
+    ptr::{addr_of, addr_of_mut, read_volatile, write_volatile},
+    sync::atomic::{fence, Ordering},
+};
+use xxos_alloc::align_up;
+
+pub const QUEUE_SIZE: usize = 16;
+
+const VIRTQ_DESC_F_NEXT: u16 = 1;
+const VIRTQ_DESC_F_WRITE: u16 = 2;
+
+#[repr(C)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+struct AvailRing {
+    flags: u16,
+    idx: u16,
+    ring: [u16; QUEUE_SIZE],
+    used_event: u16,
+}
+
+#[repr(C)]
+struct UsedElem {
+    id: u32,
+    len: u32,
+}
+
+#[repr(C)]
+struct UsedRing {
+    flags: u16,
+    idx: u16,
+    ring: [UsedElem; QUEUE_SIZE],
+    avail_event: u16,
+}
+
+// 一段交给设备的缓冲区，write表示由设备写入
+#[derive(Debug, Clone, Copy)]
+pub struct Buffer {
+    pub addr: usize,
+    pub len: usize,
+    pub write: bool,
+}
+
+// Split virtqueue
+// 按legacy接口的要求布局：描述符表与avail ring相邻，used ring从下一页开始
+// 内核堆是直接映射的，整块分配的内存在物理上连续
+pub struct VirtQueue {
+    layout: Layout,
+    base: *mut u8,
+    desc: *mut Descriptor,
+    avail: *mut AvailRing,
+    used: *mut UsedRing,
+    free: Vec<u16>,
+    last_used: u16,
+}
+
+unsafe impl Send for VirtQueue {}
+
+impl VirtQueue {
+    const fn used_offset() -> usize {
+        align_up!(
+            size_of::<Descriptor>() * QUEUE_SIZE + size_of::<AvailRing>(),
+            PGSZ
+        )
+    }
+
+    pub fn new() -> Result<Self> {
+        let size = Self::used_offset() + align_up!(size_of::<UsedRing>(), PGSZ);
+        let layout = Layout::from_size_align(size, PGSZ).unwrap();
+        let base = unsafe { alloc_zeroed(layout) };
+        if base.is_null() {
+            return Err(Errno::ENOMEM.into());
+        }
+        Ok(Self {
+            layout,
+            base,
+            desc: base.cast(),
+            avail: unsafe { base.add(size_of::<Descriptor>() * QUEUE_SIZE) }.cast(),
+            used: unsafe { base.add(Self::used_offset()) }.cast(),
+            free: (0..QUEUE_SIZE as u16).rev().collect(),
+            last_used: 0,
+        })
+    }
+
+    pub fn size(&self) -> usize {
+        QUEUE_SIZE
+    }
+
+    pub fn desc_pa(&self) -> usize {
+        self.desc as usize
+    }
+
+    pub fn avail_pa(&self) -> usize {
+        self.avail as usize
+    }
+
+    pub fn used_pa(&self) -> usize {
+        self.used as usize
+    }
+
+    pub fn num_free(&self) -> usize {
+        self.free.len()
+    }
+
+    // 用描述符链描述一个请求，放入avail ring，返回链头
+    pub fn push(&mut self, buffers: &[Buffer]) -> Option<u16> {
+        if buffers.is_empty() || buffers.len() > self.free.len() {
+            return None;
+        }
+        let ids: Vec<u16> = (0..buffers.len())
+            .map(|_| self.free.pop().unwrap())
+            .collect();
+        for (i, buffer) in buffers.iter().enumerate() {
+            let mut flags = if buffer.write { VIRTQ_DESC_F_WRITE } else { 0 };
+            let next = ids.get(i + 1).copied().unwrap_or(0);
+            if i + 1 < ids.len() {
+                flags |= VIRTQ_DESC_F_NEXT;
+            }
+            let desc = Descriptor {
+                addr: buffer.addr as u64,
+                len: buffer.len as u32,
+                flags,
+                next,
+            };
+            unsafe { write_volatile(self.desc.add(ids[i] as usize), desc) };
+        }
+
+        let head = ids[0];
+        unsafe {
+            let idx = read_volatile(addr_of!((*self.avail).idx));
+            write_volatile(
+                addr_of_mut!((*self.avail).ring[idx as usize % QUEUE_SIZE]),
+                head,
+            );
+            // 设备必须先看到描述符与ring中的内容，再看到新的idx
+            fence(Ordering::SeqCst);
+            write_volatile(addr_of_mut!((*self.avail).idx), idx.wrapping_add(1));
+            fence(Ordering::SeqCst);
+        }
+        Some(head)
+    }
+
+    // 取出一个设备已经处理完的请求，释放它的描述符链，返回(链头, 写入长度)
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        fence(Ordering::SeqCst);
+        let idx = unsafe { read_volatile(addr_of!((*self.used).idx)) };
+        if self.last_used == idx {
+            return None;
+        }
+        let elem = unsafe {
+            read_volatile(addr_of!(
+                (*self.used).ring[self.last_used as usize % QUEUE_SIZE]
+            ))
+        };
+        self.last_used = self.last_used.wrapping_add(1);
+
+        let head = elem.id as u16;
+        self.free_chain(head);
+        Some((head, elem.len))
+    }
+
+    fn free_chain(&mut self, head: u16) {
+        let mut id = head;
+        loop {
+            let desc = unsafe { read_volatile(self.desc.add(id as usize)) };
+            self.free.push(id);
+            if desc.flags & VIRTQ_DESC_F_NEXT == 0 {
+                break;
+            }
+            id = desc.next;
+        }
+    }
+}
+
+impl Drop for VirtQueue {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.base, self.layout) };
+    }
+}
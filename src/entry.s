@@ -5,9 +5,9 @@ _start:
 	add  tp, a0, x0
 	la   sp, bootstack
 	li   t0, 4096 * 2
-	addi a0, a0, 1
-	mul  a0, a0, t0
-	add  sp, sp, a0
+	addi t1, a0, 1
+	mul  t1, t1, t0
+	add  sp, sp, t1
 	call main
 
 .section .bss.stack
@@ -1,8 +1,11 @@
+use super::errno::Errno;
+use crate::mm::pagetable_frame::PageTableErr;
 use alloc::string::{String, ToString};
 use core::panic::Location;
 
 pub struct ErrorTrace {
     pub message: String,
+    errno: Option<Errno>,
     file: String,
     line: u32,
 }
@@ -13,6 +16,7 @@ impl ErrorTrace {
         let location = Location::caller();
         ErrorTrace {
             message: message.to_string(),
+            errno: None,
             file: location.file().to_string(),
             line: location.line(),
         }
@@ -20,10 +24,44 @@ impl ErrorTrace {
     pub fn from_other_error(message: &str, file: &str, line: u32) -> ErrorTrace {
         ErrorTrace {
             message: message.to_string(),
+            errno: None,
             file: file.to_string(),
             line,
         }
     }
+
+    // 带有错误号的错误，可以直接返回给用户态
+    #[track_caller]
+    pub fn from_errno(errno: Errno) -> ErrorTrace {
+        let mut error = ErrorTrace::new(errno.as_str());
+        error.errno = Some(errno);
+        error
+    }
+
+    pub fn errno(&self) -> Option<Errno> {
+        self.errno
+    }
+}
+
+impl From<Errno> for ErrorTrace {
+    #[track_caller]
+    fn from(errno: Errno) -> Self {
+        ErrorTrace::from_errno(errno)
+    }
+}
+
+impl From<PageTableErr> for ErrorTrace {
+    #[track_caller]
+    fn from(err: PageTableErr) -> Self {
+        match err {
+            PageTableErr::OutOfMemory => ErrorTrace::from_errno(Errno::ENOMEM),
+            PageTableErr::AlreadyMap => ErrorTrace::from_errno(Errno::EEXIST),
+            PageTableErr::NeverMap | PageTableErr::NotFound => {
+                ErrorTrace::from_errno(Errno::EFAULT)
+            }
+            PageTableErr::Unknown => ErrorTrace::from_errno(Errno::EINVAL),
+        }
+    }
 }
 
 impl core::fmt::Display for ErrorTrace {
@@ -1,3 +1,6 @@
 mod def;
+mod errno;
 mod error_trace;
 pub use def::Result;
+pub use errno::Errno;
+pub use error_trace::ErrorTrace;
@@ -0,0 +1,373 @@
+use super::{
+    def::{
+        flags_readable, flags_writable, makedev, DirEntry, FileType, SeekFrom, Stat, StatFs,
+        NAME_MAX, S_IFBLK, S_IFCHR, S_IFDIR,
+    },
+    vfs::{new_file, File, FileRef, FileSystem, Inode, InodeRef, SuperBlock},
+};
+use crate::{
+    block::{
+        bcache::{bread_bytes, bwrite_bytes},
+        block_device, block_devices, find_block_device, DevId,
+    },
+    console::file::ConsoleFile,
+    error::{Errno, ErrorTrace, Result},
+    random::random_usize,
+};
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicU64, Ordering};
+use xx_mutex_lock::Mutex;
+use xxos_log::info;
+
+const ROOT_INO: u64 = 1;
+// 块设备的inode号是这个值加上设备编号
+const BLOCK_INO_BASE: u64 = 0x1_0000;
+
+// 与Linux相同的主设备号
+pub const MEM_MAJOR: u32 = 1;
+pub const TTYAUX_MAJOR: u32 = 5;
+// 块设备使用实验用的主设备号，次设备号是DevId
+const BLOCK_MAJOR: u32 = 254;
+
+// 字符设备驱动实现这个trait，每次打开设备文件时调用open
+pub trait Device: Send + Sync {
+    fn open(&self, flags: u32) -> Result<FileRef>;
+}
+
+// /dev中的一个设备文件
+pub struct DevNode {
+    name: String,
+    ino: u64,
+    file_type: FileType,
+    mode: u32,
+    rdev: u64,
+    device: Arc<dyn Device>,
+}
+
+impl Inode for DevNode {
+    fn ino(&self) -> u64 {
+        self.ino
+    }
+
+    fn file_type(&self) -> FileType {
+        self.file_type
+    }
+
+    fn stat(&self) -> Result<Stat> {
+        Ok(Stat {
+            ino: self.ino,
+            mode: self.file_type.to_mode() | self.mode,
+            nlink: 1,
+            rdev: self.rdev,
+            ..Default::default()
+        })
+    }
+
+    fn open(&self, flags: u32) -> Option<Result<FileRef>> {
+        Some(self.device.open(flags))
+    }
+}
+
+static CHAR_DEVICES: Mutex<Vec<Arc<DevNode>>> = Mutex::new(Vec::new());
+static NEXT_INO: AtomicU64 = AtomicU64::new(ROOT_INO + 1);
+
+// 驱动初始化时调用，设备文件立即出现在所有挂载的devfs中
+pub fn register_char_device(
+    name: &str,
+    major: u32,
+    minor: u32,
+    mode: u32,
+    device: Arc<dyn Device>,
+) -> Result<()> {
+    if name.is_empty() || name.len() > NAME_MAX || name.contains('/') {
+        return Err(Errno::EINVAL.into());
+    }
+    let mut devices = CHAR_DEVICES.lock();
+    if devices.iter().any(|node| node.name == name) || find_block_device(name).is_some() {
+        return Err(Errno::EEXIST.into());
+    }
+    devices.push(Arc::new(DevNode {
+        name: name.to_string(),
+        ino: NEXT_INO.fetch_add(1, Ordering::Relaxed),
+        file_type: FileType::CharDevice,
+        mode: mode & 0o7777,
+        rdev: makedev(major, minor),
+        device,
+    }));
+    info!("devfs: register /dev/{} ({}, {})", name, major, minor);
+    Ok(())
+}
+
+pub fn unregister_char_device(name: &str) {
+    CHAR_DEVICES.lock().retain(|node| node.name != name);
+}
+
+// 块设备不需要注册，直接列出块设备层中的所有设备与分区
+fn block_node(dev: DevId, name: &str) -> Arc<DevNode> {
+    Arc::new(DevNode {
+        name: name.to_string(),
+        ino: BLOCK_INO_BASE + dev as u64,
+        file_type: FileType::BlockDevice,
+        mode: 0o660,
+        rdev: makedev(BLOCK_MAJOR, dev as u32),
+        device: Arc::new(BlockDeviceNode { dev }),
+    })
+}
+
+// 当前所有的设备文件，字符设备在前
+fn nodes() -> Vec<Arc<DevNode>> {
+    let mut nodes = CHAR_DEVICES.lock().clone();
+    nodes.extend(
+        block_devices()
+            .into_iter()
+            .map(|(dev, device)| block_node(dev, device.name())),
+    );
+    nodes
+}
+
+// devfs的根目录，没有子目录
+pub struct DevfsRoot;
+
+impl Inode for DevfsRoot {
+    fn ino(&self) -> u64 {
+        ROOT_INO
+    }
+
+    fn file_type(&self) -> FileType {
+        FileType::Directory
+    }
+
+    fn stat(&self) -> Result<Stat> {
+        Ok(Stat {
+            ino: ROOT_INO,
+            mode: S_IFDIR | 0o755,
+            nlink: 2,
+            ..Default::default()
+        })
+    }
+
+    fn lookup(&self, name: &str) -> Result<InodeRef> {
+        if name == "." || name == ".." {
+            return Ok(Arc::new(DevfsRoot));
+        }
+        if let Some(node) = CHAR_DEVICES.lock().iter().find(|node| node.name == name) {
+            return Ok(node.clone());
+        }
+        let (dev, _) = find_block_device(name).ok_or(Errno::ENOENT)?;
+        Ok(block_node(dev, name))
+    }
+
+    fn create(&self, _name: &str, _file_type: FileType, _mode: u32) -> Result<InodeRef> {
+        Err(Errno::EPERM.into())
+    }
+
+    fn unlink(&self, _name: &str) -> Result<()> {
+        Err(Errno::EPERM.into())
+    }
+
+    fn readdir(&self, index: usize) -> Result<Option<DirEntry>> {
+        let entry = match index {
+            0 => Some((String::from("."), ROOT_INO, FileType::Directory)),
+            1 => Some((String::from(".."), ROOT_INO, FileType::Directory)),
+            _ => nodes()
+                .into_iter()
+                .nth(index - 2)
+                .map(|node| (node.name.clone(), node.ino, node.file_type)),
+        };
+        Ok(entry.map(|(name, ino, file_type)| DirEntry {
+            ino,
+            name,
+            file_type,
+        }))
+    }
+}
+
+pub struct DevfsSuperBlock;
+
+impl SuperBlock for DevfsSuperBlock {
+    fn root(&self) -> InodeRef {
+        Arc::new(DevfsRoot)
+    }
+
+    fn statfs(&self) -> Result<StatFs> {
+        Ok(StatFs {
+            name_max: NAME_MAX,
+            ..Default::default()
+        })
+    }
+}
+
+pub struct DevfsType;
+
+impl FileSystem for DevfsType {
+    fn name(&self) -> &'static str {
+        "devfs"
+    }
+
+    fn requires_device(&self) -> bool {
+        false
+    }
+
+    fn mount(&self, _dev: Option<DevId>, _options: &str) -> Result<Arc<dyn SuperBlock>> {
+        Ok(Arc::new(DevfsSuperBlock))
+    }
+}
+
+// /dev/null, /dev/zero, /dev/random与/dev/urandom
+#[derive(Debug, Clone, Copy)]
+pub enum MemDevice {
+    Null,
+    Zero,
+    Random,
+}
+
+impl MemDevice {
+    fn minor(self) -> u32 {
+        match self {
+            MemDevice::Null => 3,
+            MemDevice::Zero => 5,
+            MemDevice::Random => 8,
+        }
+    }
+}
+
+impl Device for MemDevice {
+    fn open(&self, _flags: u32) -> Result<FileRef> {
+        new_file(*self)
+    }
+}
+
+impl File for MemDevice {
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        match self {
+            MemDevice::Null => return Ok(0),
+            MemDevice::Zero => buf.fill(0),
+            MemDevice::Random => {
+                for chunk in buf.chunks_mut(size_of::<usize>()) {
+                    chunk.copy_from_slice(&random_usize().to_ne_bytes()[..chunk.len()]);
+                }
+            }
+        }
+        Ok(buf.len())
+    }
+
+    // 写入的数据全部丢弃
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn stat(&self) -> Result<Stat> {
+        Ok(Stat {
+            mode: S_IFCHR | 0o666,
+            nlink: 1,
+            rdev: makedev(MEM_MAJOR, self.minor()),
+            ..Default::default()
+        })
+    }
+
+    fn seek(&self, _pos: SeekFrom) -> Result<u64> {
+        Ok(0)
+    }
+}
+
+struct BlockDeviceNode {
+    dev: DevId,
+}
+
+impl Device for BlockDeviceNode {
+    fn open(&self, flags: u32) -> Result<FileRef> {
+        let device = block_device(self.dev).ok_or_else(|| ErrorTrace::from_errno(Errno::ENXIO))?;
+        if flags_writable(flags) && device.is_read_only() {
+            return Err(Errno::EROFS.into());
+        }
+        new_file(BlockFile {
+            dev: self.dev,
+            size: device.capacity() * device.block_size() as u64,
+            flags,
+            offset: Mutex::new(0),
+        })
+    }
+}
+
+// 以字节为单位读写整个块设备，经过buffer cache，与文件系统看到的内容一致
+pub struct BlockFile {
+    dev: DevId,
+    size: u64,
+    flags: u32,
+    offset: Mutex<u64>,
+}
+
+impl File for BlockFile {
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        if !self.readable() {
+            return Err(Errno::EBADF.into());
+        }
+        let mut offset = self.offset.lock();
+        let len = (buf.len() as u64).min(self.size.saturating_sub(*offset)) as usize;
+        bread_bytes(self.dev, *offset, &mut buf[..len])?;
+        *offset += len as u64;
+        Ok(len)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        if !self.writable() {
+            return Err(Errno::EBADF.into());
+        }
+        let mut offset = self.offset.lock();
+        let len = (buf.len() as u64).min(self.size.saturating_sub(*offset)) as usize;
+        if len == 0 && !buf.is_empty() {
+            return Err(Errno::ENOSPC.into());
+        }
+        bwrite_bytes(self.dev, *offset, &buf[..len])?;
+        *offset += len as u64;
+        Ok(len)
+    }
+
+    fn stat(&self) -> Result<Stat> {
+        Ok(Stat {
+            ino: BLOCK_INO_BASE + self.dev as u64,
+            mode: S_IFBLK | 0o660,
+            nlink: 1,
+            rdev: makedev(BLOCK_MAJOR, self.dev as u32),
+            size: self.size as i64,
+            blksize: 512,
+            ..Default::default()
+        })
+    }
+
+    fn readable(&self) -> bool {
+        flags_readable(self.flags)
+    }
+
+    fn writable(&self) -> bool {
+        flags_writable(self.flags)
+    }
+
+    fn seek(&self, pos: SeekFrom) -> Result<u64> {
+        let mut offset = self.offset.lock();
+        let new = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::Current(delta) => offset.checked_add_signed(delta),
+            SeekFrom::End(delta) => self.size.checked_add_signed(delta),
+        };
+        *offset = new.ok_or(Errno::EINVAL)?;
+        Ok(*offset)
+    }
+}
+
+// 注册内置的字符设备
+pub fn devfs_init() {
+    let _ = register_char_device("console", TTYAUX_MAJOR, 1, 0o620, Arc::new(ConsoleFile));
+    for (name, device) in [
+        ("null", MemDevice::Null),
+        ("zero", MemDevice::Zero),
+        ("random", MemDevice::Random),
+    ] {
+        let _ = register_char_device(name, MEM_MAJOR, device.minor(), 0o666, Arc::new(device));
+    }
+    let _ = register_char_device("urandom", MEM_MAJOR, 9, 0o666, Arc::new(MemDevice::Random));
+}
@@ -0,0 +1,315 @@
+use super::{DiskInode, Ext2Fs, IND_BLOCK, NDIR_BLOCKS};
+use crate::{
+    error::{Errno, Result},
+    fs::{
+        def::{flags_writable, DirEntry, FileType, Stat, NAME_MAX},
+        pagecache::PageCache,
+        vfs::{FileRef, Inode, InodeRef},
+    },
+    mm::{def::PGSZ, page_frame::PageFrame},
+};
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+use xxos_log::warn;
+
+// 长度小于60字节的符号链接直接保存在i_block中
+const FAST_SYMLINK_MAX: u64 = 60;
+// 目录项头部: inode, rec_len, name_len, file_type
+const DIRENT_HEADER: usize = 8;
+
+struct Ext2Dirent {
+    ino: u32,
+    name: String,
+    // 0表示目录项中没有记录类型
+    file_type: u8,
+}
+
+fn dirent_file_type(file_type: u8) -> Option<FileType> {
+    match file_type {
+        1 => Some(FileType::Regular),
+        2 => Some(FileType::Directory),
+        3 => Some(FileType::CharDevice),
+        4 => Some(FileType::BlockDevice),
+        5 => Some(FileType::Fifo),
+        6 => Some(FileType::Socket),
+        7 => Some(FileType::Symlink),
+        _ => None,
+    }
+}
+
+pub struct Ext2Inode {
+    fs: Arc<Ext2Fs>,
+    ino: u32,
+    dinode: DiskInode,
+    // 只读文件系统，缓存的页不会变脏
+    cache: PageCache,
+}
+
+impl Ext2Inode {
+    pub(super) fn new(fs: Arc<Ext2Fs>, ino: u32, dinode: DiskInode) -> Self {
+        Self {
+            fs,
+            ino,
+            dinode,
+            cache: PageCache::new(),
+        }
+    }
+
+    // 文件中第n块对应的磁盘块号，0表示空洞
+    fn bmap(&self, n: u64) -> Result<u32> {
+        if n < NDIR_BLOCKS as u64 {
+            return Ok(self.dinode.block[n as usize]);
+        }
+        let per_block = self.fs.addrs_per_block() as u64;
+        let mut index = n - NDIR_BLOCKS as u64;
+        let mut span = 1;
+        // 依次是一级、二级、三级间接块
+        for level in 0..3 {
+            span *= per_block;
+            if index < span {
+                let mut blockno = self.dinode.block[IND_BLOCK + level];
+                let mut stride = span;
+                while stride > 1 && blockno != 0 {
+                    stride /= per_block;
+                    blockno = self.fs.read_addr(blockno, (index / stride) as usize)?;
+                    index %= stride;
+                }
+                return Ok(blockno);
+            }
+            index -= span;
+        }
+        Err(Errno::EFBIG.into())
+    }
+
+    fn read_data(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let size = self.dinode.size;
+        if offset >= size {
+            return Ok(0);
+        }
+        let n = (buf.len() as u64).min(size - offset) as usize;
+        let bsize = self.fs.block_size;
+        let mut done = 0;
+        while done < n {
+            let pos = offset + done as u64;
+            let boff = (pos % bsize as u64) as usize;
+            let len = (bsize - boff).min(n - done);
+            match self.bmap(pos / bsize as u64)? {
+                0 => buf[done..done + len].fill(0),
+                blockno => self
+                    .fs
+                    .read_block(blockno, boff, &mut buf[done..done + len])?,
+            }
+            done += len;
+        }
+        Ok(n)
+    }
+
+    // 读出并解析整个目录，跳过已删除的目录项
+    fn entries(&self) -> Result<Vec<Ext2Dirent>> {
+        if !self.is_dir() {
+            return Err(Errno::ENOTDIR.into());
+        }
+        let mut data = vec![0; self.dinode.size as usize];
+        let len = self.read_data(0, &mut data)?;
+        data.truncate(len);
+
+        let mut entries = Vec::new();
+        let mut pos = 0;
+        while pos + DIRENT_HEADER <= data.len() {
+            let raw = &data[pos..];
+            let ino = u32::from_le_bytes(raw[0..4].try_into().unwrap());
+            let rec_len = u16::from_le_bytes([raw[4], raw[5]]) as usize;
+            // 没有filetype特性时name_len是16位的
+            let (name_len, file_type) = if self.fs.filetype {
+                (raw[6] as usize, raw[7])
+            } else {
+                (u16::from_le_bytes([raw[6], raw[7]]) as usize, 0)
+            };
+            if rec_len < DIRENT_HEADER
+                || rec_len % 4 != 0
+                || rec_len > raw.len()
+                || DIRENT_HEADER + name_len > rec_len
+            {
+                warn!("ext2: bad directory entry in inode {}", self.ino);
+                return Err(Errno::EIO.into());
+            }
+            if ino != 0 {
+                let name = &raw[DIRENT_HEADER..DIRENT_HEADER + name_len];
+                entries.push(Ext2Dirent {
+                    ino,
+                    name: String::from_utf8_lossy(name).into_owned(),
+                    file_type,
+                });
+            }
+            pos += rec_len;
+        }
+        Ok(entries)
+    }
+
+    // 快速符号链接不占用数据块，扩展属性块除外
+    fn is_fast_symlink(&self) -> bool {
+        let ea_blocks = if self.dinode.file_acl != 0 {
+            (self.fs.block_size / 512) as u32
+        } else {
+            0
+        };
+        self.dinode.blocks == ea_blocks && self.dinode.size < FAST_SYMLINK_MAX
+    }
+}
+
+impl Drop for Ext2Inode {
+    fn drop(&mut self) {
+        self.fs.forget(self.ino);
+    }
+}
+
+impl Inode for Ext2Inode {
+    fn ino(&self) -> u64 {
+        self.ino as u64
+    }
+
+    fn file_type(&self) -> FileType {
+        FileType::from_mode(self.dinode.mode as u32).unwrap_or(FileType::Regular)
+    }
+
+    fn stat(&self) -> Result<Stat> {
+        let dinode = &self.dinode;
+        Ok(Stat {
+            dev: self.fs.dev as u64,
+            ino: self.ino as u64,
+            mode: dinode.mode as u32,
+            nlink: dinode.nlink as u32,
+            uid: dinode.uid,
+            gid: dinode.gid,
+            size: dinode.size as i64,
+            blksize: self.fs.block_size as i32,
+            blocks: dinode.blocks as i64,
+            atime: dinode.atime as i64,
+            mtime: dinode.mtime as i64,
+            ctime: dinode.ctime as i64,
+            ..Default::default()
+        })
+    }
+
+    fn size(&self) -> u64 {
+        self.dinode.size
+    }
+
+    fn lookup(&self, name: &str) -> Result<InodeRef> {
+        if name.len() > NAME_MAX {
+            return Err(Errno::ENAMETOOLONG.into());
+        }
+        let entry = self
+            .entries()?
+            .into_iter()
+            .find(|entry| entry.name == name)
+            .ok_or(Errno::ENOENT)?;
+        Ok(self.fs.iget(entry.ino)?)
+    }
+
+    fn create(&self, _name: &str, _file_type: FileType, _mode: u32) -> Result<InodeRef> {
+        Err(Errno::EROFS.into())
+    }
+
+    fn symlink(&self, _name: &str, _target: &str) -> Result<InodeRef> {
+        Err(Errno::EROFS.into())
+    }
+
+    fn unlink(&self, _name: &str) -> Result<()> {
+        Err(Errno::EROFS.into())
+    }
+
+    fn link(&self, _name: &str, _inode: &InodeRef) -> Result<()> {
+        Err(Errno::EROFS.into())
+    }
+
+    fn rename(&self, _old_name: &str, _new_dir: &InodeRef, _new_name: &str) -> Result<()> {
+        Err(Errno::EROFS.into())
+    }
+
+    fn readdir(&self, index: usize) -> Result<Option<DirEntry>> {
+        let Some(entry) = self.entries()?.into_iter().nth(index) else {
+            return Ok(None);
+        };
+        let file_type = match dirent_file_type(entry.file_type) {
+            Some(file_type) => file_type,
+            None => self.fs.iget(entry.ino)?.file_type(),
+        };
+        Ok(Some(DirEntry {
+            ino: entry.ino as u64,
+            name: entry.name,
+            file_type,
+        }))
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        match self.file_type() {
+            FileType::Directory => Err(Errno::EISDIR.into()),
+            FileType::Symlink => Err(Errno::EINVAL.into()),
+            FileType::Regular => {
+                let size = self.dinode.size;
+                if offset >= size {
+                    return Ok(0);
+                }
+                let n = (buf.len() as u64).min(size - offset) as usize;
+                self.cache.read(offset, &mut buf[..n], |index, page| {
+                    self.read_data(index * PGSZ as u64, page)?;
+                    Ok(())
+                })?;
+                Ok(n)
+            }
+            _ => self.read_data(offset, buf),
+        }
+    }
+
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> Result<usize> {
+        Err(Errno::EROFS.into())
+    }
+
+    fn truncate(&self, _size: u64) -> Result<()> {
+        Err(Errno::EROFS.into())
+    }
+
+    fn get_page(&self, index: u64) -> Result<Arc<PageFrame>> {
+        if self.file_type() != FileType::Regular {
+            return Err(Errno::ENODEV.into());
+        }
+        self.cache.get_page(index, |page| {
+            self.read_data(index * PGSZ as u64, page)?;
+            Ok(())
+        })
+    }
+
+    fn chmod(&self, _mode: u32) -> Result<()> {
+        Err(Errno::EROFS.into())
+    }
+
+    fn chown(&self, _uid: u32, _gid: u32) -> Result<()> {
+        Err(Errno::EROFS.into())
+    }
+
+    fn readlink(&self) -> Result<String> {
+        if self.file_type() != FileType::Symlink {
+            return Err(Errno::EINVAL.into());
+        }
+        let target = if self.is_fast_symlink() {
+            let bytes: Vec<u8> = self
+                .dinode
+                .block
+                .iter()
+                .flat_map(|addr| addr.to_le_bytes())
+                .take(self.dinode.size as usize)
+                .collect();
+            String::from_utf8_lossy(&bytes).into_owned()
+        } else {
+            let mut data = vec![0; self.dinode.size as usize];
+            let len = self.read_data(0, &mut data)?;
+            String::from_utf8_lossy(&data[..len]).into_owned()
+        };
+        Ok(target)
+    }
+
+    // 只读文件系统不能以写方式打开
+    fn open(&self, flags: u32) -> Option<Result<FileRef>> {
+        flags_writable(flags).then(|| Err(Errno::EROFS.into()))
+    }
+}
@@ -0,0 +1,318 @@
+mod inode;
+
+use super::{
+    def::{StatFs, S_IFMT, S_IFREG},
+    vfs::{FileSystem, Inode, InodeRef, SuperBlock},
+};
+use crate::{
+    block::{bcache::bread_bytes, block_device, DevId},
+    error::{Errno, ErrorTrace, Result},
+};
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use inode::Ext2Inode;
+use xx_mutex_lock::Mutex;
+use xxos_log::{info, warn};
+
+const EXT2_MAGIC: u16 = 0xef53;
+// 超级块总是位于卷的第1024字节处
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const SUPERBLOCK_SIZE: usize = 1024;
+const ROOT_INO: u32 = 2;
+// revision 0的inode大小固定为128字节
+const GOOD_OLD_INODE_SIZE: usize = 128;
+const GROUP_DESC_SIZE: usize = 32;
+
+// 直接块与间接块的个数
+const NDIR_BLOCKS: usize = 12;
+const IND_BLOCK: usize = 12;
+const N_BLOCKS: usize = 15;
+
+// incompatible features
+const FEATURE_INCOMPAT_FILETYPE: u32 = 0x0002;
+const FEATURE_INCOMPAT_RECOVER: u32 = 0x0004;
+const FEATURE_INCOMPAT_FLEX_BG: u32 = 0x0200;
+// 只读挂载时，flex_bg只影响各个表在磁盘上的位置
+const FEATURE_INCOMPAT_SUPP: u32 = FEATURE_INCOMPAT_FILETYPE | FEATURE_INCOMPAT_FLEX_BG;
+
+#[inline]
+fn le16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+#[inline]
+fn le32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+// 磁盘上的inode中用到的字段
+#[derive(Debug, Clone, Copy)]
+struct DiskInode {
+    mode: u16,
+    uid: u32,
+    gid: u32,
+    size: u64,
+    atime: u32,
+    ctime: u32,
+    mtime: u32,
+    nlink: u16,
+    // 以512字节为单位
+    blocks: u32,
+    block: [u32; N_BLOCKS],
+    file_acl: u32,
+}
+
+impl DiskInode {
+    fn parse(raw: &[u8]) -> Self {
+        let mut block = [0; N_BLOCKS];
+        for (i, addr) in block.iter_mut().enumerate() {
+            *addr = le32(raw, 40 + i * 4);
+        }
+        let mode = le16(raw, 0);
+        let mut size = le32(raw, 4) as u64;
+        // 只有普通文件的i_size_high是长度的高32位，目录的这个字段是i_dir_acl
+        if mode as u32 & S_IFMT == S_IFREG {
+            size |= (le32(raw, 108) as u64) << 32;
+        }
+        Self {
+            mode,
+            uid: le16(raw, 2) as u32 | (le16(raw, 120) as u32) << 16,
+            size,
+            atime: le32(raw, 8),
+            ctime: le32(raw, 12),
+            mtime: le32(raw, 16),
+            gid: le16(raw, 24) as u32 | (le16(raw, 122) as u32) << 16,
+            nlink: le16(raw, 26),
+            blocks: le32(raw, 28),
+            block,
+            file_acl: le32(raw, 104),
+        }
+    }
+}
+
+// Second extended file system, read only
+// 挂载时读入超级块与组描述符，之后只读取inode表与数据块
+pub struct Ext2Fs {
+    dev: DevId,
+    block_size: usize,
+    blocks_count: u32,
+    free_blocks: u32,
+    inodes_count: u32,
+    free_inodes: u32,
+    inodes_per_group: u32,
+    inode_size: usize,
+    // 目录项中是否记录了文件类型
+    filetype: bool,
+    // 每个块组的inode表所在的块
+    inode_tables: Vec<u32>,
+    // 在内存中的inode，同一个文件只有一个Ext2Inode，页缓存才能被共享
+    inodes: Mutex<BTreeMap<u32, Weak<Ext2Inode>>>,
+}
+
+impl Ext2Fs {
+    fn block_offset(&self, blockno: u32) -> u64 {
+        blockno as u64 * self.block_size as u64
+    }
+
+    fn check_block(&self, blockno: u32) -> Result<()> {
+        if blockno < self.blocks_count {
+            Ok(())
+        } else {
+            warn!("ext2: bad block {}", blockno);
+            Err(Errno::EIO.into())
+        }
+    }
+
+    // 读出块blockno中从offset开始的数据
+    fn read_block(&self, blockno: u32, offset: usize, buf: &mut [u8]) -> Result<()> {
+        self.check_block(blockno)?;
+        bread_bytes(self.dev, self.block_offset(blockno) + offset as u64, buf)
+    }
+
+    // 间接块中的第index个块号
+    fn read_addr(&self, blockno: u32, index: usize) -> Result<u32> {
+        let mut addr = [0; 4];
+        self.read_block(blockno, index * 4, &mut addr)?;
+        Ok(u32::from_le_bytes(addr))
+    }
+
+    fn addrs_per_block(&self) -> usize {
+        self.block_size / 4
+    }
+
+    fn read_dinode(&self, ino: u32) -> Result<DiskInode> {
+        if ino == 0 || ino > self.inodes_count {
+            return Err(Errno::EINVAL.into());
+        }
+        let group = ((ino - 1) / self.inodes_per_group) as usize;
+        let index = ((ino - 1) % self.inodes_per_group) as usize;
+        let table = *self.inode_tables.get(group).ok_or(Errno::EIO)?;
+        let mut raw = [0; GOOD_OLD_INODE_SIZE];
+        self.read_block(table, index * self.inode_size, &mut raw)?;
+        Ok(DiskInode::parse(&raw))
+    }
+
+    // 只读文件系统的inode不会改变，每次查找都从磁盘读入
+    fn iget(self: &Arc<Self>, ino: u32) -> Result<Arc<Ext2Inode>> {
+        if let Some(inode) = self.inodes.lock().get(&ino).and_then(Weak::upgrade) {
+            return Ok(inode);
+        }
+        // 读盘时不能持有inodes的锁
+        let dinode = self.read_dinode(ino)?;
+        if dinode.mode == 0 || dinode.nlink == 0 {
+            warn!("ext2: inode {} is not in use", ino);
+            return Err(Errno::ENOENT.into());
+        }
+        let mut inodes = self.inodes.lock();
+        if let Some(inode) = inodes.get(&ino).and_then(Weak::upgrade) {
+            return Ok(inode);
+        }
+        let inode = Arc::new(Ext2Inode::new(self.clone(), ino, dinode));
+        inodes.insert(ino, Arc::downgrade(&inode));
+        Ok(inode)
+    }
+
+    // 由Ext2Inode::drop调用
+    fn forget(&self, ino: u32) {
+        let mut inodes = self.inodes.lock();
+        if inodes
+            .get(&ino)
+            .is_some_and(|inode| inode.strong_count() == 0)
+        {
+            inodes.remove(&ino);
+        }
+    }
+}
+
+pub struct Ext2SuperBlock {
+    fs: Arc<Ext2Fs>,
+    root: Arc<Ext2Inode>,
+}
+
+impl SuperBlock for Ext2SuperBlock {
+    fn root(&self) -> InodeRef {
+        self.root.clone()
+    }
+
+    fn statfs(&self) -> Result<StatFs> {
+        let fs = &self.fs;
+        Ok(StatFs {
+            block_size: fs.block_size,
+            blocks: fs.blocks_count as u64,
+            free_blocks: fs.free_blocks as u64,
+            files: fs.inodes_count as u64,
+            free_files: fs.free_inodes as u64,
+            name_max: 255,
+        })
+    }
+}
+
+pub struct Ext2Type;
+
+impl FileSystem for Ext2Type {
+    fn name(&self) -> &'static str {
+        "ext2"
+    }
+
+    fn mount(&self, dev: Option<DevId>, _options: &str) -> Result<Arc<dyn SuperBlock>> {
+        let dev = dev.ok_or_else(|| ErrorTrace::from_errno(Errno::ENODEV))?;
+        let device = block_device(dev).ok_or_else(|| ErrorTrace::from_errno(Errno::ENXIO))?;
+        let mut sb = [0; SUPERBLOCK_SIZE];
+        bread_bytes(dev, SUPERBLOCK_OFFSET, &mut sb)?;
+        if le16(&sb, 56) != EXT2_MAGIC {
+            return Err(Errno::EINVAL.into());
+        }
+
+        let inodes_count = le32(&sb, 0);
+        let blocks_count = le32(&sb, 4);
+        let free_blocks = le32(&sb, 12);
+        let free_inodes = le32(&sb, 16);
+        let first_data_block = le32(&sb, 20);
+        let log_block_size = le32(&sb, 24);
+        let blocks_per_group = le32(&sb, 32);
+        let inodes_per_group = le32(&sb, 40);
+        let rev_level = le32(&sb, 76);
+        let (inode_size, incompat) = match rev_level {
+            0 => (GOOD_OLD_INODE_SIZE, 0),
+            _ => (le16(&sb, 88) as usize, le32(&sb, 96)),
+        };
+
+        if log_block_size > 6 || blocks_per_group == 0 || inodes_per_group == 0 {
+            warn!("ext2: bad super block on {}", device.name());
+            return Err(Errno::EINVAL.into());
+        }
+        let block_size = 1024usize << log_block_size;
+        if inode_size < GOOD_OLD_INODE_SIZE
+            || !inode_size.is_power_of_two()
+            || inode_size > block_size
+        {
+            warn!("ext2: bad inode size {} on {}", inode_size, device.name());
+            return Err(Errno::EINVAL.into());
+        }
+        if incompat & FEATURE_INCOMPAT_RECOVER != 0 {
+            warn!("ext2: journal of {} needs recovery", device.name());
+            return Err(Errno::EINVAL.into());
+        }
+        if incompat & !FEATURE_INCOMPAT_SUPP != 0 {
+            warn!(
+                "ext2: unsupported features {:#x} on {}",
+                incompat & !FEATURE_INCOMPAT_SUPP,
+                device.name()
+            );
+            return Err(Errno::EINVAL.into());
+        }
+        let volume_size = device.capacity() * device.block_size() as u64;
+        if first_data_block >= blocks_count || blocks_count as u64 * block_size as u64 > volume_size
+        {
+            warn!("ext2: bad super block on {}", device.name());
+            return Err(Errno::EINVAL.into());
+        }
+
+        // 组描述符表紧跟在超级块所在的块之后
+        let groups = (blocks_count - first_data_block).div_ceil(blocks_per_group) as usize;
+        if inodes_count as u64 > groups as u64 * inodes_per_group as u64 {
+            warn!("ext2: bad inode count on {}", device.name());
+            return Err(Errno::EINVAL.into());
+        }
+        let mut descs = vec![0; groups * GROUP_DESC_SIZE];
+        bread_bytes(
+            dev,
+            (first_data_block as u64 + 1) * block_size as u64,
+            &mut descs,
+        )?;
+        let inode_tables = descs
+            .chunks_exact(GROUP_DESC_SIZE)
+            .map(|desc| le32(desc, 8))
+            .collect();
+
+        let fs = Arc::new(Ext2Fs {
+            dev,
+            block_size,
+            blocks_count,
+            free_blocks,
+            inodes_count,
+            free_inodes,
+            inodes_per_group,
+            inode_size,
+            filetype: incompat & FEATURE_INCOMPAT_FILETYPE != 0,
+            inode_tables,
+            inodes: Mutex::new(BTreeMap::new()),
+        });
+        let root = fs.iget(ROOT_INO)?;
+        if !root.is_dir() {
+            return Err(Errno::ENOTDIR.into());
+        }
+        info!(
+            "ext2: {} blocks of {} bytes, {} inodes on {}",
+            blocks_count,
+            block_size,
+            inodes_count,
+            device.name()
+        );
+        Ok(Arc::new(Ext2SuperBlock { fs, root }))
+    }
+}
@@ -0,0 +1,295 @@
+use alloc::{string::String, vec::Vec};
+
+// FAT目录项，每项32字节
+pub const DIRENT_SIZE: usize = 32;
+
+pub const ATTR_READ_ONLY: u8 = 0x01;
+pub const ATTR_HIDDEN: u8 = 0x02;
+pub const ATTR_SYSTEM: u8 = 0x04;
+pub const ATTR_VOLUME_ID: u8 = 0x08;
+pub const ATTR_DIRECTORY: u8 = 0x10;
+pub const ATTR_ARCHIVE: u8 = 0x20;
+pub const ATTR_LONG_NAME: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
+
+// name[0]的特殊取值
+const ENTRY_END: u8 = 0x00;
+pub const ENTRY_DELETED: u8 = 0xe5;
+
+// 每个长文件名项保存13个UTF-16字符
+const LFN_CHARS: usize = 13;
+const LFN_LAST: u8 = 0x40;
+const LFN_MAX: usize = 255;
+// 长文件名项中各段字符的偏移
+const LFN_OFFSETS: [usize; LFN_CHARS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+// 短文件名中允许的字符(除字母与数字之外)
+const SHORT_SPECIAL: &[u8] = b"$%'-_@~`!(){}^#&";
+
+// 1980-01-01，FAT的日期不能为0
+const FAT_EPOCH_DATE: u16 = (1 << 5) | 1;
+
+// 解析出的一个目录项，长文件名已经拼接好
+#[derive(Clone)]
+pub struct FatDirent {
+    pub name: String,
+    pub short: [u8; 11],
+    pub attr: u8,
+    pub cluster: u32,
+    pub size: u32,
+    // 短文件名项在目录中的偏移
+    pub pos: usize,
+    // 长文件名项的个数，它们紧挨在短文件名项之前
+    pub lfn_count: usize,
+}
+
+impl FatDirent {
+    pub fn is_dir(&self) -> bool {
+        self.attr & ATTR_DIRECTORY != 0
+    }
+
+    pub fn is_dot(&self) -> bool {
+        self.name == "." || self.name == ".."
+    }
+
+    // FAT不区分大小写
+    pub fn matches(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+            || short_display(&self.short).eq_ignore_ascii_case(name)
+    }
+}
+
+#[inline]
+fn le16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+#[inline]
+fn le32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+// "NAME    EXT" -> "NAME.EXT"
+pub fn short_display(short: &[u8; 11]) -> String {
+    let base = core::str::from_utf8(&short[..8]).unwrap_or("").trim_end();
+    let ext = core::str::from_utf8(&short[8..]).unwrap_or("").trim_end();
+    let mut name = String::from(base);
+    // 0x05表示第一个字符为0xE5
+    if short[0] == 0x05 {
+        name.replace_range(..1, "\u{e5}");
+    }
+    if !ext.is_empty() {
+        name.push('.');
+        name.push_str(ext);
+    }
+    name
+}
+
+pub fn checksum(short: &[u8; 11]) -> u8 {
+    short.iter().fold(0u8, |sum, &c| {
+        ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(c)
+    })
+}
+
+// 解析目录的全部内容
+pub fn parse_dir(data: &[u8]) -> Vec<FatDirent> {
+    let mut entries = Vec::new();
+    let mut lfn: Vec<u16> = Vec::new();
+    let mut lfn_count = 0;
+    let mut lfn_checksum = 0;
+
+    for (index, raw) in data.chunks_exact(DIRENT_SIZE).enumerate() {
+        match raw[0] {
+            ENTRY_END => break,
+            ENTRY_DELETED => {
+                lfn.clear();
+                lfn_count = 0;
+                continue;
+            }
+            _ => {}
+        }
+
+        let attr = raw[11];
+        if attr & 0x3f == ATTR_LONG_NAME {
+            let ord = raw[0];
+            if ord & LFN_LAST != 0 {
+                lfn.clear();
+                lfn_count = 0;
+                lfn_checksum = raw[13];
+            }
+            // 长文件名项是倒序存放的，每项的字符放到前面
+            let mut part: Vec<u16> = LFN_OFFSETS.iter().map(|&o| le16(raw, o)).collect();
+            part.extend_from_slice(&lfn);
+            lfn = part;
+            lfn_count += 1;
+            continue;
+        }
+
+        let short: [u8; 11] = raw[..11].try_into().unwrap();
+        if attr & ATTR_VOLUME_ID != 0 {
+            lfn.clear();
+            lfn_count = 0;
+            continue;
+        }
+
+        let has_lfn = lfn_count > 0 && lfn_checksum == checksum(&short);
+        let name = if has_lfn {
+            let len = lfn.iter().position(|&c| c == 0).unwrap_or(lfn.len());
+            char::decode_utf16(lfn[..len].iter().copied())
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect()
+        } else {
+            // 0x08与0x10标志表示主名与扩展名为小写
+            let mut display = short_display(&short);
+            let ntres = raw[12];
+            if let Some((base, ext)) = display.clone().split_once('.') {
+                let base = if ntres & 0x08 != 0 {
+                    base.to_ascii_lowercase()
+                } else {
+                    String::from(base)
+                };
+                let ext = if ntres & 0x10 != 0 {
+                    ext.to_ascii_lowercase()
+                } else {
+                    String::from(ext)
+                };
+                display = base + "." + &ext;
+            } else if ntres & 0x08 != 0 {
+                display.make_ascii_lowercase();
+            }
+            display
+        };
+
+        entries.push(FatDirent {
+            name,
+            short,
+            attr,
+            cluster: ((le16(raw, 20) as u32) << 16) | le16(raw, 26) as u32,
+            size: le32(raw, 28),
+            pos: index * DIRENT_SIZE,
+            lfn_count: if has_lfn { lfn_count } else { 0 },
+        });
+        lfn.clear();
+        lfn_count = 0;
+    }
+    entries
+}
+
+fn is_short_char(c: u8) -> bool {
+    c.is_ascii_uppercase() || c.is_ascii_digit() || SHORT_SPECIAL.contains(&c) || c >= 0x80
+}
+
+// 名字本身就是合法的大写8.3名字时不需要长文件名
+pub fn exact_short_name(name: &str) -> Option<[u8; 11]> {
+    if name == "." || name == ".." {
+        let mut short = [b' '; 11];
+        short[..name.len()].copy_from_slice(name.as_bytes());
+        return Some(short);
+    }
+    let (base, ext) = name.split_once('.').unwrap_or((name, ""));
+    if base.is_empty()
+        || base.len() > 8
+        || ext.len() > 3
+        || !base
+            .bytes()
+            .chain(ext.bytes())
+            .all(|c| c < 0x80 && is_short_char(c))
+    {
+        return None;
+    }
+    let mut short = [b' '; 11];
+    short[..base.len()].copy_from_slice(base.as_bytes());
+    short[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+    Some(short)
+}
+
+// 为长文件名生成"BASIS~N.EXT"形式的短文件名，exists检查是否与已有的短文件名冲突
+pub fn generate_short_name(name: &str, exists: impl Fn(&[u8; 11]) -> bool) -> Option<[u8; 11]> {
+    let convert = |s: &str| -> Vec<u8> {
+        s.bytes()
+            .filter(|&c| c != b' ' && c != b'.')
+            .map(|c| {
+                let c = c.to_ascii_uppercase();
+                if c < 0x80 && is_short_char(c) {
+                    c
+                } else {
+                    b'_'
+                }
+            })
+            .collect()
+    };
+    let trimmed = name.trim_start_matches('.');
+    let (base, ext) = match trimmed.rfind('.') {
+        Some(dot) => (&trimmed[..dot], &trimmed[dot + 1..]),
+        None => (trimmed, ""),
+    };
+    let mut base = convert(base);
+    let ext = convert(ext);
+    if base.is_empty() {
+        base.push(b'_');
+    }
+
+    for n in 1..1_000_000u32 {
+        let tail = alloc::format!("~{}", n);
+        let keep = base.len().min(8 - tail.len());
+        let mut short = [b' '; 11];
+        short[..keep].copy_from_slice(&base[..keep]);
+        short[keep..keep + tail.len()].copy_from_slice(tail.as_bytes());
+        let ext_len = ext.len().min(3);
+        short[8..8 + ext_len].copy_from_slice(&ext[..ext_len]);
+        if !exists(&short) {
+            return Some(short);
+        }
+    }
+    None
+}
+
+pub fn short_entry(short: &[u8; 11], attr: u8, cluster: u32, size: u32) -> [u8; DIRENT_SIZE] {
+    let mut raw = [0; DIRENT_SIZE];
+    raw[..11].copy_from_slice(short);
+    raw[11] = attr;
+    // creation, access and write dates
+    raw[16..18].copy_from_slice(&FAT_EPOCH_DATE.to_le_bytes());
+    raw[18..20].copy_from_slice(&FAT_EPOCH_DATE.to_le_bytes());
+    raw[24..26].copy_from_slice(&FAT_EPOCH_DATE.to_le_bytes());
+    set_cluster(&mut raw, cluster);
+    raw[28..32].copy_from_slice(&size.to_le_bytes());
+    raw
+}
+
+pub fn set_cluster(raw: &mut [u8], cluster: u32) {
+    raw[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    raw[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+}
+
+pub fn set_size(raw: &mut [u8], size: u32) {
+    raw[28..32].copy_from_slice(&size.to_le_bytes());
+}
+
+// 生成name的长文件名项，按在磁盘上的顺序(最后一段在前)，名字过长时返回None
+pub fn lfn_entries(name: &str, short: &[u8; 11]) -> Option<Vec<[u8; DIRENT_SIZE]>> {
+    let units: Vec<u16> = name.encode_utf16().collect();
+    if units.is_empty() || units.len() > LFN_MAX {
+        return None;
+    }
+    let sum = checksum(short);
+    let count = units.len().div_ceil(LFN_CHARS);
+    let mut entries = Vec::with_capacity(count);
+    for seq in (1..=count).rev() {
+        let mut raw = [0; DIRENT_SIZE];
+        raw[0] = seq as u8 | if seq == count { LFN_LAST } else { 0 };
+        raw[11] = ATTR_LONG_NAME;
+        raw[13] = sum;
+        for (i, &offset) in LFN_OFFSETS.iter().enumerate() {
+            let index = (seq - 1) * LFN_CHARS + i;
+            // 名字之后是一个0，剩下的用0xFFFF填充
+            let c = match index.cmp(&units.len()) {
+                core::cmp::Ordering::Less => units[index],
+                core::cmp::Ordering::Equal => 0,
+                core::cmp::Ordering::Greater => 0xffff,
+            };
+            raw[offset..offset + 2].copy_from_slice(&c.to_le_bytes());
+        }
+        entries.push(raw);
+    }
+    Some(entries)
+}
@@ -0,0 +1,456 @@
+use super::{
+    dir::{
+        exact_short_name, generate_short_name, lfn_entries, parse_dir, set_cluster, set_size,
+        short_entry, FatDirent, ATTR_ARCHIVE, ATTR_DIRECTORY, ATTR_READ_ONLY, DIRENT_SIZE,
+        ENTRY_DELETED,
+    },
+    FatFs, FAT_FREE,
+};
+use crate::{
+    error::{Errno, Result},
+    fs::{
+        def::{DirEntry, FileType, Stat, S_IFDIR, S_IFREG},
+        vfs::{Inode, InodeRef},
+    },
+};
+use alloc::{sync::Arc, vec, vec::Vec};
+use xx_mutex_lock::Mutex;
+use xxos_log::warn;
+
+const ROOT_INO: u64 = 1;
+// 文件名中不允许出现的字符
+const INVALID_CHARS: &[char] = &['"', '*', '/', ':', '<', '>', '?', '\\', '|'];
+
+struct FatMeta {
+    cluster: u32,
+    size: u32,
+    attr: u8,
+    // 目录项已经删除，簇链在最后一个引用释放时释放
+    unlinked: bool,
+}
+
+pub struct FatInode {
+    fs: Arc<FatFs>,
+    ino: u64,
+    // 短文件名项在卷上的偏移，根目录没有目录项
+    dirent_pos: Option<u64>,
+    meta: Mutex<FatMeta>,
+}
+
+impl FatInode {
+    pub(super) fn root(fs: Arc<FatFs>) -> Self {
+        let cluster = fs.root_cluster;
+        Self {
+            fs,
+            ino: ROOT_INO,
+            dirent_pos: None,
+            meta: Mutex::new(FatMeta {
+                cluster,
+                size: 0,
+                attr: ATTR_DIRECTORY,
+                unlinked: false,
+            }),
+        }
+    }
+
+    fn is_directory(&self) -> bool {
+        self.meta.lock().attr & ATTR_DIRECTORY != 0
+    }
+
+    fn cluster_size(&self) -> usize {
+        self.fs.cluster_size
+    }
+
+    // 目录中偏移pos处在卷上的位置
+    fn disk_pos(&self, chain: &[u32], pos: usize) -> u64 {
+        let csize = self.cluster_size();
+        self.fs.cluster_offset(chain[pos / csize]) + (pos % csize) as u64
+    }
+
+    // 读出目录的簇链与全部内容
+    fn read_dir(&self) -> Result<(Vec<u32>, Vec<u8>)> {
+        let chain = self.fs.chain(self.meta.lock().cluster)?;
+        let csize = self.cluster_size();
+        let mut data = vec![0; chain.len() * csize];
+        for (i, &cluster) in chain.iter().enumerate() {
+            self.fs.read_bytes(
+                self.fs.cluster_offset(cluster),
+                &mut data[i * csize..][..csize],
+            )?;
+        }
+        Ok((chain, data))
+    }
+
+    // "."与".."由VFS处理，根目录中也没有这两项
+    fn entries(&self) -> Result<(Vec<u32>, Vec<FatDirent>)> {
+        if !self.is_directory() {
+            return Err(Errno::ENOTDIR.into());
+        }
+        let (chain, data) = self.read_dir()?;
+        let entries = parse_dir(&data)
+            .into_iter()
+            .filter(|entry| !entry.is_dot())
+            .collect();
+        Ok((chain, entries))
+    }
+
+    fn child(&self, chain: &[u32], entry: &FatDirent) -> Arc<FatInode> {
+        let pos = self.disk_pos(chain, entry.pos);
+        self.fs.iget(pos, || FatInode {
+            fs: self.fs.clone(),
+            ino: pos / DIRENT_SIZE as u64,
+            dirent_pos: Some(pos),
+            meta: Mutex::new(FatMeta {
+                cluster: entry.cluster,
+                size: entry.size,
+                attr: entry.attr,
+                unlinked: false,
+            }),
+        })
+    }
+
+    // 把首簇与大小写回目录项，已经删除的目录项可能被新的文件使用，不再写回
+    fn sync_dirent(&self, meta: &FatMeta) -> Result<()> {
+        let Some(pos) = self.dirent_pos else {
+            return Ok(());
+        };
+        if meta.unlinked {
+            return Ok(());
+        }
+        let mut raw = [0; DIRENT_SIZE];
+        self.fs.read_bytes(pos, &mut raw)?;
+        set_cluster(&mut raw, meta.cluster);
+        // 目录的大小总是0
+        set_size(
+            &mut raw,
+            if meta.attr & ATTR_DIRECTORY != 0 {
+                0
+            } else {
+                meta.size
+            },
+        );
+        self.fs.write_bytes(pos, &raw)
+    }
+
+    // 扩展簇链使文件可以容纳size字节，并清零原来末尾之后的内容
+    fn grow(&self, meta: &mut FatMeta, size: u64) -> Result<Vec<u32>> {
+        let csize = self.cluster_size() as u64;
+        let mut chain = self.fs.chain(meta.cluster)?;
+        let old = meta.size as u64;
+        if old % csize != 0 && ((old / csize) as usize) < chain.len() {
+            let cluster = chain[(old / csize) as usize];
+            let offset = self.fs.cluster_offset(cluster) + old % csize;
+            self.fs
+                .write_bytes(offset, &vec![0; (csize - old % csize) as usize])?;
+        }
+        while (chain.len() as u64) < size.div_ceil(csize) {
+            let cluster = self.fs.alloc_cluster(chain.last().copied())?;
+            if chain.is_empty() {
+                meta.cluster = cluster;
+            }
+            chain.push(cluster);
+        }
+        Ok(chain)
+    }
+
+    // 在目录中写入一组连续的目录项，没有足够的空闲项时扩展目录
+    fn insert_entries(&self, raw: &[[u8; DIRENT_SIZE]]) -> Result<()> {
+        loop {
+            let (chain, data) = self.read_dir()?;
+            let mut run = 0;
+            let mut found = None;
+            for (index, entry) in data.chunks_exact(DIRENT_SIZE).enumerate() {
+                // 结束标记之后的项全部空闲
+                if entry[0] == 0 || entry[0] == ENTRY_DELETED {
+                    run += 1;
+                    if run == raw.len() {
+                        found = Some(index + 1 - run);
+                        break;
+                    }
+                } else {
+                    run = 0;
+                }
+            }
+            if let Some(first) = found {
+                for (i, entry) in raw.iter().enumerate() {
+                    let pos = self.disk_pos(&chain, (first + i) * DIRENT_SIZE);
+                    self.fs.write_bytes(pos, entry)?;
+                }
+                return Ok(());
+            }
+            // 目录总是至少有一个簇
+            self.fs.alloc_cluster(chain.last().copied())?;
+        }
+    }
+
+    fn is_empty_dir(&self, cluster: u32) -> Result<bool> {
+        let chain = self.fs.chain(cluster)?;
+        let csize = self.cluster_size();
+        let mut data = vec![0; chain.len() * csize];
+        for (i, &c) in chain.iter().enumerate() {
+            self.fs
+                .read_bytes(self.fs.cluster_offset(c), &mut data[i * csize..][..csize])?;
+        }
+        Ok(parse_dir(&data).iter().all(|entry| entry.is_dot()))
+    }
+}
+
+// 调用者不能持有fs.lock，删除的文件在这里释放簇链
+impl Drop for FatInode {
+    fn drop(&mut self) {
+        let (unlinked, cluster) = {
+            let meta = self.meta.lock();
+            (meta.unlinked, meta.cluster)
+        };
+        if unlinked && cluster != FAT_FREE {
+            let _lock = self.fs.lock.lock();
+            if let Err(err) = self.fs.free_chain(cluster) {
+                warn!("fat: failed to free clusters of unlinked file: {}", err);
+            }
+        }
+        if let Some(pos) = self.dirent_pos {
+            self.fs.forget(pos);
+        }
+    }
+}
+
+impl Inode for FatInode {
+    fn ino(&self) -> u64 {
+        self.ino
+    }
+
+    fn file_type(&self) -> FileType {
+        if self.is_directory() {
+            FileType::Directory
+        } else {
+            FileType::Regular
+        }
+    }
+
+    // FAT没有权限与属主，只读属性去掉写权限
+    fn stat(&self) -> Result<Stat> {
+        let meta = self.meta.lock();
+        let mut mode = if meta.attr & ATTR_DIRECTORY != 0 {
+            S_IFDIR | 0o755
+        } else {
+            S_IFREG | 0o644
+        };
+        if meta.attr & ATTR_READ_ONLY != 0 {
+            mode &= !0o222;
+        }
+        Ok(Stat {
+            dev: self.fs.dev as u64,
+            ino: self.ino,
+            mode,
+            nlink: if meta.unlinked { 0 } else { 1 },
+            size: meta.size as i64,
+            blksize: self.fs.cluster_size as i32,
+            blocks: ((meta.size as u64).div_ceil(self.fs.cluster_size as u64)
+                * (self.fs.cluster_size as u64 / 512)) as i64,
+            ..Default::default()
+        })
+    }
+
+    fn size(&self) -> u64 {
+        self.meta.lock().size as u64
+    }
+
+    fn lookup(&self, name: &str) -> Result<InodeRef> {
+        let _lock = self.fs.lock.lock();
+        let (chain, entries) = self.entries()?;
+        let entry = entries
+            .iter()
+            .find(|entry| entry.matches(name))
+            .ok_or(Errno::ENOENT)?;
+        Ok(self.child(&chain, entry))
+    }
+
+    fn create(&self, name: &str, file_type: FileType, mode: u32) -> Result<InodeRef> {
+        if name == "." || name == ".." {
+            return Err(Errno::EEXIST.into());
+        }
+        if name.contains(INVALID_CHARS) || name.chars().any(|c| c < ' ') {
+            return Err(Errno::EINVAL.into());
+        }
+        let is_dir = match file_type {
+            FileType::Regular => false,
+            FileType::Directory => true,
+            _ => return Err(Errno::EPERM.into()),
+        };
+
+        let _lock = self.fs.lock.lock();
+        if self.meta.lock().unlinked {
+            return Err(Errno::ENOENT.into());
+        }
+        let (_, entries) = self.entries()?;
+        if entries.iter().any(|entry| entry.matches(name)) {
+            return Err(Errno::EEXIST.into());
+        }
+        let exists = |short: &[u8; 11]| entries.iter().any(|entry| entry.short == *short);
+        let (short, lfn) = match exact_short_name(name).filter(|short| !exists(short)) {
+            Some(short) => (short, Vec::new()),
+            None => {
+                let short = generate_short_name(name, exists).ok_or(Errno::ENOSPC)?;
+                (short, lfn_entries(name, &short).ok_or(Errno::ENAMETOOLONG)?)
+            }
+        };
+
+        let mut attr = if is_dir { ATTR_DIRECTORY } else { ATTR_ARCHIVE };
+        if mode & 0o222 == 0 {
+            attr |= ATTR_READ_ONLY;
+        }
+        // 新目录需要一个簇存放"."与".."
+        let cluster = if is_dir {
+            let cluster = self.fs.alloc_cluster(None)?;
+            let parent = match self.dirent_pos {
+                Some(_) => self.meta.lock().cluster,
+                None => FAT_FREE,
+            };
+            let dot = short_entry(&exact_short_name(".").unwrap(), ATTR_DIRECTORY, cluster, 0);
+            let dotdot = short_entry(&exact_short_name("..").unwrap(), ATTR_DIRECTORY, parent, 0);
+            let offset = self.fs.cluster_offset(cluster);
+            self.fs.write_bytes(offset, &dot)?;
+            self.fs.write_bytes(offset + DIRENT_SIZE as u64, &dotdot)?;
+            cluster
+        } else {
+            FAT_FREE
+        };
+
+        let mut raw = lfn;
+        raw.push(short_entry(&short, attr, cluster, 0));
+        if let Err(err) = self.insert_entries(&raw) {
+            if cluster != FAT_FREE {
+                let _ = self.fs.free_chain(cluster);
+            }
+            return Err(err);
+        }
+
+        let (chain, entries) = self.entries()?;
+        let entry = entries
+            .iter()
+            .find(|entry| entry.short == short)
+            .ok_or(Errno::EIO)?;
+        Ok(self.child(&chain, entry))
+    }
+
+    // 仍然打开着的文件可以继续读写，簇链在最后一个引用释放时才释放
+    fn unlink(&self, name: &str) -> Result<()> {
+        let child = {
+            let _lock = self.fs.lock.lock();
+            let (chain, entries) = self.entries()?;
+            let entry = entries
+                .iter()
+                .find(|entry| entry.matches(name))
+                .ok_or(Errno::ENOENT)?;
+            if entry.is_dir() && !self.is_empty_dir(entry.cluster)? {
+                return Err(Errno::ENOTEMPTY.into());
+            }
+
+            let child = self.child(&chain, entry);
+            // 先删除目录项，崩溃时最多丢失一些簇
+            let first = entry.pos - entry.lfn_count * DIRENT_SIZE;
+            for pos in (first..=entry.pos).step_by(DIRENT_SIZE) {
+                self.fs
+                    .write_bytes(self.disk_pos(&chain, pos), &[ENTRY_DELETED])?;
+            }
+            child.meta.lock().unlinked = true;
+            if let Some(pos) = child.dirent_pos {
+                self.fs.unhash(pos, &child);
+            }
+            child
+        };
+        // 最后一个引用在释放fs.lock之后丢弃
+        drop(child);
+        Ok(())
+    }
+
+    fn readdir(&self, index: usize) -> Result<Option<DirEntry>> {
+        let _lock = self.fs.lock.lock();
+        let (chain, entries) = self.entries()?;
+        Ok(entries.get(index).map(|entry| DirEntry {
+            ino: self.disk_pos(&chain, entry.pos) / DIRENT_SIZE as u64,
+            name: entry.name.clone(),
+            file_type: if entry.is_dir() {
+                FileType::Directory
+            } else {
+                FileType::Regular
+            },
+        }))
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let _lock = self.fs.lock.lock();
+        let (cluster, size) = {
+            let meta = self.meta.lock();
+            if meta.attr & ATTR_DIRECTORY != 0 {
+                return Err(Errno::EISDIR.into());
+            }
+            (meta.cluster, meta.size as u64)
+        };
+        if offset >= size {
+            return Ok(0);
+        }
+        let n = (buf.len() as u64).min(size - offset) as usize;
+        let chain = self.fs.chain(cluster)?;
+        let csize = self.cluster_size();
+        let mut done = 0;
+        while done < n {
+            let pos = offset as usize + done;
+            let len = (csize - pos % csize).min(n - done);
+            let cluster = *chain.get(pos / csize).ok_or(Errno::EIO)?;
+            let disk = self.fs.cluster_offset(cluster) + (pos % csize) as u64;
+            self.fs.read_bytes(disk, &mut buf[done..done + len])?;
+            done += len;
+        }
+        Ok(n)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize> {
+        let end = offset + buf.len() as u64;
+        if end > u32::MAX as u64 {
+            return Err(Errno::EFBIG.into());
+        }
+        let _lock = self.fs.lock.lock();
+        let mut meta = self.meta.lock();
+        if meta.attr & ATTR_DIRECTORY != 0 {
+            return Err(Errno::EISDIR.into());
+        }
+        let chain = self.grow(&mut meta, end)?;
+        let csize = self.cluster_size();
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset as usize + done;
+            let len = (csize - pos % csize).min(buf.len() - done);
+            let disk = self.fs.cluster_offset(chain[pos / csize]) + (pos % csize) as u64;
+            self.fs.write_bytes(disk, &buf[done..done + len])?;
+            done += len;
+        }
+        meta.size = meta.size.max(end as u32);
+        self.sync_dirent(&meta)?;
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, size: u64) -> Result<()> {
+        if size > u32::MAX as u64 {
+            return Err(Errno::EFBIG.into());
+        }
+        let _lock = self.fs.lock.lock();
+        let mut meta = self.meta.lock();
+        if meta.attr & ATTR_DIRECTORY != 0 {
+            return Err(Errno::EISDIR.into());
+        }
+        if size > meta.size as u64 {
+            self.grow(&mut meta, size)?;
+        } else {
+            let keep = (size as usize).div_ceil(self.cluster_size());
+            if keep == 0 && meta.cluster != FAT_FREE {
+                self.fs.free_chain(meta.cluster)?;
+                meta.cluster = FAT_FREE;
+            } else {
+                self.fs.truncate_chain(meta.cluster, keep)?;
+            }
+        }
+        meta.size = size as u32;
+        self.sync_dirent(&meta)
+    }
+}
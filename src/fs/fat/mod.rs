@@ -0,0 +1,369 @@
+mod dir;
+mod inode;
+
+use super::{
+    def::StatFs,
+    vfs::{FileSystem, InodeRef, SuperBlock},
+};
+use crate::{
+    block::{
+        bcache::{bflush, bread, bread_bytes, bwrite_bytes, BSIZE},
+        block_device, DevId,
+    },
+    error::{Errno, ErrorTrace, Result},
+    sched::sleeplock::SleepLock,
+};
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use inode::FatInode;
+use xx_mutex_lock::Mutex;
+use xxos_log::{info, warn};
+
+// FAT表项只用低28位
+const FAT_MASK: u32 = 0x0fff_ffff;
+const FAT_FREE: u32 = 0;
+const FAT_BAD: u32 = 0x0fff_fff7;
+// end of chain
+const FAT_EOC: u32 = 0x0fff_ffff;
+// 第一个数据簇的编号
+const FIRST_CLUSTER: u32 = 2;
+
+const FSINFO_LEAD_SIG: u32 = 0x4161_5252;
+const FSINFO_STRUC_SIG: u32 = 0x6141_7272;
+
+#[inline]
+fn le16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+#[inline]
+fn le32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+#[inline]
+fn is_eoc(entry: u32) -> bool {
+    entry >= 0x0fff_fff8
+}
+
+// FAT32 file system
+// 按字节偏移访问卷，底层经过buffer cache，写操作直接写回磁盘
+pub struct FatFs {
+    dev: DevId,
+    cluster_size: usize,
+    fat_start: u64,
+    fat_size: u64,
+    num_fats: u32,
+    data_start: u64,
+    // 数据簇的编号范围是[2, max_cluster]
+    max_cluster: u32,
+    root_cluster: u32,
+    fsinfo: Option<u64>,
+    // 修改过FAT表之后FSInfo中的空闲簇数不再可信
+    fsinfo_stale: AtomicBool,
+    next_free: AtomicU32,
+    // 所有改变目录与FAT表的操作都持有这把锁
+    lock: SleepLock<()>,
+    // 按短文件名项在卷上的偏移索引
+    inodes: Mutex<BTreeMap<u64, Weak<FatInode>>>,
+}
+
+impl FatFs {
+    fn read_bytes(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        bread_bytes(self.dev, offset, buf)
+    }
+
+    fn write_bytes(&self, offset: u64, data: &[u8]) -> Result<()> {
+        bwrite_bytes(self.dev, offset, data)
+    }
+
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        self.data_start + (cluster - FIRST_CLUSTER) as u64 * self.cluster_size as u64
+    }
+
+    fn check_cluster(&self, cluster: u32) -> Result<()> {
+        if (FIRST_CLUSTER..=self.max_cluster).contains(&cluster) {
+            Ok(())
+        } else {
+            warn!("fat: bad cluster {:#x}", cluster);
+            Err(Errno::EIO.into())
+        }
+    }
+
+    fn fat_get(&self, cluster: u32) -> Result<u32> {
+        let mut entry = [0; 4];
+        self.read_bytes(self.fat_start + cluster as u64 * 4, &mut entry)?;
+        Ok(u32::from_le_bytes(entry) & FAT_MASK)
+    }
+
+    // 写入所有的FAT副本，保留高4位
+    fn fat_set(&self, cluster: u32, value: u32) -> Result<()> {
+        for i in 0..self.num_fats as u64 {
+            let offset = self.fat_start + i * self.fat_size + cluster as u64 * 4;
+            let mut entry = [0; 4];
+            self.read_bytes(offset, &mut entry)?;
+            let value = (u32::from_le_bytes(entry) & !FAT_MASK) | (value & FAT_MASK);
+            self.write_bytes(offset, &value.to_le_bytes())?;
+        }
+        self.invalidate_fsinfo()
+    }
+
+    // 簇链上的所有簇，first为0表示空链
+    fn chain(&self, first: u32) -> Result<Vec<u32>> {
+        let mut clusters = Vec::new();
+        let mut cluster = first;
+        while cluster != FAT_FREE {
+            self.check_cluster(cluster)?;
+            // 链比簇的总数还长说明有环
+            if clusters.len() > self.max_cluster as usize {
+                return Err(Errno::EIO.into());
+            }
+            clusters.push(cluster);
+            cluster = match self.fat_get(cluster)? {
+                next if is_eoc(next) => break,
+                FAT_BAD | FAT_FREE => return Err(Errno::EIO.into()),
+                next => next,
+            };
+        }
+        Ok(clusters)
+    }
+
+    fn zero_cluster(&self, cluster: u32) -> Result<()> {
+        self.write_bytes(self.cluster_offset(cluster), &vec![0; self.cluster_size])
+    }
+
+    // 分配一个清零的簇，接在prev之后
+    fn alloc_cluster(&self, prev: Option<u32>) -> Result<u32> {
+        let count = self.max_cluster - FIRST_CLUSTER + 1;
+        let start = self.next_free.load(Ordering::Relaxed);
+        for i in 0..count {
+            let cluster = FIRST_CLUSTER + (start - FIRST_CLUSTER + i) % count;
+            if self.fat_get(cluster)? != FAT_FREE {
+                continue;
+            }
+            self.fat_set(cluster, FAT_EOC)?;
+            self.zero_cluster(cluster)?;
+            if let Some(prev) = prev {
+                self.fat_set(prev, cluster)?;
+            }
+            self.next_free.store(cluster, Ordering::Relaxed);
+            return Ok(cluster);
+        }
+        Err(Errno::ENOSPC.into())
+    }
+
+    fn free_chain(&self, first: u32) -> Result<()> {
+        for cluster in self.chain(first)? {
+            self.fat_set(cluster, FAT_FREE)?;
+        }
+        Ok(())
+    }
+
+    // 只保留簇链的前keep个簇
+    fn truncate_chain(&self, first: u32, keep: usize) -> Result<()> {
+        let clusters = self.chain(first)?;
+        if keep >= clusters.len() {
+            return Ok(());
+        }
+        if keep > 0 {
+            self.fat_set(clusters[keep - 1], FAT_EOC)?;
+        }
+        for &cluster in &clusters[keep..] {
+            self.fat_set(cluster, FAT_FREE)?;
+        }
+        Ok(())
+    }
+
+    // FSInfo中的空闲簇数与下一个空闲簇只是提示，写FAT表之后标记为未知
+    fn invalidate_fsinfo(&self) -> Result<()> {
+        let Some(fsinfo) = self.fsinfo else {
+            return Ok(());
+        };
+        if self.fsinfo_stale.swap(true, Ordering::Relaxed) {
+            return Ok(());
+        }
+        // FSI_Free_Count and FSI_Nxt_Free
+        self.write_bytes(fsinfo + 488, &[0xff; 8])
+    }
+
+    fn count_free(&self) -> Result<u64> {
+        let mut free = 0;
+        let mut entries = vec![0; BSIZE];
+        let mut cluster = 0u32;
+        while cluster <= self.max_cluster {
+            let n = ((self.max_cluster - cluster + 1) as usize).min(BSIZE / 4);
+            self.read_bytes(self.fat_start + cluster as u64 * 4, &mut entries[..n * 4])?;
+            free += (0..n)
+                .filter(|&i| {
+                    let c = cluster + i as u32;
+                    c >= FIRST_CLUSTER && le32(&entries, i * 4) & FAT_MASK == FAT_FREE
+                })
+                .count() as u64;
+            cluster += n as u32;
+        }
+        Ok(free)
+    }
+
+    // 找到目录项对应的内存inode，同一个文件只有一个FatInode
+    fn iget(self: &Arc<Self>, dirent_pos: u64, create: impl FnOnce() -> FatInode) -> Arc<FatInode> {
+        let mut inodes = self.inodes.lock();
+        if let Some(inode) = inodes.get(&dirent_pos).and_then(Weak::upgrade) {
+            return inode;
+        }
+        let inode = Arc::new(create());
+        inodes.insert(dirent_pos, Arc::downgrade(&inode));
+        inode
+    }
+
+    // 目录项被删除，之后在同一位置新建的文件不能再找到旧的inode
+    fn unhash(&self, dirent_pos: u64, inode: &Arc<FatInode>) {
+        let mut inodes = self.inodes.lock();
+        if inodes
+            .get(&dirent_pos)
+            .is_some_and(|cached| cached.ptr_eq(&Arc::downgrade(inode)))
+        {
+            inodes.remove(&dirent_pos);
+        }
+    }
+
+    fn forget(&self, dirent_pos: u64) {
+        let mut inodes = self.inodes.lock();
+        if inodes
+            .get(&dirent_pos)
+            .is_some_and(|inode| inode.strong_count() == 0)
+        {
+            inodes.remove(&dirent_pos);
+        }
+    }
+}
+
+pub struct FatSuperBlock {
+    fs: Arc<FatFs>,
+    root: Arc<FatInode>,
+}
+
+impl SuperBlock for FatSuperBlock {
+    fn root(&self) -> InodeRef {
+        self.root.clone()
+    }
+
+    fn statfs(&self) -> Result<StatFs> {
+        let _lock = self.fs.lock.lock();
+        Ok(StatFs {
+            block_size: self.fs.cluster_size,
+            blocks: (self.fs.max_cluster - FIRST_CLUSTER + 1) as u64,
+            free_blocks: self.fs.count_free()?,
+            files: 0,
+            free_files: 0,
+            name_max: 255,
+        })
+    }
+
+    fn sync(&self) -> Result<()> {
+        bflush(self.fs.dev)
+    }
+}
+
+pub struct FatType;
+
+impl FileSystem for FatType {
+    fn name(&self) -> &'static str {
+        "vfat"
+    }
+
+    fn mount(&self, dev: Option<DevId>, _options: &str) -> Result<Arc<dyn SuperBlock>> {
+        let dev = dev.ok_or_else(|| ErrorTrace::from_errno(Errno::ENODEV))?;
+        let device = block_device(dev).ok_or_else(|| ErrorTrace::from_errno(Errno::ENXIO))?;
+        let boot = bread(dev, 0)?;
+        if boot[510..512] != [0x55, 0xaa] {
+            return Err(Errno::EINVAL.into());
+        }
+
+        // BIOS parameter block
+        let bytes_per_sec = le16(&boot[..], 11) as u64;
+        let sec_per_clus = boot[13] as u64;
+        let rsvd_sec_cnt = le16(&boot[..], 14) as u64;
+        let num_fats = boot[16] as u32;
+        let root_ent_cnt = le16(&boot[..], 17);
+        let tot_sec16 = le16(&boot[..], 19) as u64;
+        let fat_sz16 = le16(&boot[..], 22);
+        let tot_sec32 = le32(&boot[..], 32) as u64;
+        let fat_sz32 = le32(&boot[..], 36) as u64;
+        let root_cluster = le32(&boot[..], 44);
+        let fsinfo_sec = le16(&boot[..], 48) as u64;
+        drop(boot);
+
+        if !bytes_per_sec.is_power_of_two()
+            || !(512..=4096).contains(&bytes_per_sec)
+            || !sec_per_clus.is_power_of_two()
+            || num_fats == 0
+            || rsvd_sec_cnt == 0
+        {
+            return Err(Errno::EINVAL.into());
+        }
+        // FAT12与FAT16的根目录区域大小不为0
+        if root_ent_cnt != 0 || fat_sz16 != 0 || fat_sz32 == 0 {
+            info!("fat: {} is not FAT32", device.name());
+            return Err(Errno::EINVAL.into());
+        }
+
+        let total_sectors = if tot_sec16 != 0 { tot_sec16 } else { tot_sec32 };
+        let data_sector = rsvd_sec_cnt + num_fats as u64 * fat_sz32;
+        let volume_size = device.capacity() * device.block_size() as u64;
+        if data_sector >= total_sectors || total_sectors * bytes_per_sec > volume_size {
+            warn!("fat: bad BPB on {}", device.name());
+            return Err(Errno::EINVAL.into());
+        }
+        let clusters = (total_sectors - data_sector) / sec_per_clus;
+        // FAT表能容纳的簇数也限制了簇号
+        let fat_entries = fat_sz32 * bytes_per_sec / 4;
+        let max_cluster = (clusters + 1).min(fat_entries - 1) as u32;
+
+        let fsinfo = match fsinfo_sec {
+            0 | 0xffff => None,
+            sector => {
+                let offset = sector * bytes_per_sec;
+                let (mut lead, mut struc) = ([0; 4], [0; 4]);
+                bread_bytes(dev, offset, &mut lead)?;
+                bread_bytes(dev, offset + 484, &mut struc)?;
+                let valid = u32::from_le_bytes(lead) == FSINFO_LEAD_SIG
+                    && u32::from_le_bytes(struc) == FSINFO_STRUC_SIG;
+                if !valid {
+                    warn!("fat: bad FSInfo sector on {}", device.name());
+                }
+                valid.then_some(offset)
+            }
+        };
+
+        let fs = Arc::new(FatFs {
+            dev,
+            cluster_size: (sec_per_clus * bytes_per_sec) as usize,
+            fat_start: rsvd_sec_cnt * bytes_per_sec,
+            fat_size: fat_sz32 * bytes_per_sec,
+            num_fats,
+            data_start: data_sector * bytes_per_sec,
+            max_cluster,
+            root_cluster,
+            fsinfo,
+            fsinfo_stale: AtomicBool::new(false),
+            next_free: AtomicU32::new(FIRST_CLUSTER),
+            lock: SleepLock::new(()),
+            inodes: Mutex::new(BTreeMap::new()),
+        });
+        fs.check_cluster(root_cluster)?;
+
+        let root = Arc::new(FatInode::root(fs.clone()));
+        info!(
+            "fat: FAT32 on {}, {} clusters of {} bytes",
+            device.name(),
+            max_cluster - 1,
+            fs.cluster_size
+        );
+        Ok(Arc::new(FatSuperBlock { fs, root }))
+    }
+}
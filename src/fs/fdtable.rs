@@ -0,0 +1,136 @@
+use super::vfs::FileRef;
+use crate::error::{Errno, ErrorTrace, Result};
+use alloc::vec::Vec;
+
+// 每个进程最多打开的文件数
+pub const NOFILE: usize = 64;
+
+#[derive(Clone)]
+struct FdEntry {
+    file: FileRef,
+    cloexec: bool,
+}
+
+// File descriptor table
+// 文件描述符是files的下标，dup出来的描述符共享同一个打开的文件(以及其中的偏移量)
+#[derive(Clone, Default)]
+pub struct FdTable {
+    files: Vec<Option<FdEntry>>,
+}
+
+impl FdTable {
+    pub const fn new() -> Self {
+        Self { files: Vec::new() }
+    }
+
+    // 分配不小于min的最小空闲描述符
+    fn alloc_from(&mut self, min: usize, file: FileRef, cloexec: bool) -> Result<usize> {
+        let fd = (min..NOFILE)
+            .find(|&fd| !matches!(self.files.get(fd), Some(Some(_))))
+            .ok_or_else(|| ErrorTrace::from_errno(Errno::EMFILE))?;
+        if fd >= self.files.len() {
+            self.files.resize(fd + 1, None);
+        }
+        self.files[fd] = Some(FdEntry { file, cloexec });
+        Ok(fd)
+    }
+
+    pub fn alloc(&mut self, file: FileRef, cloexec: bool) -> Result<usize> {
+        self.alloc_from(0, file, cloexec)
+    }
+
+    pub fn get(&self, fd: usize) -> Result<FileRef> {
+        self.files
+            .get(fd)
+            .and_then(|entry| entry.as_ref())
+            .map(|entry| entry.file.clone())
+            .ok_or_else(|| ErrorTrace::from_errno(Errno::EBADF))
+    }
+
+    pub fn close(&mut self, fd: usize) -> Result<FileRef> {
+        let entry = self
+            .files
+            .get_mut(fd)
+            .and_then(|entry| entry.take())
+            .ok_or_else(|| ErrorTrace::from_errno(Errno::EBADF))?;
+        while self.files.last().is_some_and(|entry| entry.is_none()) {
+            self.files.pop();
+        }
+        Ok(entry.file)
+    }
+
+    // 新描述符的close-on-exec总是清除的
+    pub fn dup(&mut self, fd: usize) -> Result<usize> {
+        let file = self.get(fd)?;
+        self.alloc(file, false)
+    }
+
+    // 返回被替换的文件，调用者在释放文件表的锁之后丢弃它
+    pub fn dup2(&mut self, old: usize, new: usize) -> Result<(usize, Option<FileRef>)> {
+        let file = self.get(old)?;
+        if new >= NOFILE {
+            return Err(Errno::EBADF.into());
+        }
+        if old == new {
+            return Ok((new, None));
+        }
+        if new >= self.files.len() {
+            self.files.resize(new + 1, None);
+        }
+        let replaced = self.files[new].replace(FdEntry {
+            file,
+            cloexec: false,
+        });
+        Ok((new, replaced.map(|entry| entry.file)))
+    }
+
+    pub fn set_cloexec(&mut self, fd: usize, cloexec: bool) -> Result<()> {
+        let entry = self
+            .files
+            .get_mut(fd)
+            .and_then(|entry| entry.as_mut())
+            .ok_or_else(|| ErrorTrace::from_errno(Errno::EBADF))?;
+        entry.cloexec = cloexec;
+        Ok(())
+    }
+
+    pub fn is_cloexec(&self, fd: usize) -> Result<bool> {
+        self.files
+            .get(fd)
+            .and_then(|entry| entry.as_ref())
+            .map(|entry| entry.cloexec)
+            .ok_or_else(|| ErrorTrace::from_errno(Errno::EBADF))
+    }
+
+    // fork时子进程得到一份拷贝，两边的描述符指向同一批打开的文件
+    pub fn fork(&self) -> Self {
+        self.clone()
+    }
+
+    // exec时关闭标记了close-on-exec的描述符
+    // 与close_all一样返回被关闭的文件，由调用者在锁外丢弃
+    pub fn close_on_exec(&mut self) -> Vec<FileRef> {
+        self.files
+            .iter_mut()
+            .filter(|entry| entry.as_ref().is_some_and(|e| e.cloexec))
+            .filter_map(|entry| entry.take())
+            .map(|entry| entry.file)
+            .collect()
+    }
+
+    // 进程退出时关闭所有文件
+    pub fn close_all(&mut self) -> Vec<FileRef> {
+        self.files
+            .drain(..)
+            .flatten()
+            .map(|entry| entry.file)
+            .collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &FileRef)> {
+        self.files
+            .iter()
+            .enumerate()
+            .filter_map(|(fd, entry)| entry.as_ref().map(|entry| (fd, &entry.file)))
+    }
+}
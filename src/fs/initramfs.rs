@@ -0,0 +1,155 @@
+use super::{
+    create,
+    def::{FileType, S_IFMT},
+    path::Dentry,
+    symlink,
+};
+use crate::error::{Errno, Result};
+use alloc::{collections::BTreeMap, format, sync::Arc, vec::Vec};
+use xxos_log::{info, warn};
+
+// 编译时打包进内核的cpio归档，由build.rs根据XXOS_INITRAMFS生成，没有指定时为空
+static INITRAMFS: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/initramfs.cpio"));
+
+// newc格式，070702与070701相同，只是多了校验和
+const NEWC_MAGIC: &[u8] = b"070701";
+const NEWC_CRC_MAGIC: &[u8] = b"070702";
+// 6字节magic与13个8位十六进制字段
+const HEADER_SIZE: usize = 110;
+const TRAILER: &str = "TRAILER!!!";
+
+pub fn initramfs() -> &'static [u8] {
+    INITRAMFS
+}
+
+struct CpioEntry<'a> {
+    name: &'a str,
+    ino: u32,
+    mode: u32,
+    nlink: u32,
+    data: &'a [u8],
+}
+
+struct CpioReader<'a> {
+    archive: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> CpioReader<'a> {
+    fn new(archive: &'a [u8]) -> Self {
+        Self { archive, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(len).ok_or(Errno::EINVAL)?;
+        let bytes = self.archive.get(self.pos..end).ok_or(Errno::EINVAL)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    // 头部加文件名，以及文件数据，都补齐到4字节
+    fn align(&mut self) {
+        self.pos = self.pos.next_multiple_of(4).min(self.archive.len());
+    }
+
+    // 读出下一项，遇到TRAILER!!!时返回None
+    fn next_entry(&mut self) -> Result<Option<CpioEntry<'a>>> {
+        let header = self.take(HEADER_SIZE)?;
+        if &header[..6] != NEWC_MAGIC && &header[..6] != NEWC_CRC_MAGIC {
+            return Err(Errno::EINVAL.into());
+        }
+        let field = |index: usize| -> Result<u32> {
+            let hex = &header[6 + index * 8..6 + (index + 1) * 8];
+            let hex = core::str::from_utf8(hex).map_err(|_| Errno::EINVAL)?;
+            Ok(u32::from_str_radix(hex, 16).map_err(|_| Errno::EINVAL)?)
+        };
+        let ino = field(0)?;
+        let mode = field(1)?;
+        let nlink = field(4)?;
+        let filesize = field(6)? as usize;
+        let namesize = field(11)? as usize;
+
+        // 文件名包括结尾的NUL
+        let name = self.take(namesize)?;
+        let name = name.strip_suffix(b"\0").ok_or(Errno::EINVAL)?;
+        let name = core::str::from_utf8(name).map_err(|_| Errno::EINVAL)?;
+        self.align();
+        if name == TRAILER {
+            return Ok(None);
+        }
+        let data = self.take(filesize)?;
+        self.align();
+        Ok(Some(CpioEntry {
+            name,
+            ino,
+            mode,
+            nlink,
+            data,
+        }))
+    }
+}
+
+// 在当前的根文件系统中创建一项，返回普通文件的目录项
+fn extract(path: &str, entry: &CpioEntry) -> Result<Option<Arc<Dentry>>> {
+    let perm = entry.mode & 0o7777;
+    match FileType::from_mode(entry.mode & S_IFMT) {
+        Some(FileType::Directory) => match create(path, FileType::Directory, perm) {
+            Err(err) if err.errno() == Some(Errno::EEXIST) => Ok(None),
+            result => result.map(|_| None),
+        },
+        Some(FileType::Regular) => {
+            let dentry = create(path, FileType::Regular, perm)?;
+            dentry.inode().write_at(0, entry.data)?;
+            Ok(Some(dentry))
+        }
+        Some(FileType::Symlink) => {
+            let target = core::str::from_utf8(entry.data).map_err(|_| Errno::EINVAL)?;
+            symlink(target, path).map(|_| None)
+        }
+        // 设备文件由devfs提供
+        _ => {
+            warn!("initramfs: skip special file {}", path);
+            Ok(None)
+        }
+    }
+}
+
+// 把归档解开到根目录下，已有的目录会被复用
+// 单个文件失败时跳过，归档格式错误时返回EINVAL
+pub fn unpack(archive: &[u8]) -> Result<usize> {
+    let mut reader = CpioReader::new(archive);
+    // 硬链接的数据只保存在最后一项中，之前的各项先创建为空文件
+    let mut links: BTreeMap<u32, Vec<Arc<Dentry>>> = BTreeMap::new();
+    let mut count = 0;
+    while let Some(entry) = reader.next_entry()? {
+        let name = entry.name.trim_start_matches('/');
+        let name = name.strip_prefix("./").unwrap_or(name);
+        if name.is_empty() || name == "." {
+            continue;
+        }
+        let path = format!("/{}", name);
+        let dentry = match extract(&path, &entry) {
+            Ok(dentry) => dentry,
+            Err(err) => {
+                warn!("initramfs: failed to extract {}: {}", path, err);
+                continue;
+            }
+        };
+        count += 1;
+
+        if let Some(dentry) = dentry.filter(|_| entry.nlink > 1) {
+            if entry.data.is_empty() {
+                links.entry(entry.ino).or_default().push(dentry);
+            } else {
+                // 硬链接目前解开为内容相同的多个文件
+                for link in links.remove(&entry.ino).unwrap_or_default() {
+                    if let Err(err) = link.inode().write_at(0, entry.data) {
+                        warn!("initramfs: failed to extract {}: {}", path, err);
+                    }
+                }
+            }
+        }
+    }
+    info!("initramfs: unpacked {} entries", count);
+    Ok(count)
+}
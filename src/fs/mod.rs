@@ -1 +1,364 @@
+pub mod def;
+pub mod devfs;
+pub mod ext2;
+pub mod fat;
+pub mod fdtable;
+pub mod initramfs;
+pub mod mount;
+pub mod pagecache;
+pub mod path;
+pub mod perm;
+pub mod pipe;
+pub mod procfs;
+pub mod tmpfs;
+pub mod vfs;
+pub mod xxfs;
 
+use crate::{
+    block::find_block_device,
+    error::{Errno, Result},
+    proc::cred::current_cred,
+};
+use alloc::{string::String, sync::Arc, vec::Vec};
+use def::{
+    flags_writable, DirEntry, FileType, Stat, O_CREAT, O_DIRECTORY, O_EXCL, O_TRUNC, S_ISGID,
+    S_ISUID,
+};
+use path::{
+    dcache_invalidate, dcache_lookup, dcache_purge_mount, lookup, lookup_nofollow, lookup_parent,
+    Dentry,
+};
+use perm::{check_owner, may_delete, may_open, permission, MAY_EXEC, MAY_WRITE};
+use vfs::{new_file, FileRef, InodeFile, InodeRef};
+use xxos_log::warn;
+
+// 注册文件系统并挂载根文件系统，需要在块设备初始化之后调用
+pub fn fs_init() {
+    mount::register_filesystem(Arc::new(xxfs::XxfsType));
+    mount::register_filesystem(Arc::new(fat::FatType));
+    mount::register_filesystem(Arc::new(ext2::Ext2Type));
+    mount::register_filesystem(Arc::new(tmpfs::TmpfsType));
+    mount::register_filesystem(Arc::new(devfs::DevfsType));
+    mount::register_filesystem(Arc::new(procfs::ProcfsType));
+    devfs::devfs_init();
+
+    // 内核中打包了initramfs时，把它解开到tmpfs中作为根文件系统，不再使用磁盘
+    let archive = initramfs::initramfs();
+    let mut mounted = false;
+    if !archive.is_empty() && mount_tmpfs_root() {
+        if let Err(err) = initramfs::unpack(archive) {
+            warn!("fs: bad initramfs: {}", err);
+        }
+        mounted = true;
+    }
+
+    // 根文件系统在整个磁盘或者它的第一个分区上，依次尝试每种文件系统
+    let filesystems = mount::filesystems();
+    let mounted = mounted
+        || ["vda", "vda1"]
+            .iter()
+            .filter_map(|name| find_block_device(name))
+            .any(|(dev, _)| {
+                filesystems
+                    .iter()
+                    .filter(|fs| fs.requires_device())
+                    .any(|fs| mount::mount(fs.name(), Some(dev), "/", "").is_ok())
+            });
+    // 没有磁盘时使用内存中的根文件系统
+    if !mounted {
+        warn!("fs: no root filesystem found on disk, using tmpfs");
+        if !mount_tmpfs_root() {
+            return;
+        }
+    }
+
+    // 磁盘上的根文件系统没有对应的目录时不挂载
+    for (fs_name, path) in [("devfs", "/dev"), ("proc", "/proc"), ("tmpfs", "/tmp")] {
+        if !lookup(path).is_ok_and(|dentry| dentry.inode().is_dir()) {
+            continue;
+        }
+        if let Err(err) = mount::mount(fs_name, None, path, "") {
+            warn!("fs: failed to mount {} on {}: {}", fs_name, path, err);
+        }
+    }
+}
+
+// 以tmpfs作为根文件系统，并创建挂载点
+fn mount_tmpfs_root() -> bool {
+    if let Err(err) = mount::mount("tmpfs", None, "/", "") {
+        warn!("fs: failed to mount tmpfs as root: {}", err);
+        return false;
+    }
+    let _ = create("/dev", FileType::Directory, 0o755);
+    let _ = create("/proc", FileType::Directory, 0o555);
+    let _ = create("/tmp", FileType::Directory, 0o1777);
+    true
+}
+
+// 按路径操作文件的接口，系统调用在此之上实现
+
+pub fn open(path: &str, flags: u32, mode: u32) -> Result<FileRef> {
+    let dentry = match lookup(path) {
+        Ok(_) if flags & O_CREAT != 0 && flags & O_EXCL != 0 => {
+            return Err(Errno::EEXIST.into());
+        }
+        Ok(dentry) => {
+            may_open(dentry.inode(), &current_cred(), flags)?;
+            dentry
+        }
+        // 刚创建的文件不检查权限，以只读权限创建的文件也可以写入
+        Err(err) if flags & O_CREAT != 0 && err.errno() == Some(Errno::ENOENT) => {
+            create(path, FileType::Regular, mode)?
+        }
+        Err(err) => return Err(err),
+    };
+    open_dentry(&dentry, flags)
+}
+
+pub fn open_dentry(dentry: &Arc<Dentry>, flags: u32) -> Result<FileRef> {
+    let inode = dentry.inode();
+    if inode.is_dir() {
+        if flags_writable(flags) {
+            return Err(Errno::EISDIR.into());
+        }
+    } else if flags & O_DIRECTORY != 0 {
+        return Err(Errno::ENOTDIR.into());
+    }
+    if flags & O_TRUNC != 0 && flags_writable(flags) && inode.file_type() == FileType::Regular {
+        inode.truncate(0)?;
+    }
+    match inode.open(flags) {
+        Some(file) => file,
+        None => new_file(InodeFile::new(dentry.clone(), flags)),
+    }
+}
+
+// 在父目录中创建文件或目录，已经存在时返回EEXIST
+pub fn create(path: &str, file_type: FileType, mode: u32) -> Result<Arc<Dentry>> {
+    let (parent, name) = lookup_parent(path)?;
+    if dcache_lookup(&parent, &name).is_ok() {
+        return Err(Errno::EEXIST.into());
+    }
+    permission(parent.inode(), &current_cred(), MAY_WRITE | MAY_EXEC)?;
+    parent.inode().create(&name, file_type, mode)?;
+    dcache_lookup(&parent, &name)
+}
+
+pub fn symlink(target: &str, path: &str) -> Result<Arc<Dentry>> {
+    let (parent, name) = lookup_parent(path)?;
+    if dcache_lookup(&parent, &name).is_ok() {
+        return Err(Errno::EEXIST.into());
+    }
+    permission(parent.inode(), &current_cred(), MAY_WRITE | MAY_EXEC)?;
+    parent.inode().symlink(&name, target)?;
+    dcache_lookup(&parent, &name)
+}
+
+pub fn mkdir(path: &str, mode: u32) -> Result<()> {
+    create(path, FileType::Directory, mode).map(|_| ())
+}
+
+pub fn rmdir(path: &str) -> Result<()> {
+    let (parent, name) = lookup_parent(path)?;
+    match name.as_str() {
+        "." => return Err(Errno::EINVAL.into()),
+        ".." => return Err(Errno::ENOTEMPTY.into()),
+        _ => {}
+    }
+    let dentry = dcache_lookup(&parent, &name)?;
+    if !dentry.inode().is_dir() {
+        return Err(Errno::ENOTDIR.into());
+    }
+    // 不能删除挂载点
+    if dentry.mount() != parent.mount() {
+        return Err(Errno::EBUSY.into());
+    }
+    may_delete(parent.inode(), dentry.inode(), &current_cred())?;
+    parent.inode().unlink(&name)?;
+    dcache_invalidate(&parent, &name);
+    Ok(())
+}
+
+pub fn unlink(path: &str) -> Result<()> {
+    let (parent, name) = lookup_parent(path)?;
+    let dentry = dcache_lookup(&parent, &name)?;
+    if dentry.mount() != parent.mount() {
+        return Err(Errno::EBUSY.into());
+    }
+    // 目录由rmdir删除
+    if dentry.inode().is_dir() {
+        return Err(Errno::EISDIR.into());
+    }
+    may_delete(parent.inode(), dentry.inode(), &current_cred())?;
+    parent.inode().unlink(&name)?;
+    dcache_invalidate(&parent, &name);
+    Ok(())
+}
+
+// 硬链接不能跨文件系统，也不能指向目录，old是符号链接时链接它本身
+pub fn link(old: &str, new: &str) -> Result<()> {
+    let target = lookup_nofollow(old)?;
+    if target.inode().is_dir() {
+        return Err(Errno::EPERM.into());
+    }
+    let (parent, name) = lookup_parent(new)?;
+    if parent.mount() != target.mount() {
+        return Err(Errno::EXDEV.into());
+    }
+    if dcache_lookup(&parent, &name).is_ok() {
+        return Err(Errno::EEXIST.into());
+    }
+    permission(parent.inode(), &current_cred(), MAY_WRITE | MAY_EXEC)?;
+    parent.inode().link(&name, target.inode())
+}
+
+// new已经存在时被替换，目录只能替换空目录
+pub fn rename(old: &str, new: &str) -> Result<()> {
+    let (old_parent, old_name) = lookup_parent(old)?;
+    let (new_parent, new_name) = lookup_parent(new)?;
+    for name in [&old_name, &new_name] {
+        if name == "." || name == ".." {
+            return Err(Errno::EINVAL.into());
+        }
+    }
+    let source = dcache_lookup(&old_parent, &old_name)?;
+    if source.mount() != old_parent.mount() {
+        return Err(Errno::EBUSY.into());
+    }
+    if new_parent.mount() != old_parent.mount() {
+        return Err(Errno::EXDEV.into());
+    }
+    let cred = current_cred();
+    may_delete(old_parent.inode(), source.inode(), &cred)?;
+    match dcache_lookup(&new_parent, &new_name) {
+        Ok(victim) if victim.mount() != new_parent.mount() => return Err(Errno::EBUSY.into()),
+        Ok(victim) => may_delete(new_parent.inode(), victim.inode(), &cred)?,
+        Err(_) => permission(new_parent.inode(), &cred, MAY_WRITE | MAY_EXEC)?,
+    }
+    // 目录不能移到它自己的子目录中，目录没有硬链接，比较ino就足够了
+    let is_dir = source.inode().is_dir();
+    if is_dir {
+        // 移到其他目录时要修改目录中的..
+        if new_parent.inode().ino() != old_parent.inode().ino() {
+            permission(source.inode(), &cred, MAY_WRITE)?;
+        }
+        let mut dentry = Some(&new_parent);
+        while let Some(dir) = dentry.filter(|dir| dir.mount() == source.mount()) {
+            if dir.inode().ino() == source.inode().ino() {
+                return Err(Errno::EINVAL.into());
+            }
+            dentry = dir.parent();
+        }
+    }
+
+    old_parent
+        .inode()
+        .rename(&old_name, new_parent.inode(), &new_name)?;
+    dcache_invalidate(&old_parent, &old_name);
+    dcache_invalidate(&new_parent, &new_name);
+    // 缓存中子目录项的父目录链已经过时
+    if is_dir {
+        dcache_purge_mount(source.mount());
+    }
+    Ok(())
+}
+
+pub fn readlink(path: &str) -> Result<String> {
+    lookup_nofollow(path)?.inode().readlink()
+}
+
+pub fn stat(path: &str) -> Result<Stat> {
+    lookup(path)?.inode().stat()
+}
+
+pub fn truncate(path: &str, size: u64) -> Result<()> {
+    let dentry = lookup(path)?;
+    if dentry.inode().is_dir() {
+        return Err(Errno::EISDIR.into());
+    }
+    dentry.inode().truncate(size)
+}
+
+pub fn readdir(path: &str) -> Result<Vec<DirEntry>> {
+    let dentry = lookup(path)?;
+    let inode = dentry.inode();
+    let mut entries = Vec::new();
+    while let Some(entry) = inode.readdir(entries.len())? {
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+pub fn read(path: &str, offset: u64, buf: &mut [u8]) -> Result<usize> {
+    lookup(path)?.inode().read_at(offset, buf)
+}
+
+pub fn write(path: &str, offset: u64, buf: &[u8]) -> Result<usize> {
+    lookup(path)?.inode().write_at(offset, buf)
+}
+
+// 只允许修改权限位，不是文件所在组的成员时去掉setgid位
+pub fn chmod(path: &str, mode: u32) -> Result<()> {
+    let dentry = lookup(path)?;
+    let inode = dentry.inode();
+    let cred = current_cred();
+    check_owner(inode, &cred)?;
+    let mut mode = mode & 0o7777;
+    if !cred.is_root() && !cred.in_group(inode.stat()?.gid) {
+        mode &= !S_ISGID;
+    }
+    inode.chmod(mode)
+}
+
+// None表示不修改，只有root可以修改所有者，所有者只能把组改为自己所在的组
+pub fn chown(path: &str, uid: Option<u32>, gid: Option<u32>) -> Result<()> {
+    let dentry = lookup(path)?;
+    let inode = dentry.inode();
+    let cred = current_cred();
+    let stat = inode.stat()?;
+    if !cred.is_root()
+        && (stat.uid != cred.uid
+            || uid.is_some_and(|uid| uid != stat.uid)
+            || gid.is_some_and(|gid| !cred.in_group(gid)))
+    {
+        return Err(Errno::EPERM.into());
+    }
+    inode.chown(uid.unwrap_or(stat.uid), gid.unwrap_or(stat.gid))?;
+    // 修改所有者后普通文件不再保留setuid与setgid位
+    if !inode.is_dir() && stat.mode & (S_ISUID | S_ISGID) != 0 {
+        inode.chmod(stat.mode & 0o7777 & !(S_ISUID | S_ISGID))?;
+    }
+    Ok(())
+}
+
+pub fn read_all(path: &str) -> Result<Vec<u8>> {
+    read_inode(lookup(path)?.inode())
+}
+
+// exec装载程序时使用，只能执行有执行权限的普通文件
+pub fn read_exec(path: &str) -> Result<Vec<u8>> {
+    let dentry = lookup(path)?;
+    let inode = dentry.inode();
+    if inode.file_type() != FileType::Regular {
+        return Err(Errno::EACCES.into());
+    }
+    permission(inode, &current_cred(), MAY_EXEC)?;
+    read_inode(inode)
+}
+
+// 读出整个文件
+fn read_inode(inode: &InodeRef) -> Result<Vec<u8>> {
+    if inode.is_dir() {
+        return Err(Errno::EISDIR.into());
+    }
+    let mut data = alloc::vec![0; inode.size() as usize];
+    let mut read = 0;
+    while read < data.len() {
+        match inode.read_at(read as u64, &mut data[read..])? {
+            0 => break,
+            len => read += len,
+        }
+    }
+    data.truncate(read);
+    Ok(data)
+}
@@ -0,0 +1,180 @@
+use super::{
+    path::{dcache_invalidate, dcache_purge_mount, lookup, Dentry},
+    vfs::{FileSystem, SuperBlock},
+};
+use crate::{
+    block::DevId,
+    error::{Errno, ErrorTrace, Result},
+};
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use xx_mutex_lock::Mutex;
+use xxos_log::info;
+
+pub type MountId = usize;
+
+pub struct Mount {
+    id: MountId,
+    fs_name: &'static str,
+    dev: Option<DevId>,
+    sb: Arc<dyn SuperBlock>,
+    // 挂载点，根文件系统没有挂载点
+    mountpoint: Option<Arc<Dentry>>,
+}
+
+impl Mount {
+    pub fn id(&self) -> MountId {
+        self.id
+    }
+
+    pub fn fs_name(&self) -> &'static str {
+        self.fs_name
+    }
+
+    pub fn dev(&self) -> Option<DevId> {
+        self.dev
+    }
+
+    pub fn super_block(&self) -> &Arc<dyn SuperBlock> {
+        &self.sb
+    }
+
+    pub fn mountpoint(&self) -> Option<&Arc<Dentry>> {
+        self.mountpoint.as_ref()
+    }
+
+    pub fn path(&self) -> String {
+        self.mountpoint
+            .as_ref()
+            .map_or_else(|| String::from("/"), |dentry| dentry.path())
+    }
+
+    pub fn root(self: &Arc<Self>) -> Arc<Dentry> {
+        let name = self
+            .mountpoint
+            .as_ref()
+            .map_or_else(|| String::from("/"), |m| String::from(m.name()));
+        Arc::new(Dentry::new(
+            name,
+            self.sb.root(),
+            self.id,
+            self.mountpoint.as_ref().and_then(|m| m.parent().cloned()),
+        ))
+    }
+}
+
+static FILESYSTEMS: Mutex<Vec<Arc<dyn FileSystem>>> = Mutex::new(Vec::new());
+static MOUNTS: Mutex<Vec<Arc<Mount>>> = Mutex::new(Vec::new());
+static NEXT_MOUNT_ID: AtomicUsize = AtomicUsize::new(0);
+
+pub fn register_filesystem(fs: Arc<dyn FileSystem>) {
+    let mut filesystems = FILESYSTEMS.lock();
+    if filesystems.iter().all(|f| f.name() != fs.name()) {
+        info!("vfs: register filesystem {}", fs.name());
+        filesystems.push(fs);
+    }
+}
+
+pub fn find_filesystem(name: &str) -> Option<Arc<dyn FileSystem>> {
+    FILESYSTEMS
+        .lock()
+        .iter()
+        .find(|fs| fs.name() == name)
+        .cloned()
+}
+
+pub fn filesystems() -> Vec<Arc<dyn FileSystem>> {
+    FILESYSTEMS.lock().clone()
+}
+
+// 挂载到path上，第一次挂载必须是根目录
+pub fn mount(fs_name: &str, dev: Option<DevId>, path: &str, options: &str) -> Result<()> {
+    let fs = find_filesystem(fs_name).ok_or_else(|| ErrorTrace::from_errno(Errno::ENODEV))?;
+    let mountpoint = if MOUNTS.lock().is_empty() {
+        if path != "/" {
+            return Err(Errno::ENOENT.into());
+        }
+        None
+    } else {
+        let dentry = lookup(path)?;
+        if !dentry.inode().is_dir() {
+            return Err(Errno::ENOTDIR.into());
+        }
+        Some(dentry)
+    };
+
+    let sb = fs.mount(dev, options)?;
+    let mount = Arc::new(Mount {
+        id: NEXT_MOUNT_ID.fetch_add(1, Ordering::Relaxed),
+        fs_name: fs.name(),
+        dev,
+        sb,
+        mountpoint,
+    });
+    // 缓存的挂载点目录项还指向被覆盖的目录
+    if let Some(parent) = mount.mountpoint.as_ref().and_then(|m| m.parent()) {
+        dcache_invalidate(parent, mount.mountpoint.as_ref().unwrap().name());
+    }
+    info!("vfs: mounted {} on {}", fs.name(), path);
+    MOUNTS.lock().push(mount);
+    Ok(())
+}
+
+pub fn umount(path: &str) -> Result<()> {
+    let dentry = lookup(path)?;
+    let mut mounts = MOUNTS.lock();
+    let index = mounts
+        .iter()
+        .rposition(|m| m.id == dentry.mount() && m.mountpoint.is_some())
+        .ok_or_else(|| ErrorTrace::from_errno(Errno::EINVAL))?;
+    let id = mounts[index].id;
+    // 上面还挂载着其他文件系统
+    if mounts
+        .iter()
+        .any(|m| m.mountpoint.as_ref().is_some_and(|d| d.mount() == id))
+    {
+        return Err(Errno::EBUSY.into());
+    }
+    mounts[index].sb.sync()?;
+    let mount = mounts.remove(index);
+    drop(mounts);
+
+    dcache_purge_mount(id);
+    if let Some(mountpoint) = mount.mountpoint.as_ref() {
+        if let Some(parent) = mountpoint.parent() {
+            dcache_invalidate(parent, mountpoint.name());
+        }
+    }
+    info!("vfs: unmounted {}", path);
+    Ok(())
+}
+
+pub fn mounts() -> Vec<Arc<Mount>> {
+    MOUNTS.lock().clone()
+}
+
+pub fn root_mount() -> Option<Arc<Mount>> {
+    MOUNTS
+        .lock()
+        .iter()
+        .find(|m| m.mountpoint.is_none())
+        .cloned()
+}
+
+// 最后挂载在(mount, ino)上的文件系统
+pub fn mounted_on(mount: MountId, ino: u64) -> Option<Arc<Mount>> {
+    MOUNTS
+        .lock()
+        .iter()
+        .rev()
+        .find(|m| {
+            m.mountpoint
+                .as_ref()
+                .is_some_and(|d| d.mount() == mount && d.inode().ino() == ino)
+        })
+        .cloned()
+}
+
+pub fn sync_all() -> Result<()> {
+    mounts().iter().try_for_each(|m| m.sb.sync())
+}
@@ -0,0 +1,188 @@
+use crate::{
+    error::{Errno, ErrorTrace, Result},
+    mm::{
+        def::PGSZ,
+        page_frame::{alloc_page, PageFrame},
+    },
+};
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+use xx_mutex_lock::Mutex;
+
+// 每个文件最多缓存的页数，超出时回收最久没有使用的干净页
+const MAX_CACHED_PAGES: usize = 256;
+
+// 文件内容的页缓存，按文件中的页号索引
+// 页帧由缓存与映射了它的地址空间共享，read与mmap看到的是同一份数据
+// 共享映射写入过的页标记为dirty，由文件系统在sync时写回
+// 缓存的一致性由文件系统保证：读入页与write更新缓存时都要持有inode的锁
+// 没有被映射也不需要写回的页可以随时丢弃，下次访问时重新读入
+pub struct PageCache {
+    pages: Mutex<BTreeMap<u64, CachedPage>>,
+    // 访问计数，用来找出最久没有使用的页
+    clock: AtomicU64,
+}
+
+struct CachedPage {
+    frame: Arc<PageFrame>,
+    dirty: bool,
+    last_use: u64,
+}
+
+impl CachedPage {
+    // 只被缓存引用(没有映射在地址空间中)且不需要写回
+    fn reclaimable(&self) -> bool {
+        !self.dirty && Arc::strong_count(&self.frame) == 1
+    }
+}
+
+impl PageCache {
+    pub const fn new() -> Self {
+        Self {
+            pages: Mutex::new(BTreeMap::new()),
+            clock: AtomicU64::new(0),
+        }
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    // 返回第index页，不在缓存中时由fill读入，fill执行时不持有缓存的锁
+    pub fn get_page(
+        &self,
+        index: u64,
+        fill: impl FnOnce(&mut [u8]) -> Result<()>,
+    ) -> Result<Arc<PageFrame>> {
+        if let Some(page) = self.pages.lock().get_mut(&index) {
+            page.last_use = self.tick();
+            return Ok(page.frame.clone());
+        }
+        // 内存不够时先丢弃自己的干净页
+        let mut frame = match alloc_page() {
+            Ok(frame) => frame,
+            Err(_) if self.reclaim() > 0 => alloc_page()?,
+            Err(err) => return Err(err.into()),
+        };
+        fill(frame.as_bytes_mut())?;
+        let frame = Arc::try_new(frame).map_err(|_| ErrorTrace::from_errno(Errno::ENOMEM))?;
+        let mut pages = self.pages.lock();
+        let last_use = self.tick();
+        let frame = pages
+            .entry(index)
+            .or_insert(CachedPage {
+                frame,
+                dirty: false,
+                last_use,
+            })
+            .frame
+            .clone();
+        if let Some(excess) = pages.len().checked_sub(MAX_CACHED_PAGES) {
+            Self::evict(&mut pages, excess);
+        }
+        Ok(frame)
+    }
+
+    // 按最近使用的时间从旧到新丢弃最多count个可以回收的页
+    fn evict(pages: &mut BTreeMap<u64, CachedPage>, count: usize) {
+        let mut victims: Vec<(u64, u64)> = pages
+            .iter()
+            .filter(|(_, page)| page.reclaimable())
+            .map(|(index, page)| (page.last_use, *index))
+            .collect();
+        victims.sort_unstable();
+        for (_, index) in victims.into_iter().take(count) {
+            pages.remove(&index);
+        }
+    }
+
+    // 丢弃所有可以回收的页，返回释放的页数
+    pub fn reclaim(&self) -> usize {
+        let mut pages = self.pages.lock();
+        let before = pages.len();
+        pages.retain(|_, page| !page.reclaimable());
+        before - pages.len()
+    }
+
+    // 经过缓存读取[offset, offset + buf.len())，调用者保证不越过文件末尾
+    // fill(index, page)读入第index页
+    pub fn read(
+        &self,
+        offset: u64,
+        buf: &mut [u8],
+        mut fill: impl FnMut(u64, &mut [u8]) -> Result<()>,
+    ) -> Result<()> {
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let index = pos / PGSZ as u64;
+            let poff = pos as usize % PGSZ;
+            let len = (PGSZ - poff).min(buf.len() - done);
+            let page = self.get_page(index, |page| fill(index, page))?;
+            buf[done..done + len].copy_from_slice(&page.as_bytes()[poff..poff + len]);
+            done += len;
+        }
+        Ok(())
+    }
+
+    // 文件被写入之后更新已经缓存的页，没有缓存的页下次读取时再读入
+    pub fn update(&self, offset: u64, data: &[u8]) {
+        let pages = self.pages.lock();
+        let mut done = 0;
+        while done < data.len() {
+            let pos = offset + done as u64;
+            let poff = pos as usize % PGSZ;
+            let len = (PGSZ - poff).min(data.len() - done);
+            if let Some(page) = pages.get(&(pos / PGSZ as u64)) {
+                page.frame.write_bytes(poff, &data[done..done + len]);
+            }
+            done += len;
+        }
+    }
+
+    // 丢弃size之后的页，最后一页中size之后的部分清零
+    // 仍然映射在地址空间中的页在映射撤销之前继续有效
+    pub fn truncate(&self, size: u64) {
+        let mut pages = self.pages.lock();
+        pages.split_off(&size.div_ceil(PGSZ as u64));
+        let tail = size as usize % PGSZ;
+        if tail != 0 {
+            if let Some(page) = pages.get(&(size / PGSZ as u64)) {
+                page.frame.zero_from(tail);
+            }
+        }
+    }
+
+    // 放入一个需要写回的页，替换缓存中已有的页
+    pub fn insert_dirty(&self, index: u64, frame: Arc<PageFrame>) {
+        let last_use = self.tick();
+        self.pages.lock().insert(
+            index,
+            CachedPage {
+                frame,
+                dirty: true,
+                last_use,
+            },
+        );
+    }
+
+    pub fn set_dirty(&self, index: u64) {
+        if let Some(page) = self.pages.lock().get_mut(&index) {
+            page.dirty = true;
+        }
+    }
+
+    // 取出需要写回的页，按页号排序
+    // 仍然被映射的页之后可能继续被写入，保留dirty标记，下一次sync时再写回
+    pub fn take_dirty(&self) -> Vec<(u64, Arc<PageFrame>)> {
+        let mut pages = self.pages.lock();
+        pages
+            .iter_mut()
+            .filter(|(_, page)| page.dirty)
+            .map(|(index, page)| {
+                page.dirty = Arc::strong_count(&page.frame) > 1;
+                (*index, page.frame.clone())
+            })
+            .collect()
+    }
+}
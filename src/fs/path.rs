@@ -0,0 +1,289 @@
+use super::{
+    def::{FileType, NAME_MAX, PATH_MAX},
+    mount::{mounted_on, root_mount, MountId},
+    perm::{permission, MAY_EXEC},
+    vfs::InodeRef,
+};
+use crate::{
+    cpu::current_task,
+    error::{Errno, ErrorTrace, Result},
+    proc::cred::current_cred,
+};
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use xx_mutex_lock::Mutex;
+
+// Directory entry
+// 路径查找的结果，记录名字、inode、所在的挂载与父目录，父目录用于拼出完整路径
+pub struct Dentry {
+    name: String,
+    inode: InodeRef,
+    mount: MountId,
+    parent: Option<Arc<Dentry>>,
+}
+
+impl Dentry {
+    pub fn new(name: String, inode: InodeRef, mount: MountId, parent: Option<Arc<Dentry>>) -> Self {
+        Self {
+            name,
+            inode,
+            mount,
+            parent,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn inode(&self) -> &InodeRef {
+        &self.inode
+    }
+
+    pub fn mount(&self) -> MountId {
+        self.mount
+    }
+
+    pub fn parent(&self) -> Option<&Arc<Dentry>> {
+        self.parent.as_ref()
+    }
+
+    pub fn path(&self) -> String {
+        let mut names = Vec::new();
+        let mut dentry = self;
+        while let Some(parent) = dentry.parent.as_ref() {
+            names.push(dentry.name.as_str());
+            dentry = parent;
+        }
+        if names.is_empty() {
+            return String::from("/");
+        }
+        names.iter().rev().fold(String::new(), |mut path, name| {
+            path.push('/');
+            path.push_str(name);
+            path
+        })
+    }
+}
+
+// Dentry cache
+// 按(mount, 父目录的ino, 名字)缓存查找结果，满了以后按插入顺序淘汰
+const DCACHE_SIZE: usize = 512;
+
+type DKey = (MountId, u64, String);
+
+struct DCache {
+    map: BTreeMap<DKey, Arc<Dentry>>,
+    order: VecDeque<DKey>,
+    hits: usize,
+    misses: usize,
+}
+
+static DCACHE: Mutex<DCache> = Mutex::new(DCache {
+    map: BTreeMap::new(),
+    order: VecDeque::new(),
+    hits: 0,
+    misses: 0,
+});
+
+fn dkey(parent: &Dentry, name: &str) -> DKey {
+    (parent.mount, parent.inode.ino(), name.to_string())
+}
+
+// 在parent目录中查找name，越过挂载点时返回被挂载文件系统的根
+// .与..直接沿着dentry的父目录走，不经过缓存，根目录的..是它自己
+pub fn dcache_lookup(parent: &Arc<Dentry>, name: &str) -> Result<Arc<Dentry>> {
+    if name.len() > NAME_MAX {
+        return Err(Errno::ENAMETOOLONG.into());
+    }
+    match name {
+        "." => return Ok(parent.clone()),
+        ".." => return Ok(parent.parent.clone().unwrap_or_else(|| parent.clone())),
+        _ => {}
+    }
+    let key = dkey(parent, name);
+    {
+        let mut dcache = DCACHE.lock();
+        if let Some(dentry) = dcache.map.get(&key).cloned() {
+            dcache.hits += 1;
+            return Ok(dentry);
+        }
+        dcache.misses += 1;
+    }
+
+    let mut inode = parent.inode.lookup(name)?;
+    let mut mount = parent.mount;
+    while let Some(mounted) = mounted_on(mount, inode.ino()) {
+        inode = mounted.super_block().root();
+        mount = mounted.id();
+    }
+    let dentry = Arc::new(Dentry::new(
+        name.to_string(),
+        inode,
+        mount,
+        Some(parent.clone()),
+    ));
+
+    // 移出缓存的目录项在释放锁之后才丢弃，最后一个引用释放inode时可能睡眠
+    let (evicted, replaced) = {
+        let mut dcache = DCACHE.lock();
+        let evicted = if dcache.order.len() >= DCACHE_SIZE {
+            dcache
+                .order
+                .pop_front()
+                .and_then(|old| dcache.map.remove(&old))
+        } else {
+            None
+        };
+        let replaced = dcache.map.insert(key.clone(), dentry.clone());
+        if replaced.is_none() {
+            dcache.order.push_back(key);
+        }
+        (evicted, replaced)
+    };
+    drop(evicted);
+    drop(replaced);
+    Ok(dentry)
+}
+
+// 目录项被删除或改名时调用
+pub fn dcache_invalidate(parent: &Dentry, name: &str) {
+    let key = dkey(parent, name);
+    let removed = {
+        let mut dcache = DCACHE.lock();
+        let removed = dcache.map.remove(&key);
+        if removed.is_some() {
+            dcache.order.retain(|k| *k != key);
+        }
+        removed
+    };
+    drop(removed);
+}
+
+pub fn dcache_purge_mount(mount: MountId) {
+    let removed: Vec<Arc<Dentry>> = {
+        let mut dcache = DCACHE.lock();
+        let keys: Vec<DKey> = dcache
+            .map
+            .keys()
+            .filter(|key| key.0 == mount)
+            .cloned()
+            .collect();
+        dcache.order.retain(|key| key.0 != mount);
+        keys.iter()
+            .filter_map(|key| dcache.map.remove(key))
+            .collect()
+    };
+    drop(removed);
+}
+
+pub fn dcache_stats() -> (usize, usize, usize) {
+    let dcache = DCACHE.lock();
+    (dcache.map.len(), dcache.hits, dcache.misses)
+}
+
+pub fn root_dentry() -> Result<Arc<Dentry>> {
+    root_mount()
+        .map(|mount| mount.root())
+        .ok_or_else(|| ErrorTrace::from_errno(Errno::ENOENT))
+}
+
+// 按'/'切分路径，忽略空的分量
+fn components(path: &str) -> Result<impl DoubleEndedIterator<Item = &str>> {
+    if path.is_empty() {
+        return Err(Errno::ENOENT.into());
+    }
+    if path.len() >= PATH_MAX {
+        return Err(Errno::ENAMETOOLONG.into());
+    }
+    Ok(path.split('/').filter(|name| !name.is_empty()))
+}
+
+// 展开符号链接的最大次数，超过时认为出现了循环
+const MAX_SYMLINKS: usize = 40;
+
+// 绝对路径从根目录开始，相对路径从当前进程的工作目录开始
+// 没有当前进程(内核初始化时)或者没有设置工作目录时也从根目录开始
+fn start_dentry(path: &str) -> Result<Arc<Dentry>> {
+    if !path.starts_with('/') {
+        if let Some(cwd) = current_task().and_then(|task| task.cwd().lock().clone()) {
+            return Ok(cwd);
+        }
+    }
+    root_dentry()
+}
+
+// 从start开始依次解析names中的各个分量，遇到符号链接时把目标路径的分量放回队列前面
+// 用循环代替递归，避免嵌套的符号链接耗尽内核栈
+// follow为false时不展开最后一个分量，经过的每个目录都需要执行权限
+fn walk(start: Arc<Dentry>, mut names: VecDeque<String>, follow: bool) -> Result<Arc<Dentry>> {
+    let cred = current_cred();
+    let mut dentry = start;
+    let mut links = 0;
+    while let Some(name) = names.pop_front() {
+        if !dentry.inode.is_dir() {
+            return Err(Errno::ENOTDIR.into());
+        }
+        permission(&dentry.inode, &cred, MAY_EXEC)?;
+        let next = dcache_lookup(&dentry, &name)?;
+        if next.inode.file_type() != FileType::Symlink || (names.is_empty() && !follow) {
+            dentry = next;
+            continue;
+        }
+        links += 1;
+        if links > MAX_SYMLINKS {
+            return Err(Errno::ELOOP.into());
+        }
+        // 相对的目标从符号链接所在的目录开始解析
+        let target = next.inode.readlink()?;
+        if target.starts_with('/') {
+            dentry = root_dentry()?;
+        }
+        for name in components(&target)?.rev() {
+            names.push_front(name.to_string());
+        }
+    }
+    Ok(dentry)
+}
+
+fn lookup_at(path: &str, follow: bool) -> Result<Arc<Dentry>> {
+    let names = components(path)?.map(String::from).collect();
+    // 以'/'结尾的路径必须是目录，最后的符号链接总是展开
+    let trailing = path.ends_with('/');
+    let dentry = walk(start_dentry(path)?, names, follow || trailing)?;
+    if trailing && !dentry.inode.is_dir() {
+        return Err(Errno::ENOTDIR.into());
+    }
+    Ok(dentry)
+}
+
+// 解析路径，展开所有的符号链接
+pub fn lookup(path: &str) -> Result<Arc<Dentry>> {
+    lookup_at(path, true)
+}
+
+// 最后一个分量是符号链接时返回链接本身，readlink、lstat等使用
+pub fn lookup_nofollow(path: &str) -> Result<Arc<Dentry>> {
+    lookup_at(path, false)
+}
+
+// 解析到最后一个分量的父目录，返回父目录与最后一个分量
+pub fn lookup_parent(path: &str) -> Result<(Arc<Dentry>, String)> {
+    let mut names: VecDeque<String> = components(path)?.map(String::from).collect();
+    let Some(last) = names.pop_back() else {
+        // 根目录没有父目录
+        return Err(Errno::EEXIST.into());
+    };
+    if last.len() > NAME_MAX {
+        return Err(Errno::ENAMETOOLONG.into());
+    }
+    let dentry = walk(start_dentry(path)?, names, true)?;
+    if !dentry.inode.is_dir() {
+        return Err(Errno::ENOTDIR.into());
+    }
+    Ok((dentry, last))
+}
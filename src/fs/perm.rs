@@ -0,0 +1,71 @@
+use super::{
+    def::{flags_readable, flags_writable, O_TRUNC, S_ISVTX},
+    vfs::InodeRef,
+};
+use crate::{
+    error::{Errno, Result},
+    proc::cred::Cred,
+};
+
+// 请求的访问，与权限位中rwx的位置相同
+pub const MAY_EXEC: u32 = 0o1;
+pub const MAY_WRITE: u32 = 0o2;
+pub const MAY_READ: u32 = 0o4;
+
+// 按所有者、组、其他用户的顺序选出一组rwx位，只检查第一组匹配的
+// root可以读写任何文件，但普通文件至少有一个执行位时才能执行
+pub fn permission(inode: &InodeRef, cred: &Cred, mask: u32) -> Result<()> {
+    let stat = inode.stat()?;
+    let allowed = if cred.is_root() {
+        if inode.is_dir() || stat.mode & 0o111 != 0 {
+            0o7
+        } else {
+            0o6
+        }
+    } else if stat.uid == cred.uid {
+        stat.mode >> 6
+    } else if cred.in_group(stat.gid) {
+        stat.mode >> 3
+    } else {
+        stat.mode
+    };
+    if allowed & mask != mask {
+        return Err(Errno::EACCES.into());
+    }
+    Ok(())
+}
+
+// 打开已经存在的文件时按打开方式检查
+pub fn may_open(inode: &InodeRef, cred: &Cred, flags: u32) -> Result<()> {
+    let mut mask = 0;
+    if flags_readable(flags) {
+        mask |= MAY_READ;
+    }
+    if flags_writable(flags) || flags & O_TRUNC != 0 {
+        mask |= MAY_WRITE;
+    }
+    permission(inode, cred, mask)
+}
+
+// 从dir中删除victim，或者把它改名
+// 设置了sticky位的目录中，只有文件或目录的所有者才能删除
+pub fn may_delete(dir: &InodeRef, victim: &InodeRef, cred: &Cred) -> Result<()> {
+    permission(dir, cred, MAY_WRITE | MAY_EXEC)?;
+    if cred.is_root() {
+        return Ok(());
+    }
+    let dir_stat = dir.stat()?;
+    if dir_stat.mode & S_ISVTX != 0 && dir_stat.uid != cred.uid && victim.stat()?.uid != cred.uid {
+        return Err(Errno::EPERM.into());
+    }
+    Ok(())
+}
+
+// 修改权限与所有者只允许文件的所有者与root
+pub fn check_owner(inode: &InodeRef, cred: &Cred) -> Result<()> {
+    if cred.is_root() || inode.stat()?.uid == cred.uid {
+        Ok(())
+    } else {
+        Err(Errno::EPERM.into())
+    }
+}
@@ -0,0 +1,162 @@
+use super::{
+    def::{Stat, S_IFIFO},
+    vfs::{new_file, File, FileRef},
+};
+use crate::{
+    cpu::current_task,
+    error::{Errno, ErrorTrace, Result},
+    riscv::registers::sstatus::IntrGuard,
+    sched::{chan_of, sleep, wakeup},
+    utils::RingBuffer,
+};
+use alloc::sync::Arc;
+use xx_mutex_lock::Mutex;
+
+const PIPESIZE: usize = 512;
+
+struct PipeInner {
+    buf: RingBuffer<u8, PIPESIZE>,
+    // 还没有关闭的读端与写端，dup与fork共享同一个端，不会改变计数
+    readers: usize,
+    writers: usize,
+}
+
+// Anonymous pipe
+// 读者在管道为空时睡眠在read_chan上，写者在管道满时睡眠在write_chan上
+pub struct Pipe {
+    inner: Mutex<PipeInner>,
+}
+
+impl Pipe {
+    fn read_chan(&self) -> usize {
+        chan_of(self)
+    }
+
+    fn write_chan(&self) -> usize {
+        chan_of(&self.inner)
+    }
+
+    fn stat() -> Stat {
+        Stat {
+            mode: S_IFIFO | 0o600,
+            nlink: 1,
+            blksize: PIPESIZE as i32,
+            ..Default::default()
+        }
+    }
+}
+
+pub struct PipeReader {
+    pipe: Arc<Pipe>,
+}
+
+pub struct PipeWriter {
+    pipe: Arc<Pipe>,
+}
+
+// 返回(读端, 写端)
+pub fn make_pipe() -> Result<(FileRef, FileRef)> {
+    let pipe = Arc::new(Pipe {
+        inner: Mutex::new(PipeInner {
+            buf: RingBuffer::new(),
+            readers: 1,
+            writers: 1,
+        }),
+    });
+    Ok((
+        new_file(PipeReader { pipe: pipe.clone() })?,
+        new_file(PipeWriter { pipe })?,
+    ))
+}
+
+impl File for PipeReader {
+    // 管道为空时睡眠，所有写端都关闭后返回0
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let task = current_task().ok_or_else(|| ErrorTrace::from_errno(Errno::EINVAL))?;
+        let _guard = IntrGuard::new();
+        let mut inner = self.pipe.inner.lock();
+        if inner.buf.is_empty() {
+            if inner.writers == 0 {
+                return Ok(0);
+            }
+            return Err(sleep(&task, self.pipe.read_chan()));
+        }
+        let mut len = 0;
+        while len < buf.len() {
+            let Some(c) = inner.buf.pop() else {
+                break;
+            };
+            buf[len] = c;
+            len += 1;
+        }
+        drop(inner);
+        wakeup(self.pipe.write_chan());
+        Ok(len)
+    }
+
+    fn write(&self, _buf: &[u8]) -> Result<usize> {
+        Err(Errno::EBADF.into())
+    }
+
+    fn writable(&self) -> bool {
+        false
+    }
+
+    fn stat(&self) -> Result<Stat> {
+        Ok(Pipe::stat())
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        self.pipe.inner.lock().readers -= 1;
+        wakeup(self.pipe.write_chan());
+    }
+}
+
+impl File for PipeWriter {
+    fn read(&self, _buf: &mut [u8]) -> Result<usize> {
+        Err(Errno::EBADF.into())
+    }
+
+    // 管道满时睡眠，已经写入一部分时直接返回写入的字节数
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let task = current_task().ok_or_else(|| ErrorTrace::from_errno(Errno::EINVAL))?;
+        let _guard = IntrGuard::new();
+        let mut inner = self.pipe.inner.lock();
+        if inner.readers == 0 {
+            return Err(Errno::EPIPE.into());
+        }
+        if inner.buf.is_full() {
+            return Err(sleep(&task, self.pipe.write_chan()));
+        }
+        let mut len = 0;
+        while len < buf.len() && inner.buf.push(buf[len]).is_ok() {
+            len += 1;
+        }
+        drop(inner);
+        wakeup(self.pipe.read_chan());
+        Ok(len)
+    }
+
+    fn readable(&self) -> bool {
+        false
+    }
+
+    fn stat(&self) -> Result<Stat> {
+        Ok(Pipe::stat())
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.pipe.inner.lock().writers -= 1;
+        wakeup(self.pipe.read_chan());
+    }
+}
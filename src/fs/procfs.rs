@@ -0,0 +1,528 @@
+use super::{
+    def::{DirEntry, FileType, Stat, StatFs, NAME_MAX, S_IFMT},
+    mount::mounts,
+    vfs::{FileRef, FileSystem, Inode, InodeRef, SuperBlock},
+};
+use crate::{
+    block::{
+        bcache::{bcache_stats, BSIZE},
+        block_device, DevId,
+    },
+    cpu::current_task,
+    driver::{fdt::fdt, plic::PLIC_MAX_IRQ},
+    error::{Errno, ErrorTrace, Result},
+    mm::{
+        address::VirtualMemoryAddress,
+        def::PGSZ,
+        kmem_cache::kmem_stats,
+        pm::heap_stats,
+        vm::{uvm::UserRegion, vma::Vma},
+    },
+    proc::{process::TaskRef, TASKMANAGER},
+    riscv::{
+        sv39::pteflags::{PTE_FLAG_R, PTE_FLAG_W, PTE_FLAG_X},
+        time::{read_time, timebase_frequency},
+    },
+    trap::irq::{irq_count, irq_name},
+};
+use alloc::{
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::fmt::Write;
+
+const ROOT_INO: u64 = 1;
+
+// procfs中的每个文件与目录，内容在读取时生成
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Node {
+    Root,
+    Meminfo,
+    Cpuinfo,
+    Uptime,
+    Interrupts,
+    Mounts,
+    // 指向当前进程的目录
+    SelfLink,
+    Task(usize),
+    Status(usize),
+    Maps(usize),
+    FdDir(usize),
+    Fd(usize, usize),
+    Cwd(usize),
+}
+
+const GLOBAL_FILES: [(&str, Node); 6] = [
+    ("meminfo", Node::Meminfo),
+    ("cpuinfo", Node::Cpuinfo),
+    ("uptime", Node::Uptime),
+    ("interrupts", Node::Interrupts),
+    ("mounts", Node::Mounts),
+    ("self", Node::SelfLink),
+];
+
+const TASK_FILES: [(&str, fn(usize) -> Node); 4] = [
+    ("status", Node::Status),
+    ("maps", Node::Maps),
+    ("fd", Node::FdDir),
+    ("cwd", Node::Cwd),
+];
+
+fn find_task(pid: usize) -> Result<TaskRef> {
+    TASKMANAGER
+        .lock()
+        .find(pid)
+        .ok_or_else(|| ErrorTrace::from_errno(Errno::ENOENT))
+}
+
+// 时钟计数换算为毫秒
+fn ticks_to_ms(ticks: usize) -> usize {
+    (ticks as u64 * 1000 / timebase_frequency() as u64) as usize
+}
+
+impl Node {
+    // 进程相关的inode号是(pid + 1) << 16加上文件的编号
+    fn ino(self) -> u64 {
+        let task_ino = |pid: usize, sub: usize| ((pid as u64 + 1) << 16) | sub as u64;
+        match self {
+            Node::Root => ROOT_INO,
+            Node::Task(pid) => task_ino(pid, 0),
+            Node::Status(pid) => task_ino(pid, 1),
+            Node::Maps(pid) => task_ino(pid, 2),
+            Node::FdDir(pid) => task_ino(pid, 3),
+            Node::Cwd(pid) => task_ino(pid, 4),
+            Node::Fd(pid, fd) => task_ino(pid, 0x100 + fd),
+            global => {
+                let index = GLOBAL_FILES
+                    .iter()
+                    .position(|(_, node)| *node == global)
+                    .unwrap();
+                ROOT_INO + 1 + index as u64
+            }
+        }
+    }
+
+    fn file_type(self) -> FileType {
+        match self {
+            Node::Root | Node::Task(_) | Node::FdDir(_) => FileType::Directory,
+            Node::SelfLink | Node::Fd(..) | Node::Cwd(_) => FileType::Symlink,
+            _ => FileType::Regular,
+        }
+    }
+
+    fn parent(self) -> Node {
+        match self {
+            Node::FdDir(pid) | Node::Status(pid) | Node::Maps(pid) | Node::Cwd(pid) => {
+                Node::Task(pid)
+            }
+            Node::Fd(pid, _) => Node::FdDir(pid),
+            _ => Node::Root,
+        }
+    }
+
+    // 目录中除了.与..之外的目录项
+    fn entries(self) -> Result<Vec<(String, Node)>> {
+        let entries = match self {
+            Node::Root => {
+                let mut entries: Vec<(String, Node)> = GLOBAL_FILES
+                    .iter()
+                    .map(|(name, node)| (name.to_string(), *node))
+                    .collect();
+                let mut pids: Vec<usize> = TASKMANAGER
+                    .lock()
+                    .tasks
+                    .iter()
+                    .map(|task| *task.pid())
+                    .collect();
+                pids.sort_unstable();
+                entries.extend(
+                    pids.into_iter()
+                        .map(|pid| (pid.to_string(), Node::Task(pid))),
+                );
+                entries
+            }
+            Node::Task(pid) => {
+                find_task(pid)?;
+                TASK_FILES
+                    .iter()
+                    .map(|(name, node)| (name.to_string(), node(pid)))
+                    .collect()
+            }
+            Node::FdDir(pid) => {
+                let task = find_task(pid)?;
+                let files = task.files().lock();
+                files
+                    .iter()
+                    .map(|(fd, _)| (fd.to_string(), Node::Fd(pid, fd)))
+                    .collect()
+            }
+            _ => return Err(Errno::ENOTDIR.into()),
+        };
+        Ok(entries)
+    }
+
+    fn content(self) -> Result<String> {
+        match self {
+            Node::Meminfo => Ok(meminfo()),
+            Node::Cpuinfo => Ok(cpuinfo()),
+            Node::Uptime => Ok(uptime()),
+            Node::Interrupts => Ok(interrupts()),
+            Node::Mounts => Ok(mounts_info()),
+            Node::Status(pid) => status(&find_task(pid)?),
+            Node::Maps(pid) => Ok(maps(&find_task(pid)?)),
+            Node::Root | Node::Task(_) | Node::FdDir(_) => Err(Errno::EISDIR.into()),
+            Node::SelfLink | Node::Fd(..) | Node::Cwd(_) => Err(Errno::EINVAL.into()),
+        }
+    }
+
+    fn link(self) -> Result<String> {
+        match self {
+            Node::SelfLink => {
+                let task = current_task().ok_or_else(|| ErrorTrace::from_errno(Errno::ENOENT))?;
+                Ok(task.pid().to_string())
+            }
+            Node::Fd(pid, fd) => {
+                let task = find_task(pid)?;
+                let file = task.files().lock().get(fd)?;
+                describe_file(&file)
+            }
+            Node::Cwd(pid) => {
+                let task = find_task(pid)?;
+                let cwd = task.cwd().lock();
+                Ok(cwd
+                    .as_ref()
+                    .map_or_else(|| String::from("/"), |dentry| dentry.path()))
+            }
+            _ => Err(Errno::EINVAL.into()),
+        }
+    }
+}
+
+// 没有路径的文件显示为类型加inode号
+fn describe_file(file: &FileRef) -> Result<String> {
+    if let Some(path) = file.path() {
+        return Ok(path);
+    }
+    let stat = file.stat()?;
+    let kind = match FileType::from_mode(stat.mode & S_IFMT) {
+        Some(FileType::Fifo) => "pipe",
+        Some(FileType::CharDevice) => "char",
+        Some(FileType::BlockDevice) => "block",
+        Some(FileType::Socket) => "socket",
+        _ => "anon_inode",
+    };
+    Ok(format!("{}:[{}]", kind, stat.ino))
+}
+
+fn meminfo() -> String {
+    let heap = heap_stats();
+    let user_pages: usize = TASKMANAGER
+        .lock()
+        .tasks
+        .iter()
+        .map(|task| task.resident_pages())
+        .sum();
+    let slab: usize = kmem_stats()
+        .iter()
+        .map(|cache| cache.active * cache.obj_size)
+        .sum();
+    let buffers = bcache_stats().buffers * BSIZE;
+    let mut out = String::new();
+    for (name, bytes) in [
+        ("MemTotal", heap.total),
+        ("MemFree", heap.total - heap.in_use),
+        ("MemUsed", heap.in_use),
+        ("MemPeak", heap.peak),
+        ("UserPages", user_pages * PGSZ),
+        ("Buffers", buffers),
+        ("Slab", slab),
+    ] {
+        let _ = writeln!(out, "{:<12}{:>10} kB", format!("{}:", name), bytes / 1024);
+    }
+    out
+}
+
+fn cpuinfo() -> String {
+    let mut out = String::new();
+    let Some(fdt) = fdt() else {
+        return out;
+    };
+    let cpus = fdt.nodes().filter(|node| node.name().starts_with("cpu@"));
+    for (processor, cpu) in cpus.enumerate() {
+        let string = |name| {
+            cpu.property(name)
+                .and_then(|value| core::str::from_utf8(value).ok())
+                .map_or("", |value| value.trim_end_matches('\0'))
+        };
+        let _ = writeln!(out, "processor\t: {}", processor);
+        let _ = writeln!(
+            out,
+            "hart\t\t: {}",
+            cpu.reg().map_or(processor, |(hart, _)| hart)
+        );
+        let _ = writeln!(out, "isa\t\t: {}", string("riscv,isa"));
+        let _ = writeln!(
+            out,
+            "mmu\t\t: {}",
+            string("mmu-type").trim_start_matches("riscv,")
+        );
+        let _ = writeln!(out, "timebase\t: {}\n", timebase_frequency());
+    }
+    out
+}
+
+// 开机以来的秒数，没有统计空闲时间
+fn uptime() -> String {
+    let centis = read_time() as u64 * 100 / timebase_frequency() as u64;
+    format!("{}.{:02} 0.00\n", centis / 100, centis % 100)
+}
+
+fn interrupts() -> String {
+    let mut out = String::from("       count\n");
+    for irq in 1..PLIC_MAX_IRQ {
+        let name = irq_name(irq);
+        let count = irq_count(irq);
+        if name.is_some() || count > 0 {
+            let _ = writeln!(
+                out,
+                "{:>4}: {:>10}  PLIC  {}",
+                irq,
+                count,
+                name.unwrap_or("-")
+            );
+        }
+    }
+    out
+}
+
+fn mounts_info() -> String {
+    let mut out = String::new();
+    for mount in mounts() {
+        let source = mount.dev().and_then(block_device).map_or_else(
+            || String::from(mount.fs_name()),
+            |dev| format!("/dev/{}", dev.name()),
+        );
+        let _ = writeln!(
+            out,
+            "{} {} {} rw 0 0",
+            source,
+            mount.path(),
+            mount.fs_name()
+        );
+    }
+    out
+}
+
+fn status(task: &TaskRef) -> Result<String> {
+    let pid = *task.pid();
+    let (rss, heap) = {
+        let vm = task.vm().lock();
+        let (base, brk) = vm.heap();
+        (
+            vm.resident_pages() * PGSZ,
+            brk.as_usize().saturating_sub(base.as_usize()),
+        )
+    };
+    let fds = task.files().lock().iter().count();
+    let (utime, stime) = task.cpu_time();
+    let cred = task.cred().lock().clone();
+
+    let mut out = String::new();
+    let _ = writeln!(out, "Name:\t{}", task.name());
+    let _ = writeln!(out, "State:\t{}", task.state_name());
+    let _ = writeln!(out, "Pid:\t{}", pid);
+    let _ = writeln!(out, "PPid:\t{}", task.ppid());
+    let _ = writeln!(out, "Uid:\t{}", cred.uid);
+    let _ = writeln!(out, "Gid:\t{}", cred.gid);
+    let groups: Vec<String> = cred.groups.iter().map(|gid| gid.to_string()).collect();
+    let _ = writeln!(out, "Groups:\t{}", groups.join(" "));
+    let _ = writeln!(out, "FDSize:\t{}", fds);
+    let _ = writeln!(out, "VmRSS:\t{} kB", rss / 1024);
+    let _ = writeln!(out, "VmData:\t{} kB", heap / 1024);
+    let _ = writeln!(out, "Utime:\t{} ms", ticks_to_ms(utime));
+    let _ = writeln!(out, "Stime:\t{} ms", ticks_to_ms(stime));
+    let _ = writeln!(out, "Killed:\t{}", task.is_killed());
+    Ok(out)
+}
+
+fn maps(task: &TaskRef) -> String {
+    let mut vm = task.vm().lock();
+    let (heap_base, brk) = vm.heap();
+    let stack_top = vm.layout().stack_top();
+    let regions: Vec<UserRegion> = vm.regions();
+    let vmas: Vec<Vma> = vm.vmas();
+    drop(vm);
+
+    // 程序映像、堆与栈之外的区间都来自mmap，按起始地址合并输出
+    let mut lines: Vec<(VirtualMemoryAddress, String)> = Vec::new();
+    for region in regions {
+        let perm = |flag, c| if region.flags & flag != 0 { c } else { '-' };
+        let name = if region.end == stack_top {
+            "[stack]"
+        } else if region.start >= heap_base && region.end <= brk.align_up() {
+            "[heap]"
+        } else {
+            ""
+        };
+        let line = format!(
+            "{:08x}-{:08x} {}{}{}p 00000000 00:00 0 {}",
+            region.start.as_usize(),
+            region.end.as_usize(),
+            perm(PTE_FLAG_R, 'r'),
+            perm(PTE_FLAG_W, 'w'),
+            perm(PTE_FLAG_X, 'x'),
+            name
+        );
+        lines.push((region.start, line));
+    }
+    for vma in vmas {
+        let perm = |flag, c| if vma.prot & flag != 0 { c } else { '-' };
+        let (offset, ino, path) = vma.file.as_ref().map_or((0, 0, ""), |file| {
+            (
+                file.offset,
+                file.inode.ino(),
+                file.path.as_deref().unwrap_or(""),
+            )
+        });
+        let line = format!(
+            "{:08x}-{:08x} {}{}{}{} {:08x} 00:00 {} {}",
+            vma.start.as_usize(),
+            vma.end.as_usize(),
+            perm(PTE_FLAG_R, 'r'),
+            perm(PTE_FLAG_W, 'w'),
+            perm(PTE_FLAG_X, 'x'),
+            if vma.shared { 's' } else { 'p' },
+            offset,
+            ino,
+            path
+        );
+        lines.push((vma.start, line));
+    }
+    lines.sort_by_key(|(start, _)| *start);
+
+    let mut out = String::new();
+    for (_, line) in lines {
+        let _ = writeln!(out, "{}", line);
+    }
+    out
+}
+
+pub struct ProcInode(Node);
+
+impl Inode for ProcInode {
+    fn ino(&self) -> u64 {
+        self.0.ino()
+    }
+
+    fn file_type(&self) -> FileType {
+        self.0.file_type()
+    }
+
+    fn stat(&self) -> Result<Stat> {
+        let file_type = self.0.file_type();
+        let perm = match file_type {
+            FileType::Directory => 0o555,
+            FileType::Symlink => 0o777,
+            _ => 0o444,
+        };
+        Ok(Stat {
+            ino: self.0.ino(),
+            mode: file_type.to_mode() | perm,
+            nlink: 1,
+            blksize: PGSZ as i32,
+            ..Default::default()
+        })
+    }
+
+    fn lookup(&self, name: &str) -> Result<InodeRef> {
+        let node = match name {
+            "." => self.0,
+            ".." => self.0.parent(),
+            _ => self
+                .0
+                .entries()?
+                .into_iter()
+                .find(|(entry, _)| entry == name)
+                .map(|(_, node)| node)
+                .ok_or(Errno::ENOENT)?,
+        };
+        Ok(Arc::new(ProcInode(node)))
+    }
+
+    fn create(&self, _name: &str, _file_type: FileType, _mode: u32) -> Result<InodeRef> {
+        Err(Errno::EPERM.into())
+    }
+
+    fn unlink(&self, _name: &str) -> Result<()> {
+        Err(Errno::EPERM.into())
+    }
+
+    fn readdir(&self, index: usize) -> Result<Option<DirEntry>> {
+        let entry = match index {
+            0 => Some((String::from("."), self.0)),
+            1 => Some((String::from(".."), self.0.parent())),
+            _ => self.0.entries()?.into_iter().nth(index - 2),
+        };
+        Ok(entry.map(|(name, node)| DirEntry {
+            ino: node.ino(),
+            name,
+            file_type: node.file_type(),
+        }))
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let content = self.0.content()?;
+        let bytes = content.as_bytes();
+        let start = (offset as usize).min(bytes.len());
+        let len = buf.len().min(bytes.len() - start);
+        buf[..len].copy_from_slice(&bytes[start..start + len]);
+        Ok(len)
+    }
+
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> Result<usize> {
+        Err(Errno::EACCES.into())
+    }
+
+    fn truncate(&self, _size: u64) -> Result<()> {
+        Err(Errno::EACCES.into())
+    }
+
+    fn readlink(&self) -> Result<String> {
+        self.0.link()
+    }
+}
+
+pub struct ProcSuperBlock;
+
+impl SuperBlock for ProcSuperBlock {
+    fn root(&self) -> InodeRef {
+        Arc::new(ProcInode(Node::Root))
+    }
+
+    fn statfs(&self) -> Result<StatFs> {
+        Ok(StatFs {
+            block_size: PGSZ,
+            name_max: NAME_MAX,
+            ..Default::default()
+        })
+    }
+}
+
+// process file system
+pub struct ProcfsType;
+
+impl FileSystem for ProcfsType {
+    fn name(&self) -> &'static str {
+        "proc"
+    }
+
+    fn requires_device(&self) -> bool {
+        false
+    }
+
+    fn mount(&self, _dev: Option<DevId>, _options: &str) -> Result<Arc<dyn SuperBlock>> {
+        Ok(Arc::new(ProcSuperBlock))
+    }
+}
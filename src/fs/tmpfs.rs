@@ -0,0 +1,646 @@
+use super::{
+    def::{DirEntry, FileType, Stat, StatFs, NAME_MAX, PATH_MAX},
+    vfs::{FileSystem, Inode, InodeRef, SuperBlock},
+};
+use crate::{
+    block::DevId,
+    error::{Errno, ErrorTrace, Result},
+    mm::{
+        def::PGSZ,
+        page_frame::{alloc_page, PageFrame},
+        pm::heap_stats,
+    },
+    proc::cred::current_cred,
+};
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::{Arc, Weak},
+};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use xx_mutex_lock::Mutex;
+use xxos_log::warn;
+
+const ROOT_INO: u64 = 1;
+
+// tmpfs: 文件内容保存在页帧中，不写回磁盘
+// 页数与inode数都有上限，最后一个引用释放时归还内存
+pub struct Tmpfs {
+    max_pages: usize,
+    max_inodes: usize,
+    pages: AtomicUsize,
+    inodes: AtomicUsize,
+    next_ino: AtomicU64,
+    // 按inode号找到inode，硬链接与改名时使用
+    table: Mutex<BTreeMap<u64, Weak<TmpfsInode>>>,
+}
+
+// 先占用计数再分配，超过上限时撤销
+fn reserve(count: &AtomicUsize, max: usize) -> Result<()> {
+    if count.fetch_add(1, Ordering::Relaxed) >= max {
+        count.fetch_sub(1, Ordering::Relaxed);
+        return Err(Errno::ENOSPC.into());
+    }
+    Ok(())
+}
+
+impl Tmpfs {
+    fn alloc_page(&self) -> Result<Arc<PageFrame>> {
+        reserve(&self.pages, self.max_pages)?;
+        let page = alloc_page()
+            .map_err(ErrorTrace::from)
+            .and_then(|page| Arc::try_new(page).map_err(|_| ErrorTrace::from_errno(Errno::ENOMEM)));
+        if page.is_err() {
+            self.pages.fetch_sub(1, Ordering::Relaxed);
+        }
+        page
+    }
+
+    fn free_pages(&self, count: usize) {
+        self.pages.fetch_sub(count, Ordering::Relaxed);
+    }
+
+    fn alloc_ino(&self) -> Result<u64> {
+        reserve(&self.inodes, self.max_inodes)?;
+        Ok(self.next_ino.fetch_add(1, Ordering::Relaxed))
+    }
+
+    // 另一个inode属于其他文件系统时返回EXDEV
+    fn iget(&self, ino: u64) -> Result<Arc<TmpfsInode>> {
+        self.table
+            .lock()
+            .get(&ino)
+            .and_then(Weak::upgrade)
+            .ok_or_else(|| ErrorTrace::from_errno(Errno::EXDEV))
+    }
+}
+
+enum Content {
+    // 按页号索引，没有分配的页是空洞
+    Regular {
+        // 页帧与映射了文件的地址空间共享
+        pages: BTreeMap<u64, Arc<PageFrame>>,
+        size: u64,
+    },
+    Directory {
+        entries: BTreeMap<String, Arc<TmpfsInode>>,
+        parent: Weak<TmpfsInode>,
+    },
+    Symlink(String),
+    // 设备文件与FIFO没有内容
+    Special,
+}
+
+struct TmpfsMeta {
+    mode: u32,
+    nlink: u32,
+    uid: u32,
+    gid: u32,
+}
+
+pub struct TmpfsInode {
+    fs: Arc<Tmpfs>,
+    ino: u64,
+    file_type: FileType,
+    // 指向自己，创建子目录时作为它的父目录
+    this: Weak<TmpfsInode>,
+    meta: Mutex<TmpfsMeta>,
+    content: Mutex<Content>,
+}
+
+impl TmpfsInode {
+    fn new(
+        fs: Arc<Tmpfs>,
+        ino: u64,
+        file_type: FileType,
+        mode: u32,
+        parent: Option<Weak<TmpfsInode>>,
+    ) -> Arc<Self> {
+        // 新的inode属于创建它的进程
+        let cred = current_cred();
+        let inode = Arc::new_cyclic(|this| {
+            let (content, nlink) = match file_type {
+                FileType::Regular => (
+                    Content::Regular {
+                        pages: BTreeMap::new(),
+                        size: 0,
+                    },
+                    1,
+                ),
+                // 根目录的父目录是它自己
+                FileType::Directory => (
+                    Content::Directory {
+                        entries: BTreeMap::new(),
+                        parent: parent.unwrap_or_else(|| this.clone()),
+                    },
+                    2,
+                ),
+                FileType::Symlink => (Content::Symlink(String::new()), 1),
+                _ => (Content::Special, 1),
+            };
+            Self {
+                fs,
+                ino,
+                file_type,
+                this: this.clone(),
+                meta: Mutex::new(TmpfsMeta {
+                    mode: file_type.to_mode() | (mode & 0o7777),
+                    nlink,
+                    uid: cred.uid,
+                    gid: cred.gid,
+                }),
+                content: Mutex::new(content),
+            }
+        });
+        inode.fs.table.lock().insert(ino, Arc::downgrade(&inode));
+        inode
+    }
+
+    // 对不是普通文件的inode做文件操作时的错误号
+    fn wrong_type(&self) -> ErrorTrace {
+        match self.file_type {
+            FileType::Directory => Errno::EISDIR.into(),
+            _ => Errno::EINVAL.into(),
+        }
+    }
+
+    // 在目录中新建inode，init在它出现在目录中之前填写内容
+    fn add_entry(
+        &self,
+        name: &str,
+        file_type: FileType,
+        mode: u32,
+        init: impl FnOnce(&mut Content),
+    ) -> Result<Arc<TmpfsInode>> {
+        if name.len() > NAME_MAX {
+            return Err(Errno::ENAMETOOLONG.into());
+        }
+        let mut content = self.content.lock();
+        let Content::Directory { entries, .. } = &mut *content else {
+            return Err(Errno::ENOTDIR.into());
+        };
+        if name == "." || name == ".." || entries.contains_key(name) {
+            return Err(Errno::EEXIST.into());
+        }
+        let ino = self.fs.alloc_ino()?;
+        let inode = TmpfsInode::new(
+            self.fs.clone(),
+            ino,
+            file_type,
+            mode,
+            Some(self.this.clone()),
+        );
+        init(&mut inode.content.lock());
+        entries.insert(name.to_string(), inode.clone());
+        drop(content);
+        if file_type == FileType::Directory {
+            self.nlink_add(1);
+        }
+        Ok(inode)
+    }
+
+    fn nlink_add(&self, delta: i32) {
+        let mut meta = self.meta.lock();
+        meta.nlink = meta.nlink.saturating_add_signed(delta);
+    }
+}
+
+impl Drop for TmpfsInode {
+    fn drop(&mut self) {
+        if let Content::Regular { pages, .. } = &*self.content.lock() {
+            self.fs.free_pages(pages.len());
+        }
+        self.fs.inodes.fetch_sub(1, Ordering::Relaxed);
+        self.fs.table.lock().remove(&self.ino);
+    }
+}
+
+type Entries = BTreeMap<String, Arc<TmpfsInode>>;
+// 被移动的inode与被替换的inode
+type Moved = (Arc<TmpfsInode>, Option<Arc<TmpfsInode>>);
+
+// 把src中的old_name移动到dst(为None时是src本身)中的new_name
+// 两个名字指向同一个inode时什么也不做，返回None
+fn move_entry(
+    src: &mut Entries,
+    dst: Option<&mut Entries>,
+    old_name: &str,
+    new_name: &str,
+) -> Result<Option<Moved>> {
+    let child = src.get(old_name).cloned().ok_or(Errno::ENOENT)?;
+    let victim = match &dst {
+        Some(dst) => dst.get(new_name),
+        None => src.get(new_name),
+    }
+    .cloned();
+    if let Some(victim) = &victim {
+        if Arc::ptr_eq(victim, &child) {
+            return Ok(None);
+        }
+        match (child.is_dir(), victim.is_dir()) {
+            (true, false) => return Err(Errno::ENOTDIR.into()),
+            (false, true) => return Err(Errno::EISDIR.into()),
+            (true, true) => {
+                if let Content::Directory { entries, .. } = &*victim.content.lock() {
+                    if !entries.is_empty() {
+                        return Err(Errno::ENOTEMPTY.into());
+                    }
+                }
+            }
+            (false, false) => {}
+        }
+    }
+    src.remove(old_name);
+    match dst {
+        Some(dst) => dst.insert(new_name.to_string(), child.clone()),
+        None => src.insert(new_name.to_string(), child.clone()),
+    };
+    Ok(Some((child, victim)))
+}
+
+impl Inode for TmpfsInode {
+    fn ino(&self) -> u64 {
+        self.ino
+    }
+
+    fn file_type(&self) -> FileType {
+        self.file_type
+    }
+
+    fn stat(&self) -> Result<Stat> {
+        let (size, pages) = match &*self.content.lock() {
+            Content::Regular { pages, size } => (*size, pages.len()),
+            Content::Directory { entries, .. } => (entries.len() as u64, 0),
+            Content::Symlink(target) => (target.len() as u64, 0),
+            Content::Special => (0, 0),
+        };
+        let meta = self.meta.lock();
+        Ok(Stat {
+            ino: self.ino,
+            mode: meta.mode,
+            nlink: meta.nlink,
+            uid: meta.uid,
+            gid: meta.gid,
+            size: size as i64,
+            blksize: PGSZ as i32,
+            blocks: (pages * PGSZ / 512) as i64,
+            ..Default::default()
+        })
+    }
+
+    fn size(&self) -> u64 {
+        match &*self.content.lock() {
+            Content::Regular { size, .. } => *size,
+            _ => 0,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<InodeRef> {
+        let content = self.content.lock();
+        let Content::Directory { entries, parent } = &*content else {
+            return Err(Errno::ENOTDIR.into());
+        };
+        let inode = match name {
+            "." => self.this.upgrade(),
+            ".." => parent.upgrade(),
+            _ => entries.get(name).cloned(),
+        };
+        Ok(inode.ok_or(Errno::ENOENT)?)
+    }
+
+    fn create(&self, name: &str, file_type: FileType, mode: u32) -> Result<InodeRef> {
+        // 符号链接需要同时给出目标路径
+        if file_type == FileType::Symlink {
+            return Err(Errno::EINVAL.into());
+        }
+        Ok(self.add_entry(name, file_type, mode, |_| ())?)
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<InodeRef> {
+        if target.is_empty() {
+            return Err(Errno::ENOENT.into());
+        }
+        if target.len() > PATH_MAX {
+            return Err(Errno::ENAMETOOLONG.into());
+        }
+        Ok(self.add_entry(name, FileType::Symlink, 0o777, |content| {
+            *content = Content::Symlink(target.to_string())
+        })?)
+    }
+
+    // 只移除目录项，文件的页在最后一个引用释放时归还
+    fn unlink(&self, name: &str) -> Result<()> {
+        let mut content = self.content.lock();
+        let Content::Directory { entries, .. } = &mut *content else {
+            return Err(Errno::ENOTDIR.into());
+        };
+        if name == "." || name == ".." {
+            return Err(Errno::EINVAL.into());
+        }
+        let child = entries.get(name).ok_or(Errno::ENOENT)?;
+        if child.is_dir() {
+            if let Content::Directory { entries, .. } = &*child.content.lock() {
+                if !entries.is_empty() {
+                    return Err(Errno::ENOTEMPTY.into());
+                }
+            }
+        }
+        let child = entries.remove(name).unwrap();
+        drop(content);
+        if child.is_dir() {
+            child.meta.lock().nlink = 0;
+            self.nlink_add(-1);
+        } else {
+            child.nlink_add(-1);
+        }
+        Ok(())
+    }
+
+    fn link(&self, name: &str, inode: &InodeRef) -> Result<()> {
+        if name.len() > NAME_MAX {
+            return Err(Errno::ENAMETOOLONG.into());
+        }
+        let target = self.fs.iget(inode.ino())?;
+        if target.is_dir() {
+            return Err(Errno::EPERM.into());
+        }
+        let mut content = self.content.lock();
+        let Content::Directory { entries, .. } = &mut *content else {
+            return Err(Errno::ENOTDIR.into());
+        };
+        if name == "." || name == ".." || entries.contains_key(name) {
+            return Err(Errno::EEXIST.into());
+        }
+        entries.insert(name.to_string(), target.clone());
+        drop(content);
+        target.nlink_add(1);
+        Ok(())
+    }
+
+    fn rename(&self, old_name: &str, new_dir: &InodeRef, new_name: &str) -> Result<()> {
+        if new_name.len() > NAME_MAX {
+            return Err(Errno::ENAMETOOLONG.into());
+        }
+        if [old_name, new_name]
+            .iter()
+            .any(|name| *name == "." || *name == "..")
+        {
+            return Err(Errno::EINVAL.into());
+        }
+        let new_dir = self.fs.iget(new_dir.ino())?;
+        let same_dir = new_dir.ino == self.ino;
+        let (child, victim) = if same_dir {
+            let mut content = self.content.lock();
+            let Content::Directory { entries, .. } = &mut *content else {
+                return Err(Errno::ENOTDIR.into());
+            };
+            let Some(moved) = move_entry(entries, None, old_name, new_name)? else {
+                return Ok(());
+            };
+            moved
+        } else {
+            // 按inode号的顺序锁住两个目录
+            let (mut src, mut dst) = if self.ino < new_dir.ino {
+                let src = self.content.lock();
+                (src, new_dir.content.lock())
+            } else {
+                let dst = new_dir.content.lock();
+                (self.content.lock(), dst)
+            };
+            let (Content::Directory { entries: src, .. }, Content::Directory { entries: dst, .. }) =
+                (&mut *src, &mut *dst)
+            else {
+                return Err(Errno::ENOTDIR.into());
+            };
+            let Some(moved) = move_entry(src, Some(dst), old_name, new_name)? else {
+                return Ok(());
+            };
+            moved
+        };
+
+        if let Some(victim) = victim {
+            if victim.is_dir() {
+                victim.meta.lock().nlink = 0;
+                new_dir.nlink_add(-1);
+            } else {
+                victim.nlink_add(-1);
+            }
+        }
+        // 目录的..改为指向新的父目录
+        if child.is_dir() && !same_dir {
+            if let Content::Directory { parent, .. } = &mut *child.content.lock() {
+                *parent = new_dir.this.clone();
+            }
+            self.nlink_add(-1);
+            new_dir.nlink_add(1);
+        }
+        Ok(())
+    }
+
+    fn readdir(&self, index: usize) -> Result<Option<DirEntry>> {
+        let content = self.content.lock();
+        let Content::Directory { entries, parent } = &*content else {
+            return Err(Errno::ENOTDIR.into());
+        };
+        let entry = match index {
+            0 => Some((".", self.ino, FileType::Directory)),
+            1 => Some((
+                "..",
+                parent.upgrade().map_or(self.ino, |p| p.ino),
+                FileType::Directory,
+            )),
+            _ => entries
+                .iter()
+                .nth(index - 2)
+                .map(|(name, inode)| (name.as_str(), inode.ino, inode.file_type)),
+        };
+        Ok(entry.map(|(name, ino, file_type)| DirEntry {
+            ino,
+            name: name.to_string(),
+            file_type,
+        }))
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let content = self.content.lock();
+        let Content::Regular { pages, size } = &*content else {
+            return Err(self.wrong_type());
+        };
+        if offset >= *size {
+            return Ok(0);
+        }
+        let n = (buf.len() as u64).min(size - offset) as usize;
+        let mut done = 0;
+        while done < n {
+            let pos = offset + done as u64;
+            let poff = pos as usize % PGSZ;
+            let len = (PGSZ - poff).min(n - done);
+            match pages.get(&(pos / PGSZ as u64)) {
+                Some(page) => {
+                    buf[done..done + len].copy_from_slice(&page.as_bytes()[poff..][..len])
+                }
+                None => buf[done..done + len].fill(0),
+            }
+            done += len;
+        }
+        Ok(n)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize> {
+        let mut content = self.content.lock();
+        let Content::Regular { pages, size } = &mut *content else {
+            return Err(self.wrong_type());
+        };
+        if offset.checked_add(buf.len() as u64).is_none() {
+            return Err(Errno::EFBIG.into());
+        }
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let poff = pos as usize % PGSZ;
+            let len = (PGSZ - poff).min(buf.len() - done);
+            let index = pos / PGSZ as u64;
+            if !pages.contains_key(&index) {
+                match self.fs.alloc_page() {
+                    Ok(page) => pages.insert(index, page),
+                    // 空间不足时返回已经写入的长度
+                    Err(_) if done > 0 => break,
+                    Err(err) => return Err(err),
+                };
+            }
+            pages[&index].write_bytes(poff, &buf[done..done + len]);
+            done += len;
+        }
+        *size = (*size).max(offset + done as u64);
+        Ok(done)
+    }
+
+    fn truncate(&self, new_size: u64) -> Result<()> {
+        let mut content = self.content.lock();
+        let Content::Regular { pages, size } = &mut *content else {
+            return Err(self.wrong_type());
+        };
+        if new_size < *size {
+            let removed = pages.split_off(&new_size.div_ceil(PGSZ as u64));
+            self.fs.free_pages(removed.len());
+            // 最后一页末尾的旧数据清零，文件再次变长时读出的是0
+            let tail = new_size as usize % PGSZ;
+            if let Some(page) = pages.get(&(new_size / PGSZ as u64)) {
+                page.zero_from(tail);
+            }
+        }
+        *size = new_size;
+        Ok(())
+    }
+
+    fn get_page(&self, index: u64) -> Result<Arc<PageFrame>> {
+        let mut content = self.content.lock();
+        let Content::Regular { pages, .. } = &mut *content else {
+            return Err(Errno::ENODEV.into());
+        };
+        if let Some(page) = pages.get(&index) {
+            return Ok(page.clone());
+        }
+        // 空洞在映射时分配，之后的write与映射共享这一页
+        let page = self.fs.alloc_page()?;
+        pages.insert(index, page.clone());
+        Ok(page)
+    }
+
+    fn chmod(&self, mode: u32) -> Result<()> {
+        let mut meta = self.meta.lock();
+        meta.mode = self.file_type.to_mode() | (mode & 0o7777);
+        Ok(())
+    }
+
+    fn chown(&self, uid: u32, gid: u32) -> Result<()> {
+        let mut meta = self.meta.lock();
+        meta.uid = uid;
+        meta.gid = gid;
+        Ok(())
+    }
+
+    fn readlink(&self) -> Result<String> {
+        match &*self.content.lock() {
+            Content::Symlink(target) => Ok(target.clone()),
+            _ => Err(Errno::EINVAL.into()),
+        }
+    }
+}
+
+pub struct TmpfsSuperBlock {
+    fs: Arc<Tmpfs>,
+    root: Arc<TmpfsInode>,
+}
+
+impl SuperBlock for TmpfsSuperBlock {
+    fn root(&self) -> InodeRef {
+        self.root.clone()
+    }
+
+    fn statfs(&self) -> Result<StatFs> {
+        let fs = &self.fs;
+        let pages = fs.pages.load(Ordering::Relaxed);
+        let inodes = fs.inodes.load(Ordering::Relaxed);
+        Ok(StatFs {
+            block_size: PGSZ,
+            blocks: fs.max_pages as u64,
+            free_blocks: fs.max_pages.saturating_sub(pages) as u64,
+            files: fs.max_inodes as u64,
+            free_files: fs.max_inodes.saturating_sub(inodes) as u64,
+            name_max: NAME_MAX,
+        })
+    }
+}
+
+// 解析带k、m、g后缀的大小
+fn parse_size(value: &str) -> Option<usize> {
+    let (number, shift) = match value.as_bytes().last()? {
+        b'k' | b'K' => (&value[..value.len() - 1], 10),
+        b'm' | b'M' => (&value[..value.len() - 1], 20),
+        b'g' | b'G' => (&value[..value.len() - 1], 30),
+        _ => (value, 0),
+    };
+    number.parse::<usize>().ok()?.checked_mul(1 << shift)
+}
+
+pub struct TmpfsType;
+
+impl FileSystem for TmpfsType {
+    fn name(&self) -> &'static str {
+        "tmpfs"
+    }
+
+    fn requires_device(&self) -> bool {
+        false
+    }
+
+    // 挂载参数: size=大小，nr_inodes=inode个数，默认使用一半的内存
+    fn mount(&self, _dev: Option<DevId>, options: &str) -> Result<Arc<dyn SuperBlock>> {
+        let mut max_pages = heap_stats().total / 2 / PGSZ;
+        let mut max_inodes = max_pages;
+        for option in options.split(',').filter(|option| !option.is_empty()) {
+            let (key, value) = option.split_once('=').unwrap_or((option, ""));
+            let parsed = parse_size(value);
+            match (key, parsed) {
+                ("size", Some(size)) => max_pages = size.div_ceil(PGSZ),
+                ("nr_inodes", Some(count)) => max_inodes = count,
+                _ => {
+                    warn!("tmpfs: bad option {}", option);
+                    return Err(Errno::EINVAL.into());
+                }
+            }
+        }
+
+        let fs = Arc::new(Tmpfs {
+            max_pages,
+            max_inodes,
+            pages: AtomicUsize::new(0),
+            inodes: AtomicUsize::new(1),
+            next_ino: AtomicU64::new(ROOT_INO + 1),
+            table: Mutex::new(BTreeMap::new()),
+        });
+        let root = TmpfsInode::new(fs.clone(), ROOT_INO, FileType::Directory, 0o1777, None);
+        Ok(Arc::new(TmpfsSuperBlock { fs, root }))
+    }
+}
@@ -0,0 +1,268 @@
+use super::def::{
+    flags_readable, flags_writable, DirEntry, FileType, SeekFrom, Stat, StatFs, O_APPEND,
+};
+use super::path::Dentry;
+use crate::{
+    block::DevId,
+    error::{Errno, Result},
+    mm::{kmem_cache::KmemCache, page_frame::PageFrame},
+};
+use alloc::{string::String, sync::Arc};
+use xx_mutex_lock::Mutex;
+
+pub type InodeRef = Arc<dyn Inode>;
+pub type FileAllocator = &'static KmemCache<InodeFile>;
+pub type FileRef = Arc<dyn File, FileAllocator>;
+
+// 打开的文件大多是InodeFile，cache按它的大小切分，更大的文件对象退回到全局堆
+static FILE_CACHE: KmemCache<InodeFile> = KmemCache::for_arc("files");
+
+pub fn new_file(file: impl File + 'static) -> Result<FileRef> {
+    match Arc::try_new_in(file, &FILE_CACHE) {
+        Ok(file) => Ok(file),
+        Err(_) => Err(Errno::ENOMEM.into()),
+    }
+}
+
+// Virtual File System
+// 具体的文件系统实现Inode、SuperBlock与FileSystem，VFS负责挂载与路径查找
+// 不支持的操作使用默认实现，返回对应的错误号
+
+pub trait Inode: Send + Sync {
+    fn ino(&self) -> u64;
+    fn file_type(&self) -> FileType;
+    fn stat(&self) -> Result<Stat>;
+
+    fn size(&self) -> u64 {
+        self.stat().map_or(0, |stat| stat.size as u64)
+    }
+
+    fn is_dir(&self) -> bool {
+        self.file_type() == FileType::Directory
+    }
+
+    // 目录操作
+    fn lookup(&self, _name: &str) -> Result<InodeRef> {
+        Err(Errno::ENOTDIR.into())
+    }
+
+    fn create(&self, _name: &str, _file_type: FileType, _mode: u32) -> Result<InodeRef> {
+        Err(Errno::ENOTDIR.into())
+    }
+
+    // 创建指向target的符号链接，target不需要存在
+    fn symlink(&self, _name: &str, _target: &str) -> Result<InodeRef> {
+        Err(Errno::EPERM.into())
+    }
+
+    // 删除目录项name，目录必须为空
+    fn unlink(&self, _name: &str) -> Result<()> {
+        Err(Errno::ENOTDIR.into())
+    }
+
+    // 在目录中创建指向inode的硬链接，inode与目录属于同一个文件系统
+    fn link(&self, _name: &str, _inode: &InodeRef) -> Result<()> {
+        Err(Errno::EPERM.into())
+    }
+
+    // 把目录项old_name移到同一文件系统的new_dir中，已经存在的new_name被替换
+    fn rename(&self, _old_name: &str, _new_dir: &InodeRef, _new_name: &str) -> Result<()> {
+        Err(Errno::EPERM.into())
+    }
+
+    // 返回第index个目录项，越过末尾时返回None
+    fn readdir(&self, _index: usize) -> Result<Option<DirEntry>> {
+        Err(Errno::ENOTDIR.into())
+    }
+
+    // 文件操作
+    fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> Result<usize> {
+        Err(Errno::EISDIR.into())
+    }
+
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> Result<usize> {
+        Err(Errno::EISDIR.into())
+    }
+
+    fn truncate(&self, _size: u64) -> Result<()> {
+        Err(Errno::EISDIR.into())
+    }
+
+    // 修改权限位，文件类型保持不变，权限检查由调用者完成
+    fn chmod(&self, _mode: u32) -> Result<()> {
+        Err(Errno::EPERM.into())
+    }
+
+    fn chown(&self, _uid: u32, _gid: u32) -> Result<()> {
+        Err(Errno::EPERM.into())
+    }
+
+    // 符号链接指向的路径
+    fn readlink(&self) -> Result<String> {
+        Err(Errno::EINVAL.into())
+    }
+
+    // 把共享映射写入过的页写回
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    // 页缓存中文件的第index页，read与mmap共享同一个页帧
+    // 没有页缓存的文件(设备、procfs等)不能被mmap
+    fn get_page(&self, _index: u64) -> Result<Arc<PageFrame>> {
+        Err(Errno::ENODEV.into())
+    }
+
+    // 共享映射写入了第index页
+    fn set_page_dirty(&self, _index: u64) {}
+
+    // 设备文件等需要自己的File实现时重写，None表示按普通文件打开
+    fn open(&self, _flags: u32) -> Option<Result<FileRef>> {
+        None
+    }
+}
+
+// 一个打开的文件，文件描述符指向它
+pub trait File: Send + Sync {
+    fn read(&self, buf: &mut [u8]) -> Result<usize>;
+    fn write(&self, buf: &[u8]) -> Result<usize>;
+    fn stat(&self) -> Result<Stat>;
+
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        true
+    }
+
+    fn seek(&self, _pos: SeekFrom) -> Result<u64> {
+        Err(Errno::ESPIPE.into())
+    }
+
+    // 读出下一个目录项
+    fn readdir(&self) -> Result<Option<DirEntry>> {
+        Err(Errno::ENOTDIR.into())
+    }
+
+    fn inode(&self) -> Option<InodeRef> {
+        None
+    }
+
+    // 打开时使用的路径，管道与设备等没有路径
+    fn path(&self) -> Option<String> {
+        None
+    }
+
+    // 设备相关的控制命令，arg是用户态的参数
+    fn ioctl(&self, _request: usize, _arg: usize) -> Result<usize> {
+        Err(Errno::ENOTTY.into())
+    }
+}
+
+pub trait SuperBlock: Send + Sync {
+    fn root(&self) -> InodeRef;
+    fn statfs(&self) -> Result<StatFs>;
+
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+pub trait FileSystem: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // 内存中的文件系统不需要块设备，挂载根文件系统时不会在磁盘上尝试它们
+    fn requires_device(&self) -> bool {
+        true
+    }
+
+    // dev为None时挂载不需要块设备的文件系统，options为逗号分隔的挂载参数
+    fn mount(&self, dev: Option<DevId>, options: &str) -> Result<Arc<dyn SuperBlock>>;
+}
+
+// 普通文件与目录的File实现，读写转发给inode
+// 持有打开时的目录项，文件被删除后仍然可以访问
+pub struct InodeFile {
+    dentry: Arc<Dentry>,
+    inode: InodeRef,
+    flags: u32,
+    offset: Mutex<u64>,
+}
+
+impl InodeFile {
+    pub fn new(dentry: Arc<Dentry>, flags: u32) -> Self {
+        Self {
+            inode: dentry.inode().clone(),
+            dentry,
+            flags,
+            offset: Mutex::new(0),
+        }
+    }
+}
+
+impl File for InodeFile {
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        if !self.readable() {
+            return Err(Errno::EBADF.into());
+        }
+        let mut offset = self.offset.lock();
+        let len = self.inode.read_at(*offset, buf)?;
+        *offset += len as u64;
+        Ok(len)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        if !self.writable() {
+            return Err(Errno::EBADF.into());
+        }
+        let mut offset = self.offset.lock();
+        if self.flags & O_APPEND != 0 {
+            *offset = self.inode.size();
+        }
+        let len = self.inode.write_at(*offset, buf)?;
+        *offset += len as u64;
+        Ok(len)
+    }
+
+    fn stat(&self) -> Result<Stat> {
+        self.inode.stat()
+    }
+
+    fn readable(&self) -> bool {
+        flags_readable(self.flags)
+    }
+
+    fn writable(&self) -> bool {
+        flags_writable(self.flags)
+    }
+
+    fn seek(&self, pos: SeekFrom) -> Result<u64> {
+        let mut offset = self.offset.lock();
+        let new = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::Current(delta) => offset.checked_add_signed(delta),
+            SeekFrom::End(delta) => self.inode.size().checked_add_signed(delta),
+        };
+        *offset = new.ok_or(Errno::EINVAL)?;
+        Ok(*offset)
+    }
+
+    // 目录的偏移量是目录项的序号
+    fn readdir(&self) -> Result<Option<DirEntry>> {
+        let mut offset = self.offset.lock();
+        let entry = self.inode.readdir(*offset as usize)?;
+        if entry.is_some() {
+            *offset += 1;
+        }
+        Ok(entry)
+    }
+
+    fn inode(&self) -> Option<InodeRef> {
+        Some(self.inode.clone())
+    }
+
+    fn path(&self) -> Option<String> {
+        Some(self.dentry.path())
+    }
+}
@@ -0,0 +1,796 @@
+use super::{
+    layout::{
+        read_struct, write_struct, DiskDirent, DiskInode, BSIZE, DIRENT_SIZE, DIRSIZ, MAXFILE,
+        MAXOPBLOCKS, NDIRECT, NINDIRECT,
+    },
+    Deferred, Xxfs,
+};
+use crate::{
+    block::bcache::bread,
+    error::{Errno, Result},
+    fs::{
+        def::{DirEntry, FileType, Stat, S_IFMT},
+        pagecache::PageCache,
+        vfs::{Inode, InodeRef},
+    },
+    mm::{def::PGSZ, kmem_cache::KBox, page_frame::PageFrame},
+    proc::cred::current_cred,
+    sched::sleeplock::SleepLock,
+};
+use alloc::{string::String, sync::Arc, vec};
+
+// 一个事务最多写入的字节数：数据块及其位图块、间接块与inode块都要计入日志
+const MAX_WRITE: usize = (MAXOPBLOCKS - 1 - 3) / 2 * BSIZE;
+const MAX_SIZE: u64 = (MAXFILE * BSIZE) as u64;
+
+pub struct XxfsInode {
+    fs: Arc<Xxfs>,
+    inum: u32,
+    // 磁盘inode的副本，修改后由update写回(经过日志)
+    dinode: SleepLock<KBox<DiskInode>>,
+    // 普通文件的页缓存，读入与更新都持有dinode的锁
+    cache: PageCache,
+}
+
+impl XxfsInode {
+    pub(super) fn new(fs: Arc<Xxfs>, inum: u32, dinode: KBox<DiskInode>) -> Self {
+        Self {
+            fs,
+            inum,
+            dinode: SleepLock::new(dinode),
+            cache: PageCache::new(),
+        }
+    }
+
+    pub fn inum(&self) -> u32 {
+        self.inum
+    }
+
+    pub fn mode(&self) -> u16 {
+        self.dinode.lock().mode
+    }
+
+    // 放弃事务之后用磁盘上的inode替换内存中的副本
+    pub(super) fn reload(&self, dinode: DiskInode) {
+        **self.dinode.lock() = dinode;
+    }
+
+    pub(super) fn cache(&self) -> &PageCache {
+        &self.cache
+    }
+
+    // copy a modified in-memory inode to disk, must be called inside a transaction
+    fn update(&self, dinode: &DiskInode) -> Result<()> {
+        let sb = self.fs.super_block();
+        let mut buf = bread(self.fs.dev(), sb.iblock(self.inum) as u64)?;
+        write_struct(&mut buf[DiskInode::offset(self.inum)..], dinode);
+        self.fs.log().write(&buf)
+    }
+
+    // 读出或者分配addr指向的块
+    fn slot(&self, addr: &mut u32, alloc: bool) -> Result<Option<u32>> {
+        if *addr == 0 {
+            if !alloc {
+                return Ok(None);
+            }
+            *addr = self.fs.balloc()?;
+        }
+        Ok(Some(*addr))
+    }
+
+    // 间接块block中的第index项
+    fn indirect_slot(&self, block: u32, index: usize, alloc: bool) -> Result<Option<u32>> {
+        let mut buf = bread(self.fs.dev(), block as u64)?;
+        let mut addr: u32 = read_struct(&buf[index * 4..]);
+        if addr == 0 && alloc {
+            addr = self.fs.balloc()?;
+            write_struct(&mut buf[index * 4..], &addr);
+            self.fs.log().write(&buf)?;
+        }
+        Ok((addr != 0).then_some(addr))
+    }
+
+    // 文件中第bn块对应的磁盘块号，alloc为false时空洞返回None
+    fn bmap(&self, dinode: &mut DiskInode, bn: usize, alloc: bool) -> Result<Option<u32>> {
+        if bn < NDIRECT {
+            return self.slot(&mut dinode.addrs[bn], alloc);
+        }
+        let bn = bn - NDIRECT;
+        if bn < NINDIRECT {
+            let Some(indirect) = self.slot(&mut dinode.addrs[NDIRECT], alloc)? else {
+                return Ok(None);
+            };
+            return self.indirect_slot(indirect, bn, alloc);
+        }
+        let bn = bn - NINDIRECT;
+        if bn < NINDIRECT * NINDIRECT {
+            let Some(double) = self.slot(&mut dinode.addrs[NDIRECT + 1], alloc)? else {
+                return Ok(None);
+            };
+            let Some(indirect) = self.indirect_slot(double, bn / NINDIRECT, alloc)? else {
+                return Ok(None);
+            };
+            return self.indirect_slot(indirect, bn % NINDIRECT, alloc);
+        }
+        Err(Errno::EFBIG.into())
+    }
+
+    // 释放level级间接块中从第first个数据块开始的部分，返回整个间接块是否可以释放
+    fn free_indirect(&self, block: u32, first: usize, level: u32) -> Result<bool> {
+        let per_entry = NINDIRECT.pow(level - 1);
+        let mut buf = bread(self.fs.dev(), block as u64)?;
+        let mut changed = false;
+        for i in 0..NINDIRECT {
+            let addr: u32 = read_struct(&buf[i * 4..]);
+            let start = i * per_entry;
+            if addr == 0 || start + per_entry <= first {
+                continue;
+            }
+            let free =
+                level == 1 || self.free_indirect(addr, first.saturating_sub(start), level - 1)?;
+            if free {
+                self.fs.bfree(addr)?;
+                // 整块都要释放时不必修改它的内容
+                if first > 0 {
+                    write_struct(&mut buf[i * 4..], &0u32);
+                    changed = true;
+                }
+            }
+        }
+        if first == 0 {
+            return Ok(true);
+        }
+        if changed {
+            self.fs.log().write(&buf)?;
+        }
+        Ok(false)
+    }
+
+    // 释放size之后的所有块，并把最后一块中size之后的部分清零
+    fn truncate_blocks(&self, dinode: &mut DiskInode, size: u64) -> Result<()> {
+        let keep = (size as usize).div_ceil(BSIZE);
+        for i in keep.min(NDIRECT)..NDIRECT {
+            if dinode.addrs[i] != 0 {
+                self.fs.bfree(dinode.addrs[i])?;
+                dinode.addrs[i] = 0;
+            }
+        }
+        let levels = [(NDIRECT, NDIRECT, 1), (NDIRECT + 1, NDIRECT + NINDIRECT, 2)];
+        for (slot, start, level) in levels {
+            let addr = dinode.addrs[slot];
+            if addr != 0 && self.free_indirect(addr, keep.saturating_sub(start), level)? {
+                self.fs.bfree(addr)?;
+                dinode.addrs[slot] = 0;
+            }
+        }
+
+        let tail = size as usize % BSIZE;
+        if tail != 0 {
+            if let Some(block) = self.bmap(dinode, size as usize / BSIZE, false)? {
+                let mut buf = bread(self.fs.dev(), block as u64)?;
+                buf[tail..].fill(0);
+                self.fs.log().write(&buf)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn readi(&self, dinode: &mut DiskInode, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let size = dinode.size as u64;
+        if offset >= size {
+            return Ok(0);
+        }
+        let n = (buf.len() as u64).min(size - offset) as usize;
+        let mut done = 0;
+        while done < n {
+            let pos = offset as usize + done;
+            let boff = pos % BSIZE;
+            let len = (BSIZE - boff).min(n - done);
+            match self.bmap(dinode, pos / BSIZE, false)? {
+                Some(block) => {
+                    let data = bread(self.fs.dev(), block as u64)?;
+                    buf[done..done + len].copy_from_slice(&data[boff..boff + len]);
+                }
+                None => buf[done..done + len].fill(0),
+            }
+            done += len;
+        }
+        Ok(n)
+    }
+
+    // 必须在事务中调用，调用者负责update
+    fn writei(&self, dinode: &mut DiskInode, offset: u64, buf: &[u8]) -> Result<usize> {
+        if offset >= MAX_SIZE {
+            return Err(Errno::EFBIG.into());
+        }
+        let n = (buf.len() as u64).min(MAX_SIZE - offset) as usize;
+        let mut done = 0;
+        while done < n {
+            let pos = offset as usize + done;
+            let boff = pos % BSIZE;
+            let len = (BSIZE - boff).min(n - done);
+            let block = self
+                .bmap(dinode, pos / BSIZE, true)?
+                .expect("bmap failed to allocate");
+            let mut data = bread(self.fs.dev(), block as u64)?;
+            data[boff..boff + len].copy_from_slice(&buf[done..done + len]);
+            self.fs.log().write(&data)?;
+            done += len;
+        }
+        dinode.size = dinode.size.max((offset as usize + n) as u32);
+        Ok(n)
+    }
+
+    fn read_dirent(&self, dinode: &mut DiskInode, offset: u64) -> Result<DiskDirent> {
+        let mut bytes = [0; DIRENT_SIZE];
+        if self.readi(dinode, offset, &mut bytes)? != DIRENT_SIZE {
+            return Err(Errno::EIO.into());
+        }
+        Ok(read_struct(&bytes))
+    }
+
+    // 返回(inode号, 目录项偏移)
+    fn dirlookup(&self, dinode: &mut DiskInode, name: &str) -> Result<Option<(u32, u64)>> {
+        for offset in (0..dinode.size as u64).step_by(DIRENT_SIZE) {
+            let dirent = self.read_dirent(dinode, offset)?;
+            if dirent.inum != 0 && dirent.name() == name.as_bytes() {
+                return Ok(Some((dirent.inum, offset)));
+            }
+        }
+        Ok(None)
+    }
+
+    // 写入一个目录项，优先使用空闲的目录项
+    fn dirlink(&self, dinode: &mut DiskInode, name: &str, inum: u32) -> Result<()> {
+        let dirent = DiskDirent::new(inum, name).ok_or(Errno::ENAMETOOLONG)?;
+        let mut slot = dinode.size as u64;
+        for offset in (0..dinode.size as u64).step_by(DIRENT_SIZE) {
+            if self.read_dirent(dinode, offset)?.inum == 0 {
+                slot = offset;
+                break;
+            }
+        }
+        self.write_dirent(dinode, slot, &dirent)
+    }
+
+    fn write_dirent(&self, dinode: &mut DiskInode, offset: u64, dirent: &DiskDirent) -> Result<()> {
+        let mut bytes = [0; DIRENT_SIZE];
+        write_struct(&mut bytes, dirent);
+        self.writei(dinode, offset, &bytes)?;
+        Ok(())
+    }
+
+    // 除了.与..之外没有其他目录项
+    fn dir_is_empty(&self, dinode: &mut DiskInode) -> Result<bool> {
+        for offset in (0..dinode.size as u64).step_by(DIRENT_SIZE) {
+            let dirent = self.read_dirent(dinode, offset)?;
+            if dirent.inum != 0 && dirent.name() != b"." && dirent.name() != b".." {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    // 把页缓存中的一页写回文件，文件末尾之后的部分不写入，每页拆成多个事务
+    fn write_page(&self, index: u64, page: &PageFrame) -> Result<()> {
+        let start = index * PGSZ as u64;
+        let mut written = 0;
+        while written < PGSZ {
+            let len = (PGSZ - written).min(MAX_WRITE);
+            let n = self.fs.transaction(|| {
+                let mut dinode = self.dinode.lock();
+                let pos = start + written as u64;
+                let size = dinode.size as u64;
+                if pos >= size {
+                    return Ok(0);
+                }
+                let len = (len as u64).min(size - pos) as usize;
+                self.writei(&mut dinode, pos, &page.as_bytes()[written..written + len])?;
+                self.update(&dinode)?;
+                Ok(len)
+            })?;
+            if n == 0 {
+                break;
+            }
+            written += n;
+        }
+        Ok(())
+    }
+
+    // 在目录中新建inode，必须在事务中调用并持有dir的锁
+    fn alloc_child(
+        &self,
+        dir: &mut DiskInode,
+        name: &str,
+        file_type: FileType,
+        mode: u32,
+    ) -> Result<Arc<XxfsInode>> {
+        let fs = &self.fs;
+        if dir.nlink == 0 {
+            return Err(Errno::ENOENT.into());
+        }
+        if FileType::from_mode(dir.mode as u32) != Some(FileType::Directory) {
+            return Err(Errno::ENOTDIR.into());
+        }
+        if self.dirlookup(dir, name)?.is_some() {
+            return Err(Errno::EEXIST.into());
+        }
+
+        // 新的inode属于创建它的进程，磁盘上存不下的uid与gid与chown一样拒绝
+        let cred = current_cred();
+        let uid = u16::try_from(cred.uid).map_err(|_| Errno::EINVAL)?;
+        let gid = u16::try_from(cred.gid).map_err(|_| Errno::EINVAL)?;
+        let mode = (file_type.to_mode() | (mode & 0o7777)) as u16;
+        let inum = fs.ialloc(mode, uid, gid)?;
+        let inode = fs.iget(inum)?;
+        {
+            let mut dinode = inode.dinode.lock();
+            dinode.nlink = 1;
+            if file_type == FileType::Directory {
+                // "."指向自己，".."增加父目录的链接数
+                dinode.nlink = 2;
+                inode.dirlink(&mut dinode, ".", inum)?;
+                inode.dirlink(&mut dinode, "..", self.inum)?;
+                dir.nlink += 1;
+            }
+            inode.update(&dinode)?;
+        }
+        self.dirlink(dir, name, inum)?;
+        self.update(dir)?;
+        Ok(inode)
+    }
+
+    // 没有链接的inode在最后一个引用释放之后由iput_deferred删除，必须在事务中调用
+    pub(super) fn free(&self) -> Result<()> {
+        let mut dinode = self.dinode.lock();
+        // 减少链接数的事务被放弃时，磁盘上的inode仍然有链接
+        if dinode.nlink != 0 || dinode.is_free() {
+            return Ok(());
+        }
+        self.truncate_blocks(&mut dinode, 0)?;
+        **dinode = DiskInode::default();
+        self.update(&dinode)
+    }
+
+    // rename的主体，必须在事务中调用并持有两个目录的锁，dst为None时在同一目录中改名
+    // 返回被替换的inode，调用者在事务结束后释放它
+    fn move_entry(
+        &self,
+        src: &mut DiskInode,
+        mut dst: Option<(&XxfsInode, &mut DiskInode)>,
+        old_name: &str,
+        new_name: &str,
+    ) -> Result<Option<Arc<XxfsInode>>> {
+        let dst_mode = dst.as_ref().map_or(src.mode, |(_, dinode)| dinode.mode);
+        for mode in [src.mode, dst_mode] {
+            if FileType::from_mode(mode as u32) != Some(FileType::Directory) {
+                return Err(Errno::ENOTDIR.into());
+            }
+        }
+        if dst.as_ref().is_some_and(|(_, dinode)| dinode.nlink == 0) {
+            return Err(Errno::ENOENT.into());
+        }
+        let (inum, old_offset) = self.dirlookup(src, old_name)?.ok_or(Errno::ENOENT)?;
+        let child = self.fs.iget(inum)?;
+        let child_is_dir = child.is_dir();
+
+        let victim = {
+            let target: &mut DiskInode = match dst.as_mut() {
+                Some((_, dinode)) => dinode,
+                None => src,
+            };
+            match self.dirlookup(target, new_name)? {
+                // 两个名字指向同一个inode时什么也不做
+                Some((existing, _)) if existing == inum => return Ok(None),
+                Some((existing, offset)) => {
+                    let victim = self.fs.iget(existing)?;
+                    let mut dinode = victim.dinode.lock();
+                    let victim_is_dir =
+                        FileType::from_mode(dinode.mode as u32) == Some(FileType::Directory);
+                    match (child_is_dir, victim_is_dir) {
+                        (true, false) => return Err(Errno::ENOTDIR.into()),
+                        (false, true) => return Err(Errno::EISDIR.into()),
+                        (true, true) if !victim.dir_is_empty(&mut dinode)? => {
+                            return Err(Errno::ENOTEMPTY.into());
+                        }
+                        _ => {}
+                    }
+                    // 原来的目录项直接指向被移动的inode
+                    let dirent = DiskDirent::new(inum, new_name).ok_or(Errno::ENAMETOOLONG)?;
+                    self.write_dirent(target, offset, &dirent)?;
+                    if victim_is_dir {
+                        dinode.nlink = 0;
+                        target.nlink -= 1;
+                    } else {
+                        dinode.nlink -= 1;
+                    }
+                    victim.update(&dinode)?;
+                    drop(dinode);
+                    Some(victim)
+                }
+                None => {
+                    self.dirlink(target, new_name, inum)?;
+                    None
+                }
+            }
+        };
+        self.write_dirent(src, old_offset, &DiskDirent::default())?;
+
+        // 移到其他目录中的目录要修改它的..
+        if let Some((dir, dinode)) = dst.as_mut() {
+            if child_is_dir {
+                let mut child_dinode = child.dinode.lock();
+                let (_, offset) = child
+                    .dirlookup(&mut child_dinode, "..")?
+                    .ok_or(Errno::EIO)?;
+                let dirent = DiskDirent::new(dir.inum, "..").unwrap();
+                child.write_dirent(&mut child_dinode, offset, &dirent)?;
+                child.update(&child_dinode)?;
+                src.nlink -= 1;
+                dinode.nlink += 1;
+            }
+            dir.update(dinode)?;
+        }
+        self.update(src)?;
+        Ok(victim)
+    }
+}
+
+// 释放inode与写回页缓存都需要事务，交给Xxfs::iput_deferred
+impl Drop for XxfsInode {
+    fn drop(&mut self) {
+        let dinode = self.dinode.lock();
+        // 已经释放的inode不需要再处理
+        if !dinode.is_free() {
+            if dinode.nlink == 0 {
+                self.fs.defer(self.inum, Deferred::Free);
+            } else {
+                let pages = self.cache.take_dirty();
+                if !pages.is_empty() {
+                    self.fs.defer(self.inum, Deferred::WriteBack(pages));
+                }
+            }
+        }
+        drop(dinode);
+        self.fs.forget(self.inum);
+    }
+}
+
+impl Inode for XxfsInode {
+    fn ino(&self) -> u64 {
+        self.inum as u64
+    }
+
+    fn file_type(&self) -> FileType {
+        FileType::from_mode(self.mode() as u32).unwrap_or(FileType::Regular)
+    }
+
+    fn stat(&self) -> Result<Stat> {
+        let dinode = self.dinode.lock();
+        Ok(Stat {
+            dev: self.fs.dev() as u64,
+            ino: self.inum as u64,
+            mode: dinode.mode as u32,
+            nlink: dinode.nlink as u32,
+            uid: dinode.uid as u32,
+            gid: dinode.gid as u32,
+            size: dinode.size as i64,
+            blksize: BSIZE as i32,
+            blocks: (dinode.size as i64 + 511) / 512,
+            mtime: dinode.mtime as i64,
+            ..Default::default()
+        })
+    }
+
+    fn size(&self) -> u64 {
+        self.dinode.lock().size as u64
+    }
+
+    fn lookup(&self, name: &str) -> Result<InodeRef> {
+        if name.len() > DIRSIZ {
+            return Err(Errno::ENAMETOOLONG.into());
+        }
+        let inum = {
+            let mut dinode = self.dinode.lock();
+            if FileType::from_mode(dinode.mode as u32) != Some(FileType::Directory) {
+                return Err(Errno::ENOTDIR.into());
+            }
+            self.dirlookup(&mut dinode, name)?.ok_or(Errno::ENOENT)?.0
+        };
+        Ok(self.fs.iget(inum)?)
+    }
+
+    fn create(&self, name: &str, file_type: FileType, mode: u32) -> Result<InodeRef> {
+        if name.len() > DIRSIZ {
+            return Err(Errno::ENAMETOOLONG.into());
+        }
+        if name == "." || name == ".." {
+            return Err(Errno::EEXIST.into());
+        }
+        // 符号链接需要同时给出目标路径
+        if file_type == FileType::Symlink {
+            return Err(Errno::EINVAL.into());
+        }
+        self.fs.transaction(|| {
+            let mut dir = self.dinode.lock();
+            Ok(self.alloc_child(&mut dir, name, file_type, mode)? as InodeRef)
+        })
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<InodeRef> {
+        if name.len() > DIRSIZ {
+            return Err(Errno::ENAMETOOLONG.into());
+        }
+        if name == "." || name == ".." {
+            return Err(Errno::EEXIST.into());
+        }
+        if target.is_empty() {
+            return Err(Errno::ENOENT.into());
+        }
+        // 目标路径最多占用一个数据块
+        if target.len() > BSIZE {
+            return Err(Errno::ENAMETOOLONG.into());
+        }
+        self.fs.transaction(|| {
+            let mut dir = self.dinode.lock();
+            let inode = self.alloc_child(&mut dir, name, FileType::Symlink, 0o777)?;
+            {
+                let mut dinode = inode.dinode.lock();
+                inode.writei(&mut dinode, 0, target.as_bytes())?;
+                inode.update(&dinode)?;
+            }
+            Ok(inode as InodeRef)
+        })
+    }
+
+    // 目录必须为空，inode在最后一个引用释放时才删除
+    fn unlink(&self, name: &str) -> Result<()> {
+        if name == "." || name == ".." {
+            return Err(Errno::EINVAL.into());
+        }
+        let child = self.fs.transaction(|| {
+            let mut dir = self.dinode.lock();
+            if FileType::from_mode(dir.mode as u32) != Some(FileType::Directory) {
+                return Err(Errno::ENOTDIR.into());
+            }
+            let (inum, offset) = self.dirlookup(&mut dir, name)?.ok_or(Errno::ENOENT)?;
+            let child = self.fs.iget(inum)?;
+            let mut dinode = child.dinode.lock();
+            let is_dir = FileType::from_mode(dinode.mode as u32) == Some(FileType::Directory);
+            if is_dir && !child.dir_is_empty(&mut dinode)? {
+                return Err(Errno::ENOTEMPTY.into());
+            }
+            self.write_dirent(&mut dir, offset, &DiskDirent::default())?;
+            if is_dir {
+                dinode.nlink = 0;
+                dir.nlink -= 1;
+            } else {
+                dinode.nlink -= 1;
+            }
+            child.update(&dinode)?;
+            self.update(&dir)?;
+            drop(dinode);
+            Ok(child)
+        })?;
+        // 释放inode需要新的事务
+        drop(child);
+        self.fs.iput_deferred();
+        Ok(())
+    }
+
+    fn link(&self, name: &str, inode: &InodeRef) -> Result<()> {
+        if name.len() > DIRSIZ {
+            return Err(Errno::ENAMETOOLONG.into());
+        }
+        if name == "." || name == ".." {
+            return Err(Errno::EEXIST.into());
+        }
+        let target = self.fs.iget(inode.ino() as u32)?;
+        self.fs.transaction(|| {
+            let mut dir = self.dinode.lock();
+            if dir.nlink == 0 {
+                return Err(Errno::ENOENT.into());
+            }
+            if FileType::from_mode(dir.mode as u32) != Some(FileType::Directory) {
+                return Err(Errno::ENOTDIR.into());
+            }
+            if self.dirlookup(&mut dir, name)?.is_some() {
+                return Err(Errno::EEXIST.into());
+            }
+            let mut dinode = target.dinode.lock();
+            if FileType::from_mode(dinode.mode as u32) == Some(FileType::Directory) {
+                return Err(Errno::EPERM.into());
+            }
+            if dinode.nlink == u16::MAX {
+                return Err(Errno::EMLINK.into());
+            }
+            self.dirlink(&mut dir, name, target.inum)?;
+            dinode.nlink += 1;
+            target.update(&dinode)?;
+            self.update(&dir)
+        })
+    }
+
+    fn rename(&self, old_name: &str, new_dir: &InodeRef, new_name: &str) -> Result<()> {
+        if new_name.len() > DIRSIZ {
+            return Err(Errno::ENAMETOOLONG.into());
+        }
+        if [old_name, new_name]
+            .iter()
+            .any(|name| *name == "." || *name == "..")
+        {
+            return Err(Errno::EINVAL.into());
+        }
+        let new_dir = self.fs.iget(new_dir.ino() as u32)?;
+        let replaced = self.fs.transaction(|| {
+            // 按inode号的顺序锁住两个目录
+            if new_dir.inum == self.inum {
+                let mut dir = self.dinode.lock();
+                self.move_entry(&mut dir, None, old_name, new_name)
+            } else if self.inum < new_dir.inum {
+                let mut src = self.dinode.lock();
+                let mut dst = new_dir.dinode.lock();
+                self.move_entry(&mut src, Some((&new_dir, &mut dst)), old_name, new_name)
+            } else {
+                let mut dst = new_dir.dinode.lock();
+                let mut src = self.dinode.lock();
+                self.move_entry(&mut src, Some((&new_dir, &mut dst)), old_name, new_name)
+            }
+        })?;
+        drop(replaced);
+        self.fs.iput_deferred();
+        Ok(())
+    }
+
+    fn readdir(&self, index: usize) -> Result<Option<DirEntry>> {
+        let mut dinode = self.dinode.lock();
+        if FileType::from_mode(dinode.mode as u32) != Some(FileType::Directory) {
+            return Err(Errno::ENOTDIR.into());
+        }
+        let mut seen = 0;
+        for offset in (0..dinode.size as u64).step_by(DIRENT_SIZE) {
+            let dirent = self.read_dirent(&mut dinode, offset)?;
+            if dirent.inum == 0 {
+                continue;
+            }
+            if seen == index {
+                let child = self.fs.read_dinode(dirent.inum)?;
+                return Ok(Some(DirEntry {
+                    ino: dirent.inum as u64,
+                    name: String::from_utf8_lossy(dirent.name()).into_owned(),
+                    file_type: FileType::from_mode(child.mode as u32).unwrap_or(FileType::Regular),
+                }));
+            }
+            seen += 1;
+        }
+        Ok(None)
+    }
+
+    // 普通文件经过页缓存读取
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let mut dinode = self.dinode.lock();
+        match FileType::from_mode(dinode.mode as u32) {
+            Some(FileType::Directory) => return Err(Errno::EISDIR.into()),
+            Some(FileType::Regular) => {}
+            _ => return self.readi(&mut dinode, offset, buf),
+        }
+        let size = dinode.size as u64;
+        if offset >= size {
+            return Ok(0);
+        }
+        let n = (buf.len() as u64).min(size - offset) as usize;
+        self.cache.read(offset, &mut buf[..n], |index, page| {
+            self.readi(&mut dinode, index * PGSZ as u64, page)?;
+            Ok(())
+        })?;
+        Ok(n)
+    }
+
+    // 大的写操作拆成多个事务，每个事务不会超出日志的容量
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize> {
+        let mut written = 0;
+        while written < buf.len() {
+            let len = (buf.len() - written).min(MAX_WRITE);
+            let chunk = &buf[written..written + len];
+            let result = self.fs.transaction(|| {
+                let mut dinode = self.dinode.lock();
+                if FileType::from_mode(dinode.mode as u32) == Some(FileType::Directory) {
+                    return Err(Errno::EISDIR.into());
+                }
+                let n = self.writei(&mut dinode, offset + written as u64, chunk)?;
+                self.update(&dinode)?;
+                self.cache.update(offset + written as u64, &chunk[..n]);
+                Ok(n)
+            });
+            match result {
+                Ok(n) => {
+                    written += n;
+                    if n < len {
+                        break;
+                    }
+                }
+                Err(_) if written > 0 => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(written)
+    }
+
+    fn truncate(&self, size: u64) -> Result<()> {
+        if size > MAX_SIZE {
+            return Err(Errno::EFBIG.into());
+        }
+        self.fs.transaction(|| {
+            let mut dinode = self.dinode.lock();
+            if FileType::from_mode(dinode.mode as u32) == Some(FileType::Directory) {
+                return Err(Errno::EISDIR.into());
+            }
+            if size < dinode.size as u64 {
+                self.truncate_blocks(&mut dinode, size)?;
+                self.cache.truncate(size);
+            }
+            dinode.size = size as u32;
+            self.update(&dinode)
+        })
+    }
+
+    fn get_page(&self, index: u64) -> Result<Arc<PageFrame>> {
+        let mut dinode = self.dinode.lock();
+        if FileType::from_mode(dinode.mode as u32) != Some(FileType::Regular) {
+            return Err(Errno::ENODEV.into());
+        }
+        self.cache.get_page(index, |page| {
+            self.readi(&mut dinode, index * PGSZ as u64, page)?;
+            Ok(())
+        })
+    }
+
+    fn set_page_dirty(&self, index: u64) {
+        self.cache.set_dirty(index);
+    }
+
+    // 写回dirty的页，失败时这一页与之后的页保留dirty标记
+    fn sync(&self) -> Result<()> {
+        let pages = self.cache.take_dirty();
+        for (i, (index, page)) in pages.iter().enumerate() {
+            if let Err(err) = self.write_page(*index, page) {
+                pages[i..]
+                    .iter()
+                    .for_each(|(index, _)| self.cache.set_dirty(*index));
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    fn chmod(&self, mode: u32) -> Result<()> {
+        self.fs.transaction(|| {
+            let mut dinode = self.dinode.lock();
+            dinode.mode = (dinode.mode & S_IFMT as u16) | (mode & 0o7777) as u16;
+            self.update(&dinode)
+        })
+    }
+
+    // 磁盘上的uid与gid只有16位
+    fn chown(&self, uid: u32, gid: u32) -> Result<()> {
+        let uid = u16::try_from(uid).map_err(|_| Errno::EINVAL)?;
+        let gid = u16::try_from(gid).map_err(|_| Errno::EINVAL)?;
+        self.fs.transaction(|| {
+            let mut dinode = self.dinode.lock();
+            dinode.uid = uid;
+            dinode.gid = gid;
+            self.update(&dinode)
+        })
+    }
+
+    fn readlink(&self) -> Result<String> {
+        let mut dinode = self.dinode.lock();
+        if FileType::from_mode(dinode.mode as u32) != Some(FileType::Symlink) {
+            return Err(Errno::EINVAL.into());
+        }
+        let mut target = vec![0; dinode.size as usize];
+        let len = self.readi(&mut dinode, 0, &mut target)?;
+        target.truncate(len);
+        Ok(String::from_utf8_lossy(&target).into_owned())
+    }
+}
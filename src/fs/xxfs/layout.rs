@@ -0,0 +1,190 @@
+// On-disk layout of xxfs
+// 内核与宿主机上的mkfs共用这个文件，只能依赖core
+//
+// [ boot block | super block | log | inode blocks | free bit map | data blocks ]
+
+use core::{mem::size_of, ptr};
+
+pub const BSIZE: usize = 1024;
+pub const XXFS_MAGIC: u32 = 0x7878_6673;
+
+// 超级块所在的块号
+pub const SUPERBLOCK_NO: u32 = 1;
+// 根目录的inode号，0号inode不使用
+pub const ROOTINO: u32 = 1;
+
+pub const NDIRECT: usize = 10;
+pub const NINDIRECT: usize = BSIZE / size_of::<u32>();
+// addrs[NDIRECT]为一级间接块，addrs[NDIRECT + 1]为二级间接块
+pub const MAXFILE: usize = NDIRECT + NINDIRECT + NINDIRECT * NINDIRECT;
+
+// 一次文件系统操作最多写的块数
+pub const MAXOPBLOCKS: usize = 10;
+// 日志区的数据块数，另有一个日志头
+pub const LOGSIZE: usize = MAXOPBLOCKS * 3;
+
+// inodes per block
+pub const IPB: usize = BSIZE / size_of::<DiskInode>();
+// bitmap bits per block
+pub const BPB: usize = BSIZE * 8;
+
+// 目录项中文件名的最大长度，不足时以0填充
+pub const DIRSIZ: usize = 28;
+
+/// # Safety
+/// Implementors must be `repr(C)` types without padding for which every bit
+/// pattern is valid.
+pub unsafe trait OnDisk: Copy {}
+
+pub fn read_struct<T: OnDisk>(bytes: &[u8]) -> T {
+    assert!(bytes.len() >= size_of::<T>());
+    unsafe { ptr::read_unaligned(bytes.as_ptr() as *const T) }
+}
+
+pub fn write_struct<T: OnDisk>(bytes: &mut [u8], value: &T) {
+    assert!(bytes.len() >= size_of::<T>());
+    unsafe { ptr::write_unaligned(bytes.as_mut_ptr() as *mut T, *value) }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct DiskSuperBlock {
+    pub magic: u32,
+    // size of file system image (blocks)
+    pub size: u32,
+    // number of data blocks
+    pub nblocks: u32,
+    pub ninodes: u32,
+    // number of log blocks, including the header
+    pub nlog: u32,
+    pub logstart: u32,
+    pub inodestart: u32,
+    pub bmapstart: u32,
+}
+
+unsafe impl OnDisk for u32 {}
+unsafe impl OnDisk for DiskSuperBlock {}
+
+impl DiskSuperBlock {
+    // 计算size个块、ninodes个inode的文件系统的布局
+    pub fn new(size: u32, ninodes: u32) -> Self {
+        let nlog = LOGSIZE as u32 + 1;
+        let ninodeblocks = ninodes / IPB as u32 + 1;
+        let nbitmap = size / BPB as u32 + 1;
+        let logstart = SUPERBLOCK_NO + 1;
+        let inodestart = logstart + nlog;
+        let bmapstart = inodestart + ninodeblocks;
+        let nmeta = bmapstart + nbitmap;
+        Self {
+            magic: XXFS_MAGIC,
+            size,
+            nblocks: size.saturating_sub(nmeta),
+            ninodes,
+            nlog,
+            logstart,
+            inodestart,
+            bmapstart,
+        }
+    }
+
+    // 各区域按顺序排列且互不重叠，日志至少能容纳一个操作
+    // 损坏的或者其他文件系统的超级块在挂载时被拒绝，之后的计算不会溢出
+    pub fn is_valid(&self) -> bool {
+        let inode_end = self.inodestart as u64 + (self.ninodes as u64).div_ceil(IPB as u64);
+        let bmap_end = self.bmapstart as u64 + (self.size as u64).div_ceil(BPB as u64);
+        self.magic == XXFS_MAGIC
+            && self.nblocks <= self.size
+            && self.ninodes > ROOTINO
+            && self.nlog as usize > MAXOPBLOCKS
+            && self.logstart > SUPERBLOCK_NO
+            && self.logstart as u64 + self.nlog as u64 <= self.inodestart as u64
+            && inode_end <= self.bmapstart as u64
+            && bmap_end <= self.data_start() as u64
+    }
+
+    // 第一个数据块，之前的块在位图中总是标记为已用
+    pub fn data_start(&self) -> u32 {
+        self.size - self.nblocks
+    }
+
+    // block containing inode inum
+    pub fn iblock(&self, inum: u32) -> u32 {
+        self.inodestart + inum / IPB as u32
+    }
+
+    // block of free map containing bit for block b
+    pub fn bblock(&self, b: u32) -> u32 {
+        self.bmapstart + b / BPB as u32
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct DiskInode {
+    // 文件类型与权限，与st_mode相同，0表示空闲
+    pub mode: u16,
+    pub nlink: u16,
+    pub uid: u16,
+    pub gid: u16,
+    pub size: u32,
+    pub mtime: u32,
+    pub addrs: [u32; NDIRECT + 2],
+}
+
+unsafe impl OnDisk for DiskInode {}
+
+impl DiskInode {
+    pub fn is_free(&self) -> bool {
+        self.mode == 0
+    }
+
+    // inode inum在所在块中的偏移
+    pub fn offset(inum: u32) -> usize {
+        (inum as usize % IPB) * size_of::<DiskInode>()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct DiskDirent {
+    // 0表示空闲的目录项
+    pub inum: u32,
+    pub name: [u8; DIRSIZ],
+}
+
+unsafe impl OnDisk for DiskDirent {}
+
+pub const DIRENT_SIZE: usize = size_of::<DiskDirent>();
+
+impl DiskDirent {
+    pub fn new(inum: u32, name: &str) -> Option<Self> {
+        if name.is_empty() || name.len() > DIRSIZ {
+            return None;
+        }
+        let mut dirent = Self {
+            inum,
+            name: [0; DIRSIZ],
+        };
+        dirent.name[..name.len()].copy_from_slice(name.as_bytes());
+        Some(dirent)
+    }
+
+    pub fn name(&self) -> &[u8] {
+        let len = self.name.iter().position(|&b| b == 0).unwrap_or(DIRSIZ);
+        &self.name[..len]
+    }
+}
+
+// 日志头，block[i]为日志中第i块的目标块号
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct LogHeader {
+    pub n: u32,
+    pub block: [u32; LOGSIZE],
+}
+
+unsafe impl OnDisk for LogHeader {}
+
+const _: () = assert!(IPB * size_of::<DiskInode>() == BSIZE);
+const _: () = assert!(BSIZE / DIRENT_SIZE * DIRENT_SIZE == BSIZE);
+const _: () = assert!(size_of::<LogHeader>() <= BSIZE);
@@ -0,0 +1,236 @@
+use super::layout::{read_struct, write_struct, DiskSuperBlock, LogHeader, LOGSIZE, MAXOPBLOCKS};
+use crate::{
+    block::{
+        bcache::{bdiscard, bpin, bread, bunpin, bwrite, BufRef},
+        DevId,
+    },
+    error::{Errno, Result},
+};
+use alloc::vec::Vec;
+use core::hint::spin_loop;
+use xx_mutex_lock::Mutex;
+use xxos_log::{info, warn};
+
+// Write-ahead log
+// 一个操作修改的块先记在内存中(并pin在缓存里)，操作结束时提交：
+// 先把这些块写到日志区，再写日志头，这之后才写回原位置，最后清空日志头
+// 崩溃后重新挂载时，日志头中记录的块会被重新写回，保证操作要么全部完成，要么完全没有发生
+// 操作失败时放弃事务：丢弃缓存中被修改的块，磁盘上什么也没有改变
+// 为了能够单独放弃一个操作，同一时刻只有一个操作在进行，不再把多个操作合并提交
+pub struct Log {
+    dev: DevId,
+    start: u32,
+    size: u32,
+    state: Mutex<LogState>,
+}
+
+struct LogState {
+    // 有操作正在进行(begin_op到end_op或abort_op之间)
+    busy: bool,
+    // 本次事务修改过的块号
+    blocks: Vec<u32>,
+    // 超出日志容量、没有记入日志的块，事务一定会被放弃
+    unlogged: Vec<u32>,
+    // 已经提交的事务没能写回，日志头仍然记录着它，之后的事务都被放弃
+    readonly: bool,
+}
+
+impl Log {
+    pub fn new(dev: DevId, sb: &DiskSuperBlock) -> Self {
+        Self {
+            dev,
+            start: sb.logstart,
+            size: sb.nlog,
+            state: Mutex::new(LogState {
+                busy: false,
+                blocks: Vec::new(),
+                unlogged: Vec::new(),
+                readonly: false,
+            }),
+        }
+    }
+
+    // 挂载时已经检查过nlog > MAXOPBLOCKS
+    fn capacity(&self) -> usize {
+        LOGSIZE.min(self.size as usize - 1)
+    }
+
+    fn read_head(&self) -> Result<Vec<u32>> {
+        let buf = bread(self.dev, self.start as u64)?;
+        let head: LogHeader = read_struct(buf.data());
+        let n = (head.n as usize).min(LOGSIZE);
+        Ok(head.block[..n].to_vec())
+    }
+
+    // 写日志头，这是事务真正提交的时刻
+    fn write_head(&self, blocks: &[u32]) -> Result<()> {
+        let mut head = LogHeader {
+            n: blocks.len() as u32,
+            block: [0; LOGSIZE],
+        };
+        head.block[..blocks.len()].copy_from_slice(blocks);
+        let mut buf = bread(self.dev, self.start as u64)?;
+        write_struct(buf.data_mut(), &head);
+        bwrite(&mut buf)
+    }
+
+    // copy modified blocks from cache to log
+    fn write_log(&self, blocks: &[u32]) -> Result<()> {
+        for (i, &blockno) in blocks.iter().enumerate() {
+            let from = bread(self.dev, blockno as u64)?;
+            let mut to = bread(self.dev, (self.start + 1 + i as u32) as u64)?;
+            to.data_mut().copy_from_slice(from.data());
+            bwrite(&mut to)?;
+        }
+        Ok(())
+    }
+
+    // copy committed blocks from log to their home location
+    // 出错之后不再写回剩下的块，但仍然unpin本次事务的所有块
+    fn install_trans(&self, blocks: &[u32], recovering: bool) -> Result<()> {
+        let mut result = Ok(());
+        for (i, &blockno) in blocks.iter().enumerate() {
+            if result.is_ok() {
+                result = self.install_block(i, blockno);
+            }
+            if !recovering {
+                match bread(self.dev, blockno as u64) {
+                    Ok(buf) => bunpin(&buf),
+                    Err(err) => warn!("xxfs: log unpin block {}: {}", blockno, err),
+                }
+            }
+        }
+        result
+    }
+
+    fn install_block(&self, i: usize, blockno: u32) -> Result<()> {
+        let log = bread(self.dev, (self.start + 1 + i as u32) as u64)?;
+        let mut dst = bread(self.dev, blockno as u64)?;
+        dst.data_mut().copy_from_slice(log.data());
+        bwrite(&mut dst)
+    }
+
+    // 挂载时调用，重做已经提交但还没有写回的事务
+    pub fn recover(&self) -> Result<()> {
+        let blocks = self.read_head()?;
+        if !blocks.is_empty() {
+            info!("xxfs: recovering {} blocks from log", blocks.len());
+            self.install_trans(&blocks, true)?;
+        }
+        self.write_head(&[])
+    }
+
+    // 每个文件系统操作开始时调用，等待前一个操作提交或者放弃
+    // 整个日志预留给这个操作，一个操作最多修改MAXOPBLOCKS块，不会超出容量
+    pub fn begin_op(&self) {
+        debug_assert!(self.capacity() >= MAXOPBLOCKS);
+        loop {
+            {
+                let mut state = self.state.lock();
+                if !state.busy {
+                    state.busy = true;
+                    return;
+                }
+            }
+            spin_loop();
+        }
+    }
+
+    // 操作成功时提交事务
+    pub fn end_op(&self) -> Result<()> {
+        let (blocks, unlogged) = self.take_blocks();
+        let readonly = self.state.lock().readonly;
+        let result = if readonly {
+            self.discard(&blocks, &unlogged);
+            Err(Errno::EROFS.into())
+        } else if unlogged.is_empty() {
+            self.commit(&blocks)
+        } else {
+            self.discard(&blocks, &unlogged);
+            Err(Errno::ENOSPC.into())
+        };
+        if let Err(err) = &result {
+            warn!("xxfs: log commit failed: {}", err);
+        }
+        self.state.lock().busy = false;
+        result
+    }
+
+    // 操作失败时放弃事务，缓存中被修改的块在下次读取时从磁盘重新读入
+    pub fn abort_op(&self) {
+        let (blocks, unlogged) = self.take_blocks();
+        self.discard(&blocks, &unlogged);
+        self.state.lock().busy = false;
+    }
+
+    fn take_blocks(&self) -> (Vec<u32>, Vec<u32>) {
+        let mut state = self.state.lock();
+        assert!(state.busy, "log: end of operation outside of transaction");
+        (
+            core::mem::take(&mut state.blocks),
+            core::mem::take(&mut state.unlogged),
+        )
+    }
+
+    fn discard(&self, blocks: &[u32], unlogged: &[u32]) {
+        for (&blockno, pinned) in blocks
+            .iter()
+            .map(|b| (b, true))
+            .chain(unlogged.iter().map(|b| (b, false)))
+        {
+            match bread(self.dev, blockno as u64) {
+                Ok(mut buf) => {
+                    bdiscard(&mut buf);
+                    if pinned {
+                        bunpin(&buf);
+                    }
+                }
+                Err(err) => warn!("xxfs: log discard block {}: {}", blockno, err),
+            }
+        }
+    }
+
+    fn commit(&self, blocks: &[u32]) -> Result<()> {
+        if blocks.is_empty() {
+            return Ok(());
+        }
+        // 日志头写入之前失败时，磁盘上什么也没有改变，与放弃事务相同
+        if let Err(err) = self.write_log(blocks).and_then(|_| self.write_head(blocks)) {
+            self.discard(blocks, &[]);
+            return Err(err);
+        }
+        // 日志头写入之后事务已经提交，写回失败时保留日志头，下次挂载时恢复
+        // 在此之前日志区不能再被覆盖，文件系统变为只读
+        if let Err(err) = self
+            .install_trans(blocks, false)
+            .and_then(|_| self.write_head(&[]))
+        {
+            warn!("xxfs: failed to install a committed transaction, now read-only");
+            self.state.lock().readonly = true;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    // 代替bwrite：记录块号并把块pin在缓存中，提交时才写到磁盘
+    // 同一个事务多次修改同一块只占用一个日志块(absorption)
+    // 超出日志容量说明操作没有按MAXOPBLOCKS拆分，记下这一块以便放弃事务时丢弃它的修改
+    pub fn write(&self, buf: &BufRef) -> Result<()> {
+        let blockno = buf.blockno() as u32;
+        let mut state = self.state.lock();
+        assert!(state.busy, "log: write outside of transaction");
+        if state.blocks.contains(&blockno) {
+            return Ok(());
+        }
+        if state.blocks.len() >= self.capacity() {
+            warn!("xxfs: transaction exceeds the log");
+            if !state.unlogged.contains(&blockno) {
+                state.unlogged.push(blockno);
+            }
+            return Err(Errno::ENOSPC.into());
+        }
+        state.blocks.push(blockno);
+        bpin(buf);
+        Ok(())
+    }
+}
@@ -0,0 +1,367 @@
+pub mod inode;
+pub mod layout;
+pub mod log;
+
+use super::{
+    def::StatFs,
+    vfs::{FileSystem, Inode, InodeRef, SuperBlock},
+};
+use crate::{
+    block::{
+        bcache::{bflush, bread, BSIZE},
+        block_device, DevId,
+    },
+    error::{Errno, ErrorTrace, Result},
+    mm::{kmem_cache::KmemCache, page_frame::PageFrame},
+};
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use inode::XxfsInode;
+use layout::{
+    read_struct, write_struct, DiskInode, DiskSuperBlock, BPB, DIRSIZ, ROOTINO, SUPERBLOCK_NO,
+    XXFS_MAGIC,
+};
+use log::Log;
+use xx_mutex_lock::Mutex;
+use xxos_log::{info, warn};
+
+const _: () = assert!(layout::BSIZE == BSIZE);
+
+// 内存中的inode副本
+static DINODE_CACHE: KmemCache<DiskInode> = KmemCache::new("xxfs_inode", Some(DiskInode::default));
+
+// 内存inode的最后一个引用释放之后还要修改磁盘的工作
+// Drop可能发生在事务中或者持有自旋锁时，不能开始事务，所以推迟到iput_deferred处理
+enum Deferred {
+    // 没有链接的inode，释放它的数据块与磁盘inode
+    Free,
+    // 共享映射写入过、还没有写回的页
+    WriteBack(Vec<(u64, Arc<PageFrame>)>),
+}
+
+// xxfs: the native file system of xxos
+pub struct Xxfs {
+    dev: DevId,
+    sb: DiskSuperBlock,
+    log: Log,
+    // 在内存中的inode，最后一个引用释放时从表中移除
+    inodes: Mutex<BTreeMap<u32, Weak<XxfsInode>>>,
+    // 按inode号记录推迟的工作
+    deferred: Mutex<BTreeMap<u32, Deferred>>,
+}
+
+impl Xxfs {
+    pub fn dev(&self) -> DevId {
+        self.dev
+    }
+
+    pub fn super_block(&self) -> &DiskSuperBlock {
+        &self.sb
+    }
+
+    pub fn log(&self) -> &Log {
+        &self.log
+    }
+
+    // 在一个事务中执行f，多块的修改要么全部落盘，要么都不落盘
+    // f中不能再开始事务，事务结束之后处理推迟的inode工作
+    pub fn transaction<T>(self: &Arc<Self>, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        let result = self.op(f);
+        self.iput_deferred();
+        result
+    }
+
+    // 不处理推迟工作的事务
+    // f失败时放弃事务，磁盘上的inode已经恢复，内存中被修改过的inode副本也要重新读入
+    fn op<T>(&self, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        self.log.begin_op();
+        let result = match f() {
+            Ok(value) => self.log.end_op().map(|_| value),
+            Err(err) => {
+                self.log.abort_op();
+                Err(err)
+            }
+        };
+        if result.is_err() {
+            self.reload_inodes();
+        }
+        result
+    }
+
+    fn reload_inodes(&self) {
+        let inodes: Vec<Arc<XxfsInode>> = self
+            .inodes
+            .lock()
+            .values()
+            .filter_map(Weak::upgrade)
+            .collect();
+        for inode in inodes {
+            if let Err(err) = self
+                .read_dinode(inode.inum())
+                .map(|dinode| inode.reload(dinode))
+            {
+                warn!("xxfs: failed to reload inode {}: {}", inode.inum(), err);
+            }
+        }
+    }
+
+    // 由XxfsInode::drop调用
+    fn defer(&self, inum: u32, work: Deferred) {
+        self.deferred.lock().insert(inum, work);
+    }
+
+    // 处理推迟的inode工作，必须在事务之外调用
+    // 处理过程中再次推迟的工作(例如失败时)留到下一次
+    pub fn iput_deferred(self: &Arc<Self>) {
+        let works = core::mem::take(&mut *self.deferred.lock());
+        for (inum, work) in works {
+            let result = match work {
+                Deferred::Free => self.ifree(inum),
+                Deferred::WriteBack(pages) => self.iget(inum).and_then(|inode| {
+                    for (index, frame) in pages {
+                        inode.cache().insert_dirty(index, frame);
+                    }
+                    inode.sync()
+                }),
+            };
+            if let Err(err) = result {
+                warn!("xxfs: deferred work on inode {} failed: {}", inum, err);
+            }
+        }
+    }
+
+    fn ifree(self: &Arc<Self>, inum: u32) -> Result<()> {
+        let inode = self.iget(inum)?;
+        // 又被其他人引用时，由之后的最后一个引用再次推迟
+        if Arc::strong_count(&inode) > 1 {
+            return Ok(());
+        }
+        self.op(|| inode.free())
+    }
+
+    // 分配一个清零的数据块，必须在事务中调用
+    pub fn balloc(&self) -> Result<u32> {
+        let sb = &self.sb;
+        for base in (0..sb.size).step_by(BPB) {
+            let mut buf = bread(self.dev, sb.bblock(base) as u64)?;
+            let free = (0..BPB as u32)
+                .take_while(|bi| base + bi < sb.size)
+                .find(|&bi| buf[bi as usize / 8] & (1 << (bi % 8)) == 0);
+            if let Some(bi) = free {
+                buf[bi as usize / 8] |= 1 << (bi % 8);
+                self.log.write(&buf)?;
+                drop(buf);
+                let blockno = base + bi;
+                self.bzero(blockno)?;
+                return Ok(blockno);
+            }
+        }
+        warn!("xxfs: out of blocks");
+        Err(Errno::ENOSPC.into())
+    }
+
+    pub fn bfree(&self, blockno: u32) -> Result<()> {
+        let sb = &self.sb;
+        if blockno < sb.data_start() || blockno >= sb.size {
+            warn!("xxfs: freeing bad block {}", blockno);
+            return Err(Errno::EIO.into());
+        }
+        let mut buf = bread(self.dev, sb.bblock(blockno) as u64)?;
+        let bi = blockno as usize % BPB;
+        if buf[bi / 8] & (1 << (bi % 8)) == 0 {
+            warn!("xxfs: freeing free block {}", blockno);
+            return Err(Errno::EIO.into());
+        }
+        buf[bi / 8] &= !(1 << (bi % 8));
+        self.log.write(&buf)
+    }
+
+    fn bzero(&self, blockno: u32) -> Result<()> {
+        let mut buf = bread(self.dev, blockno as u64)?;
+        buf.fill(0);
+        self.log.write(&buf)
+    }
+
+    // 在inode表中分配一个inode，返回inode号，必须在事务中调用
+    pub fn ialloc(&self, mode: u16, uid: u16, gid: u16) -> Result<u32> {
+        for inum in 1..self.sb.ninodes {
+            let mut buf = bread(self.dev, self.sb.iblock(inum) as u64)?;
+            let offset = DiskInode::offset(inum);
+            let dinode: DiskInode = read_struct(&buf[offset..]);
+            if dinode.is_free() {
+                let dinode = DiskInode {
+                    mode,
+                    uid,
+                    gid,
+                    ..Default::default()
+                };
+                write_struct(&mut buf[offset..], &dinode);
+                self.log.write(&buf)?;
+                return Ok(inum);
+            }
+        }
+        warn!("xxfs: out of inodes");
+        Err(Errno::ENOSPC.into())
+    }
+
+    fn read_dinode(&self, inum: u32) -> Result<DiskInode> {
+        let buf = bread(self.dev, self.sb.iblock(inum) as u64)?;
+        Ok(read_struct(&buf[DiskInode::offset(inum)..]))
+    }
+
+    // 找到inode号对应的内存inode，不存在时从磁盘读入
+    pub fn iget(self: &Arc<Self>, inum: u32) -> Result<Arc<XxfsInode>> {
+        if inum == 0 || inum >= self.sb.ninodes {
+            return Err(Errno::EINVAL.into());
+        }
+        if let Some(inode) = self.inodes.lock().get(&inum).and_then(Weak::upgrade) {
+            return Ok(inode);
+        }
+
+        // 读盘时不能持有inodes的锁
+        let dinode = self.read_dinode(inum)?;
+        if dinode.is_free() {
+            return Err(Errno::ENOENT.into());
+        }
+        let mut copy = DINODE_CACHE.construct()?;
+        *copy = dinode;
+
+        let mut inodes = self.inodes.lock();
+        if let Some(inode) = inodes.get(&inum).and_then(Weak::upgrade) {
+            return Ok(inode);
+        }
+        let inode = Arc::new(XxfsInode::new(self.clone(), inum, copy));
+        inodes.insert(inum, Arc::downgrade(&inode));
+        drop(inodes);
+        // 上一个内存inode释放时还没有写回的页
+        let pages = {
+            let mut deferred = self.deferred.lock();
+            match deferred.get(&inum) {
+                Some(Deferred::WriteBack(_)) => deferred.remove(&inum),
+                _ => None,
+            }
+        };
+        if let Some(Deferred::WriteBack(pages)) = pages {
+            for (index, frame) in pages {
+                inode.cache().insert_dirty(index, frame);
+            }
+        }
+        Ok(inode)
+    }
+
+    // 由XxfsInode::drop调用
+    fn forget(&self, inum: u32) {
+        let mut inodes = self.inodes.lock();
+        if inodes
+            .get(&inum)
+            .is_some_and(|inode| inode.strong_count() == 0)
+        {
+            inodes.remove(&inum);
+        }
+    }
+
+    // 写回所有在内存中的inode的页缓存
+    fn sync_inodes(self: &Arc<Self>) -> Result<()> {
+        self.iput_deferred();
+        let inodes: Vec<Arc<XxfsInode>> = self
+            .inodes
+            .lock()
+            .values()
+            .filter_map(Weak::upgrade)
+            .collect();
+        inodes.iter().try_for_each(|inode| inode.sync())
+    }
+
+    fn count_free(&self) -> Result<(u64, u64)> {
+        let sb = &self.sb;
+        let mut free_blocks = 0;
+        for base in (0..sb.size).step_by(BPB) {
+            let buf = bread(self.dev, sb.bblock(base) as u64)?;
+            free_blocks += (0..BPB as u32)
+                .take_while(|bi| base + bi < sb.size)
+                .filter(|&bi| buf[bi as usize / 8] & (1 << (bi % 8)) == 0)
+                .count() as u64;
+        }
+        let mut free_inodes = 0;
+        for inum in 1..sb.ninodes {
+            if self.read_dinode(inum)?.is_free() {
+                free_inodes += 1;
+            }
+        }
+        Ok((free_blocks, free_inodes))
+    }
+}
+
+pub struct XxfsSuperBlock {
+    fs: Arc<Xxfs>,
+    root: Arc<XxfsInode>,
+}
+
+impl SuperBlock for XxfsSuperBlock {
+    fn root(&self) -> InodeRef {
+        self.root.clone()
+    }
+
+    fn statfs(&self) -> Result<StatFs> {
+        let (free_blocks, free_files) = self.fs.count_free()?;
+        Ok(StatFs {
+            block_size: BSIZE,
+            blocks: self.fs.sb.nblocks as u64,
+            free_blocks,
+            files: self.fs.sb.ninodes as u64 - 1,
+            free_files,
+            name_max: DIRSIZ,
+        })
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.fs.sync_inodes()?;
+        bflush(self.fs.dev)
+    }
+}
+
+pub struct XxfsType;
+
+impl FileSystem for XxfsType {
+    fn name(&self) -> &'static str {
+        "xxfs"
+    }
+
+    fn mount(&self, dev: Option<DevId>, _options: &str) -> Result<Arc<dyn SuperBlock>> {
+        let dev = dev.ok_or_else(|| ErrorTrace::from_errno(Errno::ENODEV))?;
+        let device = block_device(dev).ok_or_else(|| ErrorTrace::from_errno(Errno::ENXIO))?;
+
+        let sb: DiskSuperBlock = read_struct(bread(dev, SUPERBLOCK_NO as u64)?.data());
+        let capacity = device.capacity() * device.block_size() as u64 / BSIZE as u64;
+        if sb.magic != XXFS_MAGIC {
+            return Err(Errno::EINVAL.into());
+        }
+        if sb.size as u64 > capacity || !sb.is_valid() {
+            warn!("xxfs: bad super block on {}", device.name());
+            return Err(Errno::EINVAL.into());
+        }
+
+        let fs = Arc::new(Xxfs {
+            dev,
+            sb,
+            log: Log::new(dev, &sb),
+            inodes: Mutex::new(BTreeMap::new()),
+            deferred: Mutex::new(BTreeMap::new()),
+        });
+        fs.log.recover()?;
+        let root = fs.iget(ROOTINO)?;
+        if !root.is_dir() {
+            return Err(Errno::ENOTDIR.into());
+        }
+        info!(
+            "xxfs: {} blocks, {} inodes on {}",
+            sb.size,
+            sb.ninodes,
+            device.name()
+        );
+        Ok(Arc::new(XxfsSuperBlock { fs, root }))
+    }
+}
@@ -1,5 +1,5 @@
-use crate::println;
-use core::panic::PanicInfo;
+use crate::{mm::pm::heap_stats, println};
+use core::{alloc::Layout, panic::PanicInfo};
 
 #[panic_handler]
 fn panic(_info: &PanicInfo) -> ! {
@@ -9,3 +9,14 @@ fn panic(_info: &PanicInfo) -> ! {
     println!("{}:{} {}", file, line, message);
     loop {}
 }
+
+// 内核堆耗尽时先打印堆的状态，再停机
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+    println!("{}", heap_stats());
+    panic!(
+        "memory allocation of {} bytes (align {}) failed",
+        layout.size(),
+        layout.align()
+    )
+}
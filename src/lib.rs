@@ -3,17 +3,23 @@
 #![feature(never_type)]
 #![feature(panic_info_message)]
 #![feature(new_uninit)]
+#![feature(alloc_error_handler)]
+#![feature(allocator_api)]
 
+pub mod block;
 pub mod console;
 pub mod cpu;
+pub mod driver;
 pub mod error;
 pub mod fs;
 pub mod lang_items;
 pub mod mm;
 pub mod opensbi;
 pub mod proc;
+pub mod random;
 pub mod riscv;
 pub mod sched;
+pub mod syscall;
 //pub mod task;
 pub mod trap;
 pub mod utils;
@@ -6,14 +6,15 @@ use core::sync::atomic::{AtomicBool, Ordering};
 use xxos::console::Log;
 use xxos::riscv::registers::r_tp;
 use xxos::trap::usertrap::usertrapret;
-use xxos::{mm, proc, utils};
+use xxos::{block, driver, fs, mm, proc, random, utils};
 use xxos::{println, trap};
+use xxos_log::warn;
 static STARTED: AtomicBool = AtomicBool::new(false);
 extern crate alloc;
 global_asm!(include_str!("entry.s"));
 
 #[no_mangle]
-fn main() {
+fn main(_hartid: usize, dtb: usize) {
     //thread_start();
 
     // 仅由id为0的线程执行初始化操作
@@ -23,13 +24,33 @@ fn main() {
         utils::clear_bss();
         // 初始化系统log
         xxos_log::init_log(&Log, xxos_log::Level::WARN);
+        // 拷贝设备树，它与内核堆所在的内存重叠
+        driver::fdt::fdt_init(dtb);
         // 初始化trap
         trap::kerneltrap::kernel_trap_init();
         trap::clock::clock_init();
         // 初始化内存
         mm::pm::heap_init();
+        // 初始化熵池，ASLR依赖它
+        // 内核命令行带norandmaps时关闭ASLR，便于复现问题
+        random::random_init();
+        mm::vm::layout::set_aslr(!driver::fdt::has_bootarg("norandmaps"));
         // 初始化虚拟内存
         mm::vm::kvm_init();
+        // 初始化外部中断
+        trap::irq::irq_init();
+        trap::irq::irq_init_hart();
+        // 之后控制台输出改用UART
+        if let Err(err) = driver::uart::uart_init() {
+            warn!("uart init failed: {}", err);
+        }
+        driver::virtio::virtio_init();
+        fs::fs_init();
+        // 内核命令行带selftest时运行内核自检，失败时panic
+        if driver::fdt::has_bootarg("selftest") {
+            block::queue::queue_test();
+            block::bcache::bcache_test();
+        }
         proc::process::test_initcode();
 
         // test
@@ -45,6 +66,7 @@ fn main() {
         }
         // 每个CPU都使用同一个KVM页表
         mm::vm::kvm_init();
+        trap::irq::irq_init_hart();
         println!("Thread {} start !!!", thread_id);
     }
 
@@ -0,0 +1,445 @@
+use super::pagetable_frame::{PageTable, PageTableEntry};
+use crate::riscv::sv39::PTE_PPN_SHIFT;
+use core::{
+    fmt::Display,
+    ops::{Add, AddAssign, Sub, SubAssign},
+};
+
+// 物理地址和虚拟地址使用不同的类型，并且不再提供From<usize>，
+// 构造时必须显式写明是PA还是VA，避免把VA当作PA传入
+
+// PMA have 2 fields:
+// 1. Page Offset field(0-11)
+// 2. PPN field(12-55)
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PhysicalMemoryAddress(usize); // PMA
+const PMA_OFFSET_WIDTH: u8 = 12;
+const PMA_OFFSET_MASK: usize = (1 << PMA_OFFSET_WIDTH) - 1;
+const PMA_PPN_SHIFT: u8 = 12;
+// Sv39的物理地址有56位，PPN占44位；原来的22位只能表示16GiB以内的地址
+const PMA_PPN_WIDTH: u8 = 44;
+const PMA_PPN_MASK: usize = ((1 << (PMA_PPN_SHIFT + PMA_PPN_WIDTH)) - 1) ^ PMA_OFFSET_MASK;
+
+impl PhysicalMemoryAddress {
+    #[inline]
+    pub const fn new(pa: usize) -> Self {
+        Self(pa)
+    }
+
+    #[inline]
+    pub const fn as_usize(&self) -> usize {
+        self.0
+    }
+
+    #[inline]
+    pub fn page_offset(&self) -> usize {
+        self.0 & PMA_OFFSET_MASK
+    }
+
+    #[inline]
+    pub fn align_down(&self) -> Self {
+        Self(self.0 & !PMA_OFFSET_MASK)
+    }
+
+    #[inline]
+    pub fn align_up(&self) -> Self {
+        Self((self.0 + PMA_OFFSET_MASK) & !PMA_OFFSET_MASK)
+    }
+
+    #[inline]
+    pub fn is_aligned(&self) -> bool {
+        self.page_offset() == 0
+    }
+
+    pub fn to_ppn(&self) -> PhysicalPageNumber {
+        PhysicalPageNumber((self.0 & PMA_PPN_MASK) >> PMA_PPN_SHIFT)
+    }
+
+    pub fn to_pte(&self, flags: usize) -> PageTableEntry {
+        self.to_ppn().to_pte(flags)
+    }
+
+    pub fn get_mut_pagetable(&self) -> &'static mut PageTable {
+        unsafe { (self.0 as *mut PageTable).as_mut().unwrap() }
+    }
+
+    ///
+    /// # Safety
+    /// self.0 not null
+    pub unsafe fn get_mut<T>(&self) -> &'static mut T {
+        (self.0 as *mut T).as_mut().unwrap()
+    }
+}
+
+impl Add<usize> for PhysicalMemoryAddress {
+    type Output = Self;
+    fn add(self, rhs: usize) -> Self {
+        Self(self.0 + rhs)
+    }
+}
+
+impl AddAssign<usize> for PhysicalMemoryAddress {
+    fn add_assign(&mut self, rhs: usize) {
+        self.0 += rhs;
+    }
+}
+
+impl Sub<usize> for PhysicalMemoryAddress {
+    type Output = Self;
+    fn sub(self, rhs: usize) -> Self {
+        Self(self.0 - rhs)
+    }
+}
+
+impl SubAssign<usize> for PhysicalMemoryAddress {
+    fn sub_assign(&mut self, rhs: usize) {
+        self.0 -= rhs;
+    }
+}
+
+// distance in bytes between two physical addresses
+impl Sub for PhysicalMemoryAddress {
+    type Output = usize;
+    fn sub(self, rhs: Self) -> usize {
+        self.0 - rhs.0
+    }
+}
+
+impl From<PhysicalPageNumber> for PhysicalMemoryAddress {
+    fn from(ppn: PhysicalPageNumber) -> Self {
+        ppn.to_pma()
+    }
+}
+
+impl Display for PhysicalMemoryAddress {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "pa: {:#x}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PhysicalPageNumber(usize); // PPN
+
+impl PhysicalPageNumber {
+    #[inline]
+    pub const fn new(ppn: usize) -> Self {
+        Self(ppn)
+    }
+
+    #[inline]
+    pub const fn as_usize(&self) -> usize {
+        self.0
+    }
+
+    pub fn to_pte(&self, flags: usize) -> PageTableEntry {
+        PageTableEntry::from(self.0 << PTE_PPN_SHIFT | flags)
+    }
+
+    pub fn to_pma(&self) -> PhysicalMemoryAddress {
+        PhysicalMemoryAddress(self.0 << PMA_PPN_SHIFT)
+    }
+}
+
+impl Add<usize> for PhysicalPageNumber {
+    type Output = Self;
+    fn add(self, rhs: usize) -> Self {
+        Self(self.0 + rhs)
+    }
+}
+
+impl Sub<usize> for PhysicalPageNumber {
+    type Output = Self;
+    fn sub(self, rhs: usize) -> Self {
+        Self(self.0 - rhs)
+    }
+}
+
+// number of pages between two page numbers
+impl Sub for PhysicalPageNumber {
+    type Output = usize;
+    fn sub(self, rhs: Self) -> usize {
+        self.0 - rhs.0
+    }
+}
+
+impl From<PhysicalMemoryAddress> for PhysicalPageNumber {
+    fn from(pa: PhysicalMemoryAddress) -> Self {
+        pa.to_ppn()
+    }
+}
+
+/// VMA have 2 fields:
+/// 1. Page Offset field(0-11)
+/// 2. VPN filed(12-38)
+///      VPN0: 3rd pagetable index field(12-20)
+///      VPN1: 2nd pagetable index field(21-29)
+///      VPN2: 1st pagetable index field(30-38)
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VirtualMemoryAddress(usize); // VMA
+const VMA_OFFSET_WIDTH: u8 = 12;
+const VMA_OFFSET_MASK: usize = (1 << VMA_OFFSET_WIDTH) - 1;
+const VMA_VPN_SHIFT: u8 = 12;
+const VMA_VPN_WIDTH: u8 = 27;
+const VMA_VPN_PART_WIDTH: u8 = 9;
+const VMA_VPN_MASK: usize = ((1 << (VMA_VPN_SHIFT + VMA_VPN_WIDTH)) - 1) ^ VMA_OFFSET_MASK;
+
+impl VirtualMemoryAddress {
+    #[inline]
+    pub const fn new(va: usize) -> Self {
+        Self(va)
+    }
+
+    #[inline]
+    pub const fn as_usize(&self) -> usize {
+        self.0
+    }
+
+    #[inline]
+    pub fn page_offset(&self) -> usize {
+        self.0 & VMA_OFFSET_MASK
+    }
+
+    #[inline]
+    pub fn align_down(&self) -> Self {
+        Self(self.0 & !VMA_OFFSET_MASK)
+    }
+
+    #[inline]
+    pub fn align_up(&self) -> Self {
+        Self((self.0 + VMA_OFFSET_MASK) & !VMA_OFFSET_MASK)
+    }
+
+    #[inline]
+    pub fn is_aligned(&self) -> bool {
+        self.page_offset() == 0
+    }
+
+    pub fn vpn(&self) -> VirtualPageNumber {
+        VirtualPageNumber((self.0 & VMA_VPN_MASK) >> VMA_VPN_SHIFT)
+    }
+
+    pub fn get_pagetable_index(&self, level: usize) -> usize {
+        self.vpn().get_pagetable_index(level)
+    }
+}
+
+impl Add<usize> for VirtualMemoryAddress {
+    type Output = Self;
+    fn add(self, rhs: usize) -> Self {
+        Self(self.0 + rhs)
+    }
+}
+
+impl AddAssign<usize> for VirtualMemoryAddress {
+    fn add_assign(&mut self, rhs: usize) {
+        self.0 += rhs;
+    }
+}
+
+impl Sub<usize> for VirtualMemoryAddress {
+    type Output = Self;
+    fn sub(self, rhs: usize) -> Self {
+        Self(self.0 - rhs)
+    }
+}
+
+impl SubAssign<usize> for VirtualMemoryAddress {
+    fn sub_assign(&mut self, rhs: usize) {
+        self.0 -= rhs;
+    }
+}
+
+// distance in bytes between two virtual addresses
+impl Sub for VirtualMemoryAddress {
+    type Output = usize;
+    fn sub(self, rhs: Self) -> usize {
+        self.0 - rhs.0
+    }
+}
+
+impl From<VirtualPageNumber> for VirtualMemoryAddress {
+    fn from(vpn: VirtualPageNumber) -> Self {
+        vpn.to_vma()
+    }
+}
+
+impl Display for VirtualMemoryAddress {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "va: {:#x}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VirtualPageNumber(usize); // VPN
+
+impl VirtualPageNumber {
+    #[inline]
+    pub const fn new(vpn: usize) -> Self {
+        Self(vpn)
+    }
+
+    #[inline]
+    pub const fn as_usize(&self) -> usize {
+        self.0
+    }
+
+    pub fn to_vma(&self) -> VirtualMemoryAddress {
+        VirtualMemoryAddress(self.0 << VMA_VPN_SHIFT)
+    }
+
+    pub fn get_pagetable_index(&self, level: usize) -> usize {
+        (self.0 >> (VMA_VPN_PART_WIDTH as usize * level)) & ((1 << VMA_VPN_PART_WIDTH) - 1)
+    }
+}
+
+impl Add<usize> for VirtualPageNumber {
+    type Output = Self;
+    fn add(self, rhs: usize) -> Self {
+        Self(self.0 + rhs)
+    }
+}
+
+impl Sub<usize> for VirtualPageNumber {
+    type Output = Self;
+    fn sub(self, rhs: usize) -> Self {
+        Self(self.0 - rhs)
+    }
+}
+
+// number of pages between two page numbers
+impl Sub for VirtualPageNumber {
+    type Output = usize;
+    fn sub(self, rhs: Self) -> usize {
+        self.0 - rhs.0
+    }
+}
+
+impl From<VirtualMemoryAddress> for VirtualPageNumber {
+    fn from(va: VirtualMemoryAddress) -> Self {
+        va.vpn()
+    }
+}
+
+/// 左闭右开的虚拟页区间 [start, end)
+#[derive(Debug, Clone, Copy)]
+pub struct VirtPageRange {
+    start: VirtualPageNumber,
+    end: VirtualPageNumber,
+}
+
+impl VirtPageRange {
+    pub fn new(start: VirtualPageNumber, end: VirtualPageNumber) -> Self {
+        Self { start, end }
+    }
+
+    /// every page touched by [va, va + size)
+    pub fn from_size(va: VirtualMemoryAddress, size: usize) -> Self {
+        Self {
+            start: va.align_down().vpn(),
+            end: (va + size).align_up().vpn(),
+        }
+    }
+
+    pub fn start(&self) -> VirtualPageNumber {
+        self.start
+    }
+
+    pub fn end(&self) -> VirtualPageNumber {
+        self.end
+    }
+
+    pub fn pages(&self) -> usize {
+        if self.end > self.start {
+            self.end - self.start
+        } else {
+            0
+        }
+    }
+
+    pub fn contains(&self, vpn: VirtualPageNumber) -> bool {
+        self.start <= vpn && vpn < self.end
+    }
+}
+
+impl Iterator for VirtPageRange {
+    type Item = VirtualPageNumber;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.start < self.end {
+            let vpn = self.start;
+            self.start = self.start + 1;
+            Some(vpn)
+        } else {
+            None
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.pages(), Some(self.pages()))
+    }
+}
+
+impl ExactSizeIterator for VirtPageRange {}
+
+/// 左闭右开的物理页区间 [start, end)
+#[derive(Debug, Clone, Copy)]
+pub struct PhysPageRange {
+    start: PhysicalPageNumber,
+    end: PhysicalPageNumber,
+}
+
+impl PhysPageRange {
+    pub fn new(start: PhysicalPageNumber, end: PhysicalPageNumber) -> Self {
+        Self { start, end }
+    }
+
+    /// every page touched by [pa, pa + size)
+    pub fn from_size(pa: PhysicalMemoryAddress, size: usize) -> Self {
+        Self {
+            start: pa.align_down().to_ppn(),
+            end: (pa + size).align_up().to_ppn(),
+        }
+    }
+
+    pub fn start(&self) -> PhysicalPageNumber {
+        self.start
+    }
+
+    pub fn end(&self) -> PhysicalPageNumber {
+        self.end
+    }
+
+    pub fn pages(&self) -> usize {
+        if self.end > self.start {
+            self.end - self.start
+        } else {
+            0
+        }
+    }
+
+    pub fn contains(&self, ppn: PhysicalPageNumber) -> bool {
+        self.start <= ppn && ppn < self.end
+    }
+}
+
+impl Iterator for PhysPageRange {
+    type Item = PhysicalPageNumber;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.start < self.end {
+            let ppn = self.start;
+            self.start = self.start + 1;
+            Some(ppn)
+        } else {
+            None
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.pages(), Some(self.pages()))
+    }
+}
+
+impl ExactSizeIterator for PhysPageRange {}
@@ -0,0 +1,328 @@
+use super::{
+    def::PGSZ,
+    page_frame::{alloc_page, PageFrame},
+    pagetable_frame::PageTableErr,
+};
+use alloc::{
+    alloc::{AllocError, Allocator, Global, Layout},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    cell::Cell,
+    fmt::Display,
+    marker::PhantomData,
+    mem::{size_of, MaybeUninit},
+    ops::{Deref, DerefMut},
+    ptr::NonNull,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use xx_mutex_lock::Mutex;
+use xxos_log::info;
+
+// Typed kernel object cache
+// 每个slab占用一个页帧，被切分为若干个大小相同的对象
+// 同一类型的对象集中存放，不再为每个小结构体单独申请一整页
+pub struct KmemCache<T> {
+    name: &'static str,
+    ctor: Option<fn() -> T>,
+    // 对象的内存布局，第一次分配时求出
+    layout: fn() -> Layout,
+    inner: Mutex<CacheInner>,
+    registered: AtomicBool,
+    _marker: PhantomData<fn() -> T>,
+}
+
+struct Slab {
+    frame: PageFrame,
+    free: Vec<u16>,
+}
+
+struct CacheInner {
+    // 0表示还没有确定
+    obj_size: usize,
+    obj_align: usize,
+    slabs: Vec<Slab>,
+    active: usize,
+    allocs: usize,
+    frees: usize,
+}
+
+impl CacheInner {
+    const fn new() -> Self {
+        Self {
+            obj_size: 0,
+            obj_align: 1,
+            slabs: Vec::new(),
+            active: 0,
+            allocs: 0,
+            frees: 0,
+        }
+    }
+
+    fn objs_per_slab(&self) -> usize {
+        PGSZ.checked_div(self.obj_size).unwrap_or(0)
+    }
+}
+
+// Arc<T>实际申请的内存布局：让Arc向一个只记录请求的分配器申请一次
+// 这样不需要知道Arc内部(计数器与数据)是怎样排列的
+fn arc_layout<T>() -> Layout {
+    struct Probe<'a>(&'a Cell<Option<Layout>>);
+
+    unsafe impl Allocator for Probe<'_> {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            self.0.set(Some(layout));
+            Err(AllocError)
+        }
+
+        unsafe fn deallocate(&self, _ptr: NonNull<u8>, _layout: Layout) {}
+    }
+
+    let layout = Cell::new(None);
+    let _ = Arc::try_new_in(MaybeUninit::<T>::uninit(), Probe(&layout));
+    layout.get().expect("Arc did not allocate")
+}
+
+impl<T> KmemCache<T> {
+    pub const fn new(name: &'static str, ctor: Option<fn() -> T>) -> Self {
+        assert!(size_of::<T>() <= PGSZ, "object is larger than a slab");
+        Self {
+            name,
+            ctor,
+            layout: Layout::new::<T>,
+            inner: Mutex::new(CacheInner::new()),
+            registered: AtomicBool::new(false),
+            _marker: PhantomData,
+        }
+    }
+
+    // 作为Arc<T>的分配器使用的cache，对象大小就是Arc<T>一次分配的大小
+    pub const fn for_arc(name: &'static str) -> Self {
+        Self {
+            name,
+            ctor: None,
+            layout: arc_layout::<T>,
+            inner: Mutex::new(CacheInner::new()),
+            registered: AtomicBool::new(false),
+            _marker: PhantomData,
+        }
+    }
+
+    // 对象大小向上对齐到对齐要求，大小为0的对象也占用1字节
+    fn init_layout(&self, inner: &mut CacheInner) {
+        if inner.obj_size != 0 {
+            return;
+        }
+        let layout = (self.layout)();
+        inner.obj_align = layout.align();
+        inner.obj_size = layout.size().max(1).next_multiple_of(layout.align());
+    }
+
+    // 返回None表示装不下layout，由调用者改用全局堆
+    fn alloc_raw(&'static self, layout: Layout) -> Result<Option<NonNull<u8>>, PageTableErr> {
+        if !self.registered.swap(true, Ordering::SeqCst) {
+            KMEM_CACHES.lock().push(self);
+        }
+
+        let mut inner = self.inner.lock();
+        self.init_layout(&mut inner);
+        if inner.obj_size > PGSZ
+            || layout.size() > inner.obj_size
+            || layout.align() > inner.obj_align
+        {
+            return Ok(None);
+        }
+        let obj_size = inner.obj_size;
+        let slab = match inner.slabs.iter().position(|slab| !slab.free.is_empty()) {
+            Some(index) => index,
+            None => {
+                let frame = alloc_page()?;
+                let free = (0..inner.objs_per_slab() as u16).rev().collect();
+                inner.slabs.push(Slab { frame, free });
+                inner.slabs.len() - 1
+            }
+        };
+
+        let slab = &mut inner.slabs[slab];
+        let index = slab.free.pop().unwrap() as usize;
+        let ptr = slab.frame.to_usize() + index * obj_size;
+        inner.active += 1;
+        inner.allocs += 1;
+        Ok(NonNull::new(ptr as *mut u8))
+    }
+
+    // 返回false表示这块内存不属于本cache
+    fn free_raw(&self, ptr: NonNull<u8>) -> bool {
+        let addr = ptr.as_ptr() as usize;
+        let base = addr & !(PGSZ - 1);
+        let mut inner = self.inner.lock();
+        let obj_size = inner.obj_size;
+        let Some(slab) = inner
+            .slabs
+            .iter_mut()
+            .find(|slab| slab.frame.to_usize() == base)
+        else {
+            return false;
+        };
+
+        slab.free.push(((addr - base) / obj_size) as u16);
+        inner.active -= 1;
+        inner.frees += 1;
+        true
+    }
+
+    pub fn alloc(&'static self, value: T) -> Result<KBox<T>, PageTableErr> {
+        let ptr = self
+            .alloc_raw(Layout::new::<T>())?
+            .ok_or(PageTableErr::OutOfMemory)?
+            .cast::<T>();
+        unsafe { ptr.as_ptr().write(value) };
+        Ok(KBox { ptr, cache: self })
+    }
+
+    // allocate an object initialized by the cache constructor
+    pub fn construct(&'static self) -> Result<KBox<T>, PageTableErr> {
+        let ctor = self.ctor.expect("kmem cache has no constructor");
+        self.alloc(ctor())
+    }
+}
+
+// 这些接口不依赖对象类型，供全局的统计与回收使用
+pub trait KmemCacheOps: Sync {
+    fn name(&self) -> &'static str;
+    fn stats(&self) -> KmemStats;
+    // release empty slabs, return the number of pages freed
+    fn shrink(&self) -> usize;
+}
+
+impl<T> KmemCacheOps for KmemCache<T> {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn stats(&self) -> KmemStats {
+        let inner = self.inner.lock();
+        KmemStats {
+            name: self.name,
+            obj_size: inner.obj_size,
+            objs_per_slab: inner.objs_per_slab(),
+            slabs: inner.slabs.len(),
+            active: inner.active,
+            allocs: inner.allocs,
+            frees: inner.frees,
+        }
+    }
+
+    fn shrink(&self) -> usize {
+        let mut inner = self.inner.lock();
+        let before = inner.slabs.len();
+        let objs_per_slab = inner.objs_per_slab();
+        inner.slabs.retain(|slab| slab.free.len() != objs_per_slab);
+        before - inner.slabs.len()
+    }
+}
+
+// 让cache可以作为Arc/Box的分配器使用
+// 装不下的请求退回到全局堆
+unsafe impl<T> Allocator for &'static KmemCache<T> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        match self.alloc_raw(layout) {
+            Ok(Some(ptr)) => Ok(NonNull::slice_from_raw_parts(ptr, layout.size())),
+            Ok(None) => Global.allocate(layout),
+            Err(_) => Err(AllocError),
+        }
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if !self.free_raw(ptr) {
+            Global.deallocate(ptr, layout);
+        }
+    }
+}
+
+// owning pointer to an object in a KmemCache
+pub struct KBox<T: 'static> {
+    ptr: NonNull<T>,
+    cache: &'static KmemCache<T>,
+}
+
+unsafe impl<T: Send> Send for KBox<T> {}
+unsafe impl<T: Sync> Sync for KBox<T> {}
+
+impl<T> KBox<T> {
+    pub fn as_ptr(&self) -> *mut T {
+        self.ptr.as_ptr()
+    }
+}
+
+impl<T> Deref for KBox<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T> DerefMut for KBox<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T> Drop for KBox<T> {
+    fn drop(&mut self) {
+        unsafe { self.ptr.as_ptr().drop_in_place() };
+        self.cache.free_raw(self.ptr.cast());
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct KmemStats {
+    pub name: &'static str,
+    pub obj_size: usize,
+    pub objs_per_slab: usize,
+    pub slabs: usize,
+    pub active: usize,
+    pub allocs: usize,
+    pub frees: usize,
+}
+
+impl Display for KmemStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:<12} {:>6} objs/{:<6} {:>4} bytes x {:>3}/slab, {} allocs, {} frees",
+            self.name,
+            self.active,
+            self.slabs * self.objs_per_slab,
+            self.obj_size,
+            self.objs_per_slab,
+            self.allocs,
+            self.frees
+        )
+    }
+}
+
+// 所有使用过的cache，首次分配时自动登记
+static KMEM_CACHES: Mutex<Vec<&'static dyn KmemCacheOps>> = Mutex::new(Vec::new());
+
+pub fn kmem_stats() -> Vec<KmemStats> {
+    KMEM_CACHES
+        .lock()
+        .iter()
+        .map(|cache| cache.stats())
+        .collect()
+}
+
+// 内存紧张时回收所有cache中的空闲slab
+pub fn kmem_shrink_all() -> usize {
+    let freed = KMEM_CACHES
+        .lock()
+        .iter()
+        .map(|cache| cache.shrink())
+        .sum::<usize>();
+    if freed > 0 {
+        info!("kmem: shrink released {} pages", freed);
+    }
+    freed
+}
@@ -1,4 +1,7 @@
+pub mod address;
 pub(crate) mod def;
+pub mod kmem_cache;
+pub mod oom;
 pub mod page_frame;
 pub mod pagetable_frame;
 pub mod pm;
@@ -0,0 +1,76 @@
+use super::{
+    kmem_cache::kmem_shrink_all,
+    page_frame::{alloc_page, PageFrame},
+    pagetable_frame::PageTableErr,
+};
+use crate::{cpu::current_task, proc::TASKMANAGER};
+use alloc::vec::Vec;
+use core::hint::spin_loop;
+use xxos_log::{error, warn};
+
+// 为用户页分配页帧
+// 内存耗尽时先回收各个kmem cache中的空闲slab，
+// 仍然不够时按照常驻内存大小选择一个进程杀死，回收它的页帧后重试
+// resident: 请求方地址空间当前的常驻页数，请求方自己的Uvm此时已被锁住，不能再去读取
+pub fn alloc_user_page(resident: usize) -> Result<PageFrame, PageTableErr> {
+    loop {
+        match alloc_page() {
+            Err(PageTableErr::OutOfMemory) => {
+                if kmem_shrink_all() == 0 && !oom_kill(resident) {
+                    return Err(PageTableErr::OutOfMemory);
+                }
+            }
+            result => return result,
+        }
+    }
+}
+
+// 返回true表示回收到了内存，可以重试
+fn oom_kill(resident: usize) -> bool {
+    let current = current_task().map(|task| *task.pid());
+    // 先复制出进程列表，避免持有TASKMANAGER的锁时再去锁进程的地址空间
+    let tasks: Vec<_> = TASKMANAGER.lock().tasks.iter().cloned().collect();
+
+    let victim = tasks
+        .into_iter()
+        // pid 0是初始进程，不能杀死
+        .filter(|task| *task.pid() != 0 && Some(*task.pid()) != current && !task.is_killed())
+        .map(|task| {
+            // 其他进程可能正持有自己的地址空间的锁在等待分配，不能去锁它
+            let pages = task.resident_pages();
+            (task, pages)
+        })
+        .max_by_key(|(_, pages)| *pages);
+
+    let Some((victim, pages)) = victim else {
+        error!("out of memory: no process can be killed");
+        return false;
+    };
+
+    // 请求方自己占用的内存最多时，杀死别的进程并不公平，直接让这次分配失败
+    if pages <= resident {
+        error!(
+            "out of memory: requester is the largest process ({} pages)",
+            resident
+        );
+        return false;
+    }
+
+    warn!(
+        "out of memory: kill process {} (pid {}), resident {} pages",
+        victim.name(),
+        victim.pid(),
+        pages
+    );
+    victim.kill();
+
+    // 正在其他hart上运行的进程会在下一次陷入内核时自己退出，等它离开hart
+    // 被杀死的进程不会再被调度，之后由这里回收它的内存
+    while victim.is_running() {
+        spin_loop();
+    }
+    if !victim.is_zombie() {
+        victim.exit();
+    }
+    true
+}
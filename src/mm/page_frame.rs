@@ -1,6 +1,10 @@
 extern crate alloc;
-use super::{def::PGSZ, pagetable_frame::PhysicalMemoryAddress};
-use alloc::boxed::Box;
+use super::{address::PhysicalMemoryAddress, def::PGSZ, pagetable_frame::PageTableErr};
+use alloc::alloc::{alloc_zeroed, dealloc};
+use core::alloc::Layout;
+
+// 页帧必须按页对齐，分配和释放都使用同一个Layout
+const PAGE_LAYOUT: Layout = unsafe { Layout::from_size_align_unchecked(PGSZ, PGSZ) };
 
 #[derive(Debug, Default)]
 pub struct PageFrame {
@@ -8,14 +12,15 @@ pub struct PageFrame {
 }
 
 impl PageFrame {
-    fn alloc() -> Self {
-        // 直接使用Box申请一页，并得到其裸指针(在程序的生命周期中持续有效)
-        let address = Box::<[u8; 4096]>::new_zeroed();
-        let address = unsafe { address.assume_init() };
-        let address = Box::leak(address);
-        Self {
-            address: (address as *const _ as usize).into(),
+    fn alloc() -> Result<Self, PageTableErr> {
+        // 直接从堆上申请一页，分配失败时返回错误而不是终止内核
+        let address = unsafe { alloc_zeroed(PAGE_LAYOUT) };
+        if address.is_null() {
+            return Err(PageTableErr::OutOfMemory);
         }
+        Ok(Self {
+            address: PhysicalMemoryAddress::new(address as usize),
+        })
     }
 }
 
@@ -27,8 +32,7 @@ impl From<PhysicalMemoryAddress> for PageFrame {
 
 impl Drop for PageFrame {
     fn drop(&mut self) {
-        // 重新建立一个Box，并在作用域结束时释放
-        let _a: Box<[u8; PGSZ]> = unsafe { Box::from_raw(self.address.0 as *mut _) };
+        unsafe { dealloc(self.address.as_usize() as *mut u8, PAGE_LAYOUT) };
     }
 }
 
@@ -37,10 +41,37 @@ impl PageFrame {
         self.address
     }
     pub fn to_usize(&self) -> usize {
-        self.address.0
+        self.address.as_usize()
+    }
+
+    // 页帧由这个结构独占，可以安全地作为字节数组访问
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.to_usize() as *const u8, PGSZ) }
+    }
+
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.to_usize() as *mut u8, PGSZ) }
+    }
+
+    // 页缓存中的页帧同时被多个地址空间映射，只能通过共享引用按字节写入
+    pub fn write_bytes(&self, offset: usize, data: &[u8]) {
+        assert!(offset + data.len() <= PGSZ);
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                data.as_ptr(),
+                (self.to_usize() + offset) as *mut u8,
+                data.len(),
+            )
+        };
+    }
+
+    // 把[offset, PGSZ)清零
+    pub fn zero_from(&self, offset: usize) {
+        assert!(offset <= PGSZ);
+        unsafe { core::ptr::write_bytes((self.to_usize() + offset) as *mut u8, 0, PGSZ - offset) };
     }
 }
 
-pub fn alloc_page() -> PageFrame {
+pub fn alloc_page() -> Result<PageFrame, PageTableErr> {
     PageFrame::alloc()
 }
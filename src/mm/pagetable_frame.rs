@@ -1,4 +1,8 @@
 use super::{
+    address::{
+        PhysPageRange, PhysicalMemoryAddress, PhysicalPageNumber, VirtPageRange,
+        VirtualMemoryAddress,
+    },
     def::PGSZ,
     page_frame::{alloc_page, PageFrame},
     pm::def::{kstack, phy_kstack, KERNEL_STACK_SIZE, MAX_PROCESS},
@@ -6,124 +10,15 @@ use super::{
 use crate::riscv::sv39::{pteflags::*, PTE_PPN_MASK, PTE_PPN_SHIFT};
 
 use alloc::{vec, vec::Vec};
-use core::{fmt::Display, mem::size_of, ops::IndexMut};
-use xxos_alloc::{align_down, align_up};
+use core::{mem::size_of, ops::IndexMut};
 use xxos_log::{error, info};
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PageTableErr {
     AlreadyMap,
     NeverMap,
     Unknown,
     NotFound,
-}
-
-// PMA have 2 fields:
-// 1. Page Offset field(0-11)
-// 2. PPN field(12-49)
-#[repr(transparent)]
-#[derive(Debug, Clone, Copy, Default)]
-pub struct PhysicalMemoryAddress(pub usize); // PMA
-const PMA_OFFSET_WIDTH: u8 = 12;
-const PMA_OFFSET_MASK: usize = (1 << PMA_OFFSET_WIDTH) - 1;
-const PMA_PPN_SHIFT: u8 = 12;
-const PMA_PPN_WIDTH: u8 = 22;
-const PMA_PPN_MASK: usize = ((1 << (PMA_PPN_SHIFT + PMA_PPN_WIDTH)) - 1) ^ PMA_OFFSET_MASK;
-
-impl From<usize> for PhysicalMemoryAddress {
-    fn from(pa: usize) -> Self {
-        Self(pa)
-    }
-}
-
-impl PhysicalMemoryAddress {
-    pub fn to_ppn(&self) -> PhysicalPageNumber {
-        ((self.0 & PMA_PPN_MASK) >> PMA_PPN_SHIFT).into()
-    }
-    pub fn to_pte(&self, flag: usize) -> PageTableEntry {
-        let bits = (self.0 >> 12 << 10) | flag;
-        PageTableEntry { bits }
-    }
-
-    pub fn get_mut_pagetable(&self) -> &'static mut PageTable {
-        unsafe { (self.0 as *mut PageTable).as_mut().unwrap() }
-    }
-    ///
-    /// # Safety
-    /// self.0 not null
-    pub unsafe fn get_mut<T>(&self) -> &'static mut T {
-        (self.0 as *mut T).as_mut().unwrap()
-    }
-}
-
-impl Display for PhysicalMemoryAddress {
-    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        write!(f, "pa: {:#x}", self.0)
-    }
-}
-
-#[derive(Debug, Clone, Copy, Default)]
-pub struct PhysicalPageNumber(pub usize); // PPN
-
-impl From<usize> for PhysicalPageNumber {
-    fn from(ppn: usize) -> Self {
-        Self(ppn)
-    }
-}
-
-impl PhysicalPageNumber {
-    pub fn to_pte(&self, flags: usize) -> PageTableEntry {
-        PageTableEntry::from(self.0 << PTE_PPN_SHIFT | flags)
-    }
-
-    pub fn to_pma(&self) -> PhysicalMemoryAddress {
-        PhysicalMemoryAddress::from(self.0 << PMA_PPN_SHIFT)
-    }
-}
-
-/// VMA have 2 fields:
-/// 1. Page Offset field(0-11)
-/// 2. VPN filed(12-38)
-///      VPN0: 3rd pagetable index field(12-20)
-///      VPN1: 2nd pagetable index field(21-29)
-///      VPN2: 1st pagetable index field(30-38)
-#[derive(Debug, Clone, Copy, Default)]
-pub struct VirtualMemoryAddress(pub usize); // VMA
-const VMA_OFFSET_WIDTH: u8 = 12;
-const VMA_OFFSET_MASK: usize = (1 << VMA_OFFSET_WIDTH) - 1;
-const VMA_VPN_SHIFT: u8 = 12;
-const VMA_VPN_WIDTH: u8 = 27;
-const VMA_VPN_PART_WIDTH: u8 = 9;
-const VMA_VPN_MASK: usize = ((1 << (VMA_VPN_SHIFT + VMA_VPN_WIDTH)) - 1) ^ VMA_OFFSET_MASK;
-
-impl From<usize> for VirtualMemoryAddress {
-    fn from(va: usize) -> Self {
-        Self(va)
-    }
-}
-
-impl VirtualMemoryAddress {
-    pub fn vpn(&self) -> VirtualPageNumber {
-        VirtualPageNumber::from((self.0 & VMA_VPN_MASK) >> VMA_VPN_SHIFT)
-    }
-
-    pub fn get_pagetable_index(&self, level: usize) -> usize {
-        (self.0 >> (12 + 9 * level)) & 0x1ff
-    }
-}
-
-#[derive(Debug, Clone, Copy, Default)]
-pub struct VirtualPageNumber(usize); // VPN
-
-impl From<usize> for VirtualPageNumber {
-    fn from(vpn: usize) -> Self {
-        Self(vpn)
-    }
-}
-
-impl VirtualPageNumber {
-    pub fn get_pagetable_index(&self, level: usize) -> usize {
-        (self.0 >> (VMA_VPN_PART_WIDTH as usize * level)) & ((1 << VMA_VPN_PART_WIDTH) - 1)
-    }
+    OutOfMemory,
 }
 
 #[repr(transparent)]
@@ -150,12 +45,12 @@ impl PageTableEntry {
 
     #[inline]
     pub fn to_ppn(&self) -> PhysicalPageNumber {
-        ((self.bits & PTE_PPN_MASK) >> PTE_PPN_SHIFT).into()
+        PhysicalPageNumber::new((self.bits & PTE_PPN_MASK) >> PTE_PPN_SHIFT)
     }
 
     #[inline]
     pub fn to_pma(&self) -> PhysicalMemoryAddress {
-        ((self.bits >> 10) << 12).into()
+        self.to_ppn().to_pma()
     }
 
     #[inline]
@@ -234,12 +129,12 @@ pub struct PageTableFrame {
 }
 
 impl PageTableFrame {
-    pub fn new() -> Self {
-        let page = alloc_page();
-        Self {
+    pub fn new() -> Result<Self, PageTableErr> {
+        let page = alloc_page()?;
+        Ok(Self {
             root: page.to_pma(),
             frames: vec![page],
-        }
+        })
     }
 
     pub fn save_page(&mut self, page: PageFrame) {
@@ -251,31 +146,39 @@ impl PageTableFrame {
     }
 
     pub fn get_mut_pagetable(&mut self) -> &'static mut PageTable {
-        unsafe { (self.root().0 as *mut PageTable).as_mut().unwrap() }
+        self.root.get_mut_pagetable()
     }
 
-    pub fn map_proc_stacks(&mut self) {
-        (0..MAX_PROCESS).for_each(|pid| {
-            let mut va = kstack(pid);
-            while va < kstack(pid) + KERNEL_STACK_SIZE {
-                //这里最好直接分配N个页
-                //我在这里偷懒直接使用了物理内存的最上面的一部分
-                let pa = phy_kstack(pid);
-                self.mappages(
-                    va.into(),
-                    pa.into(),
-                    KERNEL_STACK_SIZE,
-                    PTE_FLAG_X | PTE_FLAG_R | PTE_FLAG_W | PTE_FLAG_V,
-                );
-                va += KERNEL_STACK_SIZE;
-            }
-        });
+    pub fn map_proc_stacks(&mut self) -> Result<(), PageTableErr> {
+        (0..MAX_PROCESS).try_for_each(|pid| {
+            //这里最好直接分配N个页
+            //我在这里偷懒直接使用了物理内存的最上面的一部分
+            self.mappages(
+                VirtualMemoryAddress::new(kstack(pid)),
+                PhysicalMemoryAddress::new(phy_kstack(pid)),
+                KERNEL_STACK_SIZE,
+                PTE_FLAG_X | PTE_FLAG_R | PTE_FLAG_W | PTE_FLAG_V,
+            )
+        })
     }
 
     pub fn walk(
         &mut self,
         va: VirtualMemoryAddress,
         can_alloc: bool,
+    ) -> Result<&mut PageTableEntry, PageTableErr> {
+        if can_alloc {
+            self.walk_alloc(va, alloc_page)
+        } else {
+            self.walk_alloc(va, || Err(PageTableErr::NotFound))
+        }
+    }
+
+    // 缺少的中间页表页由alloc分配，用户地址空间借此走与用户页相同的OOM处理
+    pub fn walk_alloc(
+        &mut self,
+        va: VirtualMemoryAddress,
+        mut alloc: impl FnMut() -> Result<PageFrame, PageTableErr>,
     ) -> Result<&mut PageTableEntry, PageTableErr> {
         let mut pgtb = self.get_mut_pagetable();
         let mut idx = 0;
@@ -285,17 +188,13 @@ impl PageTableFrame {
                 break;
             }
             let pte = pgtb.get_index(idx);
-            if pte.is_v() {
-                pgtb = pte.get_mut_pagetable();
-            } else if can_alloc {
-                let page = alloc_page();
+            if !pte.is_v() {
+                let page = alloc()?;
                 let new_page = page.to_pma().to_pte(PTE_FLAG_V);
                 pte.set(new_page);
                 self.save_page(page);
-                pgtb = pte.get_mut_pagetable();
-            } else {
-                return Err(PageTableErr::NotFound);
             }
+            pgtb = pte.get_mut_pagetable();
         }
         Ok(pgtb.get_index(idx))
     }
@@ -308,7 +207,18 @@ impl PageTableFrame {
         pa: PhysicalMemoryAddress,
         flags: usize,
     ) -> Result<&PageTableEntry, PageTableErr> {
-        match self.walk(va, true)? {
+        self.map_with(va, pa, flags, alloc_page)
+    }
+
+    // 与map相同，页表页由alloc分配
+    pub fn map_with(
+        &mut self,
+        va: VirtualMemoryAddress,
+        pa: PhysicalMemoryAddress,
+        flags: usize,
+        alloc: impl FnMut() -> Result<PageFrame, PageTableErr>,
+    ) -> Result<&PageTableEntry, PageTableErr> {
+        match self.walk_alloc(va, alloc)? {
             pte if pte.is_v() => Err(PageTableErr::AlreadyMap),
             pte => {
                 pte.set(pa.to_pte(flags));
@@ -317,45 +227,41 @@ impl PageTableFrame {
         }
     }
 
+    // 只清除有效的PTE(重构之前条件写反了，unmap实际上什么也不做)
     pub fn unmap(&mut self, va: VirtualMemoryAddress) {
         match self.walk(va, false) {
-            Ok(pte) => {
-                if pte.is_v() {
-                    error!("it's never map");
-                } else {
-                    pte.clear();
-                };
-            }
-            Err(_) => error!("it's never map"),
+            Ok(pte) if pte.is_v() => pte.clear(),
+            _ => error!("it's never map"),
         }
     }
 
+    // map [va, va + size) to [pa, pa + size) page by page
+    // 两端按页扩展，结尾不满一页的部分也会映射整页
+    // 中途失败时撤销本次已经建立的映射，不会留下映射了一半的区间
     pub fn mappages(
         &mut self,
         va: VirtualMemoryAddress,
         pa: PhysicalMemoryAddress,
         size: usize,
         flags: usize,
-    ) {
+    ) -> Result<(), PageTableErr> {
         info!("======== mappages start ========");
-        let mut addr = align_down!(va.0, PGSZ);
-        let last = align_down!(va.0 + size, PGSZ);
-        let mut pa = pa.0;
-        while addr < last {
-            let Ok(_) = self.map(addr.into(), pa.into(), flags) else {
-                panic!("Err")
-            };
-            addr += PGSZ;
-            pa += PGSZ;
+        let vpages = VirtPageRange::from_size(va, size);
+        let ppages = PhysPageRange::from_size(pa, size);
+        for (vpn, ppn) in vpages.zip(ppages) {
+            if let Err(err) = self.map(vpn.to_vma(), ppn.to_pma(), flags) {
+                error!("mappages failed at {}: {:?}", vpn.to_vma(), err);
+                VirtPageRange::new(vpages.start(), vpn).for_each(|vpn| self.unmap(vpn.to_vma()));
+                return Err(err);
+            }
         }
         info!("======== mappages end ========");
+        Ok(())
     }
 
     pub fn unmappages(&mut self, va: VirtualMemoryAddress, size: usize) {
-        let va = (align_down!(va.0, PGSZ)).into();
-        let size = align_up!(size, PGSZ);
-        (0..size).step_by(PGSZ).for_each(|_| {
-            self.unmap(va);
+        VirtPageRange::from_size(va, size).for_each(|vpn| {
+            self.unmap(vpn.to_vma());
         });
     }
 }
@@ -1,12 +1,109 @@
 pub mod def;
 
 use crate::mm::pm::def::HEAP_TOP;
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    fmt::Display,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 use xxos_alloc::LockedSlab;
 use xxos_log::info;
 
 // 定义新的分配器
 #[global_allocator]
-static ALLOCATOR: LockedSlab = LockedSlab::new_uninit();
+static ALLOCATOR: Heap = Heap::new();
+
+// 在LockedSlab外面包一层，记录堆的使用情况
+// 内存耗尽时用于诊断
+pub struct Heap {
+    slab: LockedSlab,
+    total: AtomicUsize,
+    in_use: AtomicUsize,
+    peak: AtomicUsize,
+    allocs: AtomicUsize,
+    frees: AtomicUsize,
+    failures: AtomicUsize,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heap {
+    pub const fn new() -> Self {
+        Self {
+            slab: LockedSlab::new_uninit(),
+            total: AtomicUsize::new(0),
+            in_use: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+            allocs: AtomicUsize::new(0),
+            frees: AtomicUsize::new(0),
+            failures: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn init(&self, btm: usize, top: usize) {
+        self.total.store(top - btm, Ordering::Relaxed);
+        self.slab.init(btm, top);
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        HeapStats {
+            total: self.total.load(Ordering::Relaxed),
+            in_use: self.in_use.load(Ordering::Relaxed),
+            peak: self.peak.load(Ordering::Relaxed),
+            allocs: self.allocs.load(Ordering::Relaxed),
+            frees: self.frees.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for Heap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.slab.alloc(layout);
+        if ptr.is_null() {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.allocs.fetch_add(1, Ordering::Relaxed);
+            let in_use = self.in_use.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            self.peak.fetch_max(in_use, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.slab.dealloc(ptr, layout);
+        self.frees.fetch_add(1, Ordering::Relaxed);
+        self.in_use.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    pub total: usize,
+    pub in_use: usize,
+    pub peak: usize,
+    pub allocs: usize,
+    pub frees: usize,
+    pub failures: usize,
+}
+
+impl Display for HeapStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "heap: total {:#x} bytes, in use {:#x} bytes, peak {:#x} bytes, {} allocs, {} frees, {} failures",
+            self.total, self.in_use, self.peak, self.allocs, self.frees, self.failures
+        )
+    }
+}
+
+pub fn heap_stats() -> HeapStats {
+    ALLOCATOR.stats()
+}
 
 pub fn heap_init() {
     extern "C" {
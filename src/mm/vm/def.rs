@@ -1,3 +1,30 @@
 use super::kvm::LockedKvm;
+use crate::mm::pm::def::{PGSZ, TRAPFRAME};
+use core::sync::atomic::AtomicBool;
 
 pub static KVM: LockedKvm = LockedKvm::new();
+
+// user address space layout (without ASLR)
+//
+//  TRAMPOLINE
+//  TRAPFRAME
+//  guard page
+//  USER_STACK_TOP   stack grows down
+//  ...
+//  USER_MMAP_BASE   mmap area grows down
+//  ...
+//  heap             grows up from the end of the image
+//  USER_LOAD_BASE   PIE image
+pub const USER_STACK_TOP: usize = TRAPFRAME - PGSZ;
+pub const USER_STACK_SIZE: usize = PGSZ * 8;
+pub const USER_MMAP_BASE: usize = USER_STACK_TOP - (1 << 30);
+pub const USER_LOAD_BASE: usize = 0x10000;
+
+// 每个区域随机偏移的页数为 [0, 1 << bits)
+pub const ASLR_STACK_BITS: usize = 14;
+pub const ASLR_MMAP_BITS: usize = 16;
+pub const ASLR_LOAD_BITS: usize = 16;
+pub const ASLR_HEAP_BITS: usize = 12;
+
+// boot-time switch, new processes inherit it unless they override it
+pub static ASLR_ENABLED: AtomicBool = AtomicBool::new(true);
@@ -0,0 +1,83 @@
+use super::{uvm::Uvm, vma::Access};
+use crate::{
+    error::{Errno, Result},
+    mm::{address::VirtualMemoryAddress, def::PGSZ, pagetable_frame::PageTableErr},
+    proc::process::Tcb,
+};
+use alloc::vec::Vec;
+
+// 处理用户地址va处的缺页，失败时调用者杀死进程或者返回EFAULT
+// 读入文件页时可能睡眠，所以不能持有地址空间的锁
+pub fn handle_page_fault(task: &Tcb, va: VirtualMemoryAddress, access: Access) -> Result<()> {
+    let source = task.vm().lock().fault_source(va, access)?;
+    let page = match source {
+        Some((inode, index)) => {
+            // 文件末尾之后的页不能访问
+            if index * PGSZ as u64 >= inode.size() {
+                return Err(Errno::EFAULT.into());
+            }
+            Some(inode.get_page(index)?)
+        }
+        None => None,
+    };
+    task.vm().lock().map_fault(va, access, page)?;
+    Ok(())
+}
+
+// 映射[va, va + len)中还没有映射的页，用来在产生副作用之前检查用户缓冲区
+pub fn fault_in(task: &Tcb, va: VirtualMemoryAddress, len: usize, access: Access) -> Result<()> {
+    if len == 0 {
+        return Ok(());
+    }
+    let end = va.as_usize().checked_add(len).ok_or(Errno::EFAULT)?;
+    let mut page = va.align_down();
+    while page.as_usize() < end {
+        if !task.vm().lock().is_mapped(page, access) {
+            handle_page_fault(task, page, access)?;
+        }
+        page += PGSZ;
+    }
+    Ok(())
+}
+
+// 系统调用访问用户内存都经过这里：持有地址空间的锁执行f
+// f因为页还没有映射失败时，释放锁处理从va开始第一个没有映射的页，然后重试
+// 每次重试至少多映射一页，缺页处理失败时返回EFAULT
+fn with_user_pages<R>(
+    task: &Tcb,
+    va: VirtualMemoryAddress,
+    access: Access,
+    mut f: impl FnMut(&mut Uvm) -> core::result::Result<R, PageTableErr>,
+) -> Result<R> {
+    loop {
+        let mut vm = task.vm().lock();
+        match f(&mut vm) {
+            Err(PageTableErr::NeverMap) => {
+                let mut page = va.align_down();
+                while vm.is_mapped(page, access) {
+                    page += PGSZ;
+                }
+                drop(vm);
+                handle_page_fault(task, page, access)?;
+            }
+            result => return Ok(result?),
+        }
+    }
+}
+
+pub fn copy_from_user(task: &Tcb, dst: &mut [u8], va: VirtualMemoryAddress) -> Result<()> {
+    with_user_pages(task, va, Access::Read, |vm| vm.copy_in(dst, va))
+}
+
+pub fn copy_to_user(task: &Tcb, va: VirtualMemoryAddress, src: &[u8]) -> Result<()> {
+    with_user_pages(task, va, Access::Write, |vm| vm.copy_out(va, src))
+}
+
+// 超过max字节时返回None
+pub fn copy_str_from_user(
+    task: &Tcb,
+    va: VirtualMemoryAddress,
+    max: usize,
+) -> Result<Option<Vec<u8>>> {
+    with_user_pages(task, va, Access::Read, |vm| vm.copy_in_str(va, max))
+}
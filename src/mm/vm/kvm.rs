@@ -1,7 +1,9 @@
 use crate::{
+    driver::mmio_regions,
     mm::{
+        address::{PhysicalMemoryAddress, VirtualMemoryAddress},
         def::PGSZ,
-        pagetable_frame::PageTableFrame,
+        pagetable_frame::{PageTableErr, PageTableFrame},
         pm::def::{HEAP_TOP, TRAMPOLINE},
     },
     riscv::{
@@ -19,20 +21,14 @@ pub struct Kvm {
     pagetables: Box<PageTableFrame>,
 }
 
-impl Default for Kvm {
-    fn default() -> Self {
-        Self::new()
-    }
-}
-
 impl Kvm {
-    pub fn new() -> Self {
-        Self {
-            pagetables: Box::new(PageTableFrame::new()),
-        }
+    pub fn new() -> Result<Self, PageTableErr> {
+        Ok(Self {
+            pagetables: Box::new(PageTableFrame::new()?),
+        })
     }
 
-    pub fn init(&mut self) {
+    pub fn init(&mut self) -> Result<(), PageTableErr> {
         extern "C" {
             fn stext();
             fn strampsec();
@@ -44,54 +40,66 @@ impl Kvm {
         }
 
         // map text segment
-        self.pagetables.mappages(
-            (stext as usize).into(),
-            (stext as usize).into(),
-            (etext as usize) - (stext as usize),
+        self.map_identity(
+            stext as usize,
+            etext as usize,
             PTE_FLAG_R | PTE_FLAG_W | PTE_FLAG_X | PTE_FLAG_V,
-        );
+        )?;
 
         // trapvec code
         self.pagetables.mappages(
-            TRAMPOLINE.into(),
-            (strampsec as usize).into(),
+            VirtualMemoryAddress::new(TRAMPOLINE),
+            PhysicalMemoryAddress::new(strampsec as usize),
             PGSZ,
             PTE_FLAG_X | PTE_FLAG_R | PTE_FLAG_V,
-        );
+        )?;
 
         // map data segment
-        self.pagetables.mappages(
-            (srodata as usize).into(),
-            (srodata as usize).into(),
-            (erodata as usize) - (srodata as usize),
+        self.map_identity(
+            srodata as usize,
+            erodata as usize,
             PTE_FLAG_R | PTE_FLAG_W | PTE_FLAG_V,
-        );
+        )?;
 
-        self.pagetables.mappages(
-            (sdata as usize).into(),
-            (sdata as usize).into(),
-            (edata as usize) - (sdata as usize),
+        self.map_identity(
+            sdata as usize,
+            edata as usize,
             PTE_FLAG_R | PTE_FLAG_W | PTE_FLAG_V,
-        );
+        )?;
 
         // map all Physical Memory
-        self.pagetables.mappages(
-            (edata as usize).into(),
-            (edata as usize).into(),
-            HEAP_TOP - (edata as usize),
+        self.map_identity(
+            edata as usize,
+            HEAP_TOP,
             PTE_FLAG_R | PTE_FLAG_W | PTE_FLAG_V,
-        );
+        )?;
+
+        // map device MMIO
+        for (base, size) in mmio_regions() {
+            self.map_identity(base, base + size, PTE_FLAG_R | PTE_FLAG_W | PTE_FLAG_V)?;
+        }
 
         // map kernel stack
-        self.pagetables.map_proc_stacks();
+        self.pagetables.map_proc_stacks()?;
         self.write_satp();
+        Ok(())
+    }
+
+    // 内核采用直接映射，va == pa
+    fn map_identity(&mut self, start: usize, end: usize, flags: usize) -> Result<(), PageTableErr> {
+        self.pagetables.mappages(
+            VirtualMemoryAddress::new(start),
+            PhysicalMemoryAddress::new(start),
+            end - start,
+            flags,
+        )
     }
 
     pub fn as_satp(&self) -> Satp {
         let ppn = self.pagetables.root().to_ppn();
         let mut satp = Satp::new();
         satp.set_mode(crate::riscv::registers::satp::Mode::Sv39);
-        satp.set_ppn(ppn.0);
+        satp.set_ppn(ppn.as_usize());
         satp
     }
 
@@ -122,8 +130,9 @@ impl LockedKvm {
 
 pub fn kvmmake() -> Kvm {
     info!("============ kvmmake start ============");
-    let mut kvm = Kvm::new();
-    kvm.init();
+    // 内核页表建立失败时无法继续运行
+    let mut kvm = Kvm::new().expect("no memory for kernel pagetable");
+    kvm.init().expect("failed to map kernel address space");
     info!("============ kvmmake end ============");
     kvm
 }
@@ -0,0 +1,93 @@
+use super::def::{
+    ASLR_ENABLED, ASLR_HEAP_BITS, ASLR_LOAD_BITS, ASLR_MMAP_BITS, ASLR_STACK_BITS, USER_LOAD_BASE,
+    USER_MMAP_BASE, USER_STACK_TOP,
+};
+use crate::{
+    mm::{address::VirtualMemoryAddress, def::PGSZ},
+    random::random_usize,
+};
+use core::sync::atomic::Ordering;
+use xxos_log::info;
+
+pub fn set_aslr(enable: bool) {
+    info!("user ASLR {}", if enable { "enabled" } else { "disabled" });
+    ASLR_ENABLED.store(enable, Ordering::SeqCst);
+}
+
+pub fn aslr_enabled() -> bool {
+    ASLR_ENABLED.load(Ordering::SeqCst)
+}
+
+// random page-aligned offset in [0, (1 << bits) * PGSZ)
+fn random_offset(bits: usize) -> usize {
+    (random_usize() & ((1 << bits) - 1)) * PGSZ
+}
+
+/// 用户地址空间中各区域的基址
+/// 在进程创建(exec)时确定，之后不再改变
+#[derive(Debug, Clone, Copy)]
+pub struct UserLayout {
+    randomized: bool,
+    load_base: VirtualMemoryAddress,
+    stack_top: VirtualMemoryAddress,
+    mmap_base: VirtualMemoryAddress,
+    heap_offset: usize,
+}
+
+impl Default for UserLayout {
+    fn default() -> Self {
+        Self::new(aslr_enabled())
+    }
+}
+
+impl UserLayout {
+    pub fn new(randomize: bool) -> Self {
+        if randomize {
+            Self::randomized()
+        } else {
+            Self::fixed()
+        }
+    }
+
+    pub fn fixed() -> Self {
+        Self {
+            randomized: false,
+            load_base: VirtualMemoryAddress::new(USER_LOAD_BASE),
+            stack_top: VirtualMemoryAddress::new(USER_STACK_TOP),
+            mmap_base: VirtualMemoryAddress::new(USER_MMAP_BASE),
+            heap_offset: 0,
+        }
+    }
+
+    pub fn randomized() -> Self {
+        Self {
+            randomized: true,
+            load_base: VirtualMemoryAddress::new(USER_LOAD_BASE + random_offset(ASLR_LOAD_BITS)),
+            stack_top: VirtualMemoryAddress::new(USER_STACK_TOP - random_offset(ASLR_STACK_BITS)),
+            mmap_base: VirtualMemoryAddress::new(USER_MMAP_BASE - random_offset(ASLR_MMAP_BITS)),
+            heap_offset: random_offset(ASLR_HEAP_BITS),
+        }
+    }
+
+    pub fn is_randomized(&self) -> bool {
+        self.randomized
+    }
+
+    // where a position independent image is loaded
+    pub fn load_base(&self) -> VirtualMemoryAddress {
+        self.load_base
+    }
+
+    pub fn stack_top(&self) -> VirtualMemoryAddress {
+        self.stack_top
+    }
+
+    pub fn mmap_base(&self) -> VirtualMemoryAddress {
+        self.mmap_base
+    }
+
+    // the heap starts after the image, so its base depends on where the image ends
+    pub fn heap_base(&self, image_end: VirtualMemoryAddress) -> VirtualMemoryAddress {
+        image_end.align_up() + self.heap_offset
+    }
+}
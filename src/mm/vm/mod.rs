@@ -1,8 +1,11 @@
 use self::def::KVM;
 
 pub mod def;
+pub mod fault;
 pub mod kvm;
+pub mod layout;
 pub mod uvm;
+pub mod vma;
 
 pub fn kvm_init() {
     KVM.install_kvm()
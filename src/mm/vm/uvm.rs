@@ -1,72 +1,594 @@
+use super::{
+    layout::UserLayout,
+    vma::{Access, Vma},
+};
 use crate::{
+    fs::vfs::InodeRef,
     mm::{
+        address::{PhysicalMemoryAddress, VirtPageRange, VirtualMemoryAddress, VirtualPageNumber},
         def::PGSZ,
-        page_frame::alloc_page,
-        pagetable_frame::PageTableFrame,
+        oom::alloc_user_page,
+        page_frame::PageFrame,
+        pagetable_frame::{PageTableEntry, PageTableErr, PageTableFrame},
         pm::def::{TRAMPOLINE, TRAPFRAME},
     },
-    proc::process::Tcb,
     riscv::{
         registers::satp::Satp,
-        sv39::pteflags::{PTE_FLAG_R, PTE_FLAG_V, PTE_FLAG_W, PTE_FLAG_X},
+        sv39::pteflags::{PTE_FLAG_R, PTE_FLAG_U, PTE_FLAG_V, PTE_FLAG_W, PTE_FLAG_X},
     },
 };
-use alloc::boxed::Box;
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec::Vec};
+use core::{
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+// 一段连续映射、权限相同的用户页
+#[derive(Debug, Clone, Copy)]
+pub struct UserRegion {
+    pub start: VirtualMemoryAddress,
+    pub end: VirtualMemoryAddress,
+    pub flags: usize,
+}
 
 // User Virtual Memory
 // 完成用户态的虚拟内存映射，采用随机映射的方式
-/// # Unsafety
-/// 不稳定的数据结构，现阶段先不使用，待文件系统完成时使用
-/// 涉及到数据读取之类的
-#[derive(Default)]
+// 用户页由frames按虚拟页号持有，页表本身占用的页由pagetables持有
+// 文件映射的页与页缓存共享，所以frames中的页帧使用引用计数
+// mmap建立的区间记录在vmas中(按起始地址索引)，其中的页在缺页时才映射
 pub struct Uvm {
     pagetables: Box<PageTableFrame>,
+    frames: BTreeMap<VirtualPageNumber, Arc<PageFrame>>,
+    vmas: BTreeMap<VirtualMemoryAddress, Vma>,
+    layout: UserLayout,
+    heap_base: VirtualMemoryAddress,
+    brk: VirtualMemoryAddress,
+    // frames中的页数，与所属的进程共享，OOM时不需要锁住地址空间就可以读取
+    // exec时新旧两个地址空间共用同一个计数，旧的释放之后恢复准确
+    resident: Arc<AtomicUsize>,
 }
 
 impl Uvm {
-    pub fn map_trap(&mut self, trapframe: usize) -> &mut Self {
+    pub fn new(layout: UserLayout, resident: Arc<AtomicUsize>) -> Result<Self, PageTableErr> {
+        Ok(Self {
+            pagetables: Box::new(PageTableFrame::new()?),
+            frames: BTreeMap::new(),
+            vmas: BTreeMap::new(),
+            layout,
+            heap_base: VirtualMemoryAddress::default(),
+            brk: VirtualMemoryAddress::default(),
+            resident,
+        })
+    }
+
+    pub fn layout(&self) -> &UserLayout {
+        &self.layout
+    }
+
+    // trapframe所在的整页映射到TRAPFRAME，页内的其他trapframe没有PTE_FLAG_U，用户态无法访问
+    pub fn map_trap(&mut self, trapframe: usize) -> Result<&mut Self, PageTableErr> {
         extern "C" {
             fn strampsec();
         }
 
         // map trapvec code
-        self.pagetables.mappages(
-            TRAMPOLINE.into(),
-            (strampsec as usize).into(),
-            PGSZ,
+        self.map_page(
+            VirtualMemoryAddress::new(TRAMPOLINE),
+            PhysicalMemoryAddress::new(strampsec as usize),
             PTE_FLAG_V | PTE_FLAG_R | PTE_FLAG_X,
-        );
+        )?;
 
-        self.pagetables.mappages(
-            TRAPFRAME.into(),
-            trapframe.into(),
-            PGSZ,
+        if let Err(err) = self.map_page(
+            VirtualMemoryAddress::new(TRAPFRAME),
+            PhysicalMemoryAddress::new(trapframe).align_down(),
             PTE_FLAG_R | PTE_FLAG_W | PTE_FLAG_X | PTE_FLAG_V,
-        );
+        ) {
+            self.pagetables.unmap(VirtualMemoryAddress::new(TRAMPOLINE));
+            return Err(err);
+        }
+
+        Ok(self)
+    }
+
+    // allocate zeroed pages for [va, va + size) and map them
+    // 分配失败时释放本次已经分配的页并撤销映射
+    pub fn alloc_pages(
+        &mut self,
+        va: VirtualMemoryAddress,
+        size: usize,
+        flags: usize,
+    ) -> Result<&mut Self, PageTableErr> {
+        let range = VirtPageRange::from_size(va, size);
+        for vpn in range {
+            if let Err(err) = self.alloc_page_at(vpn, flags) {
+                self.dealloc_pages(range.start().to_vma(), (vpn - range.start()) * PGSZ);
+                return Err(err);
+            }
+        }
+        Ok(self)
+    }
+
+    fn alloc_page_at(&mut self, vpn: VirtualPageNumber, flags: usize) -> Result<(), PageTableErr> {
+        let page = self.alloc_user_frame()?;
+        self.map_page(vpn.to_vma(), page.to_pma(), flags)?;
+        self.insert_frame(vpn, page);
+        Ok(())
+    }
+
+    fn insert_frame(&mut self, vpn: VirtualPageNumber, frame: Arc<PageFrame>) {
+        if self.frames.insert(vpn, frame).is_none() {
+            self.resident.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn remove_frame(&mut self, vpn: VirtualPageNumber) -> Option<Arc<PageFrame>> {
+        let frame = self.frames.remove(&vpn)?;
+        self.resident.fetch_sub(1, Ordering::Relaxed);
+        Some(frame)
+    }
+
+    // 页表页与用户页一样通过alloc_user_page分配，内存不够时同样会回收或者杀死进程
+    fn map_page(
+        &mut self,
+        va: VirtualMemoryAddress,
+        pa: PhysicalMemoryAddress,
+        flags: usize,
+    ) -> Result<(), PageTableErr> {
+        let resident = self.resident_pages();
+        self.pagetables
+            .map_with(va, pa, flags, || alloc_user_page(resident))?;
+        Ok(())
+    }
+
+    fn alloc_user_frame(&self) -> Result<Arc<PageFrame>, PageTableErr> {
+        let page = alloc_user_page(self.resident_pages())?;
+        Arc::try_new(page).map_err(|_| PageTableErr::OutOfMemory)
+    }
+
+    // unmap [va, va + size) and free the pages owned by this address space
+    pub fn dealloc_pages(&mut self, va: VirtualMemoryAddress, size: usize) {
+        for vpn in VirtPageRange::from_size(va, size) {
+            if self.remove_frame(vpn).is_some() {
+                self.pagetables.unmap(vpn.to_vma());
+            }
+        }
+    }
+
+    // release every user page, the pagetable itself is kept
+    // 返回所有的映射区间，调用者在释放锁之后丢弃它们(最后一个引用释放inode时可能睡眠)
+    pub fn release(&mut self) -> Vec<Vma> {
+        while let Some((vpn, _page)) = self.frames.pop_first() {
+            self.resident.fetch_sub(1, Ordering::Relaxed);
+            self.pagetables.unmap(vpn.to_vma());
+        }
+        core::mem::take(&mut self.vmas).into_values().collect()
+    }
+
+    // fork时复制地址空间，私有的页复制一份，共享映射与还没有写入的页缓存页由父子进程共用
+    // TRAMPOLINE与TRAPFRAME不在frames中，由调用者为子进程映射
+    pub fn fork(&mut self, resident: Arc<AtomicUsize>) -> Result<Uvm, PageTableErr> {
+        let mut child = Uvm::new(self.layout, resident)?;
+        child.vmas = self.vmas.clone();
+        child.heap_base = self.heap_base;
+        child.brk = self.brk;
+
+        let frames: Vec<(VirtualPageNumber, Arc<PageFrame>)> = self
+            .frames
+            .iter()
+            .map(|(vpn, frame)| (*vpn, frame.clone()))
+            .collect();
+        for (vpn, frame) in frames {
+            let va = vpn.to_vma();
+            let flags = self.pagetables.walk(va, false)?.bits()
+                & (PTE_FLAG_U | PTE_FLAG_V | PTE_FLAG_R | PTE_FLAG_W | PTE_FLAG_X);
+            // 私有文件映射中只读的页来自页缓存，写入时由缺页处理复制
+            let share = self
+                .vma(va)
+                .is_some_and(|vma| vma.shared || (vma.file.is_some() && flags & PTE_FLAG_W == 0));
+            let frame = if share {
+                frame
+            } else {
+                self.copy_frame(&frame)?
+            };
+            child.map_frame(va, frame, flags)?;
+        }
+        Ok(child)
+    }
+
+    // 进程占用的用户页数，OOM时按此选择要杀死的进程
+    pub fn resident_pages(&self) -> usize {
+        self.frames.len()
+    }
+
+    // [heap_base, brk)
+    pub fn heap(&self) -> (VirtualMemoryAddress, VirtualMemoryAddress) {
+        (self.heap_base, self.brk)
+    }
+
+    // 按地址顺序合并mmap区间之外相邻的用户页，/proc/<pid>/maps使用
+    pub fn regions(&mut self) -> Vec<UserRegion> {
+        let vpns: Vec<VirtualPageNumber> = self.frames.keys().copied().collect();
+        let mut regions: Vec<UserRegion> = Vec::new();
+        for vpn in vpns {
+            let va = vpn.to_vma();
+            if self.vma(va).is_some() {
+                continue;
+            }
+            let Ok(pte) = self.pagetables.walk(va, false) else {
+                continue;
+            };
+            let flags = pte.bits() & (PTE_FLAG_R | PTE_FLAG_W | PTE_FLAG_X | PTE_FLAG_U);
+            match regions.last_mut() {
+                Some(last) if last.end == va && last.flags == flags => last.end = va + PGSZ,
+                _ => regions.push(UserRegion {
+                    start: va,
+                    end: va + PGSZ,
+                    flags,
+                }),
+            }
+        }
+        regions
+    }
+
+    // 给已经映射的页增加权限，装载程序时几个段共用一页使用
+    pub fn add_page_flags(
+        &mut self,
+        va: VirtualMemoryAddress,
+        flags: usize,
+    ) -> Result<(), PageTableErr> {
+        match self.pagetables.walk(va, false)? {
+            pte if pte.is_v() => {
+                pte.set(PageTableEntry::from(pte.bits() | flags));
+                Ok(())
+            }
+            _ => Err(PageTableErr::NeverMap),
+        }
+    }
+
+    pub fn translate(&mut self, va: VirtualMemoryAddress) -> Option<PhysicalMemoryAddress> {
+        match self.pagetables.walk(va, false) {
+            Ok(pte) if pte.is_v() => Some(pte.to_pma() + va.page_offset()),
+            _ => None,
+        }
+    }
+
+    // 检查用户页的权限，返回va所在页对应的物理地址
+    fn translate_user(
+        &mut self,
+        va: VirtualMemoryAddress,
+        write: bool,
+    ) -> Result<PhysicalMemoryAddress, PageTableErr> {
+        let access = if write { Access::Write } else { Access::Read };
+        if !self.is_mapped(va, access) {
+            return Err(PageTableErr::NeverMap);
+        }
+        let pte = self.pagetables.walk(va, false)?;
+        Ok(pte.to_pma() + va.page_offset())
+    }
 
-        self
+    // copy from user space [va, va + dst.len()) to dst
+    pub fn copy_in(
+        &mut self,
+        dst: &mut [u8],
+        mut va: VirtualMemoryAddress,
+    ) -> Result<(), PageTableErr> {
+        let mut copied = 0;
+        while copied < dst.len() {
+            let pa = self.translate_user(va, false)?;
+            let len = (PGSZ - va.page_offset()).min(dst.len() - copied);
+            unsafe {
+                ptr::copy_nonoverlapping(
+                    pa.as_usize() as *const u8,
+                    dst[copied..].as_mut_ptr(),
+                    len,
+                )
+            };
+            copied += len;
+            va += len;
+        }
+        Ok(())
+    }
+
+    // copy src to user space [va, va + src.len())
+    pub fn copy_out(
+        &mut self,
+        mut va: VirtualMemoryAddress,
+        src: &[u8],
+    ) -> Result<(), PageTableErr> {
+        let mut copied = 0;
+        while copied < src.len() {
+            let pa = self.translate_user(va, true)?;
+            let len = (PGSZ - va.page_offset()).min(src.len() - copied);
+            unsafe {
+                ptr::copy_nonoverlapping(src[copied..].as_ptr(), pa.as_usize() as *mut u8, len)
+            };
+            copied += len;
+            va += len;
+        }
+        Ok(())
+    }
+
+    // 装载程序时写入用户页，不检查写权限(代码段不可写)
+    pub fn copy_image(
+        &mut self,
+        mut va: VirtualMemoryAddress,
+        src: &[u8],
+    ) -> Result<(), PageTableErr> {
+        let mut copied = 0;
+        while copied < src.len() {
+            let pa = self.translate(va).ok_or(PageTableErr::NeverMap)?;
+            let len = (PGSZ - va.page_offset()).min(src.len() - copied);
+            unsafe {
+                ptr::copy_nonoverlapping(src[copied..].as_ptr(), pa.as_usize() as *mut u8, len)
+            };
+            copied += len;
+            va += len;
+        }
+        Ok(())
+    }
+
+    // copy a NUL-terminated string from user space, at most max bytes without the NUL
+    // 超过max仍没有遇到NUL时返回None
+    pub fn copy_in_str(
+        &mut self,
+        mut va: VirtualMemoryAddress,
+        max: usize,
+    ) -> Result<Option<Vec<u8>>, PageTableErr> {
+        let mut bytes = Vec::new();
+        while bytes.len() <= max {
+            let pa = self.translate_user(va, false)?;
+            let len = PGSZ - va.page_offset();
+            let page = unsafe { core::slice::from_raw_parts(pa.as_usize() as *const u8, len) };
+            match page.iter().position(|&b| b == 0) {
+                Some(end) => {
+                    bytes.extend_from_slice(&page[..end]);
+                    return Ok((bytes.len() <= max).then_some(bytes));
+                }
+                None => bytes.extend_from_slice(page),
+            }
+            va += len;
+        }
+        Ok(None)
+    }
+
+    pub fn init_heap(&mut self, image_end: VirtualMemoryAddress) {
+        self.heap_base = self.layout.heap_base(image_end);
+        self.brk = self.heap_base;
+    }
+
+    // grow or shrink the heap, return the old program break
+    pub fn sbrk(&mut self, increment: isize) -> Result<VirtualMemoryAddress, PageTableErr> {
+        let old = self.brk;
+        let new = VirtualMemoryAddress::new(old.as_usize().wrapping_add_signed(increment));
+        // 堆不能越过mmap区域与已经建立的映射，超出时与内存耗尽同样处理
+        if new < self.heap_base || new > self.heap_limit() {
+            return Err(PageTableErr::OutOfMemory);
+        }
+
+        if new > old {
+            self.alloc_pages(
+                old.align_up(),
+                new.align_up() - old.align_up(),
+                PTE_FLAG_U | PTE_FLAG_V | PTE_FLAG_R | PTE_FLAG_W,
+            )?;
+        } else {
+            self.dealloc_pages(new.align_up(), old.align_up() - new.align_up());
+        }
+        self.brk = new;
+        Ok(old)
     }
 
-    /// # Safety
-    /// 这个函数不稳定,之后可能需要结合src copy一起使用，现在先不使用
-    pub unsafe fn mappages(&mut self, va: usize, size: usize, flags: usize) -> &mut Self {
-        let mut va = va;
-        let last = va + size;
-        while va < last {
-            let page = alloc_page();
-            let pa = page.to_pma();
-            self.pagetables.save_page(page);
-            self.pagetables.mappages(va.into(), pa, PGSZ, flags);
-            va += PGSZ;
+    pub fn vmas(&self) -> Vec<Vma> {
+        self.vmas.values().cloned().collect()
+    }
+
+    // 堆最多增长到最低的映射区间
+    fn heap_limit(&self) -> VirtualMemoryAddress {
+        self.vmas
+            .values()
+            .next()
+            .map_or(self.layout.mmap_base(), |vma| vma.start)
+    }
+
+    fn vma(&self, va: VirtualMemoryAddress) -> Option<&Vma> {
+        self.vmas
+            .range(..=va)
+            .next_back()
+            .map(|(_, vma)| vma)
+            .filter(|vma| vma.contains(va))
+    }
+
+    // 建立映射区间[start, start + len)，页在缺页时才分配
+    // fixed为None时从mmap_base向下寻找第一个足够大的空闲区间
+    // 指定的地址必须位于堆与mmap_base之间，其中原有的映射被撤销并返回，与munmap相同
+    pub fn mmap(
+        &mut self,
+        fixed: Option<VirtualMemoryAddress>,
+        len: usize,
+        mut vma: Vma,
+    ) -> Result<(VirtualMemoryAddress, Vec<Vma>), PageTableErr> {
+        let floor = self.brk.align_up();
+        let (start, old) = match fixed {
+            Some(start) => {
+                let mmap_base = self.layout.mmap_base();
+                if start < floor || start > mmap_base || mmap_base - start < len {
+                    return Err(PageTableErr::Unknown);
+                }
+                (start, self.munmap(start, start + len))
+            }
+            None => (self.find_free(floor, len)?, Vec::new()),
+        };
+        vma.start = start;
+        vma.end = start + len;
+        self.vmas.insert(start, vma);
+        Ok((start, old))
+    }
+
+    fn find_free(
+        &self,
+        floor: VirtualMemoryAddress,
+        len: usize,
+    ) -> Result<VirtualMemoryAddress, PageTableErr> {
+        let mut top = self.layout.mmap_base();
+        for vma in self.vmas.values().rev() {
+            if top - vma.end >= len {
+                return Ok(top - len);
+            }
+            top = vma.start;
+        }
+        if top >= floor && top - floor >= len {
+            Ok(top - len)
+        } else {
+            Err(PageTableErr::OutOfMemory)
         }
-        self
+    }
+
+    // 撤销[start, end)中的映射，部分覆盖的区间被切开
+    // 返回被移除的部分，调用者在释放锁之后写回共享映射并丢弃它们
+    pub fn munmap(&mut self, start: VirtualMemoryAddress, end: VirtualMemoryAddress) -> Vec<Vma> {
+        let keys: Vec<VirtualMemoryAddress> = self
+            .vmas
+            .range(..end)
+            .filter(|(_, vma)| vma.end > start)
+            .map(|(key, _)| *key)
+            .collect();
+        let mut removed = Vec::new();
+        for key in keys {
+            let mut vma = self.vmas.remove(&key).unwrap();
+            if vma.start < start {
+                let tail = vma.split_off(start);
+                self.vmas.insert(vma.start, vma);
+                vma = tail;
+            }
+            if vma.end > end {
+                let tail = vma.split_off(end);
+                self.vmas.insert(tail.start, tail);
+            }
+            removed.push(vma);
+        }
+        for vma in &removed {
+            self.dealloc_pages(vma.start, vma.end - vma.start);
+        }
+        removed
+    }
+
+    // [start, end)中共享文件映射的inode，msync使用
+    pub fn shared_files(
+        &self,
+        start: VirtualMemoryAddress,
+        end: VirtualMemoryAddress,
+    ) -> Vec<InodeRef> {
+        self.vmas
+            .range(..end)
+            .filter(|(_, vma)| vma.end > start && vma.shared)
+            .filter_map(|(_, vma)| vma.file.as_ref().map(|file| file.inode.clone()))
+            .collect()
+    }
+
+    // 页表中的映射已经允许这次访问
+    pub fn is_mapped(&mut self, va: VirtualMemoryAddress, access: Access) -> bool {
+        match self.pagetables.walk(va, false) {
+            Ok(pte) if pte.is_v() && pte.is_u() => match access {
+                Access::Read => pte.is_r(),
+                Access::Write => pte.is_w(),
+                Access::Exec => pte.is_x(),
+            },
+            _ => false,
+        }
+    }
+
+    // 缺页处理的第一步：检查访问是否合法
+    // 需要从文件读入页时返回inode与页号，调用者在释放锁之后读入，再调用map_fault
+    pub fn fault_source(
+        &mut self,
+        va: VirtualMemoryAddress,
+        access: Access,
+    ) -> Result<Option<(InodeRef, u64)>, PageTableErr> {
+        let vma = self.vma(va).ok_or(PageTableErr::NeverMap)?;
+        if !vma.allows(access) {
+            return Err(PageTableErr::NeverMap);
+        }
+        let source = vma
+            .file_page(va)
+            .map(|(inode, index)| (inode.clone(), index));
+        if self.frames.contains_key(&va.vpn()) {
+            // 页已经映射，只需要写时复制或者打开写权限
+            return Ok(None);
+        }
+        Ok(source)
+    }
+
+    // 缺页处理的第二步：建立映射，page是fault_source要求读入的页缓存中的页
+    pub fn map_fault(
+        &mut self,
+        va: VirtualMemoryAddress,
+        access: Access,
+        page: Option<Arc<PageFrame>>,
+    ) -> Result<(), PageTableErr> {
+        let vma = self.vma(va).ok_or(PageTableErr::NeverMap)?.clone();
+        let va = va.align_down();
+        let flags = PTE_FLAG_U | PTE_FLAG_V | vma.prot;
+        let write = access == Access::Write;
+
+        // 已经映射的页只会因为写入只读的页缓存页而缺页
+        let mapped = self.frames.get(&va.vpn()).cloned();
+        let frame = match (mapped.clone(), vma.file_page(va), page) {
+            _ if self.is_mapped(va, access) => return Ok(()),
+            (Some(frame), _, _) => frame,
+            (None, None, _) => {
+                let frame = self.alloc_user_frame()?;
+                return self.map_frame(va, frame, flags);
+            }
+            (None, Some(_), Some(page)) => page,
+            (None, Some(_), None) => return Err(PageTableErr::NotFound),
+        };
+
+        let (frame, flags) = match vma.file_page(va) {
+            // 私有映射写入时复制一份，之前与页缓存共享同一页
+            _ if write && !vma.shared => (self.copy_frame(&frame)?, flags),
+            // 共享映射写入时记录需要写回的页
+            Some((inode, index)) if write => {
+                inode.set_page_dirty(index);
+                (frame, flags)
+            }
+            // 先映射为只读，第一次写入时再次缺页
+            _ => (frame, flags & !PTE_FLAG_W),
+        };
+        if mapped.is_some() {
+            self.pagetables.unmap(va);
+        }
+        self.map_frame(va, frame, flags)
+    }
+
+    fn copy_frame(&self, src: &PageFrame) -> Result<Arc<PageFrame>, PageTableErr> {
+        let page = self.alloc_user_frame()?;
+        page.write_bytes(0, src.as_bytes());
+        Ok(page)
+    }
+
+    fn map_frame(
+        &mut self,
+        va: VirtualMemoryAddress,
+        frame: Arc<PageFrame>,
+        flags: usize,
+    ) -> Result<(), PageTableErr> {
+        self.map_page(va, frame.to_pma(), flags)?;
+        self.insert_frame(va.vpn(), frame);
+        Ok(())
     }
 
     pub fn as_satp(&self) -> Satp {
         let ppn = self.pagetables.root().to_ppn();
         let mut satp = Satp::new();
         satp.set_mode(crate::riscv::registers::satp::Mode::Sv39);
-        satp.set_ppn(ppn.0);
+        satp.set_ppn(ppn.as_usize());
         satp
     }
 }
+
+impl Drop for Uvm {
+    fn drop(&mut self) {
+        self.resident
+            .fetch_sub(self.frames.len(), Ordering::Relaxed);
+    }
+}
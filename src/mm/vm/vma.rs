@@ -0,0 +1,71 @@
+use crate::{
+    fs::vfs::InodeRef,
+    mm::{address::VirtualMemoryAddress, def::PGSZ},
+    riscv::sv39::pteflags::{PTE_FLAG_R, PTE_FLAG_W, PTE_FLAG_X},
+};
+use alloc::string::String;
+
+// 引起缺页的访问类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Exec,
+}
+
+// 文件映射：start对应文件中的offset，offset按页对齐
+#[derive(Clone)]
+pub struct VmaFile {
+    pub inode: InodeRef,
+    pub offset: u64,
+    // 打开文件时使用的路径，/proc/<pid>/maps显示
+    pub path: Option<String>,
+}
+
+// mmap建立的一段虚拟地址区间[start, end)
+// 页在第一次访问时由缺页处理映射，file为None时是匿名映射
+#[derive(Clone)]
+pub struct Vma {
+    pub start: VirtualMemoryAddress,
+    pub end: VirtualMemoryAddress,
+    // PTE_FLAG_R/W/X的组合
+    pub prot: usize,
+    // MAP_SHARED：写入对其他映射与read可见，并写回文件
+    // MAP_PRIVATE：第一次写入时复制一页(copy-on-write)
+    pub shared: bool,
+    pub file: Option<VmaFile>,
+}
+
+impl Vma {
+    pub fn contains(&self, va: VirtualMemoryAddress) -> bool {
+        self.start <= va && va < self.end
+    }
+
+    pub fn allows(&self, access: Access) -> bool {
+        let flag = match access {
+            Access::Read => PTE_FLAG_R,
+            Access::Write => PTE_FLAG_W,
+            Access::Exec => PTE_FLAG_X,
+        };
+        self.prot & flag != 0
+    }
+
+    // va所在的页在文件中的页号
+    pub fn file_page(&self, va: VirtualMemoryAddress) -> Option<(&InodeRef, u64)> {
+        self.file.as_ref().map(|file| {
+            let pos = file.offset + (va.align_down() - self.start) as u64;
+            (&file.inode, pos / PGSZ as u64)
+        })
+    }
+
+    // 在at处切开，self保留[start, at)，返回[at, end)
+    pub fn split_off(&mut self, at: VirtualMemoryAddress) -> Vma {
+        let mut tail = self.clone();
+        tail.start = at;
+        if let Some(file) = &mut tail.file {
+            file.offset += (at - self.start) as u64;
+        }
+        self.end = at;
+        tail
+    }
+}
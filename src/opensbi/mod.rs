@@ -12,6 +12,14 @@ impl Opensbi {
         sbi_call(SBI_CONSOLE_PUTCHAR, c, 0, 0, 0);
     }
 
+    // legacy getchar，没有输入时返回-1
+    pub fn console_getchar() -> Option<u8> {
+        match sbi_call(SBI_CONSOLE_GETCHAR, 0, 0, 0, 0) as isize {
+            -1 => None,
+            c => Some(c as u8),
+        }
+    }
+
     // 启动硬件线程(hart, Hardware Thread)
     // 在risc-v中，一个hart就是一个CPU
     pub fn sbi_hsm_hart_start(hart_id: usize) -> usize {
@@ -0,0 +1,34 @@
+use crate::cpu::current_task;
+use alloc::vec::Vec;
+
+pub const ROOT_UID: u32 = 0;
+// 附加组的最大个数
+pub const NGROUPS_MAX: usize = 32;
+
+// 进程的身份，uid为0的root是超级用户，不受文件权限的限制
+#[derive(Debug, Clone, Default)]
+pub struct Cred {
+    pub uid: u32,
+    pub gid: u32,
+    // 附加组，检查组权限时与gid同样对待
+    pub groups: Vec<u32>,
+}
+
+impl Cred {
+    pub fn root() -> Self {
+        Self::default()
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.uid == ROOT_UID
+    }
+
+    pub fn in_group(&self, gid: u32) -> bool {
+        self.gid == gid || self.groups.contains(&gid)
+    }
+}
+
+// 当前进程的身份，内核初始化时还没有进程，以root的身份运行
+pub fn current_cred() -> Cred {
+    current_task().map_or_else(Cred::root, |task| task.cred().lock().clone())
+}
@@ -0,0 +1,208 @@
+use crate::{
+    error::{Errno, Result},
+    mm::{
+        address::{VirtPageRange, VirtualMemoryAddress},
+        def::PGSZ,
+        vm::{def::USER_STACK_SIZE, uvm::Uvm},
+    },
+    riscv::sv39::pteflags::{PTE_FLAG_R, PTE_FLAG_U, PTE_FLAG_V, PTE_FLAG_W, PTE_FLAG_X},
+};
+use alloc::vec::Vec;
+
+// 只支持RV64的静态链接程序，ET_DYN按静态PIE装载到layout的load_base
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const ET_DYN: u16 = 3;
+const EM_RISCV: u16 = 243;
+
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+
+// program header types
+const PT_LOAD: u32 = 1;
+const PT_INTERP: u32 = 3;
+
+// segment permissions
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+// auxiliary vector
+const AT_NULL: u64 = 0;
+const AT_PAGESZ: u64 = 6;
+const AT_ENTRY: u64 = 9;
+
+#[inline]
+fn le16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+#[inline]
+fn le32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+#[inline]
+fn le64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+struct ProgramHeader {
+    p_type: u32,
+    flags: u32,
+    offset: usize,
+    vaddr: usize,
+    filesz: usize,
+    memsz: usize,
+}
+
+impl ProgramHeader {
+    fn parse(raw: &[u8]) -> Self {
+        Self {
+            p_type: le32(raw, 0),
+            flags: le32(raw, 4),
+            offset: le64(raw, 8) as usize,
+            vaddr: le64(raw, 16) as usize,
+            filesz: le64(raw, 32) as usize,
+            memsz: le64(raw, 40) as usize,
+        }
+    }
+
+    fn pte_flags(&self) -> usize {
+        let mut flags = PTE_FLAG_U | PTE_FLAG_V;
+        for (pf, pte) in [(PF_R, PTE_FLAG_R), (PF_W, PTE_FLAG_W), (PF_X, PTE_FLAG_X)] {
+            if self.flags & pf != 0 {
+                flags |= pte;
+            }
+        }
+        flags
+    }
+}
+
+// 装载后的入口与映像末尾，堆从映像末尾之后开始
+pub struct ElfImage {
+    pub entry: VirtualMemoryAddress,
+    pub end: VirtualMemoryAddress,
+}
+
+// 把PT_LOAD段映射到vm中，失败时已经映射的页由调用者释放
+pub fn load(vm: &mut Uvm, image: &[u8]) -> Result<ElfImage> {
+    if image.len() < EHDR_SIZE
+        || &image[..4] != ELF_MAGIC
+        || image[4] != ELFCLASS64
+        || image[5] != ELFDATA2LSB
+        || le16(image, 18) != EM_RISCV
+    {
+        return Err(Errno::ENOEXEC.into());
+    }
+    let bias = match le16(image, 16) {
+        ET_EXEC => 0,
+        ET_DYN => vm.layout().load_base().as_usize(),
+        _ => return Err(Errno::ENOEXEC.into()),
+    };
+    let entry = le64(image, 24) as usize;
+    let phoff = le64(image, 32) as usize;
+    let phentsize = le16(image, 54) as usize;
+    let phnum = le16(image, 56) as usize;
+    if phentsize < PHDR_SIZE {
+        return Err(Errno::ENOEXEC.into());
+    }
+    let phdrs = phnum
+        .checked_mul(phentsize)
+        .and_then(|size| image.get(phoff..phoff.checked_add(size)?))
+        .ok_or(Errno::ENOEXEC)?;
+
+    let limit = vm.layout().mmap_base().as_usize();
+    let mut end = 0;
+    for raw in phdrs.chunks_exact(phentsize) {
+        let ph = ProgramHeader::parse(raw);
+        match ph.p_type {
+            PT_LOAD => {}
+            // 没有动态链接器
+            PT_INTERP => return Err(Errno::ENOEXEC.into()),
+            _ => continue,
+        }
+        let start = bias.checked_add(ph.vaddr).ok_or(Errno::ENOEXEC)?;
+        let seg_end = start.checked_add(ph.memsz).ok_or(Errno::ENOEXEC)?;
+        let data = ph
+            .offset
+            .checked_add(ph.filesz)
+            .and_then(|data_end| image.get(ph.offset..data_end))
+            .ok_or(Errno::ENOEXEC)?;
+        if ph.filesz > ph.memsz || seg_end > limit {
+            return Err(Errno::ENOEXEC.into());
+        }
+
+        // 相邻的段可能共用一页，这一页的权限是各个段权限的并集
+        let start = VirtualMemoryAddress::new(start);
+        for vpn in
+            VirtPageRange::from_size(start.align_down(), seg_end - start.align_down().as_usize())
+        {
+            if vm.translate(vpn.to_vma()).is_none() {
+                vm.alloc_pages(vpn.to_vma(), PGSZ, ph.pte_flags())?;
+            } else {
+                vm.add_page_flags(vpn.to_vma(), ph.pte_flags())?;
+            }
+        }
+        // 新分配的页已经清零，.bss不需要再处理
+        vm.copy_image(start, data)?;
+        end = end.max(seg_end);
+    }
+    if end == 0 {
+        return Err(Errno::ENOEXEC.into());
+    }
+    Ok(ElfImage {
+        entry: VirtualMemoryAddress::new(bias + entry),
+        end: VirtualMemoryAddress::new(end),
+    })
+}
+
+// 分配用户栈，按照System V ABI放入argc、argv、envp与auxv
+// 返回栈指针与argv的地址
+pub fn init_stack(
+    vm: &mut Uvm,
+    elf: &ElfImage,
+    argv: &[&str],
+) -> Result<(VirtualMemoryAddress, VirtualMemoryAddress)> {
+    let stack_top = vm.layout().stack_top();
+    vm.alloc_pages(
+        stack_top - USER_STACK_SIZE,
+        USER_STACK_SIZE,
+        PTE_FLAG_U | PTE_FLAG_V | PTE_FLAG_R | PTE_FLAG_W,
+    )?;
+
+    // 字符串放在栈顶
+    let mut sp = stack_top;
+    let mut pointers = Vec::with_capacity(argv.len());
+    for arg in argv.iter().rev() {
+        sp -= arg.len() + 1;
+        vm.copy_out(sp, arg.as_bytes())?;
+        vm.copy_out(sp + arg.len(), &[0])?;
+        pointers.push(sp.as_usize() as u64);
+    }
+    pointers.reverse();
+
+    let mut words = Vec::with_capacity(argv.len() + 8);
+    words.push(argv.len() as u64);
+    words.extend_from_slice(&pointers);
+    // argv与envp都以NULL结尾，没有环境变量
+    words.extend_from_slice(&[0, 0]);
+    words.extend_from_slice(&[
+        AT_PAGESZ,
+        PGSZ as u64,
+        AT_ENTRY,
+        elf.entry.as_usize() as u64,
+    ]);
+    words.extend_from_slice(&[AT_NULL, 0]);
+    let size = words.len() * size_of::<u64>();
+    if stack_top.as_usize() - sp.as_usize() + size + 16 > USER_STACK_SIZE {
+        return Err(Errno::E2BIG.into());
+    }
+    // 栈指针16字节对齐
+    let sp = VirtualMemoryAddress::new((sp.as_usize() - size) & !0xf);
+    let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    vm.copy_out(sp, &bytes)?;
+    Ok((sp, sp + size_of::<u64>()))
+}
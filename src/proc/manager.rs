@@ -1,11 +1,46 @@
-use super::process::Tcb;
+use super::process::TaskRef;
+use crate::riscv::registers::sstatus::IntrGuard;
 use alloc::{collections::VecDeque, sync::Arc};
-use xx_mutex_lock::Mutex;
+use core::ops::{Deref, DerefMut};
+use xx_mutex_lock::{Mutex, MutexGuard};
 
-pub type LockedManager = Mutex<TaskManager>;
+// 中断处理函数会唤醒进程，持有调度队列的锁时必须关中断
+pub struct LockedManager(Mutex<TaskManager>);
+
+pub struct ManagerGuard<'a> {
+    guard: MutexGuard<'a, TaskManager>,
+    _intr: IntrGuard,
+}
+
+impl LockedManager {
+    pub const fn new(manager: TaskManager) -> Self {
+        Self(Mutex::new(manager))
+    }
+
+    pub fn lock(&self) -> ManagerGuard<'_> {
+        let intr = IntrGuard::new();
+        ManagerGuard {
+            guard: self.0.lock(),
+            _intr: intr,
+        }
+    }
+}
+
+impl Deref for ManagerGuard<'_> {
+    type Target = TaskManager;
+    fn deref(&self) -> &TaskManager {
+        &self.guard
+    }
+}
+
+impl DerefMut for ManagerGuard<'_> {
+    fn deref_mut(&mut self) -> &mut TaskManager {
+        &mut self.guard
+    }
+}
 
 pub struct TaskManager {
-    pub tasks: VecDeque<Arc<Tcb>>,
+    pub tasks: VecDeque<TaskRef>,
 }
 
 impl TaskManager {
@@ -15,13 +50,30 @@ impl TaskManager {
         }
     }
 
-    pub fn push(&mut self, task: Arc<Tcb>) {
+    pub fn push(&mut self, task: TaskRef) {
         self.tasks.push_back(task);
     }
 
-    pub fn pop(&mut self) -> Option<Arc<Tcb>> {
-        let task = self.tasks.pop_front();
-        self.tasks.push_back(task.clone().unwrap());
-        task
+    // 轮转调度，跳过睡眠中以及正在其他hart上运行的进程
+    // prev是当前hart上一个运行的进程，选中的进程被标记为Running
+    pub fn pop(&mut self, prev: Option<&TaskRef>) -> Option<TaskRef> {
+        for _ in 0..self.tasks.len() {
+            let task = self.tasks.pop_front()?;
+            self.tasks.push_back(task.clone());
+            let is_prev = prev.is_some_and(|prev| Arc::ptr_eq(prev, &task));
+            if task.dispatch(is_prev) {
+                return Some(task);
+            }
+        }
+        None
+    }
+
+    pub fn find(&self, pid: usize) -> Option<TaskRef> {
+        self.tasks.iter().find(|task| *task.pid() == pid).cloned()
+    }
+
+    pub fn remove(&mut self, pid: usize) -> Option<TaskRef> {
+        let index = self.tasks.iter().position(|task| *task.pid() == pid)?;
+        self.tasks.remove(index)
     }
 }
@@ -1,8 +1,17 @@
+pub mod cred;
+pub mod elf;
 pub mod linkedlist;
 pub mod manager;
 pub mod process;
 
 use self::manager::TaskManager;
+use crate::mm::kmem_cache::KmemCache;
 use manager::LockedManager;
+use process::{Tcb, TrapFrame};
 
 pub static TASKMANAGER: LockedManager = LockedManager::new(TaskManager::init());
+
+// Tcb由Arc管理，cache的对象大小是Arc<Tcb>一次分配的大小
+pub static TCB_CACHE: KmemCache<Tcb> = KmemCache::for_arc("tcb");
+pub static TRAPFRAME_CACHE: KmemCache<TrapFrame> =
+    KmemCache::new("trapframe", Some(TrapFrame::default));
@@ -1,17 +1,34 @@
-use super::TASKMANAGER;
-use crate::mm::page_frame::{alloc_page, PageFrame};
-use crate::mm::pagetable_frame::PageTableFrame;
-use crate::mm::pm::def::{kstack, KERNEL_STACK_SIZE, TRAMPOLINE, TRAPFRAME};
-use crate::mm::vm::uvm::Uvm;
+use super::cred::Cred;
+use super::elf;
+use super::{TASKMANAGER, TCB_CACHE, TRAPFRAME_CACHE};
+use crate::console::file::ConsoleFile;
+use crate::cpu::is_running;
+use crate::error::{Errno, ErrorTrace};
+use crate::fs::{self, fdtable::FdTable, path::Dentry, vfs::new_file};
+use crate::mm::address::VirtualMemoryAddress;
+use crate::mm::kmem_cache::{KBox, KmemCache};
+use crate::mm::pagetable_frame::PageTableErr;
+use crate::mm::pm::def::{kstack, KERNEL_STACK_SIZE, MAX_PROCESS, TRAPFRAME};
+use crate::mm::vm::{
+    layout::{aslr_enabled, UserLayout},
+    uvm::Uvm,
+};
+use crate::riscv::registers::sstatus::IntrGuard;
 use crate::riscv::sv39::pteflags::{PTE_FLAG_R, PTE_FLAG_U, PTE_FLAG_V, PTE_FLAG_W, PTE_FLAG_X};
+use crate::riscv::time;
 use crate::{cpu::Context, mm::def::PGSZ};
 use alloc::string::{String, ToString};
 use alloc::{
     sync::{Arc, Weak},
     vec::Vec,
 };
-use core::{default, ptr};
+use core::{
+    default, ptr,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
 use macros::Getter;
+use xx_mutex_lock::Mutex;
+use xxos_log::warn;
 
 pub static INITCODE: [u8; 52] = [
     0x17, 0x05, 0x00, 0x00, 0x13, 0x05, 0x45, 0x02, 0x97, 0x05, 0x00, 0x00, 0x93, 0x85, 0x35, 0x02,
@@ -20,6 +37,26 @@ pub static INITCODE: [u8; 52] = [
     0x00, 0x00, 0x00, 0x00,
 ];
 
+pub type TcbAllocator = &'static KmemCache<Tcb>;
+// 进程的共享引用，Tcb本身放在TCB_CACHE中
+pub type TaskRef = Arc<Tcb, TcbAllocator>;
+
+pub fn new_task_ref(task: Tcb) -> Result<TaskRef, PageTableErr> {
+    Arc::try_new_in(task, &TCB_CACHE).map_err(|_| PageTableErr::OutOfMemory)
+}
+
+// 下一个尝试分配的pid，0留给init
+static NEXT_PID: AtomicUsize = AtomicUsize::new(1);
+
+// 内核栈按pid划分，所以pid不能超过MAX_PROCESS
+// 依次向后查找，刚退出的进程的pid不会马上被复用
+fn alloc_pid() -> Option<usize> {
+    let manager = TASKMANAGER.lock();
+    (0..MAX_PROCESS)
+        .map(|_| NEXT_PID.fetch_add(1, Ordering::Relaxed) % MAX_PROCESS)
+        .find(|&pid| manager.find(pid).is_none() && !is_running(pid))
+}
+
 #[derive(Clone, Copy, Default, Debug)]
 #[repr(C)]
 pub struct TrapFrame {
@@ -73,107 +110,387 @@ impl default::Default for State {
         Self::Ready
     }
 }
-#[derive(Default, Getter)]
+#[derive(Getter)]
 pub struct Tcb {
     name: String,
-    state: State,
+    state: Mutex<State>,
     pid: usize,
-    killed: bool,
+    killed: AtomicBool,
+    // 睡眠时等待的通道，0表示没有睡眠
+    wait_chan: AtomicUsize,
     exit_code: usize,
-    parent: Option<Weak<Tcb>>,
+    parent: Option<Weak<Tcb, TcbAllocator>>,
     context: Context,
     kstack: usize,
-    pagetable: PageTableFrame,
-    trapframe: Option<&'static mut TrapFrame>,
-    children: Vec<Arc<Tcb>>,
-    frames: Vec<PageFrame>,
-    vm: Uvm,
+    trapframe: Option<KBox<TrapFrame>>,
+    children: Vec<TaskRef>,
+    vm: Mutex<Uvm>,
+    files: Mutex<FdTable>,
+    // 当前工作目录，None表示根目录
+    cwd: Mutex<Option<Arc<Dentry>>>,
+    cred: Mutex<Cred>,
+    // 创建时刻与用户态、内核态的运行时间，以rdtime的计数为单位
+    start_time: usize,
+    utime: AtomicUsize,
+    stime: AtomicUsize,
+    // 最近一次开始计时的时刻，0表示没有在运行
+    last_switch: AtomicUsize,
+    // 地址空间的常驻页数，由Uvm维护
+    resident: Arc<AtomicUsize>,
+    // 下一次exec时是否随机化地址空间布局，fork时继承
+    randomize: AtomicBool,
 }
 
 impl Tcb {
-    // allocate memory to store data
-    /// # Safety
-    /// ask for 4096 size page
-    pub unsafe fn alloc<T: Sized>(&mut self) -> *mut T {
-        if core::mem::size_of::<T>() > PGSZ {
-            panic!("Error the struct size more than a page")
+    pub fn new(name: &str, pid: usize) -> Result<Self, PageTableErr> {
+        let resident = Arc::try_new(AtomicUsize::new(0)).map_err(|_| PageTableErr::OutOfMemory)?;
+        Ok(Self {
+            name: name.to_string(),
+            state: Mutex::new(State::default()),
+            pid,
+            killed: AtomicBool::new(false),
+            wait_chan: AtomicUsize::new(0),
+            exit_code: 0,
+            parent: None,
+            context: Context::default(),
+            kstack: kstack(pid),
+            trapframe: None,
+            children: Vec::new(),
+            vm: Mutex::new(Uvm::new(UserLayout::default(), resident.clone())?),
+            files: Mutex::new(FdTable::new()),
+            cwd: Mutex::new(None),
+            cred: Mutex::new(Cred::root()),
+            start_time: time::read_time(),
+            utime: AtomicUsize::new(0),
+            stime: AtomicUsize::new(0),
+            last_switch: AtomicUsize::new(0),
+            resident,
+            randomize: AtomicBool::new(aslr_enabled()),
+        })
+    }
+
+    pub fn set_state(&self, state: State) {
+        // 中断处理函数可能正在唤醒这个进程
+        let _guard = IntrGuard::new();
+        *self.state.lock() = state;
+    }
+
+    // /proc/<pid>/status中显示的状态
+    pub fn state_name(&self) -> &'static str {
+        let _guard = IntrGuard::new();
+        match *self.state.lock() {
+            State::Running => "R (running)",
+            State::Ready => "R (runnable)",
+            State::Sleep => "S (sleeping)",
+            State::Zombie => "Z (zombie)",
+        }
+    }
+
+    // 没有父进程时返回0
+    pub fn ppid(&self) -> usize {
+        self.parent
+            .as_ref()
+            .and_then(Weak::upgrade)
+            .map_or(0, |parent| parent.pid)
+    }
+
+    // 调度器选中进程时调用，成功时进程标记为Running
+    // Running的进程正在某个hart上，只有这个hart(is_prev)可以继续运行它
+    pub fn dispatch(&self, is_prev: bool) -> bool {
+        let mut state = self.state.lock();
+        match *state {
+            // 被杀死的进程不再运行，由杀死它的一方回收
+            State::Ready if !self.is_killed() => {
+                *state = State::Running;
+                true
+            }
+            State::Running => {
+                is_prev && !self.is_killed() && self.wait_chan.load(Ordering::SeqCst) == 0
+            }
+            _ => false,
         }
+    }
 
-        let frame = alloc_page();
-        let ret = frame.to_usize();
+    // 正在某个hart上运行
+    pub fn is_running(&self) -> bool {
+        let _guard = IntrGuard::new();
+        matches!(*self.state.lock(), State::Running)
+    }
 
-        self.frames.push(frame);
-        ret as *mut T
+    pub fn is_zombie(&self) -> bool {
+        let _guard = IntrGuard::new();
+        matches!(*self.state.lock(), State::Zombie)
     }
 
-    pub fn get_mut_trapframe(&self) -> Option<&mut TrapFrame> {
-        if let Some(ref ptr) = self.trapframe {
-            let ptr = (*ptr) as *const _ as usize;
-            unsafe { Some((ptr as *mut TrapFrame).as_mut().unwrap()) }
-        } else {
-            None
+    // hart切换到别的进程时调用，此后其他hart才可以调度这个进程
+    // 等待中的进程这时才进入Sleep
+    pub fn deschedule(&self) {
+        let _guard = IntrGuard::new();
+        let mut state = self.state.lock();
+        if matches!(*state, State::Running) {
+            *state = if self.wait_chan.load(Ordering::SeqCst) != 0 {
+                State::Sleep
+            } else {
+                State::Ready
+            };
         }
     }
+
+    // 进程仍在运行，记录等待的通道，离开hart时由deschedule进入Sleep
+    pub fn sleep_on(&self, chan: usize) {
+        let _guard = IntrGuard::new();
+        let _state = self.state.lock();
+        self.wait_chan.store(chan, Ordering::SeqCst);
+    }
+
+    // 唤醒在chan上等待的进程，chan为0时无条件唤醒
+    // 还没有离开hart的进程只清除等待的通道，之后由deschedule标记为Ready
+    pub fn wakeup(&self, chan: usize) -> bool {
+        let mut state = self.state.lock();
+        let waiting = self.wait_chan.load(Ordering::SeqCst);
+        if waiting == 0 || (chan != 0 && waiting != chan) {
+            return false;
+        }
+        match *state {
+            State::Sleep => *state = State::Ready,
+            State::Running => {}
+            _ => return false,
+        }
+        self.wait_chan.store(0, Ordering::SeqCst);
+        true
+    }
+
+    // 从用户态陷入内核，结算用户态时间
+    pub fn enter_kernel(&self) {
+        let now = time::read_time();
+        let last = self.last_switch.swap(now, Ordering::Relaxed);
+        if last != 0 {
+            self.utime.fetch_add(now - last, Ordering::Relaxed);
+        }
+    }
+
+    // 陷入处理完毕，等待重新调度的时间不计入
+    pub fn leave_kernel(&self) {
+        let last = self.last_switch.swap(0, Ordering::Relaxed);
+        if last != 0 {
+            self.stime
+                .fetch_add(time::read_time() - last, Ordering::Relaxed);
+        }
+    }
+
+    // 即将返回用户态
+    pub fn enter_user(&self) {
+        self.last_switch.store(time::read_time(), Ordering::Relaxed);
+    }
+
+    // (用户态时间, 内核态时间)
+    pub fn cpu_time(&self) -> (usize, usize) {
+        (
+            self.utime.load(Ordering::Relaxed),
+            self.stime.load(Ordering::Relaxed),
+        )
+    }
+
+    // 不需要锁住地址空间，OOM时读取其他进程使用
+    pub fn resident_pages(&self) -> usize {
+        self.resident.load(Ordering::Relaxed)
+    }
+
+    pub fn kill(&self) {
+        self.killed.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_killed(&self) -> bool {
+        self.killed.load(Ordering::SeqCst)
+    }
+
+    // 进程退出：从调度队列中移除，并立即归还它占用的用户内存
+    // 调用者需要保证进程没有在任何hart上运行
+    pub fn exit(&self) {
+        self.set_state(State::Zombie);
+        TASKMANAGER.lock().remove(self.pid);
+        // 映射区间持有inode，在地址空间的锁之外释放
+        let vmas = self.vm.lock().release();
+        drop(vmas);
+        let files = self.files.lock().close_all();
+        drop(files);
+        *self.cwd.lock() = None;
+    }
+
+    // fork时子进程继承父进程的文件描述符与工作目录
+    pub fn inherit_files(&self, parent: &Tcb) {
+        let files = parent.files.lock().fork();
+        let old = core::mem::replace(&mut *self.files.lock(), files);
+        drop(old);
+        *self.cwd.lock() = parent.cwd.lock().clone();
+    }
+
+    // fork时子进程继承父进程的身份
+    pub fn inherit_cred(&self, parent: &Tcb) {
+        *self.cred.lock() = parent.cred.lock().clone();
+    }
+
+    // exec成功装载新程序之后调用
+    pub fn close_on_exec(&self) {
+        let files = self.files.lock().close_on_exec();
+        drop(files);
+    }
+
+    // 用path中的程序替换进程的地址空间，返回argc
+    // 装载失败时原来的地址空间保持不变
+    pub fn exec(&self, path: &str, argv: &[&str]) -> Result<usize, ErrorTrace> {
+        let image = fs::read_exec(path)?;
+        let trapframe = self.trapframe.as_ref().expect("get trapframe err");
+        // 每次exec重新选择各区域的基址
+        let layout = UserLayout::new(self.aslr());
+        let mut vm = Uvm::new(layout, self.resident.clone())?;
+        let elf = elf::load(&mut vm, &image)?;
+        let (sp, argv_va) = elf::init_stack(&mut vm, &elf, argv)?;
+        vm.init_heap(elf.end);
+        vm.map_trap(trapframe.as_ptr() as usize)?;
+
+        // 原来的地址空间在锁之外释放
+        let old = core::mem::replace(&mut *self.vm.lock(), vm);
+        drop(old);
+        self.close_on_exec();
+
+        let trapframe = self.get_mut_trapframe().expect("get trapframe err");
+        *trapframe = TrapFrame::default();
+        trapframe.epc = elf.entry.as_usize();
+        trapframe.sp = sp.as_usize();
+        trapframe.a1 = argv_va.as_usize();
+        Ok(argv.len())
+    }
+
+    // 单独为某个进程打开或关闭ASLR，从下一次exec开始生效
+    pub fn set_aslr(&self, enable: bool) {
+        self.randomize.store(enable, Ordering::Relaxed);
+    }
+
+    pub fn aslr(&self) -> bool {
+        self.randomize.load(Ordering::Relaxed)
+    }
+
+    pub fn get_mut_trapframe(&self) -> Option<&mut TrapFrame> {
+        self.trapframe
+            .as_ref()
+            .map(|trapframe| unsafe { trapframe.as_ptr().as_mut().unwrap() })
+    }
+
+    // trapframe在用户地址空间中的虚拟地址
+    pub fn trapframe_va(&self) -> usize {
+        let trapframe = self.trapframe.as_ref().expect("get trapframe err");
+        TRAPFRAME + (trapframe.as_ptr() as usize & (PGSZ - 1))
+    }
+}
+
+// 复制当前进程，子进程从fork返回0
+// 子进程共享父进程打开的文件，继承工作目录与身份
+pub fn fork(parent: &TaskRef) -> Result<TaskRef, ErrorTrace> {
+    let pid = alloc_pid().ok_or(Errno::EAGAIN)?;
+    let mut child = Tcb::new(&parent.name, pid)?;
+    let mut trapframe = TRAPFRAME_CACHE.construct()?;
+    *trapframe = *parent.get_mut_trapframe().expect("get trapframe err");
+    trapframe.a0 = 0;
+
+    let mut vm = parent.vm.lock().fork(child.resident.clone())?;
+    vm.map_trap(trapframe.as_ptr() as usize)?;
+    child.vm = Mutex::new(vm);
+
+    child.parent = Some(Arc::downgrade(parent));
+    child.context.sp = kstack(pid) + KERNEL_STACK_SIZE;
+    child.trapframe = Some(trapframe);
+    child.inherit_files(parent);
+    child.inherit_cred(parent);
+    child.set_aslr(parent.aslr());
+    child.set_state(State::Ready);
+
+    let child = new_task_ref(child)?;
+    TASKMANAGER.lock().push(child.clone());
+    Ok(child)
+}
+
+// 第一个用户进程执行的程序
+pub const INIT_PATH: &str = "/init";
+
+// 装载/init，栈上只有argv[0]
+fn load_init(vm: &mut Uvm, trapframe: &mut TrapFrame) -> Result<(), ErrorTrace> {
+    let image = fs::read_exec(INIT_PATH)?;
+    let elf = elf::load(vm, &image)?;
+    let (sp, argv) = elf::init_stack(vm, &elf, &[INIT_PATH])?;
+    vm.init_heap(elf.end);
+    trapframe.epc = elf.entry.as_usize();
+    trapframe.sp = sp.as_usize();
+    trapframe.a0 = 1;
+    trapframe.a1 = argv.as_usize();
+    Ok(())
 }
 
-// 创建一个初始进程
-pub fn zero_task() -> Tcb {
-    fn init_zero_task_pagetable(trapframe: usize) -> PageTableFrame {
-        extern "C" {
-            fn strampsec();
+fn load_initcode(vm: &mut Uvm, trapframe: &mut TrapFrame) -> Result<(), PageTableErr> {
+    // INITCODE不是位置无关代码(argv使用了绝对地址)，只能装载在0地址
+    let entry = VirtualMemoryAddress::new(0);
+    vm.alloc_pages(
+        entry,
+        PGSZ,
+        PTE_FLAG_U | PTE_FLAG_V | PTE_FLAG_X | PTE_FLAG_R | PTE_FLAG_W,
+    )?;
+    // map user stack
+    let stack_top = vm.layout().stack_top();
+    vm.alloc_pages(
+        stack_top - PGSZ,
+        PGSZ,
+        PTE_FLAG_U | PTE_FLAG_V | PTE_FLAG_R | PTE_FLAG_W,
+    )?;
+    vm.init_heap(entry + PGSZ);
+
+    let pa = vm.translate(entry).ok_or(PageTableErr::NeverMap)?;
+    unsafe { ptr::copy_nonoverlapping(INITCODE.as_ptr(), pa.get_mut(), INITCODE.len()) };
+    trapframe.epc = entry.as_usize();
+    trapframe.sp = stack_top.as_usize();
+    Ok(())
+}
+
+// 创建一个初始进程，执行根文件系统中的/init，没有时运行INITCODE
+pub fn zero_task() -> Result<Tcb, ErrorTrace> {
+    let mut task = Tcb::new("init", 0)?;
+    let mut trapframe = TRAPFRAME_CACHE.construct()?;
+
+    {
+        let mut vm = task.vm.lock();
+        if let Err(err) = load_init(&mut vm, &mut trapframe) {
+            warn!(
+                "failed to load {}: {}, run initcode instead",
+                INIT_PATH, err
+            );
+            vm.release();
+            load_initcode(&mut vm, &mut trapframe)?;
+            task.name = String::from("initcode");
         }
+        // map trapvec code and trapframe
+        vm.map_trap(trapframe.as_ptr() as usize)?;
+    }
 
-        let mut pagetable = PageTableFrame::new();
-        let page = alloc_page();
-        let pa = page.to_pma();
-
-        pagetable.save_page(page);
-        // map pagetable frame
-        pagetable.mappages(
-            0.into(),
-            pa,
-            PGSZ,
-            PTE_FLAG_U | PTE_FLAG_V | PTE_FLAG_X | PTE_FLAG_R | PTE_FLAG_W,
-        );
-        // map trapvec code
-        pagetable.mappages(
-            TRAMPOLINE.into(),
-            (strampsec as usize).into(),
-            PGSZ,
-            PTE_FLAG_V | PTE_FLAG_X | PTE_FLAG_R,
-        );
-        // map trapframe
-        pagetable.mappages(
-            TRAPFRAME.into(),
-            trapframe.into(),
-            PGSZ,
-            PTE_FLAG_V | PTE_FLAG_X | PTE_FLAG_R | PTE_FLAG_W,
-        );
-        unsafe { ptr::copy_nonoverlapping(INITCODE.as_ptr(), pa.get_mut(), INITCODE.len()) }
-        pagetable
-    }
-
-    let mut task = Tcb::default();
-    let trapframe = unsafe {
-        let trapframe = task.alloc::<TrapFrame>();
-        (*trapframe).epc = 0;
-        (*trapframe).sp = PGSZ;
-        trapframe
-    };
-
-    task.name = "initcode".to_string();
-    task.pid = 0;
     task.context.sp = kstack(0) + KERNEL_STACK_SIZE;
     task.context.ra = 0; //TODO: add userret
-    task.trapframe = unsafe { trapframe.as_mut() };
-    task.kstack = kstack(0);
-    task.state = State::Ready;
-    task.killed = false;
-    task.pagetable = init_zero_task_pagetable(trapframe as usize);
-    task
+    task.trapframe = Some(trapframe);
+
+    // stdin, stdout and stderr
+    {
+        let mut files = task.files.lock();
+        let console = new_file(ConsoleFile).map_err(|_| PageTableErr::OutOfMemory)?;
+        for _ in 0..3 {
+            files
+                .alloc(console.clone(), false)
+                .map_err(|_| PageTableErr::OutOfMemory)?;
+        }
+    }
+    task.set_state(State::Ready);
+    Ok(task)
 }
 
 pub fn test_initcode() {
-    let task = zero_task();
-    TASKMANAGER.lock().push(Arc::new(task));
+    match zero_task().and_then(|task| Ok(new_task_ref(task)?)) {
+        Ok(task) => TASKMANAGER.lock().push(task),
+        Err(err) => panic!("failed to create the init process: {}", err),
+    }
 }
@@ -0,0 +1,8 @@
+use super::Entropy;
+use core::sync::atomic::AtomicUsize;
+use xx_mutex_lock::Mutex;
+
+pub static ENTROPY: Mutex<Entropy> = Mutex::new(Entropy::new());
+
+// 中断处理中只做无锁的异或，下一次取随机数时再混入熵池
+pub static JITTER: AtomicUsize = AtomicUsize::new(0);
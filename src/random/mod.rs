@@ -0,0 +1,68 @@
+pub mod def;
+
+use crate::riscv::{registers::r_tp, time};
+use core::sync::atomic::Ordering;
+use def::{ENTROPY, JITTER};
+
+// Kernel entropy source
+// 没有硬件随机数发生器，只能依赖启动时刻与中断到达时刻的时钟抖动，
+// 输出经过splitmix64/xorshift64*混合，不能用于密码学用途
+pub struct Entropy {
+    state: u64,
+}
+
+impl Default for Entropy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Entropy {
+    pub const fn new() -> Self {
+        Self {
+            state: 0x9e37_79b9_7f4a_7c15,
+        }
+    }
+
+    pub fn add_entropy(&mut self, value: usize) {
+        self.state = splitmix64(self.state ^ value as u64);
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        // xorshift64*
+        let mut x = self.state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state = x;
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+pub fn random_init() {
+    let mut entropy = ENTROPY.lock();
+    entropy.add_entropy(time::read_time());
+    entropy.add_entropy(time::read_cycle());
+    entropy.add_entropy(r_tp());
+}
+
+// called from interrupt handlers, must not take any lock
+#[inline]
+pub fn add_interrupt_entropy() {
+    let cycle = time::read_cycle();
+    let old = JITTER.load(Ordering::Relaxed);
+    JITTER.store(old.rotate_left(7) ^ cycle, Ordering::Relaxed);
+}
+
+pub fn random_usize() -> usize {
+    let mut entropy = ENTROPY.lock();
+    entropy.add_entropy(JITTER.swap(0, Ordering::Relaxed) ^ time::read_cycle());
+    entropy.next_u64() as usize
+}
@@ -1 +1,7 @@
+use core::arch::asm;
 
+// wait for interrupt
+#[inline]
+pub fn wfi() {
+    unsafe { asm!("wfi") }
+}
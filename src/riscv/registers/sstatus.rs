@@ -108,3 +108,29 @@ pub fn intr_off() {
 pub fn intr_on() {
     Sstatus::_set(SIE);
 }
+
+// 持有期间关闭中断，drop时恢复原先的状态
+// 与中断处理函数共享的锁必须在关中断时获取，否则同一个hart上会死锁
+pub struct IntrGuard(bool);
+
+impl Default for IntrGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IntrGuard {
+    pub fn new() -> Self {
+        let enabled = Sstatus::read().sie();
+        intr_off();
+        Self(enabled)
+    }
+}
+
+impl Drop for IntrGuard {
+    fn drop(&mut self) {
+        if self.0 {
+            intr_on();
+        }
+    }
+}
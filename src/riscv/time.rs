@@ -1,5 +1,9 @@
+use crate::driver::fdt::fdt;
 use core::arch::asm;
 
+// QEMU virt的时钟频率，设备树中没有timebase-frequency时使用
+const DEFAULT_TIMEBASE_FREQ: usize = 10_000_000;
+
 pub fn read_time() -> usize {
     let mut bits: usize;
     unsafe { asm!("rdtime {}",out(reg) bits) };
@@ -11,3 +15,14 @@ pub fn read_cycle() -> usize {
     unsafe { asm!("rdcycle {}",out(reg) bits) };
     bits
 }
+
+// rdtime每秒增加的次数
+pub fn timebase_frequency() -> usize {
+    fdt()
+        .and_then(|fdt| fdt.nodes().find(|node| node.name() == "cpus"))
+        .and_then(|cpus| cpus.property("timebase-frequency"))
+        .filter(|value| value.len() >= 4)
+        .map_or(DEFAULT_TIMEBASE_FREQ, |value| {
+            u32::from_be_bytes(value[..4].try_into().unwrap()) as usize
+        })
+}
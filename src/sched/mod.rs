@@ -1 +1,39 @@
+pub mod sleeplock;
 
+use crate::{
+    error::{Errno, ErrorTrace},
+    proc::{process::Tcb, TASKMANAGER},
+    riscv::{
+        cpu::wfi,
+        registers::sstatus::{intr_off, intr_on},
+    },
+};
+
+// 睡眠与唤醒
+// 内核没有独立的内核线程上下文，进程不能在系统调用中途让出CPU
+// 需要阻塞时把进程标记为睡眠并返回ERESTARTSYS，系统调用入口回退epc，
+// 进程被唤醒后重新执行ecall，再次检查等待的条件
+
+// 以对象的地址作为等待通道
+pub fn chan_of<T>(object: &T) -> usize {
+    object as *const T as usize
+}
+
+// 必须在持有等待条件所在的锁时调用，否则会丢失唤醒
+pub fn sleep(task: &Tcb, chan: usize) -> ErrorTrace {
+    task.sleep_on(chan);
+    ErrorTrace::from_errno(Errno::ERESTARTSYS)
+}
+
+pub fn wakeup(chan: usize) {
+    TASKMANAGER.lock().tasks.iter().for_each(|task| {
+        task.wakeup(chan);
+    });
+}
+
+// 没有可运行的进程时等待中断
+pub fn idle() {
+    intr_on();
+    wfi();
+    intr_off();
+}
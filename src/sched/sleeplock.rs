@@ -0,0 +1,120 @@
+use crate::{
+    cpu::current_task,
+    riscv::registers::sstatus::{intr_off, intr_on, Sstatus},
+};
+use core::{
+    cell::UnsafeCell,
+    hint::spin_loop,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+// 没有进程持有锁
+const NO_HOLDER: usize = usize::MAX;
+
+// Long-term lock for data that is held across device I/O
+// 内核没有独立的内核线程上下文，等待者不能让出CPU，只能在打开中断的情况下忙等，
+// 这样持有者等待的设备中断仍然可以送达
+pub struct SleepLock<T> {
+    locked: AtomicBool,
+    // pid of the holder, for debugging
+    holder: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for SleepLock<T> {}
+unsafe impl<T: Send> Sync for SleepLock<T> {}
+
+impl<T> SleepLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            holder: AtomicUsize::new(NO_HOLDER),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn try_acquire(&self) -> bool {
+        if self
+            .locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return false;
+        }
+        let pid = current_task().map_or(NO_HOLDER, |task| *task.pid());
+        self.holder.store(pid, Ordering::Relaxed);
+        true
+    }
+
+    pub fn acquire(&self) {
+        if self.try_acquire() {
+            return;
+        }
+        let enabled = Sstatus::read().sie();
+        intr_on();
+        while !self.try_acquire() {
+            spin_loop();
+        }
+        if !enabled {
+            intr_off();
+        }
+    }
+
+    pub fn release(&self) {
+        self.holder.store(NO_HOLDER, Ordering::Relaxed);
+        self.locked.store(false, Ordering::Release);
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    pub fn holder(&self) -> Option<usize> {
+        match self.holder.load(Ordering::Relaxed) {
+            NO_HOLDER => None,
+            pid => Some(pid),
+        }
+    }
+
+    pub fn lock(&self) -> SleepLockGuard<'_, T> {
+        self.acquire();
+        SleepLockGuard { lock: self }
+    }
+
+    /// # Safety
+    /// The caller must hold the lock.
+    pub unsafe fn get(&self) -> &T {
+        &*self.data.get()
+    }
+
+    /// # Safety
+    /// The caller must hold the lock.
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn get_mut(&self) -> &mut T {
+        &mut *self.data.get()
+    }
+}
+
+pub struct SleepLockGuard<'a, T> {
+    lock: &'a SleepLock<T>,
+}
+
+impl<T> Deref for SleepLockGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for SleepLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for SleepLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.release();
+    }
+}
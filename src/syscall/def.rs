@@ -0,0 +1,61 @@
+// 系统调用号与xv6保持一致，INITCODE依赖这套编号
+pub const SYS_FORK: usize = 1;
+pub const SYS_EXEC: usize = 7;
+pub const SYS_GETPID: usize = 11;
+pub const SYS_SBRK: usize = 12;
+pub const SYS_PIPE: usize = 4;
+pub const SYS_READ: usize = 5;
+pub const SYS_FSTAT: usize = 8;
+pub const SYS_CHDIR: usize = 9;
+pub const SYS_DUP: usize = 10;
+pub const SYS_OPEN: usize = 15;
+pub const SYS_WRITE: usize = 16;
+pub const SYS_UNLINK: usize = 18;
+pub const SYS_LINK: usize = 19;
+pub const SYS_MKDIR: usize = 20;
+pub const SYS_CLOSE: usize = 21;
+// 以下为xv6之外新增的系统调用
+pub const SYS_IOCTL: usize = 22;
+pub const SYS_LSEEK: usize = 23;
+pub const SYS_DUP2: usize = 24;
+pub const SYS_MOUNT: usize = 25;
+pub const SYS_UMOUNT: usize = 26;
+pub const SYS_GETCWD: usize = 27;
+pub const SYS_RMDIR: usize = 28;
+pub const SYS_RENAME: usize = 29;
+pub const SYS_GETDENTS: usize = 30;
+pub const SYS_SYMLINK: usize = 31;
+pub const SYS_READLINK: usize = 32;
+pub const SYS_SETUID: usize = 33;
+pub const SYS_GETUID: usize = 34;
+pub const SYS_CHMOD: usize = 35;
+pub const SYS_CHOWN: usize = 36;
+pub const SYS_MMAP: usize = 37;
+pub const SYS_MUNMAP: usize = 38;
+pub const SYS_MSYNC: usize = 39;
+pub const SYS_SETGID: usize = 40;
+pub const SYS_GETGID: usize = 41;
+pub const SYS_SETGROUPS: usize = 42;
+pub const SYS_PERSONALITY: usize = 43;
+
+// ioctl requests
+pub const TCGETS: usize = 0x5401;
+pub const TCSETS: usize = 0x5402;
+
+// mmap prot与flags，取值与Linux相同
+pub const PROT_READ: usize = 0x1;
+pub const PROT_WRITE: usize = 0x2;
+pub const PROT_EXEC: usize = 0x4;
+pub const MAP_SHARED: usize = 0x01;
+pub const MAP_PRIVATE: usize = 0x02;
+pub const MAP_FIXED: usize = 0x10;
+pub const MAP_ANONYMOUS: usize = 0x20;
+
+// personality，取值与Linux相同
+pub const PER_QUERY: usize = 0xffff_ffff;
+pub const ADDR_NO_RANDOMIZE: usize = 0x0040000;
+
+// msync flags
+pub const MS_ASYNC: usize = 1;
+pub const MS_INVALIDATE: usize = 2;
+pub const MS_SYNC: usize = 4;
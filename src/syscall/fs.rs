@@ -0,0 +1,311 @@
+use crate::{
+    block::find_block_device,
+    error::{Errno, ErrorTrace, Result},
+    fs::{
+        self,
+        def::{SeekFrom, Stat, O_CLOEXEC, PATH_MAX},
+        path::lookup,
+        perm::{permission, MAY_EXEC},
+        pipe::make_pipe,
+    },
+    mm::{
+        address::VirtualMemoryAddress,
+        vm::{
+            fault::{copy_from_user, copy_str_from_user, copy_to_user, fault_in},
+            vma::Access,
+        },
+    },
+    proc::process::Tcb,
+};
+use alloc::{string::String, vec, vec::Vec};
+use core::{mem::size_of, slice};
+
+// 一次读写最多在内核中缓冲的字节数，超出的部分作为短读写返回
+const MAX_IO: usize = 64 * 1024;
+
+pub const SEEK_SET: usize = 0;
+pub const SEEK_CUR: usize = 1;
+pub const SEEK_END: usize = 2;
+
+// struct linux_dirent64中d_name之前的部分：d_ino, d_off, d_reclen, d_type
+const DIRENT64_HEADER: usize = 19;
+
+// 从用户空间读入以NUL结尾的路径
+pub fn user_path(task: &Tcb, va: usize) -> Result<String> {
+    let bytes = copy_str_from_user(task, VirtualMemoryAddress::new(va), PATH_MAX - 1)?
+        .ok_or_else(|| ErrorTrace::from_errno(Errno::ENAMETOOLONG))?;
+    String::from_utf8(bytes).map_err(|_| Errno::EINVAL.into())
+}
+
+pub fn sys_open(task: &Tcb, path: usize, flags: usize, mode: usize) -> Result<usize> {
+    let path = user_path(task, path)?;
+    let flags = flags as u32;
+    let file = fs::open(&path, flags, mode as u32)?;
+    task.files().lock().alloc(file, flags & O_CLOEXEC != 0)
+}
+
+pub fn sys_close(task: &Tcb, fd: usize) -> Result<usize> {
+    // 最后一个引用释放时可能写回数据，不能持有文件表的锁
+    let file = task.files().lock().close(fd)?;
+    drop(file);
+    Ok(0)
+}
+
+pub fn sys_read(task: &Tcb, fd: usize, buf: usize, len: usize) -> Result<usize> {
+    let file = task.files().lock().get(fd)?;
+    if !file.readable() {
+        return Err(Errno::EBADF.into());
+    }
+    let mut data = vec![0; len.min(MAX_IO)];
+    // 终端与管道的数据读出后就被消耗了，读取之前先确认缓冲区可写
+    let buf = VirtualMemoryAddress::new(buf);
+    fault_in(task, buf, data.len(), Access::Write)?;
+    let len = file.read(&mut data)?;
+    copy_to_user(task, buf, &data[..len])?;
+    Ok(len)
+}
+
+pub fn sys_write(task: &Tcb, fd: usize, buf: usize, len: usize) -> Result<usize> {
+    let file = task.files().lock().get(fd)?;
+    if !file.writable() {
+        return Err(Errno::EBADF.into());
+    }
+    let mut data = vec![0; len.min(MAX_IO)];
+    copy_from_user(task, &mut data, VirtualMemoryAddress::new(buf))?;
+    file.write(&data)
+}
+
+pub fn sys_lseek(task: &Tcb, fd: usize, offset: isize, whence: usize) -> Result<usize> {
+    let file = task.files().lock().get(fd)?;
+    let pos = match whence {
+        SEEK_SET if offset >= 0 => SeekFrom::Start(offset as u64),
+        SEEK_CUR => SeekFrom::Current(offset as i64),
+        SEEK_END => SeekFrom::End(offset as i64),
+        _ => return Err(Errno::EINVAL.into()),
+    };
+    Ok(file.seek(pos)? as usize)
+}
+
+pub fn sys_dup(task: &Tcb, fd: usize) -> Result<usize> {
+    task.files().lock().dup(fd)
+}
+
+pub fn sys_dup2(task: &Tcb, old: usize, new: usize) -> Result<usize> {
+    let (fd, replaced) = task.files().lock().dup2(old, new)?;
+    drop(replaced);
+    Ok(fd)
+}
+
+pub fn sys_fstat(task: &Tcb, fd: usize, statbuf: usize) -> Result<usize> {
+    let file = task.files().lock().get(fd)?;
+    let stat = file.stat()?;
+    let bytes =
+        unsafe { slice::from_raw_parts(&stat as *const Stat as *const u8, size_of::<Stat>()) };
+    copy_to_user(task, VirtualMemoryAddress::new(statbuf), bytes)?;
+    Ok(0)
+}
+
+// fds指向两个int，依次写入读端与写端
+pub fn sys_pipe(task: &Tcb, fds: usize) -> Result<usize> {
+    let (reader, writer) = make_pipe()?;
+    let (rfd, wfd) = {
+        let mut files = task.files().lock();
+        let rfd = files.alloc(reader, false)?;
+        match files.alloc(writer, false) {
+            Ok(wfd) => (rfd, wfd),
+            Err(err) => {
+                let reader = files.close(rfd);
+                drop(files);
+                drop(reader);
+                return Err(err);
+            }
+        }
+    };
+    let mut bytes = [0; 8];
+    bytes[..4].copy_from_slice(&(rfd as i32).to_ne_bytes());
+    bytes[4..].copy_from_slice(&(wfd as i32).to_ne_bytes());
+    // 写入用户空间时可能缺页，不能持有文件表的锁
+    if let Err(err) = copy_to_user(task, VirtualMemoryAddress::new(fds), &bytes) {
+        let closed = {
+            let mut files = task.files().lock();
+            [files.close(rfd), files.close(wfd)]
+        };
+        drop(closed);
+        return Err(err);
+    }
+    Ok(0)
+}
+
+// 只有root可以挂载与卸载文件系统
+fn require_root(task: &Tcb) -> Result<()> {
+    if !task.cred().lock().is_root() {
+        return Err(Errno::EPERM.into());
+    }
+    Ok(())
+}
+
+// source是块设备名(可以带/dev/前缀)，不需要设备的文件系统可以为NULL，flags被忽略
+pub fn sys_mount(
+    task: &Tcb,
+    source: usize,
+    target: usize,
+    fstype: usize,
+    data: usize,
+) -> Result<usize> {
+    require_root(task)?;
+    let target = user_path(task, target)?;
+    let fstype = user_path(task, fstype)?;
+    let dev = match source {
+        0 => None,
+        source => {
+            let source = user_path(task, source)?;
+            let name = source.strip_prefix("/dev/").unwrap_or(&source);
+            let (dev, _) = find_block_device(name).ok_or(Errno::ENODEV)?;
+            Some(dev)
+        }
+    };
+    let options = match data {
+        0 => String::new(),
+        data => user_path(task, data)?,
+    };
+    fs::mount::mount(&fstype, dev, &target, &options)?;
+    Ok(0)
+}
+
+pub fn sys_umount(task: &Tcb, target: usize) -> Result<usize> {
+    require_root(task)?;
+    let target = user_path(task, target)?;
+    fs::mount::umount(&target)?;
+    Ok(0)
+}
+
+pub fn sys_ioctl(task: &Tcb, fd: usize, request: usize, arg: usize) -> Result<usize> {
+    let file = task.files().lock().get(fd)?;
+    file.ioctl(request, arg)
+}
+
+pub fn sys_chdir(task: &Tcb, path: usize) -> Result<usize> {
+    let path = user_path(task, path)?;
+    let dentry = lookup(&path)?;
+    if !dentry.inode().is_dir() {
+        return Err(Errno::ENOTDIR.into());
+    }
+    let cred = task.cred().lock().clone();
+    permission(dentry.inode(), &cred, MAY_EXEC)?;
+    *task.cwd().lock() = Some(dentry);
+    Ok(0)
+}
+
+// 返回包括结尾NUL在内的长度，缓冲区不够时返回ERANGE
+pub fn sys_getcwd(task: &Tcb, buf: usize, size: usize) -> Result<usize> {
+    let path = task
+        .cwd()
+        .lock()
+        .as_ref()
+        .map_or_else(|| String::from("/"), |dentry| dentry.path());
+    let mut bytes = path.into_bytes();
+    bytes.push(0);
+    if bytes.len() > size {
+        return Err(Errno::ERANGE.into());
+    }
+    copy_to_user(task, VirtualMemoryAddress::new(buf), &bytes)?;
+    Ok(bytes.len())
+}
+
+pub fn sys_mkdir(task: &Tcb, path: usize, mode: usize) -> Result<usize> {
+    let path = user_path(task, path)?;
+    fs::mkdir(&path, mode as u32)?;
+    Ok(0)
+}
+
+pub fn sys_rmdir(task: &Tcb, path: usize) -> Result<usize> {
+    let path = user_path(task, path)?;
+    fs::rmdir(&path)?;
+    Ok(0)
+}
+
+pub fn sys_unlink(task: &Tcb, path: usize) -> Result<usize> {
+    let path = user_path(task, path)?;
+    fs::unlink(&path)?;
+    Ok(0)
+}
+
+pub fn sys_link(task: &Tcb, old: usize, new: usize) -> Result<usize> {
+    let old = user_path(task, old)?;
+    let new = user_path(task, new)?;
+    fs::link(&old, &new)?;
+    Ok(0)
+}
+
+pub fn sys_rename(task: &Tcb, old: usize, new: usize) -> Result<usize> {
+    let old = user_path(task, old)?;
+    let new = user_path(task, new)?;
+    fs::rename(&old, &new)?;
+    Ok(0)
+}
+
+pub fn sys_symlink(task: &Tcb, target: usize, path: usize) -> Result<usize> {
+    let target = user_path(task, target)?;
+    let path = user_path(task, path)?;
+    fs::symlink(&target, &path)?;
+    Ok(0)
+}
+
+// 与Linux相同，结果不以NUL结尾，超出缓冲区的部分被截断
+pub fn sys_readlink(task: &Tcb, path: usize, buf: usize, size: usize) -> Result<usize> {
+    let path = user_path(task, path)?;
+    let target = fs::readlink(&path)?;
+    let len = target.len().min(size);
+    copy_to_user(
+        task,
+        VirtualMemoryAddress::new(buf),
+        &target.as_bytes()[..len],
+    )?;
+    Ok(len)
+}
+
+// 按struct linux_dirent64的格式填入尽量多的目录项，返回写入的字节数，0表示已经读完
+pub fn sys_getdents(task: &Tcb, fd: usize, buf: usize, len: usize) -> Result<usize> {
+    let file = task.files().lock().get(fd)?;
+    if !file.inode().is_some_and(|inode| inode.is_dir()) {
+        return Err(Errno::ENOTDIR.into());
+    }
+    let len = len.min(MAX_IO);
+    let mut data = Vec::new();
+    while let Some(entry) = file.readdir()? {
+        let reclen = (DIRENT64_HEADER + entry.name.len() + 1).next_multiple_of(8);
+        if data.len() + reclen > len {
+            // 放不下的目录项留到下一次读取
+            file.seek(SeekFrom::Current(-1))?;
+            if data.is_empty() {
+                return Err(Errno::EINVAL.into());
+            }
+            break;
+        }
+        // d_off是下一个目录项的位置
+        let next = file.seek(SeekFrom::Current(0))?;
+        let start = data.len();
+        data.extend_from_slice(&entry.ino.to_ne_bytes());
+        data.extend_from_slice(&(next as i64).to_ne_bytes());
+        data.extend_from_slice(&(reclen as u16).to_ne_bytes());
+        data.push(entry.file_type.to_dirent_type());
+        data.extend_from_slice(entry.name.as_bytes());
+        data.resize(start + reclen, 0);
+    }
+    copy_to_user(task, VirtualMemoryAddress::new(buf), &data)?;
+    Ok(data.len())
+}
+
+pub fn sys_chmod(task: &Tcb, path: usize, mode: usize) -> Result<usize> {
+    let path = user_path(task, path)?;
+    fs::chmod(&path, mode as u32)?;
+    Ok(0)
+}
+
+// uid或gid为-1时不修改
+pub fn sys_chown(task: &Tcb, path: usize, uid: usize, gid: usize) -> Result<usize> {
+    let path = user_path(task, path)?;
+    let id = |id: usize| Some(id as u32).filter(|id| *id != u32::MAX);
+    fs::chown(&path, id(uid), id(gid))?;
+    Ok(0)
+}
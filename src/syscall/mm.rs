@@ -0,0 +1,143 @@
+use super::def::{
+    MAP_ANONYMOUS, MAP_FIXED, MAP_PRIVATE, MAP_SHARED, MS_ASYNC, MS_INVALIDATE, MS_SYNC, PROT_EXEC,
+    PROT_READ, PROT_WRITE,
+};
+use crate::{
+    error::{Errno, Result},
+    fs::def::FileType,
+    mm::{
+        address::VirtualMemoryAddress,
+        def::PGSZ,
+        vm::vma::{Vma, VmaFile},
+    },
+    proc::process::Tcb,
+    riscv::sv39::pteflags::{PTE_FLAG_R, PTE_FLAG_W, PTE_FLAG_X},
+};
+use alloc::vec::Vec;
+use xxos_log::warn;
+
+// 按页对齐的区间[addr, addr + len)
+fn page_range(addr: usize, len: usize) -> Result<(VirtualMemoryAddress, VirtualMemoryAddress)> {
+    if addr % PGSZ != 0 || len == 0 {
+        return Err(Errno::EINVAL.into());
+    }
+    let end = len
+        .checked_next_multiple_of(PGSZ)
+        .and_then(|len| addr.checked_add(len))
+        .ok_or(Errno::EINVAL)?;
+    Ok((
+        VirtualMemoryAddress::new(addr),
+        VirtualMemoryAddress::new(end),
+    ))
+}
+
+// 撤销的共享映射写入过的页写回文件，区间在这里丢弃
+fn write_back(vmas: Vec<Vma>) -> Result<()> {
+    let mut result = Ok(());
+    for vma in vmas.iter().filter(|vma| vma.shared) {
+        if let Some(file) = &vma.file {
+            if let Err(err) = file.inode.sync() {
+                result = Err(err);
+            }
+        }
+    }
+    result
+}
+
+pub fn sys_mmap(
+    task: &Tcb,
+    addr: usize,
+    len: usize,
+    prot: usize,
+    flags: usize,
+    fd: usize,
+    offset: usize,
+) -> Result<usize> {
+    let shared = match flags & (MAP_SHARED | MAP_PRIVATE) {
+        MAP_SHARED => true,
+        MAP_PRIVATE => false,
+        _ => return Err(Errno::EINVAL.into()),
+    };
+    if offset % PGSZ != 0 || prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+        return Err(Errno::EINVAL.into());
+    }
+    let fixed = flags & MAP_FIXED != 0;
+    // 没有MAP_FIXED时addr只是提示，这里忽略它
+    let (start, end) = page_range(if fixed { addr } else { 0 }, len)?;
+
+    let file = if flags & MAP_ANONYMOUS != 0 {
+        None
+    } else {
+        let file = task.files().lock().get(fd)?;
+        let inode = file.inode().ok_or(Errno::ENODEV)?;
+        if inode.file_type() != FileType::Regular {
+            return Err(Errno::ENODEV.into());
+        }
+        // 共享的可写映射会写回文件，文件必须以可写方式打开
+        if !file.readable() || (shared && prot & PROT_WRITE != 0 && !file.writable()) {
+            return Err(Errno::EACCES.into());
+        }
+        Some(VmaFile {
+            inode,
+            offset: offset as u64,
+            path: file.path(),
+        })
+    };
+
+    let mut pte_flags = 0;
+    for (p, flag) in [
+        (PROT_READ, PTE_FLAG_R),
+        (PROT_WRITE, PTE_FLAG_W),
+        (PROT_EXEC, PTE_FLAG_X),
+    ] {
+        if prot & p != 0 {
+            pte_flags |= flag;
+        }
+    }
+    // RISC-V中可写的页必须可读
+    if pte_flags & PTE_FLAG_W != 0 {
+        pte_flags |= PTE_FLAG_R;
+    }
+    let vma = Vma {
+        start,
+        end,
+        prot: pte_flags,
+        shared,
+        file,
+    };
+
+    let result = task
+        .vm()
+        .lock()
+        .mmap(fixed.then_some(start), end - start, vma);
+    let (addr, old) = result?;
+    if let Err(err) = write_back(old) {
+        warn!("mmap: write back replaced mapping: {}", err);
+    }
+    Ok(addr.as_usize())
+}
+
+pub fn sys_munmap(task: &Tcb, addr: usize, len: usize) -> Result<usize> {
+    let (start, end) = page_range(addr, len)?;
+    let removed = task.vm().lock().munmap(start, end);
+    if let Err(err) = write_back(removed) {
+        warn!("munmap: write back failed: {}", err);
+    }
+    Ok(0)
+}
+
+// 把[addr, addr + len)中共享映射写入过的页写回文件
+// 页缓存与read共享同一份数据，MS_INVALIDATE不需要做什么
+pub fn sys_msync(task: &Tcb, addr: usize, len: usize, flags: usize) -> Result<usize> {
+    if flags & !(MS_ASYNC | MS_INVALIDATE | MS_SYNC) != 0
+        || flags & (MS_ASYNC | MS_SYNC) == MS_ASYNC | MS_SYNC
+    {
+        return Err(Errno::EINVAL.into());
+    }
+    let (start, end) = page_range(addr, len)?;
+    let inodes = task.vm().lock().shared_files(start, end);
+    for inode in inodes {
+        inode.sync()?;
+    }
+    Ok(0)
+}
@@ -1 +1,83 @@
+pub mod def;
+mod fs;
+mod mm;
+mod process;
 
+use crate::{
+    error::{Errno, Result},
+    proc::process::Tcb,
+};
+use def::*;
+use xxos_log::warn;
+
+// 系统调用入口，参数依次位于a0-a5，调用号位于a7
+// 返回值写回a0，出错时返回负的错误号
+pub fn syscall(task: &Tcb) {
+    let trapframe = task.get_mut_trapframe().expect("get trapframe err");
+    let id = trapframe.a7;
+    let args = [
+        trapframe.a0,
+        trapframe.a1,
+        trapframe.a2,
+        trapframe.a3,
+        trapframe.a4,
+        trapframe.a5,
+    ];
+
+    let ret: Result<usize> = match id {
+        SYS_READ => fs::sys_read(task, args[0], args[1], args[2]),
+        SYS_WRITE => fs::sys_write(task, args[0], args[1], args[2]),
+        SYS_OPEN => fs::sys_open(task, args[0], args[1], args[2]),
+        SYS_CLOSE => fs::sys_close(task, args[0]),
+        SYS_LSEEK => fs::sys_lseek(task, args[0], args[1] as isize, args[2]),
+        SYS_DUP => fs::sys_dup(task, args[0]),
+        SYS_DUP2 => fs::sys_dup2(task, args[0], args[1]),
+        SYS_PIPE => fs::sys_pipe(task, args[0]),
+        SYS_FSTAT => fs::sys_fstat(task, args[0], args[1]),
+        SYS_MOUNT => fs::sys_mount(task, args[0], args[1], args[2], args[4]),
+        SYS_UMOUNT => fs::sys_umount(task, args[0]),
+        SYS_IOCTL => fs::sys_ioctl(task, args[0], args[1], args[2]),
+        SYS_CHDIR => fs::sys_chdir(task, args[0]),
+        SYS_GETCWD => fs::sys_getcwd(task, args[0], args[1]),
+        SYS_MKDIR => fs::sys_mkdir(task, args[0], args[1]),
+        SYS_RMDIR => fs::sys_rmdir(task, args[0]),
+        SYS_UNLINK => fs::sys_unlink(task, args[0]),
+        SYS_LINK => fs::sys_link(task, args[0], args[1]),
+        SYS_RENAME => fs::sys_rename(task, args[0], args[1]),
+        SYS_SYMLINK => fs::sys_symlink(task, args[0], args[1]),
+        SYS_READLINK => fs::sys_readlink(task, args[0], args[1], args[2]),
+        SYS_GETDENTS => fs::sys_getdents(task, args[0], args[1], args[2]),
+        SYS_CHMOD => fs::sys_chmod(task, args[0], args[1]),
+        SYS_CHOWN => fs::sys_chown(task, args[0], args[1], args[2]),
+        SYS_MMAP => mm::sys_mmap(task, args[0], args[1], args[2], args[3], args[4], args[5]),
+        SYS_MUNMAP => mm::sys_munmap(task, args[0], args[1]),
+        SYS_MSYNC => mm::sys_msync(task, args[0], args[1], args[2]),
+        SYS_FORK => process::sys_fork(task),
+        SYS_EXEC => process::sys_exec(task, args[0], args[1]),
+        SYS_GETPID => process::sys_getpid(task),
+        SYS_SBRK => process::sys_sbrk(task, args[0] as isize),
+        SYS_SETUID => process::sys_setuid(task, args[0]),
+        SYS_GETUID => process::sys_getuid(task),
+        SYS_SETGID => process::sys_setgid(task, args[0]),
+        SYS_GETGID => process::sys_getgid(task),
+        SYS_SETGROUPS => process::sys_setgroups(task, args[0], args[1]),
+        SYS_PERSONALITY => process::sys_personality(task, args[0]),
+        _ => {
+            warn!("pid {}: unknown syscall {}", task.pid(), id);
+            Err(Errno::ENOSYS.into())
+        }
+    };
+
+    trapframe.a0 = match ret {
+        Ok(value) => value,
+        // 进程进入睡眠，回到ecall处等待唤醒后重新执行，参数保持不变
+        Err(err) if err.errno() == Some(Errno::ERESTARTSYS) => {
+            trapframe.epc -= 4;
+            return;
+        }
+        Err(err) => {
+            let errno = err.errno().unwrap_or(Errno::EINVAL);
+            -(errno as isize) as usize
+        }
+    };
+}
@@ -0,0 +1,114 @@
+use super::{
+    def::{ADDR_NO_RANDOMIZE, PER_QUERY},
+    fs::user_path,
+};
+use crate::{
+    cpu::current_task,
+    error::{Errno, Result},
+    mm::{address::VirtualMemoryAddress, vm::fault::copy_from_user},
+    proc::{
+        cred::NGROUPS_MAX,
+        process::{self, Tcb},
+    },
+};
+use alloc::{string::String, vec::Vec};
+use core::mem::size_of;
+
+// exec最多接受的参数个数
+const MAXARG: usize = 32;
+
+pub fn sys_getpid(task: &Tcb) -> Result<usize> {
+    Ok(*task.pid())
+}
+
+pub fn sys_fork(_task: &Tcb) -> Result<usize> {
+    let parent = current_task().ok_or(Errno::ESRCH)?;
+    let child = process::fork(&parent)?;
+    Ok(*child.pid())
+}
+
+// argv是以NULL结尾的字符串指针数组
+pub fn sys_exec(task: &Tcb, path: usize, argv: usize) -> Result<usize> {
+    let path = user_path(task, path)?;
+    let mut args: Vec<String> = Vec::new();
+    loop {
+        if args.len() > MAXARG {
+            return Err(Errno::E2BIG.into());
+        }
+        let mut word = [0; size_of::<usize>()];
+        let va = VirtualMemoryAddress::new(argv + args.len() * size_of::<usize>());
+        copy_from_user(task, &mut word, va)?;
+        match usize::from_le_bytes(word) {
+            0 => break,
+            arg => args.push(user_path(task, arg)?),
+        }
+    }
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    Ok(task.exec(&path, &args)?)
+}
+
+pub fn sys_sbrk(task: &Tcb, increment: isize) -> Result<usize> {
+    let old = task.vm().lock().sbrk(increment)?;
+    Ok(old.as_usize())
+}
+
+// root可以切换到任意用户，其他用户只能设置为自己的uid
+pub fn sys_setuid(task: &Tcb, uid: usize) -> Result<usize> {
+    let uid = uid as u32;
+    let mut cred = task.cred().lock();
+    if !cred.is_root() && cred.uid != uid {
+        return Err(Errno::EPERM.into());
+    }
+    cred.uid = uid;
+    Ok(0)
+}
+
+pub fn sys_getuid(task: &Tcb) -> Result<usize> {
+    Ok(task.cred().lock().uid as usize)
+}
+
+// 与setuid相同，非root只能设置为自己的gid
+pub fn sys_setgid(task: &Tcb, gid: usize) -> Result<usize> {
+    let gid = gid as u32;
+    let mut cred = task.cred().lock();
+    if !cred.is_root() && cred.gid != gid {
+        return Err(Errno::EPERM.into());
+    }
+    cred.gid = gid;
+    Ok(0)
+}
+
+pub fn sys_getgid(task: &Tcb) -> Result<usize> {
+    Ok(task.cred().lock().gid as usize)
+}
+
+// list指向size个u32，只有root可以修改附加组
+pub fn sys_setgroups(task: &Tcb, size: usize, list: usize) -> Result<usize> {
+    if !task.cred().lock().is_root() {
+        return Err(Errno::EPERM.into());
+    }
+    if size > NGROUPS_MAX {
+        return Err(Errno::EINVAL.into());
+    }
+    let mut bytes = [0; NGROUPS_MAX * size_of::<u32>()];
+    let bytes = &mut bytes[..size * size_of::<u32>()];
+    copy_from_user(task, bytes, VirtualMemoryAddress::new(list))?;
+    let groups = bytes
+        .chunks_exact(size_of::<u32>())
+        .map(|gid| u32::from_ne_bytes(gid.try_into().unwrap()))
+        .collect();
+    task.cred().lock().groups = groups;
+    Ok(0)
+}
+
+// 只支持ADDR_NO_RANDOMIZE，从下一次exec开始生效，返回原来的persona
+pub fn sys_personality(task: &Tcb, persona: usize) -> Result<usize> {
+    let old = if task.aslr() { 0 } else { ADDR_NO_RANDOMIZE };
+    if persona != PER_QUERY {
+        if persona & !ADDR_NO_RANDOMIZE != 0 {
+            return Err(Errno::EINVAL.into());
+        }
+        task.set_aslr(persona & ADDR_NO_RANDOMIZE == 0);
+    }
+    Ok(old)
+}
@@ -1,8 +1,11 @@
+use super::def::CLOCK_COUNTS;
 use crate::{
     opensbi::Opensbi,
+    random::add_interrupt_entropy,
     riscv::{registers::sie::Sie, time},
 };
 use xx_mutex_lock::Mutex;
+use xxos_log::warn;
 
 const TIMEBASE: usize = 100000;
 
@@ -15,6 +18,16 @@ pub fn clock_set_next_event() {
     Opensbi::sbi_set_timer(time::read_time() + TIMEBASE);
 }
 
+// 内核态与用户态的时钟中断都由这里处理
+pub fn clock_handler() {
+    add_interrupt_entropy();
+    clock_set_next_event();
+    if CLOCK_COUNTS.add_counts() == 100 {
+        CLOCK_COUNTS.clear_counts();
+        warn!("100 counts");
+    }
+}
+
 pub struct ClockCounts(Mutex<usize>);
 
 impl Default for ClockCounts {
@@ -0,0 +1,100 @@
+use crate::{
+    driver::plic::{plic, Plic, PLIC_MAX_IRQ},
+    error::{Errno, ErrorTrace, Result},
+    random::add_interrupt_entropy,
+    riscv::registers::{r_tp, sie::Sie, sstatus::IntrGuard},
+};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use xx_mutex_lock::Mutex;
+use xxos_log::warn;
+
+// 外部中断的处理函数，参数为中断号
+pub type IrqHandler = fn(irq: usize);
+
+const IRQ_PRIORITY: u32 = 1;
+
+// 处理函数与设备名，设备名显示在/proc/interrupts中
+// 中断处理时也要获取这把锁，其他地方获取时必须关中断
+static IRQ_HANDLERS: Mutex<[Option<(&'static str, IrqHandler)>; PLIC_MAX_IRQ]> =
+    Mutex::new([None; PLIC_MAX_IRQ]);
+// 每个中断号被处理的次数
+#[allow(clippy::declare_interior_mutable_const)]
+const IRQ_COUNT_INIT: AtomicUsize = AtomicUsize::new(0);
+static IRQ_COUNTS: [AtomicUsize; PLIC_MAX_IRQ] = [IRQ_COUNT_INIT; PLIC_MAX_IRQ];
+
+// 由启动hart调用，之后每个hart还需要调用irq_init_hart
+pub fn irq_init() {
+    let plic = plic();
+    for irq in 1..PLIC_MAX_IRQ {
+        plic.set_priority(irq, 0);
+    }
+}
+
+pub fn irq_init_hart() {
+    plic().set_threshold(Plic::supervisor_context(r_tp()), 0);
+    Sie::set_sext();
+}
+
+// 注册外部中断的处理函数，并在所有hart上打开该中断
+pub fn register_irq(irq: usize, name: &'static str, handler: IrqHandler) -> Result<()> {
+    if irq == 0 || irq >= PLIC_MAX_IRQ {
+        return Err(ErrorTrace::from_errno(Errno::EINVAL));
+    }
+    let _guard = IntrGuard::new();
+    let mut handlers = IRQ_HANDLERS.lock();
+    if handlers[irq].is_some() {
+        return Err(ErrorTrace::from_errno(Errno::EBUSY));
+    }
+    handlers[irq] = Some((name, handler));
+
+    let plic = plic();
+    plic.set_priority(irq, IRQ_PRIORITY);
+    for hart in 0..crate::cpu::NCPU {
+        plic.enable(Plic::supervisor_context(hart), irq);
+    }
+    Ok(())
+}
+
+pub fn unregister_irq(irq: usize) {
+    if irq == 0 || irq >= PLIC_MAX_IRQ {
+        return;
+    }
+    let plic = plic();
+    for hart in 0..crate::cpu::NCPU {
+        plic.disable(Plic::supervisor_context(hart), irq);
+    }
+    plic.set_priority(irq, 0);
+    let _guard = IntrGuard::new();
+    IRQ_HANDLERS.lock()[irq] = None;
+}
+
+pub fn irq_count(irq: usize) -> usize {
+    IRQ_COUNTS
+        .get(irq)
+        .map_or(0, |count| count.load(Ordering::Relaxed))
+}
+
+pub fn irq_name(irq: usize) -> Option<&'static str> {
+    let _guard = IntrGuard::new();
+    let handlers = IRQ_HANDLERS.lock();
+    handlers
+        .get(irq)
+        .and_then(|handler| handler.map(|(name, _)| name))
+}
+
+// 内核态与用户态的外部中断都由这里处理
+pub fn external_interrupt_handler() {
+    add_interrupt_entropy();
+    let plic = plic();
+    let context = Plic::supervisor_context(r_tp());
+    while let Some(irq) = plic.claim(context) {
+        IRQ_COUNTS[irq].fetch_add(1, Ordering::Relaxed);
+        // 调用处理函数前释放锁，处理函数中可以再注册中断
+        let handler = IRQ_HANDLERS.lock()[irq];
+        match handler {
+            Some((_, handler)) => handler(irq),
+            None => warn!("unexpected interrupt irq = {}", irq),
+        }
+        plic.complete(context, irq);
+    }
+}
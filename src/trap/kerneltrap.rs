@@ -6,7 +6,7 @@ use crate::{
         stval::Stval,
         stvec::{Stvec, TrapMode},
     },
-    trap::{clock::clock_set_next_event, def::CLOCK_COUNTS, kernelvec, strampsec},
+    trap::{clock::clock_handler, irq::external_interrupt_handler, kernelvec},
 };
 use xxos_log::{error, warn};
 
@@ -39,11 +39,10 @@ pub fn kernel_trap_handler() {
             warn!("UserTimer");
         }
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
-            clock_set_next_event();
-            if CLOCK_COUNTS.add_counts() == 100 {
-                CLOCK_COUNTS.clear_counts();
-                warn!("100 counts");
-            }
+            clock_handler();
+        }
+        Trap::Interrupt(Interrupt::SupervisorExternal) => {
+            external_interrupt_handler();
         }
         /* 异常处理 */
         Trap::Exception(Exception::Breakpoint) => {
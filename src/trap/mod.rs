@@ -1,5 +1,6 @@
 pub mod clock;
 pub mod def;
+pub mod irq;
 pub mod kerneltrap;
 pub mod usertrap;
 
@@ -1,34 +1,34 @@
 use crate::{
+    cpu::{current_task, set_current_task},
     mm::{
-        pagetable_frame,
-        pagetable_frame::PageTableFrame,
+        address::VirtualMemoryAddress,
         pm::def::{kstack, KERNEL_STACK_SIZE, TRAMPOLINE},
+        vm::{fault::handle_page_fault, vma::Access},
     },
-    proc::{process::Tcb, TASKMANAGER},
+    proc::{process::TaskRef, TASKMANAGER},
     riscv::{
         self,
         registers::{
             r_tp,
-            satp::Satp,
+            scause::{Exception, Interrupt, Scause, Trap},
             sepc,
             sstatus::{self, intr_off, intr_on},
+            stval::Stval,
             stvec,
         },
     },
-    trap::{strampsec, userret, uservec},
+    sched::idle,
+    syscall::syscall,
+    trap::{
+        clock::clock_handler, irq::external_interrupt_handler, kernelvec, strampsec, userret,
+        uservec,
+    },
 };
-use xxos_log::warn;
+use alloc::sync::Arc;
+use xxos_log::{error, warn};
 
 #[no_mangle]
 pub extern "C" fn usertrapret() {
-    fn as_satp(pagetable: &PageTableFrame) -> Satp {
-        let ppn = pagetable.root().to_ppn();
-        let mut satp = Satp::new();
-        satp.set_mode(crate::riscv::registers::satp::Mode::Sv39);
-        satp.set_ppn(ppn.0);
-        satp
-    }
-
     intr_off();
 
     // 设置用户中断向量表(保存虚拟地址)
@@ -37,7 +37,16 @@ pub extern "C" fn usertrapret() {
         stvec::TrapMode::Direct,
     );
 
-    let mut task: alloc::sync::Arc<Tcb> = TASKMANAGER.lock().pop().expect("No Task in Manger");
+    let prev = current_task();
+    let task: TaskRef = loop {
+        // 先释放调度队列的锁，中断处理函数可能需要唤醒进程
+        let next = TASKMANAGER.lock().pop(prev.as_ref());
+        match next {
+            Some(task) => break task,
+            None => idle(),
+        }
+    };
+    set_current_task(Some(task.clone()));
     let pid = task.pid();
     let trapframe: &mut crate::proc::process::TrapFrame =
         task.get_mut_trapframe().expect("get trapframe err");
@@ -48,17 +57,95 @@ pub extern "C" fn usertrapret() {
     sstatus::Sstatus::set_spp(sstatus::SPP::User);
     sstatus::Sstatus::set_spie();
     sepc::Sepc::_write(trapframe.epc);
-    let satp = as_satp(task.pagetable()).bits();
+    let satp = task.vm().lock().as_satp().bits();
+    let trapframe_va = task.trapframe_va();
+    task.enter_user();
+    // 上一个进程的内核栈一直用到这里，之后才允许其他hart运行它
+    if let Some(prev) = prev.filter(|prev| !Arc::ptr_eq(prev, &task)) {
+        prev.deschedule();
+    }
+    // 跳转之后不会返回，必须在这里释放引用计数
+    drop(task);
     let next_fn: usize = TRAMPOLINE + (userret as usize - strampsec as usize);
     unsafe {
-        let fn_0: extern "C" fn(usize) -> ! = core::mem::transmute(next_fn);
-        fn_0(satp) // (*next_fn)(satp)
+        let fn_0: extern "C" fn(usize, usize) -> ! = core::mem::transmute(next_fn);
+        fn_0(satp, trapframe_va) // (*next_fn)(satp, trapframe)
     }
 }
 
 #[no_mangle]
 pub fn usertrap() {
-    // TODO: set trap handler
-    warn!("run into usertrap");
-    panic!("loop in usertrap")
+    // 已经进入内核，之后的中断与异常交给kernelvec处理
+    stvec::Stvec::write(kernelvec as usize, stvec::TrapMode::Direct);
+
+    let task = current_task().expect("usertrap without current task");
+    task.enter_kernel();
+    let trapframe = task.get_mut_trapframe().expect("get trapframe err");
+    // save user program counter
+    trapframe.epc = sepc::Sepc::read().bits();
+
+    let scause = Scause::read();
+    match scause.cause() {
+        Trap::Exception(Exception::UserEnvCall) => {
+            // 返回到ecall的下一条指令
+            trapframe.epc += 4;
+            intr_on();
+            syscall(&task);
+        }
+        Trap::Exception(
+            cause @ (Exception::InstructionPageFault
+            | Exception::LoadPageFault
+            | Exception::StorePageFault),
+        ) => {
+            // stval在开中断之前读取，之后的中断会覆盖它
+            let va = Stval::read().bits();
+            let access = match cause {
+                Exception::InstructionPageFault => Access::Exec,
+                Exception::LoadPageFault => Access::Read,
+                _ => Access::Write,
+            };
+            intr_on();
+            if let Err(err) = handle_page_fault(&task, VirtualMemoryAddress::new(va), access) {
+                warn!(
+                    "pid {}: page fault at {:#x} ({:?}), sepc = {:#x}: {}, killed",
+                    task.pid(),
+                    va,
+                    access,
+                    trapframe.epc,
+                    err
+                );
+                task.kill();
+            }
+        }
+        Trap::Interrupt(Interrupt::SupervisorTimer) => {
+            clock_handler();
+        }
+        Trap::Interrupt(Interrupt::SupervisorExternal) => {
+            external_interrupt_handler();
+        }
+        _ => {
+            error!(
+                "pid {}: unexpected usertrap {:?}, sepc = {:#x}, stval = {:#x}",
+                task.pid(),
+                scause.cause(),
+                trapframe.epc,
+                Stval::read().bits()
+            );
+            panic!("loop in usertrap")
+        }
+    }
+
+    // 之后会操作调度队列，关中断直到返回用户态
+    intr_off();
+
+    // 被杀死的进程不再返回用户态
+    if task.is_killed() {
+        set_current_task(None);
+        task.exit();
+    }
+
+    // usertrapret不会返回，先释放对进程的引用
+    task.leave_kernel();
+    drop(task);
+    usertrapret();
 }
@@ -20,14 +20,14 @@ uservec:
         # user page table.
         #
 
-        # save user a0 in sscratch so
-        # a0 can be used to get at TRAPFRAME.
-        csrw sscratch, a0
-
-        # each process has a separate p->trapframe memory area,
-        # but it's mapped to the same virtual address
-        # (TRAPFRAME) in every process's user page table.
-        li a0, 274877898752
+        # swap a0 and sscratch, so that a0 holds the
+        # virtual address of this process's trapframe
+        # and sscratch holds the user a0.
+        # trapframes come from a slab, the page holding
+        # them is mapped at TRAPFRAME in every process's
+        # user page table, the trapframe itself lives at
+        # TRAPFRAME + its offset inside that page.
+        csrrw a0, sscratch, a0
         
         # save the user registers in TRAPFRAME
         sd ra, 40(a0)
@@ -93,17 +93,20 @@ uservec:
 
 .globl userret
 userret:
-        # userret(pagetable)
+        # userret(pagetable, trapframe)
         # called by usertrapret() in trap.c to
         # switch from kernel to user.
         # a0: user page table, for satp.
+        # a1: user virtual address of the trapframe.
 
         # switch to the user page table.
         sfence.vma zero, zero
         csrw satp, a0
         sfence.vma zero, zero
 
-        li a0, 274877898752
+        # uservec finds the trapframe through sscratch
+        csrw sscratch, a1
+        mv a0, a1
 
         # restore all but a0 from TRAPFRAME
         ld ra, 40(a0)